@@ -0,0 +1,153 @@
+//! Checking the contents a registry stores against the checksums recorded in
+//! its index when each version was published, for operators auditing a
+//! registry's integrity.
+
+use crate::checksum::Checksum;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_source::{PackageSource, PackageSourceProvider};
+
+/// How many versions are downloaded at once.
+const CONCURRENT_DOWNLOADS: usize = 16;
+
+/// What `audit_contents` found.
+#[derive(Debug, Default)]
+pub struct ContentsAudit {
+    /// How many versions had a checksum to check their contents against.
+    /// Versions published before checksums were recorded are skipped.
+    pub checked: usize,
+
+    /// Versions whose stored contents don't match the checksum in the index.
+    pub mismatched: Vec<Mismatch>,
+
+    /// Versions whose contents couldn't be downloaded, with the reason.
+    pub missing: Vec<(PackageId, String)>,
+}
+
+impl ContentsAudit {
+    pub fn is_clean(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    pub package_id: PackageId,
+
+    /// The checksum recorded in the index.
+    pub recorded: String,
+
+    /// The checksum of the stored contents, made with the recorded checksum's
+    /// algorithm.
+    pub actual: Checksum,
+}
+
+/// Downloads the contents of every version of the named packages that has a
+/// checksum in the index and checks them against it. Versions are downloaded
+/// concurrently, but reported in the order the index lists them.
+pub fn audit_contents(
+    source: &PackageSource,
+    names: &[PackageName],
+) -> anyhow::Result<ContentsAudit> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .max_blocking_threads(CONCURRENT_DOWNLOADS)
+        .enable_all()
+        .build()?;
+
+    let mut handles = Vec::new();
+
+    for name in names {
+        for manifest in source.all_versions(name)? {
+            let recorded = match manifest.checksum {
+                Some(checksum) => checksum,
+                None => continue,
+            };
+
+            let package_id = manifest.package_id();
+            let source = source.clone();
+
+            handles.push(runtime.spawn_blocking(move || {
+                log::debug!("Downloading {} to audit it", package_id);
+                let contents = source.download_package(&package_id);
+                (package_id, recorded, contents)
+            }));
+        }
+    }
+
+    let mut audit = ContentsAudit::default();
+
+    for handle in handles {
+        let (package_id, recorded, contents) = runtime
+            .block_on(handle)
+            .expect("Package failed to be audited.");
+        audit.checked += 1;
+
+        let contents = match contents {
+            Ok(contents) => contents,
+            Err(err) => {
+                audit.missing.push((package_id, format!("{:#}", err)));
+                continue;
+            }
+        };
+
+        // A checksum that can't be parsed can't match anything.
+        let actual = match recorded.parse::<Checksum>() {
+            Ok(expected) if expected.matches(contents.data()) => continue,
+            Ok(expected) => Checksum::compute_with(expected.algorithm(), contents.data()),
+            Err(_) => Checksum::compute(contents.data()),
+        };
+
+        audit.mismatched.push(Mismatch {
+            package_id,
+            recorded,
+            actual,
+        });
+    }
+
+    Ok(audit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::TestRegistry;
+
+    #[test]
+    fn tampered_contents() {
+        // biff/one-dependency's contents were changed after it was published,
+        // biff/minimal-shared's contents are gone, and
+        // biff/transitive-dependency has no checksum to check.
+        let source = PackageSource::TestRegistry(TestRegistry::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/audit-registry"
+        )));
+
+        let names = source.package_names().unwrap();
+        let audit = audit_contents(&source, &names).unwrap();
+
+        assert_eq!(audit.checked, 3);
+
+        let mismatched: Vec<String> = audit
+            .mismatched
+            .iter()
+            .map(|mismatch| mismatch.package_id.to_string())
+            .collect();
+        assert_eq!(mismatched, vec!["biff/one-dependency@0.1.0"]);
+        assert_eq!(
+            audit.mismatched[0].recorded,
+            "sha256:3fbdca6265dee72fbf1601e4121167efd74c724791d515b1f0e0d32e44b86c04"
+        );
+        assert_ne!(
+            audit.mismatched[0].actual.to_string(),
+            audit.mismatched[0].recorded
+        );
+
+        let missing: Vec<String> = audit
+            .missing
+            .iter()
+            .map(|(package_id, _)| package_id.to_string())
+            .collect();
+        assert_eq!(missing, vec!["biff/minimal-shared@0.1.0"]);
+    }
+}
@@ -0,0 +1,161 @@
+//! Checksums of package contents, written like `sha256:<hex>` so that other
+//! algorithms can be added later without changing the format.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use sha2::{Digest, Sha256};
+
+/// The algorithm used when computing new checksums.
+pub const DEFAULT_ALGORITHM: ChecksumAlgorithm = ChecksumAlgorithm::Sha256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    const ALL: &'static [ChecksumAlgorithm] = &[ChecksumAlgorithm::Sha256];
+
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    fn digest(self, data: &[u8]) -> String {
+        match self {
+            ChecksumAlgorithm::Sha256 => hex::encode(Sha256::digest(data)),
+        }
+    }
+
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.prefix() == prefix)
+    }
+}
+
+/// A hex-encoded digest along with the algorithm that produced it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Checksum {
+    algorithm: ChecksumAlgorithm,
+    digest: String,
+}
+
+impl Checksum {
+    /// Computes the checksum of `data` with the default algorithm.
+    pub fn compute(data: &[u8]) -> Self {
+        Self::compute_with(DEFAULT_ALGORITHM, data)
+    }
+
+    pub fn compute_with(algorithm: ChecksumAlgorithm, data: &[u8]) -> Self {
+        Self {
+            algorithm,
+            digest: algorithm.digest(data),
+        }
+    }
+
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
+    }
+
+    /// The hex-encoded digest, without the algorithm prefix.
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    /// Whether `data` has this checksum, using this checksum's algorithm.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.algorithm.digest(data) == self.digest
+    }
+
+    /// Tells whether a checksum is written the old way, as a bare SHA-256 hex
+    /// digest without an algorithm prefix.
+    pub fn is_legacy(value: &str) -> bool {
+        !value.contains(':')
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        // Checksums were bare SHA-256 digests before they had a prefix.
+        let (prefix, digest) = value
+            .split_once(':')
+            .unwrap_or((ChecksumAlgorithm::Sha256.prefix(), value));
+
+        let algorithm = match ChecksumAlgorithm::from_prefix(prefix) {
+            Some(algorithm) => algorithm,
+            None => {
+                let supported: Vec<&str> = ChecksumAlgorithm::ALL
+                    .iter()
+                    .map(|algorithm| algorithm.prefix())
+                    .collect();
+
+                bail!(
+                    "Checksum `{}` uses an unknown algorithm `{}`. Supported algorithms are: {}",
+                    value,
+                    prefix,
+                    supported.join(", ")
+                );
+            }
+        };
+
+        let digest = digest.to_ascii_lowercase();
+        hex::decode(&digest).with_context(|| format!("Checksum `{}` is not valid hex", value))?;
+
+        Ok(Self { algorithm, digest })
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}:{}", self.algorithm.prefix(), self.digest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn prefixed() {
+        let checksum: Checksum = format!("sha256:{}", HELLO_SHA256).parse().unwrap();
+
+        assert_eq!(checksum.algorithm(), ChecksumAlgorithm::Sha256);
+        assert!(checksum.matches(b"hello"));
+        assert!(!checksum.matches(b"goodbye"));
+        assert_eq!(checksum, Checksum::compute(b"hello"));
+        assert_eq!(checksum.to_string(), format!("sha256:{}", HELLO_SHA256));
+    }
+
+    #[test]
+    fn bare_legacy() {
+        assert!(Checksum::is_legacy(HELLO_SHA256));
+
+        let checksum: Checksum = HELLO_SHA256.parse().unwrap();
+
+        assert_eq!(checksum.algorithm(), ChecksumAlgorithm::Sha256);
+        assert!(checksum.matches(b"hello"));
+        assert_eq!(checksum.to_string(), format!("sha256:{}", HELLO_SHA256));
+    }
+
+    #[test]
+    fn unknown_algorithm() {
+        let err = "md5:5d41402abc4b2a76b9719d911017c592"
+            .parse::<Checksum>()
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Checksum `md5:5d41402abc4b2a76b9719d911017c592` uses an unknown algorithm `md5`. \
+             Supported algorithms are: sha256"
+        );
+    }
+}
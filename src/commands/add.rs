@@ -0,0 +1,169 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use semver::Version;
+use structopt::StructOpt;
+use toml_edit::{table, Document};
+
+use crate::manifest::Manifest;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::{PackageSource, PackageSourceMap, PackageSourceProvider, TestRegistry};
+use crate::GlobalOptions;
+
+use super::utils::set_requirement;
+use super::InstallSubcommand;
+
+/// Add a dependency to this project's manifest and install it.
+#[derive(Debug, StructOpt)]
+pub struct AddSubcommand {
+    /// Path to the project to add the dependency to.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The package to add, like `evaera/signal` or `evaera/signal@^1`. The
+    /// newest version that matches is used, leaving out pre-releases unless the
+    /// requirement names one.
+    pub package: String,
+
+    /// The name to refer to the package by. Defaults to the package's name in
+    /// PascalCase, like `Signal`.
+    #[structopt(long = "alias")]
+    pub alias: Option<String>,
+
+    /// Add the package to `server-dependencies`.
+    #[structopt(long = "server", conflicts_with = "dev")]
+    pub server: bool,
+
+    /// Add the package to `dev-dependencies`.
+    #[structopt(long = "dev")]
+    pub dev: bool,
+}
+
+impl AddSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+        let package_req: PackageReq = self.package.parse()?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        let version = newest_match(&package_sources, &package_req)?;
+        let requirement = format!("{}@{}", package_req.name(), version);
+
+        let table_name = if self.server {
+            "server-dependencies"
+        } else if self.dev {
+            "dev-dependencies"
+        } else {
+            "dependencies"
+        };
+
+        let manifest_path = global.manifest_file(&self.project_path);
+        let contents = fs_err::read_to_string(&manifest_path)?;
+        let mut doc: Document = contents.parse().with_context(|| {
+            format!(
+                "failed to parse manifest at path {}",
+                manifest_path.display()
+            )
+        })?;
+
+        if !doc.as_table_mut().contains_table(table_name) {
+            doc[table_name] = table();
+        }
+
+        // Depending on the package already means its requirement is updated,
+        // keeping whatever alias it already has.
+        let existing_alias = doc[table_name].as_table().and_then(|dependencies| {
+            dependencies.iter().find_map(|(alias, item)| {
+                let existing: PackageReq = item.as_str()?.parse().ok()?;
+
+                if existing.name() == package_req.name() {
+                    Some(alias.to_owned())
+                } else {
+                    None
+                }
+            })
+        });
+
+        let alias = match (existing_alias, self.alias) {
+            (Some(existing), _) => existing,
+            (None, Some(alias)) => alias,
+            (None, None) => default_alias(package_req.name()),
+        };
+
+        set_requirement(&mut doc[table_name][alias.as_str()], &requirement);
+        fs_err::write(&manifest_path, doc.to_string())?;
+
+        println!("Added {} = \"{}\" to {}", alias, requirement, table_name);
+
+        InstallSubcommand {
+            project_path: self.project_path,
+            ..Default::default()
+        }
+        .run(global)
+    }
+}
+
+/// Finds the newest version of a package that matches the requirement, from
+/// the first source that has any.
+fn newest_match(
+    package_sources: &PackageSourceMap,
+    package_req: &PackageReq,
+) -> anyhow::Result<Version> {
+    for source in package_sources.source_order() {
+        let registry = package_sources.get(source).unwrap();
+
+        let newest = match registry.query(package_req) {
+            Ok(manifests) => manifests
+                .into_iter()
+                .map(|manifest| manifest.package.version)
+                .max(),
+            Err(err) => {
+                log::debug!(
+                    "Source {:?} could not provide {}: {:#}",
+                    source,
+                    package_req,
+                    err
+                );
+                None
+            }
+        };
+
+        if let Some(version) = newest {
+            return Ok(version);
+        }
+    }
+
+    bail!("No version of {} could be found", package_req)
+}
+
+/// Turns a package's name into an alias, like `roblox-ts` into `RobloxTs`.
+fn default_alias(name: &PackageName) -> String {
+    name.name()
+        .split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,68 @@
+use anyhow::bail;
+use crossterm::style::{Color, SetForegroundColor};
+use structopt::StructOpt;
+
+use crate::audit::audit_contents;
+use crate::package_source::PackageSourceProvider;
+use crate::GlobalOptions;
+
+use super::utils::registry_source;
+
+/// Check every package's stored contents against the checksum the registry
+/// recorded in its index when the package was published.
+#[derive(Debug, StructOpt)]
+pub struct AuditContentsSubcommand {
+    /// The registry to audit.
+    pub registry: String,
+}
+
+impl AuditContentsSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let source = registry_source(&self.registry, &global)?;
+
+        if !source.capabilities().can_enumerate {
+            bail!(
+                "{} can't list the packages it has, so its contents can't be audited",
+                self.registry
+            );
+        }
+
+        source.update()?;
+        let names = source.package_names()?;
+        let audit = audit_contents(&source, &names)?;
+
+        for mismatch in &audit.mismatched {
+            println!(
+                "{}   Mismatch {}{} (recorded {}, stored {})",
+                SetForegroundColor(Color::Red),
+                SetForegroundColor(Color::Reset),
+                mismatch.package_id,
+                mismatch.recorded,
+                mismatch.actual
+            );
+        }
+
+        for (package_id, reason) in &audit.missing {
+            println!(
+                "{}    Missing {}{} ({})",
+                SetForegroundColor(Color::Red),
+                SetForegroundColor(Color::Reset),
+                package_id,
+                reason
+            );
+        }
+
+        println!(
+            "Checked {} package versions: {} mismatched, {} missing their contents",
+            audit.checked,
+            audit.mismatched.len(),
+            audit.missing.len()
+        );
+
+        if !audit.is_clean() {
+            bail!("{} failed the audit", self.registry);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::package_contents::PackageContents;
+
+/// Print the checksum the project would have once it's packaged, to compare
+/// against the one a registry recorded.
+#[derive(Debug, StructOpt)]
+pub struct ChecksumSubcommand {
+    /// Path to the project to compute the checksum of
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+}
+
+impl ChecksumSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let contents = PackageContents::pack_from_path(&self.project_path)?;
+        println!("{}", contents.checksum());
+
+        Ok(())
+    }
+}
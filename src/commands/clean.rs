@@ -0,0 +1,49 @@
+use std::io;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::installation::InstallationContext;
+use crate::lockfile::LOCKFILE_NAME;
+use crate::manifest::Manifest;
+use crate::GlobalOptions;
+
+/// Remove the packages Wally installed into this project.
+#[derive(Debug, StructOpt)]
+pub struct CleanSubcommand {
+    /// Path to the project to clean.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Also remove the project's lockfile.
+    #[structopt(long = "lockfile")]
+    pub lockfile: bool,
+}
+
+impl CleanSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+
+        let installation = InstallationContext::new(
+            &self.project_path,
+            manifest.place.shared_packages,
+            manifest.place.server_packages,
+        );
+
+        for path in installation.clean_managed()? {
+            println!("Removed {}", path.display());
+        }
+
+        if self.lockfile {
+            let lockfile_path = self.project_path.join(LOCKFILE_NAME);
+
+            match fs_err::remove_file(&lockfile_path) {
+                Ok(()) => println!("Removed {}", lockfile_path.display()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
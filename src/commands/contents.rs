@@ -0,0 +1,102 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use structopt::StructOpt;
+
+use crate::manifest::Manifest;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSource, PackageSourceMap, PackageSourceProvider, TestRegistry};
+use crate::GlobalOptions;
+
+/// List the files inside a published package, without installing it.
+#[derive(Debug, StructOpt)]
+pub struct ContentsSubcommand {
+    /// Path to the project whose registry should be searched.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The package version to look inside, like `evaera/signal@1.2.0`.
+    pub package: PackageId,
+
+    /// Print this file from the package, like `src/init.lua`, instead of
+    /// listing every file.
+    #[structopt(long = "show")]
+    pub show: Option<PathBuf>,
+}
+
+impl ContentsSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        let contents = download(&package_sources, &self.package)?;
+
+        if let Some(path) = &self.show {
+            std::io::stdout().write_all(&contents.read_file(path)?)?;
+            return Ok(());
+        }
+
+        let entries = contents.entries()?;
+        let total: u64 = entries.iter().map(|entry| entry.size).sum();
+
+        for entry in &entries {
+            if entry.is_dir {
+                println!("{:>10}  {}/", "", entry.path.display());
+            } else {
+                println!("{:>10}  {}", entry.size, entry.path.display());
+            }
+        }
+
+        println!(
+            "{} files, {} bytes",
+            entries.iter().filter(|entry| !entry.is_dir).count(),
+            total
+        );
+
+        Ok(())
+    }
+}
+
+/// Downloads the package from the first source that has it.
+fn download(
+    package_sources: &PackageSourceMap,
+    package_id: &PackageId,
+) -> anyhow::Result<PackageContents> {
+    for source_id in package_sources.source_order() {
+        let source = package_sources.get(source_id).unwrap();
+
+        match source.get(package_id) {
+            Ok(Some(_)) => return source.download_package(package_id),
+            Ok(None) => continue,
+            Err(err) => {
+                log::debug!(
+                    "Source {:?} could not provide {}: {:#}",
+                    source_id,
+                    package_id,
+                    err
+                );
+            }
+        }
+    }
+
+    bail!("No package {} could be found", package_id)
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use structopt::StructOpt;
+
+use crate::graph::PackageGraph;
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::resolution::resolve;
+use crate::GlobalOptions;
+
+use super::utils::add_dependency_sources;
+
+/// Print the resolved package graph of this project, as pinned by its
+/// lockfile.
+#[derive(Debug, StructOpt)]
+pub struct GraphSubcommand {
+    /// Path to the project to print the graph of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Print the graph as JSON adjacency lists, for other tools to analyze.
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+impl GraphSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let graph = self.graph(&global)?;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&graph)?);
+        } else {
+            for edge in &graph.edges {
+                println!("{} -> {} ({})", edge.from, edge.to, edge.alias);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The graph this command prints. Optional dependencies are left out
+    /// unless they're on by default, like `wally install` does without
+    /// `--features`.
+    pub fn graph(&self, global: &GlobalOptions) -> anyhow::Result<PackageGraph> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?
+            .with_features(false, &[])?;
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!("This project has no lockfile. Run wally install to create one.")
+        })?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(
+                PackageSource::from_registry_spec(
+                    &manifest.package.registry,
+                    global.max_download_size,
+                    global.max_index_entries,
+                )?
+                .with_index_rev(lockfile.index_rev.clone())?,
+            )
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        add_dependency_sources(&mut package_sources, &manifest, global)?;
+
+        let try_to_use: BTreeSet<PackageId> = lockfile.as_ids().collect();
+        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+
+        PackageGraph::from_resolve(
+            &manifest,
+            &resolved,
+            &lockfile.checksums(),
+            &package_sources,
+        )
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_source::{PackageSource, PackageSourceMap, PackageSourceProvider, TestRegistry};
+use crate::GlobalOptions;
+
+/// Add dependencies to this project based on an existing folder of vendored
+/// packages, or a package.json-like file.
+#[derive(Debug, StructOpt)]
+pub struct ImportSubcommand {
+    /// Path to the project to add dependencies to.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The folder of vendored packages or the package.json-like file to
+    /// import dependencies from.
+    pub source: PathBuf,
+}
+
+impl ImportSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        let found = if self.source.is_dir() {
+            scan_folder(&self.source)?
+        } else {
+            scan_package_json(&self.source)?
+        };
+
+        let mut lines = Vec::new();
+        let mut todos = Vec::new();
+
+        for package in found {
+            if manifest.dependencies.contains_key(&package.alias) {
+                log::info!("Skipping {} as it's already a dependency", package.alias);
+                continue;
+            }
+
+            let name = match &package.name {
+                Some(name) => name,
+                None => {
+                    todos.push(format!("{} could not be identified", package.alias));
+                    continue;
+                }
+            };
+
+            match find_match(&package_sources, name, package.version.as_ref()) {
+                Some(package_id) => {
+                    println!("Matched {} to {}", package.alias, package_id);
+
+                    lines.push(format!(
+                        "{} = {}",
+                        toml_key(&package.alias),
+                        toml::Value::String(package_id.to_string())
+                    ));
+                }
+                None => {
+                    let wanted = match &package.version {
+                        Some(version) => format!("{}@{}", name, version),
+                        None => name.to_string(),
+                    };
+
+                    todos.push(format!(
+                        "{} wants {}, which is not in the registry",
+                        package.alias, wanted
+                    ));
+                }
+            }
+        }
+
+        for todo in &todos {
+            println!("TODO: {}", todo);
+            lines.push(format!("# TODO: {}", todo));
+        }
+
+        let manifest_path = global.manifest_file(&self.project_path);
+        let contents = fs_err::read_to_string(&manifest_path)?;
+        fs_err::write(&manifest_path, insert_dependencies(&contents, &lines))?;
+
+        Ok(())
+    }
+}
+
+/// A package found while scanning, along with whatever we could work out about
+/// which registry package it is.
+struct FoundPackage {
+    alias: String,
+    name: Option<PackageName>,
+    version: Option<Version>,
+}
+
+/// The parts of a package.json file that we understand.
+#[derive(Deserialize)]
+struct PackageJson {
+    name: Option<String>,
+    version: Option<String>,
+
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
+}
+
+/// Scans a folder of vendored packages. Every folder or Lua file inside is
+/// treated as a package, identified by its `wally.toml` or `package.json` if
+/// it has one.
+fn scan_folder(folder: &Path) -> anyhow::Result<Vec<FoundPackage>> {
+    let mut found = Vec::new();
+
+    let mut entries = fs_err::read_dir(folder)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        if path.is_dir() {
+            let (name, version) = if path.join(MANIFEST_FILE_NAME).is_file() {
+                let manifest = Manifest::load(&path)?;
+                (Some(manifest.package.name), Some(manifest.package.version))
+            } else if path.join("package.json").is_file() {
+                let package_json = read_package_json(&path.join("package.json"))?;
+                let name = package_json.name.as_deref().and_then(parse_npm_name);
+                let version = package_json.version.as_deref().and_then(parse_npm_version);
+                (name, version)
+            } else {
+                (None, None)
+            };
+
+            found.push(FoundPackage {
+                alias: file_name,
+                name,
+                version,
+            });
+        } else if let Some(alias) = file_name.strip_suffix(".lua") {
+            found.push(FoundPackage {
+                alias: alias.to_owned(),
+                name: None,
+                version: None,
+            });
+        }
+    }
+
+    Ok(found)
+}
+
+/// Reads the dependencies listed in a package.json-like file.
+fn scan_package_json(path: &Path) -> anyhow::Result<Vec<FoundPackage>> {
+    let package_json = read_package_json(path)?;
+
+    Ok(package_json
+        .dependencies
+        .iter()
+        .map(|(npm_name, npm_version)| {
+            let name = parse_npm_name(npm_name);
+            let alias = match &name {
+                Some(name) => name.name().to_owned(),
+                None => npm_name.clone(),
+            };
+
+            FoundPackage {
+                alias,
+                name,
+                version: parse_npm_version(npm_version),
+            }
+        })
+        .collect())
+}
+
+fn read_package_json(path: &Path) -> anyhow::Result<PackageJson> {
+    let contents = fs_err::read_to_string(path)?;
+    serde_json::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Turns a scoped npm package name like `@roblox/roact` into a Wally package
+/// name. Unscoped names can't be matched because Wally always needs a scope.
+fn parse_npm_name(npm_name: &str) -> Option<PackageName> {
+    let (scope, name) = npm_name.strip_prefix('@')?.split_once('/')?;
+    PackageName::new(scope, name).ok()
+}
+
+/// Turns a version or a simple version range like `^1.2.0` into a version.
+fn parse_npm_version(npm_version: &str) -> Option<Version> {
+    Version::parse(npm_version.trim_start_matches(|c| c == '^' || c == '~' || c == '=')).ok()
+}
+
+/// Finds the version of a package in the registry that best matches the one we
+/// found: the same version if it exists, otherwise the newest compatible one.
+fn find_match(
+    package_sources: &PackageSourceMap,
+    name: &PackageName,
+    version: Option<&Version>,
+) -> Option<PackageId> {
+    let available: Vec<Version> = package_sources.source_order().iter().find_map(|source| {
+        let registry = package_sources.get(source).unwrap();
+
+        match registry.all_versions(name) {
+            Ok(manifests) if !manifests.is_empty() => Some(
+                manifests
+                    .into_iter()
+                    .map(|manifest| manifest.package.version)
+                    .collect(),
+            ),
+            Ok(_) => None,
+            Err(err) => {
+                log::debug!("Source {:?} could not provide {}: {:#}", source, name, err);
+                None
+            }
+        }
+    })?;
+
+    let chosen = match version {
+        Some(version) if available.contains(version) => version.clone(),
+        Some(version) => {
+            let compatible = VersionReq::parse(&version.to_string()).ok()?;
+            available
+                .into_iter()
+                .filter(|available| compatible.matches(available))
+                .max()?
+        }
+        None => available.into_iter().max()?,
+    };
+
+    Some(PackageId::new(name.clone(), chosen))
+}
+
+/// Formats an alias as a TOML key, quoting it if it isn't a valid bare key.
+fn toml_key(alias: &str) -> String {
+    let is_bare = !alias.is_empty()
+        && alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if is_bare {
+        alias.to_owned()
+    } else {
+        toml::Value::String(alias.to_owned()).to_string()
+    }
+}
+
+/// Adds lines to the `[dependencies]` table of a manifest, creating the table
+/// if it doesn't exist yet.
+fn insert_dependencies(manifest: &str, lines: &[String]) -> String {
+    if lines.is_empty() {
+        return manifest.to_owned();
+    }
+
+    let mut output = String::new();
+    let mut inserted = false;
+
+    for line in manifest.lines() {
+        output.push_str(line);
+        output.push('\n');
+
+        if !inserted && line.trim() == "[dependencies]" {
+            for new_line in lines {
+                output.push_str(new_line);
+                output.push('\n');
+            }
+
+            inserted = true;
+        }
+    }
+
+    if !inserted {
+        output.push_str("\n[dependencies]\n");
+
+        for new_line in lines {
+            output.push_str(new_line);
+            output.push('\n');
+        }
+    }
+
+    output
+}
@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::manifest::Manifest;
+use crate::package_info::{PackageInfo, PackageQuery};
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::GlobalOptions;
+
+/// Show what the registry knows about a package.
+#[derive(Debug, StructOpt)]
+pub struct InfoSubcommand {
+    /// Path to the project whose registry should be searched.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The package to show, like `evaera/signal`. An exact version, like
+    /// `evaera/signal@1.2.0`, shows that version instead of the newest one.
+    pub package: PackageQuery,
+
+    /// How to print the package's metadata, `text` or `json`.
+    #[structopt(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    pub format: String,
+}
+
+impl InfoSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        let info = PackageInfo::lookup(&package_sources, &self.package)?;
+
+        if self.format == "json" {
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        } else {
+            print!("{}", info.to_text());
+        }
+
+        Ok(())
+    }
+}
@@ -1,24 +1,41 @@
 use std::collections::BTreeSet;
 
 use std::io::Write;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
 
+use anyhow::Context;
 use structopt::StructOpt;
+use toml_edit::Document;
 
 use crate::installation::InstallationContext;
+use crate::integrity::{IntegrityStore, LockfileStore, PinnedStore, UnverifiedStore};
 use crate::lockfile::Lockfile;
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
-use crate::resolution::resolve;
-
-use super::utils::{generate_dependency_changes, render_update_difference};
+use crate::package_name::PackageName;
+use crate::package_source::{PackageSource, PackageSourceId, PackageSourceMap, TestRegistry};
+use crate::preflight::{check_disk_space, estimate_install_size, SystemDiskSpace};
+use crate::report::{CacheHits, Report, TimingReport, Timings};
+use crate::resolution::{resolve, resolve_traced, ErrorMode, Resolve};
+use crate::resolution_cache::{resolve_cached, Resolution};
+use crate::resolution_conflict::{ResolutionConflict, ResolutionErrors};
+use crate::resolution_trace::ResolutionTrace;
+use crate::warnings;
+
+use super::utils::{
+    add_dependency_sources, generate_dependency_changes, indexed_checksums, print_warning,
+    render_update_difference, set_requirement,
+};
 use super::GlobalOptions;
 
+/// How many of the slowest packages `--timings` lists.
+const SLOWEST_PACKAGES: usize = 10;
+
 /// Install all of the dependencies of this project.
 #[derive(Debug, StructOpt)]
 pub struct InstallSubcommand {
@@ -29,28 +46,228 @@ pub struct InstallSubcommand {
     /// Flag to error if the lockfile does not match with the latest dependencies.
     #[structopt(long = "locked")]
     pub locked: bool,
+
+    /// Remove every installed package and extract them all again, using the
+    /// versions pinned in the lockfile. The lockfile is left untouched.
+    #[structopt(long = "reinstall")]
+    pub reinstall: bool,
+
+    /// The target to install dependencies for, as declared in the manifest's
+    /// `package.targets`. Only the default dependencies are used if unset.
+    #[structopt(long = "target")]
+    pub target: Option<String>,
+
+    /// Also write a JSON report of what was installed to this path, with the
+    /// resolved graph, checksums, cache hits, and timings.
+    #[structopt(long = "report")]
+    pub report: Option<PathBuf>,
+
+    /// Rewrite dependencies in the manifest that don't give a version to
+    /// require the version they resolved to, like `scope/name@1.2.3`.
+    #[structopt(long = "pin")]
+    pub pin: bool,
+
+    /// Symlink path dependencies into the package folders instead of copying
+    /// them, so that edits to them show up without installing again.
+    #[structopt(long = "link")]
+    pub link: bool,
+
+    /// Write every decision made while resolving the package graph to this
+    /// path, one JSON object per line, in the order they were made. The graph
+    /// is always solved again instead of being reused.
+    #[structopt(long = "trace-resolution")]
+    pub trace_resolution: Option<PathBuf>,
+
+    /// Fail instead of warning when a package's peer dependency isn't
+    /// provided by a package that depends on it.
+    #[structopt(long = "strict-peers")]
+    pub strict_peers: bool,
+
+    /// What to do when resolution runs into a problem: `fail-fast` stops at
+    /// the first one, and `collect` keeps going to report every problem at
+    /// once.
+    #[structopt(long = "error-mode", default_value = "fail-fast", possible_values = &["fail-fast", "collect"])]
+    pub error_mode: ErrorMode,
+
+    /// Pick the version of each package that its registry recommends, when
+    /// one satisfies the requirement, instead of the newest one. Versions
+    /// pinned by the lockfile are still kept.
+    #[structopt(long = "prefer-recommended")]
+    pub prefer_recommended: bool,
+
+    /// Only install this package and everything it depends on. The whole
+    /// graph is still resolved, and the lockfile still covers all of it.
+    #[structopt(long = "only")]
+    pub only: Option<PackageName>,
+
+    /// Warn about packages locked to an older patch than their requirements
+    /// allow, naming the newest patch that could be used instead.
+    #[structopt(long = "warn-stale-patches")]
+    pub warn_stale_patches: bool,
+
+    /// Install exactly the packages pinned in the lockfile, verifying their
+    /// checksums, without reading the manifest or resolving anything. Works
+    /// even if the project has no manifest.
+    #[structopt(
+        long = "lockfile-only",
+        conflicts_with_all = &["locked", "reinstall", "target", "pin", "link", "trace_resolution", "only", "index_rev", "no_default_features", "features", "timings"]
+    )]
+    pub lockfile_only: bool,
+
+    /// Install exactly the packages pinned in another lockfile, like a
+    /// teammate's, the same way `--lockfile-only` does. The project's own
+    /// lockfile is left untouched.
+    #[structopt(
+        long = "from-lockfile",
+        conflicts_with_all = &["locked", "reinstall", "target", "pin", "link", "trace_resolution", "only", "lockfile_only", "index_rev", "no_default_features", "features", "timings"]
+    )]
+    pub from_lockfile: Option<PathBuf>,
+
+    /// Resolve against the registry's Git index as it was at this commit
+    /// instead of the latest one. The commit packages were resolved against
+    /// is always recorded in the lockfile.
+    #[structopt(long = "index-rev")]
+    pub index_rev: Option<String>,
+
+    /// Fail instead of warning when the volume the project is on looks like
+    /// it doesn't have room for the packages being installed.
+    #[structopt(long = "require-space")]
+    pub require_space: bool,
+
+    /// Install packages even if their contents don't match the checksum
+    /// recorded for them, warning about each one, to inspect what the
+    /// registry actually sent. Only meant for debugging checksum mismatches.
+    /// The lockfile isn't updated with anything installed this way.
+    #[structopt(
+        long = "no-verify",
+        conflicts_with_all = &["locked", "lockfile_only", "from_lockfile"]
+    )]
+    pub no_verify: bool,
+
+    /// Leave out the optional dependencies that are installed by default.
+    /// Any named with `--features` are still installed.
+    #[structopt(long = "no-default-features")]
+    pub no_default_features: bool,
+
+    /// Install these optional dependencies, by alias, whether or not they're
+    /// installed by default, like `--features Foo,Bar`.
+    #[structopt(long = "features", use_delimiter = true)]
+    pub features: Vec<String>,
+
+    /// Check each downloaded package's entry in its registry's index against
+    /// the manifest packed into the package, failing if they disagree, like
+    /// an index that lists different dependencies than the package has.
+    #[structopt(long = "verify-manifests")]
+    pub verify_manifests: bool,
+
+    /// How to report a conflict that keeps the package graph from being
+    /// resolved, `text` or `json`. The JSON report is printed to stdout.
+    #[structopt(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    pub format: String,
+
+    /// Print how long each phase of the install took, along with the
+    /// packages that were slowest to install. Given a path, the timings are
+    /// also written there as JSON.
+    #[structopt(long = "timings")]
+    pub timings: Option<Option<PathBuf>>,
+
+    /// Fail unless the installed graph has this graph hash, which every
+    /// install prints. It covers the ID and checksum of every package, so a
+    /// rerun can be checked to install exactly the same packages.
+    #[structopt(long = "locked-transitive")]
+    pub locked_transitive: Option<String>,
+}
+
+impl Default for InstallSubcommand {
+    /// The same install that `wally install` runs with no flags.
+    fn default() -> Self {
+        Self {
+            project_path: PathBuf::from("."),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: ErrorMode::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }
+    }
 }
 
 impl InstallSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+        if self.lockfile_only || self.from_lockfile.is_some() {
+            return self.install_from_lockfile(global);
+        }
+
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?
+            .for_target(self.target.as_deref())?
+            .with_features(self.no_default_features, &self.features)?;
+
+        let mut stored_lockfile = match Lockfile::load(&self.project_path)? {
+            Some(lockfile) => lockfile,
+            None if self.reinstall => anyhow::bail!(
+                "--reinstall installs the packages pinned in the lockfile, but there is no \
+                 lockfile yet. Try running wally install first."
+            ),
+            None => Lockfile::from_manifest(&manifest),
+        };
+
+        // Each target has its own pins, and only the ones for the target
+        // being installed are used.
+        let lockfile = stored_lockfile.for_target(self.target.as_deref());
 
-        let lockfile = Lockfile::load(&self.project_path)?
-            .unwrap_or_else(|| Lockfile::from_manifest(&manifest));
+        // Installs that have to match the lockfile exactly resolve against
+        // the same index commit it was resolved against.
+        let index_rev = match &self.index_rev {
+            Some(index_rev) => Some(index_rev.clone()),
+            None if self.locked || self.reinstall => lockfile.index_rev.clone(),
+            None => None,
+        };
 
+        // Registries fetch their index when they're first asked for
+        // something, which happens while looking for their fallbacks.
+        let index_update_start = Instant::now();
         let default_registry: Box<PackageSource> = if global.test_registry {
             Box::new(PackageSource::TestRegistry(TestRegistry::new(
                 &manifest.package.registry,
             )))
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
-                &manifest.package.registry,
-            )?))
+            Box::new(
+                PackageSource::from_registry_spec(
+                    &manifest.package.registry,
+                    global.max_download_size,
+                    global.max_index_entries,
+                )?
+                .with_index_rev(index_rev)?,
+            )
         };
 
         let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
         package_sources.add_fallbacks()?;
 
+        add_dependency_sources(&mut package_sources, &manifest, &global)?;
+        let index_update_time = index_update_start.elapsed();
+
         let try_to_use = lockfile.as_ids().collect();
 
         let progress = ProgressBar::new(0).with_style(
@@ -59,6 +276,15 @@ impl InstallSubcommand {
 
         progress.enable_steady_tick(Duration::from_millis(100));
 
+        if self.no_verify {
+            print_warning(
+                &progress,
+                "--no-verify is set, so packages whose contents don't match their checksum will \
+                 be installed anyway. Never use it outside of debugging."
+                    .to_owned(),
+            );
+        }
+
         if self.locked {
             progress.println(format!(
                 "{} Verifying {}lockfile is up-to-date...",
@@ -66,7 +292,10 @@ impl InstallSubcommand {
                 SetForegroundColor(Color::Reset)
             ));
 
-            let latest_graph = resolve(&manifest, &BTreeSet::new(), &package_sources)?;
+            let latest_graph = self.report_conflict(
+                &progress,
+                resolve(&manifest, &BTreeSet::new(), &package_sources),
+            )?;
 
             if try_to_use != latest_graph.activated {
                 progress.finish_and_clear();
@@ -113,7 +342,70 @@ impl InstallSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+        // Nothing changing since the last install means the graph it solved
+        // still holds, so it's reused instead of being solved again.
+        let resolve_start = Instant::now();
+        let resolution = match &self.trace_resolution {
+            // A cached graph has no decisions to trace. The trace is written
+            // even if resolution fails, since that's when it's most useful.
+            Some(trace_path) => {
+                let mut trace = ResolutionTrace::new();
+                let resolved = resolve_traced(
+                    &manifest,
+                    &try_to_use,
+                    &package_sources,
+                    self.error_mode,
+                    self.prefer_recommended,
+                    &mut trace,
+                );
+                trace.save(trace_path)?;
+
+                Resolution {
+                    resolve: self.report_conflict(&progress, resolved)?,
+                    from_cache: false,
+                }
+            }
+            None => {
+                let mut resolution = resolve_cached(
+                    global.resolution_cache_dir().as_deref(),
+                    &self.project_path,
+                    &manifest,
+                    &try_to_use,
+                    &package_sources,
+                    self.prefer_recommended,
+                );
+
+                // Only graphs that resolved are cached, and those resolve the
+                // same way whichever the error mode, so the graph is only
+                // solved again to collect every problem once one was found.
+                if resolution.is_err() && self.error_mode == ErrorMode::Collect {
+                    resolution = resolve_traced(
+                        &manifest,
+                        &try_to_use,
+                        &package_sources,
+                        ErrorMode::Collect,
+                        self.prefer_recommended,
+                        &mut ResolutionTrace::disabled(),
+                    )
+                    .map(|resolve| Resolution {
+                        resolve,
+                        from_cache: false,
+                    });
+                }
+
+                self.report_conflict(&progress, resolution)?
+            }
+        };
+        let resolve_time = resolve_start.elapsed();
+        let resolved = resolution.resolve;
+
+        if self.reinstall && resolved.activated != try_to_use {
+            progress.finish_and_clear();
+            anyhow::bail!(
+                "The lockfile is out of date, so the packages it pins can't be reinstalled. \
+                 Try running wally install first."
+            );
+        }
 
         progress.println(format!(
             "{}   Resolved {}{} dependencies",
@@ -122,37 +414,456 @@ impl InstallSubcommand {
             resolved.activated.len() - 1
         ));
 
-        let new_lockfile = Lockfile::from_resolve(&resolved);
-        new_lockfile.save(&self.project_path)?;
+        let peer_check = resolved.check_peer_dependencies(self.strict_peers);
+        if peer_check.is_err() {
+            progress.finish_and_clear();
+        }
 
-        progress.println(format!(
-            "{}  Generated {}lockfile",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        for warning in peer_check? {
+            print_warning(&progress, warning);
+        }
+
+        if self.warn_stale_patches {
+            let stale_patches = resolved.stale_patches(&manifest, &package_sources);
+            if stale_patches.is_err() {
+                progress.finish_and_clear();
+            }
+
+            for warning in stale_patches? {
+                print_warning(&progress, warning);
+            }
+        }
 
-        progress.set_message(format!(
-            "{}  Cleaning {}package destination...",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
         let root_package_id = PackageId::new(manifest.package.name, manifest.package.version);
+
+        let license_check =
+            manifest
+                .license_policy
+                .enforce(&root_package_id, &resolved, &package_sources);
+        if license_check.is_err() {
+            progress.finish_and_clear();
+        }
+        license_check?;
+
+        let mut new_lockfile = Lockfile::from_resolve(&resolved, &manifest.package.registry);
+        new_lockfile.conflict_policy = manifest.package.conflict_policy;
+        new_lockfile.index_rev = package_sources.index_rev()?;
+
+        // A new lockfile counts as refreshed, since everything in it was just
+        // resolved.
+        let refreshed = lockfile.packages.is_empty();
+        new_lockfile.set_resolved_at(&manifest, &lockfile, refreshed);
+
+        if let Some(max_age) = manifest.package.lockfile_max_age {
+            if let Some(warning) = new_lockfile.staleness_warning(max_age, SystemTime::now()) {
+                print_warning(&progress, warning);
+            }
+        }
+
+        // A published package's contents never change, so the checksums we
+        // already know about still apply.
+        new_lockfile.set_checksums(&lockfile.checksums());
+        // Packages installed from the cache don't say where their contents
+        // came from, so the lockfile keeps the source it already had for them.
+        new_lockfile.set_sources(&lockfile.sources());
+
+        let precheck = self.check_graph_hash_before_install(
+            &mut new_lockfile,
+            &lockfile,
+            &root_package_id,
+            &resolved,
+            &package_sources,
+        );
+        if precheck.is_err() {
+            progress.finish_and_clear();
+        }
+        precheck?;
+
+        if !self.reinstall {
+            stored_lockfile.set_target(self.target.as_deref(), new_lockfile.clone());
+            stored_lockfile.save(&self.project_path)?;
+
+            progress.println(format!(
+                "{}  Generated {}lockfile",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            ));
+        }
+
+        progress.finish_and_clear();
+
+        if self.pin {
+            pin_latest(
+                &global.manifest_file(&self.project_path),
+                &root_package_id,
+                &resolved,
+            )?;
+        }
+        let temp_dir = global.temp_dir(&self.project_path, Some(&manifest));
         let installation = InstallationContext::new(
             &self.project_path,
             manifest.place.shared_packages,
             manifest.place.server_packages,
+        )
+        .with_linked_path_dependencies(self.link)
+        .with_destinations(manifest.dependency_destinations.clone())
+        .with_link_style(
+            manifest.place.link_style,
+            manifest.place.link_loader.clone(),
+        )
+        .with_concurrency(global.download_concurrency, global.extract_concurrency)
+        .with_manifest_verification(self.verify_manifests)
+        .with_temp_dir(temp_dir)
+        .with_package_cache(global.package_cache());
+
+        if self.reinstall {
+            for path in installation.clean_managed()? {
+                log::info!("Removed {}", path.display());
+            }
+        }
+
+        let report_resolve = self.report.as_ref().map(|_| resolved.clone());
+
+        let resolved = match &self.only {
+            Some(name) => resolved.subtree(&root_package_id, name)?,
+            None => resolved,
+        };
+
+        self.preflight(&installation, &root_package_id, &resolved, &package_sources)?;
+
+        let integrity = PinnedStore::new(
+            LockfileStore::new(&new_lockfile.checksums())?,
+            manifest.dependency_checksums.clone(),
         );
+        let integrity: Arc<dyn IntegrityStore> = if self.no_verify {
+            Arc::new(UnverifiedStore::new(integrity))
+        } else {
+            Arc::new(integrity)
+        };
 
-        installation.clean()?;
-        progress.println(format!(
-            "{}    Cleaned {}package destination",
+        let install_start = Instant::now();
+        let installed = installation.install(
+            package_sources,
+            root_package_id.clone(),
+            resolved,
+            integrity,
+        )?;
+        let install_time = install_start.elapsed();
+
+        new_lockfile.set_checksums(&installed.checksums);
+        new_lockfile.set_sources(&installed.sources);
+        new_lockfile.set_links(&installed.linked);
+        let graph_hash = self.check_graph_hash(&new_lockfile)?;
+
+        // Contents that weren't verified can't be trusted with a place in the
+        // lockfile.
+        if !self.reinstall && !self.no_verify {
+            stored_lockfile.set_target(self.target.as_deref(), new_lockfile);
+            stored_lockfile.save(&self.project_path)?;
+        }
+
+        print_graph_hash(&graph_hash);
+
+        if let Some(timings_path) = &self.timings {
+            let timings = TimingReport::new(
+                index_update_time,
+                resolve_time,
+                &installed.timings,
+                SLOWEST_PACKAGES,
+            );
+            print!("{}", timings);
+
+            if let Some(path) = timings_path {
+                timings.save(path)?;
+            }
+        }
+
+        if let (Some(path), Some(resolve)) = (&self.report, report_resolve) {
+            let report = Report {
+                root: root_package_id,
+                resolve,
+                checksums: installed.checksums,
+                cache_hits: CacheHits {
+                    resolution: resolution.from_cache,
+                    packages: installed.reused,
+                },
+                timings: Timings::new(resolve_time, install_time),
+            };
+
+            report.save(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn install_from_lockfile(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let lockfile = match &self.from_lockfile {
+            Some(path) => Lockfile::load_file(path)?
+                .with_context(|| format!("There is no lockfile at {}", path.display()))?,
+            None => Lockfile::load(&self.project_path)?.with_context(|| {
+                format!(
+                    "--lockfile-only installs the packages pinned in the lockfile, but there is \
+                     no lockfile in {}",
+                    self.project_path.display()
+                )
+            })?,
+        };
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &lockfile.registry,
+            )))
+        } else {
+            Box::new(
+                PackageSource::from_registry_spec(
+                    &lockfile.registry,
+                    global.max_download_size,
+                    global.max_index_entries,
+                )
+                .and_then(|source| source.with_index_rev(lockfile.index_rev.clone()))
+                .with_context(|| {
+                    format!(
+                        "The lockfile's registry {} can't be used",
+                        lockfile.registry
+                    )
+                })?,
+            )
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        let (root_package_id, resolved) = lockfile.to_resolve(&package_sources)?;
+        let graph_hash = self.check_graph_hash(&lockfile)?;
+
+        // Without a checksum there's nothing to say the contents are the ones
+        // that were pinned.
+        let checksums = lockfile.checksums();
+        for package_id in &resolved.activated {
+            if *package_id != root_package_id && !checksums.contains_key(package_id) {
+                anyhow::bail!(
+                    "{} has no checksum in the lockfile, so it can't be installed with \
+                     --lockfile-only. Try running wally install first.",
+                    package_id
+                );
+            }
+        }
+
+        let installation = InstallationContext::new(&self.project_path, None, None)
+            .with_concurrency(global.download_concurrency, global.extract_concurrency)
+            .with_manifest_verification(self.verify_manifests)
+            // The manifest isn't read, so its `temp-dir` doesn't apply here.
+            // Only --temp-dir does.
+            .with_temp_dir(global.temp_dir(&self.project_path, None))
+            .with_package_cache(global.package_cache());
+        self.preflight(&installation, &root_package_id, &resolved, &package_sources)?;
+
+        let installed = installation.install(
+            package_sources,
+            root_package_id,
+            resolved,
+            Arc::new(LockfileStore::new(&checksums)?),
+        )?;
+
+        println!(
+            "{}  Installed {}{} packages from the lockfile",
             SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
-        progress.finish_and_clear();
+            SetForegroundColor(Color::Reset),
+            installed.checksums.len()
+        );
+        print_graph_hash(&graph_hash);
+
+        Ok(())
+    }
+
+    /// The graph hash of `lockfile`, failing if it isn't the one given to
+    /// `--locked-transitive`.
+    fn check_graph_hash(&self, lockfile: &Lockfile) -> anyhow::Result<String> {
+        let graph_hash = lockfile.graph_hash();
+
+        if let Some(expected) = &self.locked_transitive {
+            if *expected != graph_hash {
+                anyhow::bail!(
+                    "The installed graph hash is {}, but --locked-transitive expected {}. \
+                     Compare the lockfile against the one the expected hash came from to see \
+                     what changed.",
+                    graph_hash,
+                    expected
+                );
+            }
+        }
+
+        Ok(graph_hash)
+    }
+
+    /// Checks the graph hash for `--locked-transitive` before anything is
+    /// written or downloaded, using the checksums known without downloading
+    /// anything: the ones in the lockfile and the ones registries recorded in
+    /// their index. Those are the checksums packages are then verified
+    /// against, so the installed graph ends up with the same hash.
+    fn check_graph_hash_before_install(
+        &self,
+        new_lockfile: &mut Lockfile,
+        lockfile: &Lockfile,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<()> {
+        if self.locked_transitive.is_none() {
+            return Ok(());
+        }
+
+        new_lockfile.set_checksums(&indexed_checksums(
+            root_package_id,
+            resolved,
+            package_sources,
+        )?);
+        new_lockfile.set_checksums(&lockfile.checksums());
+
+        // Path dependencies never have a checksum.
+        let checksums = new_lockfile.checksums();
+        for (package_id, metadata) in &resolved.metadata {
+            let is_local = matches!(metadata.source_registry, PackageSourceId::Local(_));
+
+            if package_id != root_package_id && !is_local && !checksums.contains_key(package_id) {
+                anyhow::bail!(
+                    "{} has no checksum in the lockfile or its registry's index, so \
+                     --locked-transitive can't check the graph hash before installing it. Try \
+                     running wally install without --locked-transitive first.",
+                    package_id
+                );
+            }
+        }
+
+        self.check_graph_hash(new_lockfile)?;
+        Ok(())
+    }
 
-        installation.install(package_sources, root_package_id, resolved)?;
+    /// Checks that the project's volume has room for the packages about to be
+    /// installed, since running out of space partway through would leave the
+    /// package folders half installed.
+    fn preflight(
+        &self,
+        installation: &InstallationContext,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<()> {
+        let estimate = estimate_install_size(
+            root_package_id,
+            resolved,
+            &installation.recorded_packages()?,
+            package_sources,
+        )?;
+
+        if let Some(message) = check_disk_space(&SystemDiskSpace, &self.project_path, &estimate)? {
+            if self.require_space {
+                anyhow::bail!(message);
+            }
+
+            warnings::warn(message);
+        }
 
         Ok(())
     }
+
+    /// Prints the conflict that kept the package graph from being resolved
+    /// as JSON when `--format json` is set, passing the result through. When
+    /// problems were collected, every conflict among them is printed as a
+    /// list.
+    fn report_conflict<T>(
+        &self,
+        progress: &ProgressBar,
+        result: anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        if let (Err(err), "json") = (&result, self.format.as_str()) {
+            let conflict = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<ResolutionConflict>());
+            let collected = err
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<ResolutionErrors>());
+
+            if let Some(conflict) = conflict {
+                progress.finish_and_clear();
+                println!("{}", serde_json::to_string_pretty(conflict)?);
+            } else if let Some(collected) = collected {
+                let conflicts: Vec<&ResolutionConflict> = collected.conflicts().collect();
+                progress.finish_and_clear();
+                println!("{}", serde_json::to_string_pretty(&conflicts)?);
+            }
+        }
+
+        result
+    }
+}
+
+fn print_graph_hash(graph_hash: &str) {
+    println!(
+        "{} Graph hash {}{}",
+        SetForegroundColor(Color::DarkGreen),
+        SetForegroundColor(Color::Reset),
+        graph_hash
+    );
+}
+
+/// Rewrites the dependencies in the manifest that don't give a version to
+/// require the version they resolved to. Bare versions are caret requirements,
+/// so later compatible versions can still be picked up.
+fn pin_latest(manifest_path: &Path, root: &PackageId, resolve: &Resolve) -> anyhow::Result<()> {
+    let contents = fs_err::read_to_string(&manifest_path)?;
+    let mut doc: Document = contents.parse().with_context(|| {
+        format!(
+            "failed to parse manifest at path {}",
+            manifest_path.display()
+        )
+    })?;
+
+    let realms = [
+        ("dependencies", &resolve.shared_dependencies),
+        ("server-dependencies", &resolve.server_dependencies),
+        ("dev-dependencies", &resolve.dev_dependencies),
+        ("build-dependencies", &resolve.build_dependencies),
+    ];
+
+    let mut pinned = Vec::new();
+
+    for (table_name, dependencies) in &realms {
+        let resolved = match dependencies.get(root) {
+            Some(resolved) => resolved,
+            None => continue,
+        };
+
+        let table = match doc[*table_name].as_table_mut() {
+            Some(table) => table,
+            None => continue,
+        };
+
+        let unversioned: Vec<String> = table
+            .iter()
+            .filter(|(_, item)| item.as_str().map_or(false, |req| !req.contains('@')))
+            .map(|(alias, _)| alias.to_owned())
+            .collect();
+
+        for alias in unversioned {
+            if let Some(package_id) = resolved.get(&alias) {
+                let requirement = format!("{}@{}", package_id.name(), package_id.version());
+                set_requirement(&mut table[alias.as_str()], &requirement);
+                pinned.push(format!("{} = \"{}\"", alias, requirement));
+            }
+        }
+    }
+
+    if !pinned.is_empty() {
+        fs_err::write(&manifest_path, doc.to_string())?;
+
+        for entry in &pinned {
+            println!("Pinned {}", entry);
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, format_err};
+use structopt::StructOpt;
+
+use crate::lockfile::{Lockfile, LOCKFILE_NAME};
+use crate::lockfile_diff::LockfileDiff;
+use crate::lockfile_merge::merge;
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+
+/// Work with this project's lockfile.
+#[derive(Debug, StructOpt)]
+pub struct LockfileSubcommand {
+    #[structopt(subcommand)]
+    pub command: LockfileCommand,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum LockfileCommand {
+    Fmt(LockfileFmtSubcommand),
+    Diff(LockfileDiffSubcommand),
+    Merge(LockfileMergeSubcommand),
+}
+
+impl LockfileSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        match self.command {
+            LockfileCommand::Fmt(subcommand) => subcommand.run(),
+            LockfileCommand::Diff(subcommand) => subcommand.run(),
+            LockfileCommand::Merge(subcommand) => subcommand.run(),
+        }
+    }
+}
+
+/// Rewrite the lockfile in its canonical form, without changing its contents.
+#[derive(Debug, StructOpt)]
+pub struct LockfileFmtSubcommand {
+    /// Path to the project whose lockfile should be formatted.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Check that the lockfile is already formatted instead of rewriting it,
+    /// erroring if it isn't.
+    #[structopt(long = "check-format")]
+    pub check_format: bool,
+}
+
+impl LockfileFmtSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let lockfile_path = self.project_path.join(LOCKFILE_NAME);
+        let contents = fs_err::read_to_string(&lockfile_path)?;
+
+        let lockfile = Lockfile::load(&self.project_path)?
+            .ok_or_else(|| format_err!("{} does not exist", lockfile_path.display()))?;
+        let canonical = lockfile.to_canonical_string()?;
+
+        if contents == canonical {
+            return Ok(());
+        }
+
+        if self.check_format {
+            bail!(
+                "{} is not formatted. Run `wally lockfile fmt` to format it.",
+                lockfile_path.display()
+            );
+        }
+
+        fs_err::write(&lockfile_path, canonical)?;
+        println!("Formatted {}", lockfile_path.display());
+
+        Ok(())
+    }
+}
+
+/// Summarize how the packages pinned by one lockfile differ from another,
+/// like the base branch's lockfile and a pull request's.
+#[derive(Debug, StructOpt)]
+pub struct LockfileDiffSubcommand {
+    /// The lockfile to compare against.
+    pub old: PathBuf,
+
+    /// The lockfile with the changes.
+    pub new: PathBuf,
+
+    /// Path to the project whose manifest says which realm each of its
+    /// dependencies is used from. Changes are grouped without realms if it
+    /// has no manifest.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Print the changes as JSON instead of as text.
+    #[structopt(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    pub format: String,
+}
+
+impl LockfileDiffSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let old = load_file(&self.old)?;
+        let new = load_file(&self.new)?;
+
+        let manifest = if self.project_path.join(MANIFEST_FILE_NAME).exists() {
+            Some(Manifest::load(&self.project_path)?)
+        } else {
+            None
+        };
+
+        let diff = LockfileDiff::new(&old, &new, manifest.as_ref());
+
+        if self.format == "json" {
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        } else {
+            print!("{}", diff);
+        }
+
+        Ok(())
+    }
+}
+
+/// Combine the lockfiles of several projects that are built together into
+/// one, failing if any package is pinned at conflicting versions across them.
+#[derive(Debug, StructOpt)]
+pub struct LockfileMergeSubcommand {
+    /// The lockfiles to merge.
+    #[structopt(required = true, min_values = 2)]
+    pub lockfiles: Vec<PathBuf>,
+
+    /// Write the merged lockfile to this path instead of printing it.
+    #[structopt(long = "output")]
+    pub output: Option<PathBuf>,
+}
+
+impl LockfileMergeSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let lockfiles = self
+            .lockfiles
+            .iter()
+            .map(|path| Ok((path.display().to_string(), load_file(path)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let contents = merge(&lockfiles)?.to_canonical_string()?;
+
+        match &self.output {
+            Some(path) => fs_err::write(path, contents)?,
+            None => print!("{}", contents),
+        }
+
+        Ok(())
+    }
+}
+
+fn load_file(path: &PathBuf) -> anyhow::Result<Lockfile> {
+    Lockfile::load_file(path)?.ok_or_else(|| format_err!("{} does not exist", path.display()))
+}
@@ -13,6 +13,7 @@ use crate::{
     auth::AuthStore,
     manifest::Manifest,
     package_index::{PackageIndex, PackageIndexConfig},
+    GlobalOptions,
 };
 
 /// Log into a registry.
@@ -110,24 +111,26 @@ fn prompt_github_auth(api: url::Url, github_oauth_id: &str) -> anyhow::Result<()
     AuthStore::set_token(api.as_str(), Some(&auth.access_token))
 }
 
-fn fetch_package_index_config(project_path: &Path) -> anyhow::Result<PackageIndexConfig> {
-    let manifest = Manifest::load(project_path)?;
+fn fetch_package_index_config(manifest_path: &Path) -> anyhow::Result<PackageIndexConfig> {
+    let manifest = Manifest::load_file(manifest_path)?;
     let registry = Url::parse(&manifest.package.registry)?;
     let package_index = PackageIndex::new(&registry, None)?;
     package_index.config()
 }
 
 impl LoginSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest_path = global.manifest_file(&self.project_path);
+
         match (self.token, self.api) {
             (Some(token), Some(api)) => AuthStore::set_token(&api, Some(&token)),
             (Some(token), None) => {
-                let config = fetch_package_index_config(&self.project_path)?;
+                let config = fetch_package_index_config(&manifest_path)?;
 
                 AuthStore::set_token(config.api.as_str(), Some(&token))
             }
             (None, _) => {
-                let config = fetch_package_index_config(&self.project_path)?;
+                let config = fetch_package_index_config(&manifest_path)?;
 
                 match config.github_oauth_id {
                     None => prompt_api_key(config.api),
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
-use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex};
+use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex, GlobalOptions};
 
 /// Log out of a registry.
 #[derive(Debug, StructOpt)]
@@ -13,8 +13,8 @@ pub struct LogoutSubcommand {
 }
 
 impl LogoutSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
         let registry = url::Url::parse(&manifest.package.registry)?;
         let package_index = PackageIndex::new(&registry, None)?;
         let api = package_index.config()?.api;
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use structopt::StructOpt;
 
 use crate::manifest::Manifest;
+use crate::GlobalOptions;
 
 /// Print a Wally manifest as a line of JSON.
 ///
@@ -15,8 +16,8 @@ pub struct ManifestToJsonSubcommand {
 }
 
 impl ManifestToJsonSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
         println!("{}", serde_json::to_string(&manifest)?);
 
         Ok(())
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::lockfile::{Lockfile, LOCKFILE_NAME, LOCKFILE_VERSION};
+
+/// Upgrade the lockfile to the current version of the lockfile format,
+/// keeping every pin and checksum instead of resolving it again.
+#[derive(Debug, StructOpt)]
+pub struct MigrateLockfileSubcommand {
+    /// Path to the project whose lockfile should be upgraded.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+}
+
+impl MigrateLockfileSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let lockfile_path = self.project_path.join(LOCKFILE_NAME);
+        let contents = fs_err::read_to_string(&lockfile_path)?;
+
+        let mut lockfile: Lockfile = contents.parse()?;
+        let from = lockfile.migrate()?;
+
+        if from == LOCKFILE_VERSION {
+            println!(
+                "{} is already at lockfile version {}",
+                lockfile_path.display(),
+                LOCKFILE_VERSION
+            );
+            return Ok(());
+        }
+
+        lockfile.save(&self.project_path)?;
+        println!(
+            "Migrated {} from lockfile version {} to {}",
+            lockfile_path.display(),
+            from,
+            LOCKFILE_VERSION
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::bail;
+use crossterm::style::{Color, SetForegroundColor};
+use structopt::StructOpt;
+
+use crate::mirror::mirror;
+use crate::package_name::PackageName;
+use crate::package_source::PackageSourceProvider;
+use crate::GlobalOptions;
+
+use super::utils::registry_source;
+
+/// Copy packages from one registry to another, keeping their versions and
+/// contents as they are.
+#[derive(Debug, StructOpt)]
+pub struct MirrorSubcommand {
+    /// The registry to copy packages from.
+    pub source: String,
+
+    /// The registry to publish the packages to.
+    pub destination: String,
+
+    /// A package to copy every version of. Can be given more than once.
+    #[structopt(long = "package")]
+    pub packages: Vec<PackageName>,
+
+    /// Copy every package the source registry has.
+    #[structopt(long = "all")]
+    pub all: bool,
+}
+
+impl MirrorSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        if self.all == !self.packages.is_empty() {
+            bail!("Give either --all or at least one --package to mirror");
+        }
+
+        let source = registry_source(&self.source, &global)?;
+        let destination = registry_source(&self.destination, &global)?;
+
+        if self.all && !source.capabilities().can_enumerate {
+            bail!(
+                "{} can't list the packages it has, so --all can't be used. Name the packages \
+                 to mirror with --package instead.",
+                self.source
+            );
+        }
+
+        if !destination.capabilities().can_publish {
+            bail!(
+                "{} doesn't support publishing, so packages can't be mirrored to it",
+                self.destination
+            );
+        }
+
+        source.update()?;
+        destination.update()?;
+
+        let names = if self.all {
+            source.package_names()?
+        } else {
+            self.packages
+        };
+
+        let report = mirror(&source, &destination, &names)?;
+
+        for (package_id, checksum) in &report.copied {
+            println!(
+                "{}     Copied {}{} ({})",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset),
+                package_id,
+                checksum
+            );
+        }
+
+        for package_id in &report.skipped {
+            println!(
+                "{}    Skipped {}{} (already in {})",
+                SetForegroundColor(Color::Yellow),
+                SetForegroundColor(Color::Reset),
+                package_id,
+                self.destination
+            );
+        }
+
+        println!(
+            "Copied {} and skipped {} package versions",
+            report.copied.len(),
+            report.skipped.len()
+        );
+
+        Ok(())
+    }
+}
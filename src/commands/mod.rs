@@ -1,26 +1,79 @@
+mod add;
+mod audit_contents;
+mod checksum;
+mod clean;
+mod contents;
+mod graph;
+mod import;
+mod info;
 mod init;
 mod install;
+mod lockfile;
 mod login;
 mod logout;
 mod manifest_to_json;
+mod migrate_lockfile;
+mod mirror;
 mod package;
+mod prune_cache;
 mod publish;
+mod relock;
+mod remove;
+mod resolve;
+mod resolve_version;
+mod run;
+mod sbom;
 mod search;
 mod update;
 mod utils;
+mod validate_manifest;
 
+pub use add::AddSubcommand;
+pub use audit_contents::AuditContentsSubcommand;
+pub use checksum::ChecksumSubcommand;
+pub use clean::CleanSubcommand;
+pub use contents::ContentsSubcommand;
+pub use graph::GraphSubcommand;
+pub use import::ImportSubcommand;
+pub use info::InfoSubcommand;
 pub use init::InitSubcommand;
 pub use install::InstallSubcommand;
+pub use lockfile::{LockfileCommand, LockfileFmtSubcommand, LockfileSubcommand};
 pub use login::LoginSubcommand;
 pub use logout::LogoutSubcommand;
 pub use manifest_to_json::ManifestToJsonSubcommand;
+pub use migrate_lockfile::MigrateLockfileSubcommand;
+pub use mirror::MirrorSubcommand;
 pub use package::PackageSubcommand;
-pub use publish::PublishSubcommand;
+pub use prune_cache::PruneCacheSubcommand;
+pub use publish::{PublishSubcommand, DEFAULT_MAX_PUBLISH_SIZE};
+pub use relock::RelockSubcommand;
+pub use remove::RemoveSubcommand;
+pub use resolve::ResolveSubcommand;
+pub use resolve_version::ResolveVersionSubcommand;
+pub use run::RunSubcommand;
+pub use sbom::SbomSubcommand;
 pub use search::SearchSubcommand;
 pub use update::{PackageSpec, UpdateSubcommand};
+pub use validate_manifest::ValidateManifestSubcommand;
 
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::bail;
 use structopt::StructOpt;
 
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::package_cache::PackageCache;
+use crate::package_index::{DEFAULT_MAX_INDEX_ENTRIES, DEFAULT_MAX_INDEX_ENTRIES_ARG};
+use crate::package_source::{DEFAULT_MAX_DOWNLOAD_SIZE, DEFAULT_MAX_DOWNLOAD_SIZE_ARG};
+use crate::pipeline::{
+    DEFAULT_DOWNLOAD_CONCURRENCY, DEFAULT_DOWNLOAD_CONCURRENCY_ARG, DEFAULT_EXTRACT_CONCURRENCY,
+    DEFAULT_EXTRACT_CONCURRENCY_ARG,
+};
+use crate::warnings;
+
 #[derive(Debug, StructOpt)]
 #[structopt(about = env!("CARGO_PKG_DESCRIPTION"))]
 pub struct Args {
@@ -32,18 +85,87 @@ pub struct Args {
 }
 
 impl Args {
-    pub fn run(self) -> anyhow::Result<()> {
-        match self.subcommand {
+    pub fn run(mut self) -> anyhow::Result<()> {
+        let deny_warnings = self.global.deny_warnings;
+
+        if let Some(manifest_path) = &self.global.manifest_path {
+            let project_path = match manifest_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+                _ => PathBuf::from("."),
+            };
+
+            match self.subcommand.project_path_mut() {
+                Some(path) => *path = project_path,
+                None => bail!("--manifest-path can't be used with this command"),
+            }
+
+            // Packages always have their manifest at wally.toml, so anything
+            // that packs the project needs it to be there.
+            let packs_project = matches!(
+                self.subcommand,
+                Subcommand::Package(_)
+                    | Subcommand::Publish(_)
+                    | Subcommand::Checksum(_)
+                    | Subcommand::ValidateManifest(_)
+            );
+            if packs_project && manifest_path.file_name() != Some(OsStr::new(MANIFEST_FILE_NAME)) {
+                bail!(
+                    "This command needs the manifest to be named {}",
+                    MANIFEST_FILE_NAME
+                );
+            }
+        }
+
+        // Only warnings from this command count, even if others are running
+        // at the same time.
+        let collector = warnings::Collector::default();
+        let result = collector.scope(|| match self.subcommand {
             Subcommand::Publish(subcommand) => subcommand.run(self.global),
             Subcommand::Init(subcommand) => subcommand.run(),
-            Subcommand::Login(subcommand) => subcommand.run(),
-            Subcommand::Logout(subcommand) => subcommand.run(),
+            Subcommand::Login(subcommand) => subcommand.run(self.global),
+            Subcommand::Logout(subcommand) => subcommand.run(self.global),
             Subcommand::Update(subcommand) => subcommand.run(self.global),
-            Subcommand::Search(subcommand) => subcommand.run(),
+            Subcommand::Search(subcommand) => subcommand.run(self.global),
             Subcommand::Package(subcommand) => subcommand.run(),
             Subcommand::Install(subcommand) => subcommand.run(self.global),
-            Subcommand::ManifestToJson(subcommand) => subcommand.run(),
+            Subcommand::ManifestToJson(subcommand) => subcommand.run(self.global),
+            Subcommand::Clean(subcommand) => subcommand.run(self.global),
+            Subcommand::Import(subcommand) => subcommand.run(self.global),
+            Subcommand::Lockfile(subcommand) => subcommand.run(),
+            Subcommand::MigrateLockfile(subcommand) => subcommand.run(),
+            Subcommand::ValidateManifest(subcommand) => subcommand.run(),
+            Subcommand::Add(subcommand) => subcommand.run(self.global),
+            Subcommand::Remove(subcommand) => subcommand.run(self.global),
+            Subcommand::Run(subcommand) => subcommand.run(self.global),
+            Subcommand::Sbom(subcommand) => subcommand.run(self.global),
+            Subcommand::Mirror(subcommand) => subcommand.run(self.global),
+            Subcommand::Info(subcommand) => subcommand.run(self.global),
+            Subcommand::Contents(subcommand) => subcommand.run(self.global),
+            Subcommand::Checksum(subcommand) => subcommand.run(),
+            Subcommand::Graph(subcommand) => subcommand.run(self.global),
+            Subcommand::Relock(subcommand) => subcommand.run(self.global),
+            Subcommand::AuditContents(subcommand) => subcommand.run(self.global),
+            Subcommand::PruneCache(subcommand) => subcommand.run(self.global),
+            Subcommand::Resolve(subcommand) => subcommand.run(self.global),
+            Subcommand::ResolveVersion(subcommand) => subcommand.run(self.global),
+        });
+        result?;
+
+        let warnings = collector.take();
+        if deny_warnings && !warnings.is_empty() {
+            let mut message = format!(
+                "Failing because of {} warning(s) and --deny-warnings:",
+                warnings.len()
+            );
+            for warning in &warnings {
+                message.push_str("\n  - ");
+                message.push_str(warning);
+            }
+
+            bail!(message);
         }
+
+        Ok(())
     }
 }
 
@@ -55,6 +177,63 @@ pub struct GlobalOptions {
     #[structopt(global = true, parse(from_occurrences), long = "verbose", short)]
     pub verbosity: u8,
 
+    /// The largest package, in bytes, to accept when downloading from a
+    /// registry. Downloads are aborted as soon as they pass this size.
+    #[structopt(global = true, long = "max-download-size", default_value = DEFAULT_MAX_DOWNLOAD_SIZE_ARG)]
+    pub max_download_size: u64,
+
+    /// The folder to cache things in between runs, like resolutions and
+    /// downloaded packages. Wally uses the system's cache folder when run from
+    /// the command line, and caches nothing if this is unset otherwise.
+    #[structopt(global = true, long = "cache-dir")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Cache packages as files shared between the versions of a package that
+    /// have them, instead of as whole zips, to save space.
+    #[structopt(global = true, long = "dedupe-cache")]
+    pub dedupe_cache: bool,
+
+    /// How many whole days a cached package can be used without being
+    /// checked against its checksum again. Cached packages are checked every
+    /// time they're used if this is unset.
+    #[structopt(global = true, long = "cache-revalidate-after")]
+    pub cache_revalidate_after: Option<u64>,
+
+    /// The most versions to read from one package's file in a registry's
+    /// index, which guards against registries sending endless files.
+    #[structopt(global = true, long = "max-index-entries", default_value = DEFAULT_MAX_INDEX_ENTRIES_ARG)]
+    pub max_index_entries: usize,
+
+    /// How many packages to download at once when installing.
+    #[structopt(global = true, long = "download-concurrency", default_value = DEFAULT_DOWNLOAD_CONCURRENCY_ARG)]
+    pub download_concurrency: usize,
+
+    /// How many downloaded packages to extract at once when installing,
+    /// separately from how many are being downloaded.
+    #[structopt(global = true, long = "extract-concurrency", default_value = DEFAULT_EXTRACT_CONCURRENCY_ARG)]
+    pub extract_concurrency: usize,
+
+    /// Fail instead of accessing the network, naming the operation that
+    /// needed it. Sources on disk, like path dependencies, still work.
+    #[structopt(global = true, long = "assert-offline")]
+    pub assert_offline: bool,
+
+    /// Fail after running the command if it warned about anything, listing
+    /// every warning.
+    #[structopt(global = true, long = "deny-warnings")]
+    pub deny_warnings: bool,
+
+    /// Path to the project's manifest, for projects whose manifest isn't a
+    /// wally.toml in the current folder. The project is the folder the
+    /// manifest is in, and this takes the place of --project-path.
+    #[structopt(global = true, long = "manifest-path")]
+    pub manifest_path: Option<PathBuf>,
+
+    /// The folder that installs extract packages into before moving them into
+    /// the package folders, in place of the manifest's `temp-dir`.
+    #[structopt(global = true, long = "temp-dir")]
+    pub temp_dir: Option<PathBuf>,
+
     /// Flag to indidate if we will be using a test registry. Usable only by tests.
     #[structopt(skip)]
     pub test_registry: bool,
@@ -72,6 +251,17 @@ impl Default for GlobalOptions {
     fn default() -> Self {
         Self {
             verbosity: 0,
+            max_download_size: DEFAULT_MAX_DOWNLOAD_SIZE,
+            cache_dir: None,
+            dedupe_cache: false,
+            cache_revalidate_after: None,
+            max_index_entries: DEFAULT_MAX_INDEX_ENTRIES,
+            download_concurrency: DEFAULT_DOWNLOAD_CONCURRENCY,
+            extract_concurrency: DEFAULT_EXTRACT_CONCURRENCY,
+            assert_offline: false,
+            deny_warnings: false,
+            manifest_path: None,
+            temp_dir: None,
             test_registry: false,
             use_temp_index: false,
             check_token: None,
@@ -79,6 +269,56 @@ impl Default for GlobalOptions {
     }
 }
 
+impl GlobalOptions {
+    /// The manifest of the project at `project_path`, which is the one given
+    /// with `--manifest-path` if there was one.
+    pub fn manifest_file(&self, project_path: &Path) -> PathBuf {
+        match &self.manifest_path {
+            Some(manifest_path) => manifest_path.clone(),
+            None => project_path.join(MANIFEST_FILE_NAME),
+        }
+    }
+
+    /// The folder that installs into the project at `project_path` stage
+    /// packages in, if one was configured.
+    pub fn temp_dir(&self, project_path: &Path, manifest: Option<&Manifest>) -> Option<PathBuf> {
+        let from_manifest = manifest
+            .and_then(|manifest| manifest.package.temp_dir.as_ref())
+            .map(|temp_dir| project_path.join(temp_dir));
+
+        self.temp_dir.clone().or(from_manifest)
+    }
+
+    /// The folder resolutions are cached in, if there's a cache folder.
+    pub fn resolution_cache_dir(&self) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|cache_dir| cache_dir.join("resolutions"))
+    }
+
+    /// The cache that installs keep downloaded packages in, if there's a
+    /// cache folder.
+    pub fn package_cache(&self) -> Option<PackageCache> {
+        // So many days that they don't fit in a `Duration` might as well be
+        // forever.
+        let revalidate_after = self
+            .cache_revalidate_after
+            .map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60)));
+
+        self.cache_dir.as_ref().map(|cache_dir| {
+            PackageCache::new(cache_dir.join("packages"))
+                .with_dedupe(self.dedupe_cache)
+                .with_revalidate_after(revalidate_after)
+        })
+    }
+}
+
+/// The folder Wally caches things in when `--cache-dir` isn't given, if the
+/// system has one.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|cache_dir| cache_dir.join("wally"))
+}
+
 #[derive(Debug, StructOpt)]
 pub enum Subcommand {
     Init(InitSubcommand),
@@ -90,4 +330,62 @@ pub enum Subcommand {
     Search(SearchSubcommand),
     Package(PackageSubcommand),
     ManifestToJson(ManifestToJsonSubcommand),
+    Clean(CleanSubcommand),
+    Import(ImportSubcommand),
+    Lockfile(LockfileSubcommand),
+    MigrateLockfile(MigrateLockfileSubcommand),
+    ValidateManifest(ValidateManifestSubcommand),
+    Add(AddSubcommand),
+    Remove(RemoveSubcommand),
+    Run(RunSubcommand),
+    Sbom(SbomSubcommand),
+    Mirror(MirrorSubcommand),
+    Info(InfoSubcommand),
+    Contents(ContentsSubcommand),
+    Checksum(ChecksumSubcommand),
+    Graph(GraphSubcommand),
+    Relock(RelockSubcommand),
+    AuditContents(AuditContentsSubcommand),
+    PruneCache(PruneCacheSubcommand),
+    Resolve(ResolveSubcommand),
+    ResolveVersion(ResolveVersionSubcommand),
+}
+
+impl Subcommand {
+    /// The folder of the project the command works on, for commands that
+    /// work on one.
+    fn project_path_mut(&mut self) -> Option<&mut PathBuf> {
+        match self {
+            Subcommand::Install(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Update(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Publish(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Login(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Logout(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Search(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Package(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::ManifestToJson(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Clean(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Import(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Lockfile(LockfileSubcommand {
+                command: LockfileCommand::Fmt(subcommand),
+            }) => Some(&mut subcommand.project_path),
+            Subcommand::MigrateLockfile(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::ValidateManifest(subcommand) => Some(&mut subcommand.path),
+            Subcommand::Add(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Remove(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Run(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Sbom(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Info(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Contents(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Checksum(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Graph(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Relock(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Resolve(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::ResolveVersion(subcommand) => Some(&mut subcommand.project_path),
+            Subcommand::Init(_)
+            | Subcommand::Mirror(_)
+            | Subcommand::AuditContents(_)
+            | Subcommand::PruneCache(_) => None,
+        }
+    }
 }
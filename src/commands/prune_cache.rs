@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use structopt::StructOpt;
+use ubyte::ToByteUnit;
+
+use crate::checksum::Checksum;
+use crate::lockfile::Lockfile;
+use crate::GlobalOptions;
+
+/// Remove every package from the cache that none of the given projects'
+/// lockfiles pin, so that no project ends up downloading its packages again.
+#[derive(Debug, StructOpt)]
+pub struct PruneCacheSubcommand {
+    /// The projects whose packages are kept in the cache. Every target of
+    /// each project's lockfile counts.
+    #[structopt(long = "keep-used", required = true, min_values = 1)]
+    pub keep_used: Vec<PathBuf>,
+
+    /// List what would be removed without removing anything.
+    #[structopt(long = "dry-run")]
+    pub dry_run: bool,
+}
+
+impl PruneCacheSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let package_cache = global
+            .package_cache()
+            .context("There is no cache folder to prune. Try passing --cache-dir.")?;
+
+        let mut keep = HashSet::new();
+        for project_path in &self.keep_used {
+            let lockfile = Lockfile::load(project_path)?.with_context(|| {
+                format!(
+                    "There is no lockfile in {}, so there's no telling which packages it uses",
+                    project_path.display()
+                )
+            })?;
+
+            let targets = lockfile.targets.keys().map(|target| Some(target.as_str()));
+            for target in std::iter::once(None).chain(targets) {
+                for (package_id, checksum) in lockfile.for_target(target).checksums() {
+                    let checksum: Checksum = checksum.parse().with_context(|| {
+                        format!(
+                            "The lockfile in {} has an invalid checksum for {}",
+                            project_path.display(),
+                            package_id
+                        )
+                    })?;
+                    keep.insert(checksum);
+                }
+            }
+        }
+
+        let pruned = package_cache.prune(&keep, self.dry_run)?;
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+
+        for checksum in &pruned.packages {
+            println!("{} {}", verb, checksum);
+        }
+
+        println!(
+            "{} {} cached packages and {} shared files, {} altogether",
+            verb,
+            pruned.packages.len(),
+            pruned.blobs,
+            pruned.bytes.bytes()
+        );
+
+        Ok(())
+    }
+}
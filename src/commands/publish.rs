@@ -1,17 +1,25 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context};
 use structopt::StructOpt;
-use ubyte::ToByteUnit;
 use url::Url;
 
 use crate::{
-    auth::AuthStore, manifest::Manifest, package_contents::PackageContents,
-    package_index::PackageIndex, GlobalOptions,
+    auth::AuthStore, git_util, ingest::simulate_ingest, manifest::Manifest,
+    package_contents::PackageContents, package_index::PackageIndex, validation::validate_manifest,
+    GlobalOptions,
 };
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The largest package, in bytes, that can be published unless configured
+/// otherwise.
+pub const DEFAULT_MAX_PUBLISH_SIZE: u64 = 2 * 1024 * 1024;
+
+/// [`DEFAULT_MAX_PUBLISH_SIZE`] as the command line spells it.
+const DEFAULT_MAX_PUBLISH_SIZE_ARG: &str = "2097152";
+
 /// Publish this project to a registry.
 #[derive(Debug, StructOpt)]
 pub struct PublishSubcommand {
@@ -22,16 +30,46 @@ pub struct PublishSubcommand {
     /// Auth token to use
     #[structopt(long = "token")]
     pub token: Option<String>,
+
+    /// Allow publishing even if package files have uncommitted changes
+    #[structopt(long = "allow-dirty")]
+    pub allow_dirty: bool,
+
+    /// The largest package, in bytes, that can be published.
+    #[structopt(long = "max-size", default_value = DEFAULT_MAX_PUBLISH_SIZE_ARG)]
+    pub max_size: u64,
+
+    /// Check that the registry would accept this package by ingesting it into
+    /// a throwaway copy of its index, without publishing anything.
+    #[structopt(long = "simulate-ingest")]
+    pub simulate_ingest: bool,
 }
 
 impl PublishSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
 
         if manifest.package.private {
             bail!("Cannot publish private package.");
         }
 
+        let problems = validate_manifest(&self.project_path, None)?;
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems
+                .iter()
+                .map(|problem| format!("{}: {}", problem.field, problem.message))
+                .collect();
+
+            bail!(
+                "The manifest has problems that must be fixed before publishing:\n{}",
+                problems.join("\n")
+            );
+        }
+
+        if !self.allow_dirty {
+            check_working_tree(&self.project_path)?;
+        }
+
         let index_url = if global.test_registry {
             let index_path = Path::new(&manifest.package.registry)
                 .join("index")
@@ -49,10 +87,32 @@ impl PublishSubcommand {
         };
 
         let api = package_index.config()?.api;
-        let contents = PackageContents::pack_from_path(&self.project_path)?;
-
-        if contents.data().len() > 2.mebibytes() {
-            bail!("Package size exceeds 2MB. Reduce package size and try again.");
+        let contents =
+            PackageContents::pack_from_path_with_limit(&self.project_path, self.max_size)?;
+        contents.check_entries()?;
+
+        if self.simulate_ingest {
+            let problems = simulate_ingest(package_index.path(), &manifest, &contents)?;
+
+            if !problems.is_empty() {
+                let problems: Vec<String> = problems
+                    .iter()
+                    .map(|problem| format!("{}: {}", problem.field, problem.message))
+                    .collect();
+
+                bail!(
+                    "Ingesting {} would leave the index invalid:\n{}",
+                    manifest.package_id(),
+                    problems.join("\n")
+                );
+            }
+
+            println!(
+                "{} would be accepted by {}",
+                manifest.package_id(),
+                package_index.url()
+            );
+            return Ok(());
         }
 
         let auth = match self.token {
@@ -92,3 +152,43 @@ impl PublishSubcommand {
         Ok(())
     }
 }
+
+/// Refuses to publish a package whose files have uncommitted changes, so that
+/// what's published always matches what's committed. Projects that aren't in a
+/// Git repository are not checked.
+fn check_working_tree(project_path: &Path) -> anyhow::Result<()> {
+    let changed = match git_util::uncommitted_changes(project_path)? {
+        Some(changed) => changed,
+        None => return Ok(()),
+    };
+
+    let project_path = fs_err::canonicalize(project_path)?;
+    let package_files = PackageContents::filtered_contents(&project_path)?
+        .into_iter()
+        .map(fs_err::canonicalize)
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    // Deleted files can't be part of the package, but they differ from what's
+    // committed all the same.
+    let dirty: Vec<_> = changed
+        .iter()
+        .filter(|path| {
+            package_files.contains(*path) || (!path.exists() && path.starts_with(&project_path))
+        })
+        .collect();
+
+    if !dirty.is_empty() {
+        let dirty_list: Vec<_> = dirty
+            .iter()
+            .map(|path| format!("  {}", path.display()))
+            .collect();
+
+        bail!(
+            "Package files have uncommitted changes:\n{}\nCommit them or use --allow-dirty to \
+             publish anyway.",
+            dirty_list.join("\n")
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use structopt::StructOpt;
+
+use crate::installation::InstallationContext;
+use crate::lockfile::{Lockfile, LOCKFILE_NAME};
+use crate::manifest::Manifest;
+use crate::warnings;
+use crate::GlobalOptions;
+
+/// Rebuild a lost lockfile without resolving the package graph again.
+#[derive(Debug, StructOpt)]
+pub struct RelockSubcommand {
+    /// Path to the project to rebuild the lockfile of.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// Rebuild the lockfile from the packages installed into the project's
+    /// package folders, with the versions, links, and checksums they were
+    /// installed with.
+    #[structopt(long = "from-installed")]
+    pub from_installed: bool,
+}
+
+impl RelockSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        if !self.from_installed {
+            bail!(
+                "Nothing to rebuild the lockfile from. Pass --from-installed to use the installed \
+                 packages."
+            );
+        }
+
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+
+        let lockfile_path = self.project_path.join(LOCKFILE_NAME);
+        if lockfile_path.exists() {
+            bail!(
+                "{} already exists. Remove it first if it should be rebuilt from the installed \
+                 packages.",
+                lockfile_path.display()
+            );
+        }
+
+        let installation = InstallationContext::new(
+            &self.project_path,
+            manifest.place.shared_packages.clone(),
+            manifest.place.server_packages.clone(),
+        )
+        .with_destinations(manifest.dependency_destinations.clone());
+
+        let installed = installation.read_installed()?;
+        if installed.packages.is_empty() && installed.root_dependencies.is_empty() {
+            bail!("No installed packages were found. Run wally install to create the lockfile.");
+        }
+
+        let (lockfile, unknown) = Lockfile::from_installed(&manifest, &installed);
+        for problem in unknown {
+            warnings::warn(problem);
+        }
+
+        lockfile.save(&self.project_path)?;
+        println!(
+            "Rebuilt {} from {} installed packages",
+            lockfile_path.display(),
+            installed.packages.len()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use structopt::StructOpt;
+use toml_edit::{Document, Item};
+
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Manifest, DEPENDENCY_TABLES};
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::resolution::resolve;
+use crate::GlobalOptions;
+
+use super::utils::add_dependency_sources;
+
+/// Remove a dependency from this project's manifest and lockfile.
+#[derive(Debug, StructOpt)]
+pub struct RemoveSubcommand {
+    /// Path to the project to remove the dependency from.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The name of the package to remove, like `evaera/signal`.
+    pub package: PackageName,
+}
+
+impl RemoveSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest_path = global.manifest_file(&self.project_path);
+        let contents = fs_err::read_to_string(&manifest_path)?;
+        let mut doc: Document = contents.parse().with_context(|| {
+            format!(
+                "failed to parse manifest at path {}",
+                manifest_path.display()
+            )
+        })?;
+
+        let lockfile = Lockfile::load(&self.project_path)?;
+
+        let mut removed = Vec::new();
+
+        for table_name in DEPENDENCY_TABLES {
+            if !doc.as_table().contains_table(table_name) {
+                continue;
+            }
+
+            let dependencies = match doc[*table_name].as_table_mut() {
+                Some(dependencies) => dependencies,
+                None => continue,
+            };
+
+            let aliases: Vec<String> = dependencies
+                .iter()
+                .filter(|(_, item)| dependency_name(item).as_ref() == Some(&self.package))
+                .map(|(alias, _)| alias.to_owned())
+                .collect();
+
+            for alias in aliases {
+                dependencies.remove(&alias);
+                removed.push(format!("{}.{}", table_name, alias));
+            }
+        }
+
+        if removed.is_empty() {
+            let dependents = lockfile
+                .as_ref()
+                .map(|lockfile| dependents(lockfile, &self.package))
+                .unwrap_or_default();
+
+            if dependents.is_empty() {
+                bail!("{} is not a dependency of this project", self.package);
+            }
+
+            let dependents: Vec<String> = dependents.iter().map(ToString::to_string).collect();
+            bail!(
+                "{} is not a direct dependency of this project, so it can't be removed. It's \
+                 required by {}, which would need to be removed instead.",
+                self.package,
+                dependents.join(", ")
+            );
+        }
+
+        fs_err::write(&manifest_path, doc.to_string())?;
+
+        for entry in &removed {
+            println!("Removed {}", entry);
+        }
+
+        // Resolve with the same optional dependencies `wally install` uses by
+        // default, so ones that are off don't end up in the lockfile.
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?
+            .with_features(false, &[])?;
+        let lockfile = match lockfile {
+            Some(lockfile) => lockfile,
+            None => return Ok(()),
+        };
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        add_dependency_sources(&mut package_sources, &manifest, &global)?;
+
+        // Everything else stays at the version it's locked to. Packages that
+        // only the removed dependency needed won't be activated, so they drop
+        // out of the lockfile and are pruned by the next install.
+        let try_to_use = lockfile.as_ids().collect();
+        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+
+        let mut new_lockfile = Lockfile::from_resolve(&resolved, &manifest.package.registry);
+        new_lockfile.conflict_policy = manifest.package.conflict_policy;
+        new_lockfile.index_rev = package_sources.index_rev()?;
+        new_lockfile.set_checksums(&lockfile.checksums());
+        new_lockfile.set_sources(&lockfile.sources());
+        new_lockfile.set_resolved_at(&manifest, &lockfile, false);
+        new_lockfile.save(&self.project_path)?;
+
+        Ok(())
+    }
+}
+
+/// The name of the package a manifest dependency refers to, which is either a
+/// package requirement or a table with a `name` key.
+fn dependency_name(item: &Item) -> Option<PackageName> {
+    let package_req = match item.as_str() {
+        Some(package_req) => package_req.parse::<PackageReq>().ok()?,
+        None => {
+            let table = item.as_value()?.as_inline_table()?;
+            return table.get("name")?.as_str()?.parse().ok();
+        }
+    };
+
+    Some(package_req.name().clone())
+}
+
+/// The packages in the lockfile that depend on the package with this name.
+fn dependents(lockfile: &Lockfile, name: &PackageName) -> BTreeSet<PackageName> {
+    lockfile
+        .packages
+        .iter()
+        .filter_map(|lock_package| match lock_package {
+            LockPackage::Registry(lock_package) => {
+                let depends_on_name = lock_package
+                    .dependencies
+                    .iter()
+                    .any(|(_, package_id)| package_id.name() == name);
+
+                if depends_on_name {
+                    Some(lock_package.name.clone())
+                } else {
+                    None
+                }
+            }
+            LockPackage::Git(_) => None,
+        })
+        .collect()
+}
@@ -0,0 +1,95 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::resolution::resolve;
+use crate::GlobalOptions;
+
+use super::utils::{add_dependency_sources, indexed_checksums};
+
+/// Resolve the project's package graph and print the lockfile an install
+/// would write, using only the registries' indexes. No package contents are
+/// downloaded, and the project is left untouched.
+#[derive(Debug, StructOpt)]
+pub struct ResolveSubcommand {
+    /// Path to the project to resolve.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The target to resolve for, as declared in the manifest's
+    /// `package.targets`. Only the default dependencies are used if unset.
+    #[structopt(long = "target")]
+    pub target: Option<String>,
+
+    /// Write the lockfile to this path instead of printing it.
+    #[structopt(long = "output")]
+    pub output: Option<PathBuf>,
+}
+
+impl ResolveSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?
+            .for_target(self.target.as_deref())?
+            .with_features(false, &[])?;
+
+        let mut stored_lockfile = match Lockfile::load(&self.project_path)? {
+            Some(lockfile) => lockfile,
+            None => Lockfile::from_manifest(&manifest),
+        };
+        let lockfile = stored_lockfile.for_target(self.target.as_deref());
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        add_dependency_sources(&mut package_sources, &manifest, &global)?;
+
+        let try_to_use: BTreeSet<PackageId> = lockfile.as_ids().collect();
+        let resolved = resolve(&manifest, &try_to_use, &package_sources)?;
+
+        let mut new_lockfile = Lockfile::from_resolve(&resolved, &manifest.package.registry);
+        new_lockfile.conflict_policy = manifest.package.conflict_policy;
+        new_lockfile.index_rev = package_sources.index_rev()?;
+        new_lockfile.set_resolved_at(&manifest, &lockfile, lockfile.packages.is_empty());
+
+        // Without the contents, the only checksums to go on are the ones
+        // already in the lockfile and the ones registries recorded when each
+        // package was published.
+        new_lockfile.set_checksums(&indexed_checksums(
+            &manifest.package_id(),
+            &resolved,
+            &package_sources,
+        )?);
+        new_lockfile.set_checksums(&lockfile.checksums());
+        new_lockfile.set_sources(&lockfile.sources());
+
+        stored_lockfile.set_target(self.target.as_deref(), new_lockfile);
+        let contents = stored_lockfile.to_canonical_string()?;
+
+        match &self.output {
+            Some(path) => fs_err::write(path, contents)?,
+            None => print!("{}", contents),
+        }
+
+        Ok(())
+    }
+}
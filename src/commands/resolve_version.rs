@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_req::PackageReq;
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::resolution::resolve_version;
+use crate::GlobalOptions;
+
+use super::utils::add_dependency_sources;
+
+/// Print the version a package requirement would resolve to if it were added
+/// to the project, without changing anything.
+#[derive(Debug, StructOpt)]
+pub struct ResolveVersionSubcommand {
+    /// Path to the project whose registries and overrides should be used.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The requirement to resolve, like `evaera/signal@^1.2`.
+    pub package: PackageReq,
+
+    /// How to print the resolved package, `text` or `json`.
+    #[structopt(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    pub format: String,
+}
+
+#[derive(Serialize)]
+struct ResolvedVersion<'a> {
+    requirement: &'a PackageReq,
+    resolved: &'a PackageId,
+}
+
+impl ResolveVersionSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?
+            .with_features(false, &[])?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        add_dependency_sources(&mut package_sources, &manifest, &global)?;
+
+        let resolved = resolve_version(&manifest, &self.package, &package_sources)?;
+
+        if self.format == "json" {
+            let output = ResolvedVersion {
+                requirement: &self.package,
+                resolved: &resolved,
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        } else {
+            println!("{}", resolved);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{bail, Context};
+use structopt::StructOpt;
+
+use crate::manifest::Manifest;
+use crate::GlobalOptions;
+
+/// Run one of the scripts defined in this project's `[scripts]` table.
+#[derive(Debug, StructOpt)]
+pub struct RunSubcommand {
+    /// Path to the project to run the script in.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The name of the script to run.
+    pub script: String,
+
+    /// Extra arguments to pass along to the script.
+    #[structopt(last = true)]
+    pub args: Vec<String>,
+}
+
+impl RunSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+
+        let script = match manifest.scripts.get(&self.script) {
+            Some(script) => script,
+            None if manifest.scripts.is_empty() => {
+                bail!(
+                    "There is no script named `{}`, and this project doesn't define any scripts",
+                    self.script
+                );
+            }
+            None => {
+                let available: Vec<&str> = manifest.scripts.keys().map(String::as_str).collect();
+                bail!(
+                    "There is no script named `{}`. Available scripts: {}",
+                    self.script,
+                    available.join(", ")
+                );
+            }
+        };
+
+        let mut command_line = script.clone();
+        for arg in &self.args {
+            command_line.push(' ');
+            command_line.push_str(arg);
+        }
+
+        log::info!("Running `{}`", command_line);
+
+        let status = shell_command(&command_line)
+            .current_dir(&self.project_path)
+            .status()
+            .with_context(|| format!("failed to run script `{}`", self.script))?;
+
+        if !status.success() {
+            bail!("Script `{}` failed with {}", self.script, status);
+        }
+
+        Ok(())
+    }
+}
+
+/// Builds a command that runs `command_line` with the platform's shell, so that
+/// scripts can use pipes and other shell features.
+#[cfg(windows)]
+fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(command_line);
+    command
+}
+
+#[cfg(not(windows))]
+fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(command_line);
+    command
+}
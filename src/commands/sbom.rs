@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use structopt::StructOpt;
+use time::OffsetDateTime;
+
+use crate::lockfile::Lockfile;
+use crate::manifest::Manifest;
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::sbom::{Sbom, SbomFormat};
+use crate::GlobalOptions;
+
+use super::utils::add_dependency_sources;
+
+/// Print a software bill of materials for the packages in this project's
+/// lockfile.
+#[derive(Debug, StructOpt)]
+pub struct SbomSubcommand {
+    /// Path to the project to describe.
+    #[structopt(long = "project-path", default_value = ".")]
+    pub project_path: PathBuf,
+
+    /// The format to write the bill of materials in, either cyclonedx or spdx.
+    #[structopt(long = "format", default_value = "cyclonedx")]
+    pub format: SbomFormat,
+}
+
+impl SbomSubcommand {
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+        let lockfile = Lockfile::load(&self.project_path)?.ok_or_else(|| {
+            format_err!("This project has no lockfile. Run wally install to create one.")
+        })?;
+
+        let default_registry: Box<PackageSource> = if global.test_registry {
+            Box::new(PackageSource::TestRegistry(TestRegistry::new(
+                &manifest.package.registry,
+            )))
+        } else {
+            Box::new(PackageSource::from_registry_spec(
+                &manifest.package.registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
+        };
+
+        let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
+        package_sources.add_fallbacks()?;
+
+        add_dependency_sources(&mut package_sources, &manifest, &global)?;
+
+        let sbom = Sbom::from_lockfile(&manifest, &lockfile, &package_sources)?;
+        let document = sbom.to_format(self.format, &timestamp(OffsetDateTime::now_utc()));
+
+        println!("{}", serde_json::to_string_pretty(&document)?);
+
+        Ok(())
+    }
+}
+
+/// Formats a time like `2024-01-02T03:04:05Z`, which is what SPDX expects.
+fn timestamp(time: OffsetDateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
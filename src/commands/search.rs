@@ -7,7 +7,8 @@ use reqwest::{blocking::Client, header::AUTHORIZATION};
 use serde::Deserialize;
 use structopt::StructOpt;
 
-use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex};
+use crate::package_source::{PackageSource, PackageSourceProvider, TestRegistry};
+use crate::{auth::AuthStore, manifest::Manifest, package_index::PackageIndex, GlobalOptions};
 
 /// Search a registry for packages matching a query.
 #[derive(Debug, StructOpt)]
@@ -21,35 +22,21 @@ pub struct SearchSubcommand {
 }
 
 impl SearchSubcommand {
-    pub fn run(self) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
-        let registry = url::Url::parse(&manifest.package.registry)?;
-        let auth_store = AuthStore::load()?;
-        let package_index = PackageIndex::new(&registry, None)?;
-        let api = package_index.config()?.api;
-
-        let auth = auth_store.tokens.get(api.as_str());
-
-        let client = Client::new();
-        let mut request = client
-            .get(api.join("/v1/package-search/")?)
-            .query(&[("query", &self.query)]);
-
-        if let Some(auth) = auth {
-            request = request.header(AUTHORIZATION, format!("Bearer {}", auth));
-        }
-
-        let response = request.send()?;
-
-        if !response.status().is_success() {
-            bail!(
-                "Failed to search: {} {}",
-                response.status(),
-                response.text()?
-            );
-        }
-
-        let mut results: Vec<SearchResult> = response.json()?;
+    pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?;
+        let registry = &manifest.package.registry;
+
+        let source = if global.test_registry {
+            PackageSource::TestRegistry(TestRegistry::new(registry))
+        } else {
+            PackageSource::from_registry_spec(
+                registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?
+        };
+
+        let mut results = search(&source, registry, &self.query)?;
         println!();
 
         for result in &mut results {
@@ -84,10 +71,134 @@ impl SearchSubcommand {
     }
 }
 
-#[derive(Deserialize)]
+/// Searches the registry with its search API if it has one. Otherwise, its
+/// packages are listed and matched against the query one by one.
+fn search(
+    source: &PackageSource,
+    registry: &str,
+    query: &str,
+) -> anyhow::Result<Vec<SearchResult>> {
+    let capabilities = source.capabilities();
+
+    if capabilities.supports_search {
+        search_api(registry, query)
+    } else if capabilities.can_enumerate {
+        search_package_list(source, query)
+    } else {
+        bail!(
+            "The registry {} doesn't support searching, and can't list its packages to search \
+             through them",
+            registry
+        )
+    }
+}
+
+fn search_api(registry: &str, query: &str) -> anyhow::Result<Vec<SearchResult>> {
+    let registry = url::Url::parse(registry)?;
+    let auth_store = AuthStore::load()?;
+    let package_index = PackageIndex::new(&registry, None)?;
+    let api = package_index.config()?.api;
+
+    let auth = auth_store.tokens.get(api.as_str());
+
+    let client = Client::new();
+    let mut request = client
+        .get(api.join("/v1/package-search/")?)
+        .query(&[("query", query)]);
+
+    if let Some(auth) = auth {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", auth));
+    }
+
+    let response = request.send()?;
+
+    if !response.status().is_success() {
+        bail!(
+            "Failed to search: {} {}",
+            response.status(),
+            response.text()?
+        );
+    }
+
+    Ok(response.json()?)
+}
+
+/// Finds the packages whose name or newest description contains the query,
+/// ignoring case.
+fn search_package_list(source: &PackageSource, query: &str) -> anyhow::Result<Vec<SearchResult>> {
+    let query = query.to_lowercase();
+    let mut results = Vec::new();
+
+    for name in source.package_names()? {
+        let versions = source.all_versions(&name)?;
+        let description = match versions.last() {
+            Some(newest) => newest.package.description.clone(),
+            None => continue,
+        };
+
+        let matches = name.to_string().to_lowercase().contains(&query)
+            || description.as_deref().map_or(false, |description| {
+                description.to_lowercase().contains(&query)
+            });
+
+        if matches {
+            results.push(SearchResult {
+                scope: name.scope().to_owned(),
+                name: name.name().to_owned(),
+                versions: versions
+                    .iter()
+                    .map(|manifest| manifest.package.version.to_string())
+                    .collect(),
+                description,
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+#[derive(Debug, Deserialize)]
 struct SearchResult {
     pub scope: String,
     pub name: String,
     pub versions: Vec<String>,
     pub description: Option<String>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+
+    #[test]
+    fn search_by_listing_packages() {
+        let source = PackageSource::TestRegistry(TestRegistry::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry"
+        )));
+
+        let results = search(&source, "primary-registry", "INDIRECT-dependency-a").unwrap();
+        let found: Vec<_> = results
+            .iter()
+            .map(|result| format!("{}/{} {:?}", result.scope, result.name, result.versions))
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![r#"diamond-graph/indirect-dependency-a ["0.1.0", "0.1.1", "0.2.0", "0.2.1"]"#]
+        );
+    }
+
+    #[test]
+    fn search_without_search_or_listing() {
+        let source = InMemoryRegistry::new().source();
+
+        let err = search(&source, "in-memory", "minimal").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The registry in-memory doesn't support searching, and can't list its packages to \
+             search through them"
+        );
+    }
+}
@@ -2,21 +2,27 @@ use std::collections::BTreeSet;
 
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::installation::InstallationContext;
+use crate::integrity::{LockfileStore, PinnedStore};
 use crate::lockfile::Lockfile;
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
+use crate::package_source::{PackageSource, PackageSourceMap, TestRegistry};
+use crate::resolution::ErrorMode;
+use crate::resolution_trace::ResolutionTrace;
 use crate::{resolution, GlobalOptions};
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
 
-use super::utils::{generate_dependency_changes, render_update_difference};
+use super::utils::{
+    add_dependency_sources, generate_dependency_changes, print_warning, render_update_difference,
+};
 
 /// Update all of the dependencies of this project.
 #[derive(Debug, StructOpt)]
@@ -25,33 +31,79 @@ pub struct UpdateSubcommand {
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
 
+    /// The target to update dependencies for, as declared in the manifest's
+    /// `package.targets`. Only the default dependencies are used if unset.
+    #[structopt(long = "target")]
+    pub target: Option<String>,
+
     /// An optional list of dependencies to update.
     /// They must be valid package name with an optional version requirement.
     pub package_specs: Vec<PackageSpec>,
+
+    /// Fail instead of warning when a package's peer dependency isn't
+    /// provided by a package that depends on it.
+    #[structopt(long = "strict-peers")]
+    pub strict_peers: bool,
+
+    /// What to do when resolution runs into a problem: `fail-fast` stops at
+    /// the first one, and `collect` keeps going to report every problem at
+    /// once.
+    #[structopt(long = "error-mode", default_value = "fail-fast", possible_values = &["fail-fast", "collect"])]
+    pub error_mode: ErrorMode,
+
+    /// Pick the version of each package that its registry recommends, when
+    /// one satisfies the requirement, instead of the newest one. Versions
+    /// pinned by the lockfile are still kept.
+    #[structopt(long = "prefer-recommended")]
+    pub prefer_recommended: bool,
+}
+
+impl Default for UpdateSubcommand {
+    /// The same update that `wally update` runs with no arguments.
+    fn default() -> Self {
+        Self {
+            project_path: PathBuf::from("."),
+            target: None,
+            package_specs: Vec::new(),
+            strict_peers: false,
+            error_mode: ErrorMode::default(),
+            prefer_recommended: false,
+        }
+    }
 }
 
 impl UpdateSubcommand {
     pub fn run(self, global: GlobalOptions) -> anyhow::Result<()> {
-        let manifest = Manifest::load(&self.project_path)?;
+        let manifest = Manifest::load_file(&global.manifest_file(&self.project_path))?
+            .for_target(self.target.as_deref())?
+            .with_features(false, &[])?;
 
-        let lockfile = match Lockfile::load(&self.project_path)? {
+        let mut stored_lockfile = match Lockfile::load(&self.project_path)? {
             Some(lockfile) => lockfile,
             None => Lockfile::from_manifest(&manifest),
         };
+        let lockfile = stored_lockfile.for_target(self.target.as_deref());
 
         let default_registry: Box<PackageSource> = if global.test_registry {
             Box::new(PackageSource::TestRegistry(TestRegistry::new(
                 &manifest.package.registry,
             )))
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+            Box::new(PackageSource::from_registry_spec(
                 &manifest.package.registry,
-            )?))
+                global.max_download_size,
+                global.max_index_entries,
+            )?)
         };
 
         let mut package_sources = PackageSourceMap::new(default_registry);
+        if global.assert_offline {
+            package_sources.assert_offline();
+        }
         package_sources.add_fallbacks()?;
 
+        add_dependency_sources(&mut package_sources, &manifest, &global)?;
+
         // If the user didn't specify any targets, then update all of the packages.
         // Otherwise, find the target packages to update.
         let try_to_use = if self.package_specs.is_empty() {
@@ -89,7 +141,14 @@ impl UpdateSubcommand {
                 SetForegroundColor(Color::Reset)
             ));
 
-        let resolved_graph = resolution::resolve(&manifest, &try_to_use, &package_sources)?;
+        let resolved_graph = resolution::resolve_traced(
+            &manifest,
+            &try_to_use,
+            &package_sources,
+            self.error_mode,
+            self.prefer_recommended,
+            &mut ResolutionTrace::disabled(),
+        )?;
 
         progress.println(format!(
             "{}   Resolved {}{} total dependencies",
@@ -98,6 +157,15 @@ impl UpdateSubcommand {
             resolved_graph.activated.len() - 1
         ));
 
+        let peer_check = resolved_graph.check_peer_dependencies(self.strict_peers);
+        if peer_check.is_err() {
+            progress.finish_and_clear();
+        }
+
+        for warning in peer_check? {
+            print_warning(&progress, warning);
+        }
+
         progress.enable_steady_tick(Duration::from_millis(100));
         progress.suspend(|| {
             let dependency_changes = generate_dependency_changes(
@@ -107,7 +175,16 @@ impl UpdateSubcommand {
             render_update_difference(&dependency_changes, &mut std::io::stdout()).unwrap();
         });
 
-        Lockfile::from_resolve(&resolved_graph).save(&self.project_path)?;
+        let mut new_lockfile = Lockfile::from_resolve(&resolved_graph, &manifest.package.registry);
+        new_lockfile.conflict_policy = manifest.package.conflict_policy;
+        new_lockfile.index_rev = package_sources.index_rev()?;
+        new_lockfile.set_checksums(&lockfile.checksums());
+        // Cached packages aren't downloaded, so only the old lockfile knows
+        // where their contents were redirected to.
+        new_lockfile.set_sources(&lockfile.sources());
+        new_lockfile.set_resolved_at(&manifest, &lockfile, self.package_specs.is_empty());
+        stored_lockfile.set_target(self.target.as_deref(), new_lockfile.clone());
+        stored_lockfile.save(&self.project_path)?;
 
         progress.println(format!(
             "{}    Updated {}lockfile",
@@ -116,25 +193,20 @@ impl UpdateSubcommand {
         ));
 
         let root_package_id = manifest.package_id();
+        let temp_dir = global.temp_dir(&self.project_path, Some(&manifest));
         let installation_context = InstallationContext::new(
             &self.project_path,
             manifest.place.shared_packages,
             manifest.place.server_packages,
-        );
-
-        progress.set_message(format!(
-            "{}  Cleaning {}package destination...",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
-
-        installation_context.clean()?;
-
-        progress.println(format!(
-            "{}    Cleaned {}package destination",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        )
+        .with_destinations(manifest.dependency_destinations.clone())
+        .with_link_style(
+            manifest.place.link_style,
+            manifest.place.link_loader.clone(),
+        )
+        .with_concurrency(global.download_concurrency, global.extract_concurrency)
+        .with_temp_dir(temp_dir)
+        .with_package_cache(global.package_cache());
 
         progress.finish_with_message(format!(
             "{}{}  Starting installation {}",
@@ -143,7 +215,20 @@ impl UpdateSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        installation_context.install(package_sources, root_package_id, resolved_graph)?;
+        let installed = installation_context.install(
+            package_sources,
+            root_package_id,
+            resolved_graph,
+            Arc::new(PinnedStore::new(
+                LockfileStore::new(&new_lockfile.checksums())?,
+                manifest.dependency_checksums.clone(),
+            )),
+        )?;
+
+        new_lockfile.set_checksums(&installed.checksums);
+        new_lockfile.set_sources(&installed.sources);
+        stored_lockfile.set_target(self.target.as_deref(), new_lockfile);
+        stored_lockfile.save(&self.project_path)?;
 
         Ok(())
     }
@@ -170,10 +255,12 @@ impl FromStr for PackageSpec {
     type Err = anyhow::Error;
 
     fn from_str(value: &str) -> anyhow::Result<Self> {
-        if let Ok(package_req) = value.parse() {
-            Ok(PackageSpec::Required(package_req))
-        } else if let Ok(package_name) = value.parse() {
+        // A bare name also parses as a requirement for the latest version, but
+        // it should name every version of the package here.
+        if let Ok(package_name) = value.parse() {
             Ok(PackageSpec::Named(package_name))
+        } else if let Ok(package_req) = value.parse() {
+            Ok(PackageSpec::Required(package_req))
         } else {
             anyhow::bail!(
                 "Was unable to parse {} into a package requirement or a package name!",
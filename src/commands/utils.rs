@@ -1,7 +1,123 @@
+use crate::manifest::Manifest;
+use crate::package_source::{
+    AssertOffline, LocalPackage, PackageSource, PackageSourceId, PackageSourceMap,
+    PackageSourceProvider, TestRegistry,
+};
+use crate::resolution::Resolve;
+use crate::warnings;
+use crate::GlobalOptions;
 use crate::{package_id::PackageId, package_name::PackageName};
 use crossterm::style::{Color, SetForegroundColor};
+use indicatif::ProgressBar;
 use serde::Serialize;
-use std::{collections::BTreeSet, io::Write};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+use toml_edit::{value, Item, Value};
+
+/// Adds the sources that the root manifest assigns to specific dependencies,
+/// which aren't searched for any other package.
+pub(crate) fn add_dependency_sources(
+    package_sources: &mut PackageSourceMap,
+    manifest: &Manifest,
+    global: &GlobalOptions,
+) -> anyhow::Result<()> {
+    for registry in manifest.dependency_registries.values() {
+        let source = if global.test_registry {
+            PackageSource::TestRegistry(TestRegistry::new(registry))
+        } else {
+            PackageSource::from_registry_spec(
+                registry,
+                global.max_download_size,
+                global.max_index_entries,
+            )?
+        };
+
+        package_sources.add_source(PackageSourceId::for_registry(registry), Box::new(source));
+    }
+
+    for path in manifest.dependency_paths.values() {
+        package_sources.add_source(
+            PackageSourceId::Local(path.clone()),
+            Box::new(PackageSource::Local(LocalPackage::new(path.clone()))),
+        );
+    }
+
+    Ok(())
+}
+
+/// The source for a registry named on the command line, rather than by a
+/// project's manifest.
+pub(crate) fn registry_source(spec: &str, global: &GlobalOptions) -> anyhow::Result<PackageSource> {
+    let source = if global.test_registry {
+        PackageSource::TestRegistry(TestRegistry::new(spec))
+    } else {
+        PackageSource::from_registry_spec(spec, global.max_download_size, global.max_index_entries)?
+    };
+
+    if global.assert_offline {
+        Ok(PackageSource::AssertOffline(AssertOffline::new(Box::new(
+            source,
+        ))))
+    } else {
+        Ok(source)
+    }
+}
+
+/// The checksums that registries recorded in their index for the packages the
+/// root resolved to. Path dependencies and packages published before
+/// checksums were recorded have none.
+pub(crate) fn indexed_checksums(
+    root: &PackageId,
+    resolved: &Resolve,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<BTreeMap<PackageId, String>> {
+    let mut checksums = BTreeMap::new();
+
+    for (package_id, metadata) in &resolved.metadata {
+        if package_id == root || matches!(metadata.source_registry, PackageSourceId::Local(_)) {
+            continue;
+        }
+
+        let source = match package_sources.get(&metadata.source_registry) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        if let Some(checksum) = source.get(package_id)?.and_then(|entry| entry.checksum) {
+            checksums.insert(package_id.clone(), checksum);
+        }
+    }
+
+    Ok(checksums)
+}
+
+/// Prints a warning above the progress bar and records it for
+/// `--deny-warnings`.
+pub(crate) fn print_warning(progress: &ProgressBar, warning: String) {
+    progress.println(format!(
+        "{}    Warning {}{}",
+        SetForegroundColor(Color::Yellow),
+        SetForegroundColor(Color::Reset),
+        warning
+    ));
+    warnings::record(warning);
+}
+
+/// Sets a dependency's requirement in the manifest. A requirement that's
+/// replaced keeps the whitespace and comment around it, like a note on why
+/// it's held back.
+pub(crate) fn set_requirement(item: &mut Item, requirement: &str) {
+    let new_item = match item.as_value() {
+        Some(old) => Item::Value(
+            Value::from(requirement).decorated(old.decor().prefix(), old.decor().suffix()),
+        ),
+        None => value(requirement),
+    };
+
+    *item = new_item;
+}
 
 #[derive(Debug, Eq, PartialEq, Serialize)]
 pub(crate) enum DependencyChange {
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use anyhow::bail;
+use structopt::StructOpt;
+
+use crate::validation::validate_manifest;
+
+/// Check that a manifest could be published, reporting every problem with it
+/// at once.
+///
+/// Used by registry CI to vet packages before they're added to the index.
+#[derive(Debug, StructOpt)]
+pub struct ValidateManifestSubcommand {
+    /// Path to the project whose manifest should be checked.
+    #[structopt(default_value = ".")]
+    pub path: PathBuf,
+
+    /// Path to a checkout of a package index. If given, the package's version
+    /// must not already be in it.
+    #[structopt(long = "index")]
+    pub index: Option<PathBuf>,
+
+    /// How to print the problems that were found, `text` or `json`.
+    #[structopt(long = "format", default_value = "text", possible_values = &["text", "json"])]
+    pub format: String,
+}
+
+impl ValidateManifestSubcommand {
+    pub fn run(self) -> anyhow::Result<()> {
+        let problems = validate_manifest(&self.path, self.index.as_deref())?;
+
+        if self.format == "json" {
+            let output = serde_json::json!({
+                "valid": problems.is_empty(),
+                "problems": problems,
+            });
+
+            println!("{}", output);
+        } else {
+            for problem in &problems {
+                if problem.field.is_empty() {
+                    println!("error: {}", problem.message);
+                } else {
+                    println!("error: {}: {}", problem.field, problem.message);
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!("Found {} problem(s) with the manifest", problems.len());
+        }
+
+        if self.format != "json" {
+            println!("Manifest is valid");
+        }
+
+        Ok(())
+    }
+}
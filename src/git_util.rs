@@ -5,12 +5,13 @@
 //! https://github.com/rust-lang/cargo/blob/master/src/cargo/sources/git/utils.rs
 
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, format_err, Context};
 use git2::build::RepoBuilder;
 use git2::{
     Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository, RepositoryInitOptions,
+    StatusOptions,
 };
 use url::Url;
 use walkdir::WalkDir;
@@ -219,3 +220,35 @@ pub fn update_index(access_token: Option<String>, repository: &Repository) -> an
 
     Ok(())
 }
+
+/// Lists tracked files with uncommitted changes in the Git repository that
+/// contains `path`, including staged changes and deletions. Paths are absolute
+/// and based on the canonical path of the repository's working directory.
+///
+/// Returns `None` if `path` is not inside a Git working directory.
+pub fn uncommitted_changes(path: &Path) -> anyhow::Result<Option<Vec<PathBuf>>> {
+    let repository = match Repository::discover(path) {
+        Ok(repository) => repository,
+        Err(_) => return Ok(None),
+    };
+
+    let workdir = match repository.workdir() {
+        Some(workdir) => fs_err::canonicalize(workdir)?,
+        None => return Ok(None),
+    };
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+
+    let statuses = repository
+        .statuses(Some(&mut options))
+        .context("could not read the status of the Git repository")?;
+
+    let changed = statuses
+        .iter()
+        .filter(|entry| !entry.status().is_empty())
+        .filter_map(|entry| entry.path().map(|path| workdir.join(path)))
+        .collect();
+
+    Ok(Some(changed))
+}
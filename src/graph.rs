@@ -0,0 +1,180 @@
+//! A project's resolved package graph as adjacency lists, for tools that want
+//! to analyze the graph themselves. This is what `wally graph --json` prints,
+//! so fields may be added but never renamed or removed.
+
+use std::collections::BTreeMap;
+
+use anyhow::{format_err, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{Manifest, Realm};
+use crate::package_id::PackageId;
+use crate::package_req::PackageReq;
+use crate::package_source::{PackageSourceMap, PackageSourceProvider};
+use crate::resolution::Resolve;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PackageGraph {
+    /// The project the graph was resolved for.
+    pub root: PackageId,
+
+    /// Every package in the graph, sorted by ID.
+    pub nodes: Vec<GraphNode>,
+
+    /// Every dependency between packages, sorted by the package that depends
+    /// on the other and then by alias.
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphNode {
+    pub id: PackageId,
+
+    /// The realm the package is installed into.
+    pub realm: Realm,
+
+    /// The checksum pinned in the lockfile, which the project itself and
+    /// path dependencies don't have.
+    pub checksum: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphEdge {
+    /// The package that has the dependency.
+    pub from: PackageId,
+
+    /// The package the dependency resolved to.
+    pub to: PackageId,
+
+    /// The name `from` gives the dependency in its manifest.
+    pub alias: String,
+
+    /// Which of `from`'s dependency lists the dependency is in.
+    pub realm: Realm,
+
+    /// What `from` asked for. It's missing for dependencies that aren't in
+    /// `from`'s manifest under `realm`, like ones for an install target.
+    pub requirement: Option<PackageReq>,
+}
+
+impl PackageGraph {
+    /// Builds the graph from a resolve of `root_manifest`, reading each
+    /// package's manifest from its source to find its requirements.
+    pub fn from_resolve(
+        root_manifest: &Manifest,
+        resolve: &Resolve,
+        checksums: &BTreeMap<PackageId, String>,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<Self> {
+        let root = root_manifest.package_id();
+
+        let mut manifests = BTreeMap::new();
+        manifests.insert(root.clone(), root_manifest.clone());
+
+        let mut nodes = Vec::new();
+        for package_id in &resolve.activated {
+            let metadata = resolve
+                .metadata
+                .get(package_id)
+                .ok_or_else(|| format_err!("{} is missing from the resolve", package_id))?;
+
+            if *package_id != root {
+                let manifest = package_sources
+                    .get(&metadata.source_registry)
+                    .map(|source| source.get(package_id))
+                    .transpose()?
+                    .flatten()
+                    .with_context(|| format!("Could not find the manifest of {}", package_id))?;
+                manifests.insert(package_id.clone(), manifest);
+            }
+
+            nodes.push(GraphNode {
+                id: package_id.clone(),
+                realm: metadata.origin_realm,
+                checksum: checksums.get(package_id).cloned(),
+            });
+        }
+
+        let realms = [
+            (Realm::Shared, &resolve.shared_dependencies),
+            (Realm::Server, &resolve.server_dependencies),
+            (Realm::Dev, &resolve.dev_dependencies),
+            (Realm::Build, &resolve.build_dependencies),
+        ];
+
+        let mut edges = Vec::new();
+        for (realm, dependencies) in &realms {
+            for (from, dependencies) in dependencies.iter() {
+                let requirements = manifests.get(from).map(|manifest| match realm {
+                    Realm::Shared => &manifest.dependencies,
+                    Realm::Server => &manifest.server_dependencies,
+                    Realm::Dev => &manifest.dev_dependencies,
+                    Realm::Build => &manifest.build_dependencies,
+                });
+
+                for (alias, to) in dependencies {
+                    edges.push(GraphEdge {
+                        from: from.clone(),
+                        to: to.clone(),
+                        alias: alias.clone(),
+                        realm: *realm,
+                        requirement: requirements
+                            .and_then(|requirements| requirements.get(alias))
+                            .cloned(),
+                    });
+                }
+            }
+        }
+
+        edges.sort_by(|a, b| (&a.from, &a.alias).cmp(&(&b.from, &b.alias)));
+
+        Ok(Self { root, nodes, edges })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn json_graph() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        registry.publish(
+            PackageBuilder::new("biff/one-dependency@0.1.0")
+                .with_dep("Minimal", "biff/minimal@^0.1.0"),
+        );
+        registry.publish(PackageBuilder::new("biff/server@1.2.0").with_realm(Realm::Server));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("OneDependency", "biff/one-dependency@^0.1.0")
+            .with_server_dep("Server", "biff/server@=1.2.0")
+            .into_manifest();
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolve = resolve(&root, &Default::default(), &package_sources).unwrap();
+
+        let mut checksums = BTreeMap::new();
+        checksums.insert(
+            "biff/minimal@0.1.0".parse().unwrap(),
+            "sha256:aaaa".to_owned(),
+        );
+        checksums.insert(
+            "biff/one-dependency@0.1.0".parse().unwrap(),
+            "sha256:bbbb".to_owned(),
+        );
+        checksums.insert(
+            "biff/server@1.2.0".parse().unwrap(),
+            "sha256:cccc".to_owned(),
+        );
+
+        let graph =
+            PackageGraph::from_resolve(&root, &resolve, &checksums, &package_sources).unwrap();
+
+        insta::assert_snapshot!(serde_json::to_string_pretty(&graph).unwrap());
+    }
+}
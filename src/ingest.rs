@@ -0,0 +1,228 @@
+//! Simulates a registry ingesting a published package, without touching the
+//! real index. Registry CI uses this to check that accepting a submission would
+//! leave the index valid.
+//!
+//! The submission is written into a throwaway copy of the index laid out like
+//! a test registry, with the index in `index` and package contents in
+//! `contents`, and the copy is then verified as a whole.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::Context;
+use semver::Version;
+use walkdir::WalkDir;
+
+use crate::manifest::Manifest;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::validation::Problem;
+
+/// Writes `manifest` and `contents` into a temporary copy of the package index
+/// checked out at `index_path`, then verifies the copy. The index itself is
+/// never modified.
+///
+/// Returns every problem the ingested index would have.
+pub fn simulate_ingest(
+    index_path: &Path,
+    manifest: &Manifest,
+    contents: &PackageContents,
+) -> anyhow::Result<Vec<Problem>> {
+    let staging = tempfile::tempdir()?;
+    let staged_index = staging.path().join("index");
+
+    copy_index(index_path, &staged_index)
+        .with_context(|| format!("could not copy index at {}", index_path.display()))?;
+
+    let name = &manifest.package.name;
+    let package_path = staged_index.join(name.scope()).join(name.name());
+    fs_err::create_dir_all(package_path.parent().unwrap())?;
+
+    // Entries are appended the same way `PackageIndex::publish` does it.
+    let mut entries = if package_path.exists() {
+        fs_err::read_to_string(&package_path)?
+    } else {
+        String::new()
+    };
+    if !entries.is_empty() && !entries.ends_with('\n') {
+        entries.push('\n');
+    }
+    entries.push_str(&serde_json::to_string(manifest)?);
+    entries.push('\n');
+    fs_err::write(&package_path, entries)?;
+
+    let contents_path = staging
+        .path()
+        .join("contents")
+        .join(name.scope())
+        .join(name.name())
+        .join(format!("{}.zip", manifest.package.version));
+    fs_err::create_dir_all(contents_path.parent().unwrap())?;
+    fs_err::write(&contents_path, contents.data())?;
+
+    verify_registry(staging.path(), &[manifest.package_id()])
+}
+
+/// Verifies a registry laid out like a test registry at `registry_path`:
+/// every index entry must be a valid manifest filed under its own name, no
+/// package may list a version twice, and every package in `with_contents`
+/// must have its contents present.
+pub fn verify_registry(
+    registry_path: &Path,
+    with_contents: &[PackageId],
+) -> anyhow::Result<Vec<Problem>> {
+    let index_path = registry_path.join("index");
+    let mut problems = Vec::new();
+
+    let walker = WalkDir::new(&index_path)
+        .min_depth(2)
+        .max_depth(2)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+
+    for entry in walker {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let components: Vec<_> = entry
+            .path()
+            .strip_prefix(&index_path)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let field = components.join("/");
+
+        // Names are lowercased when parsed, so a file whose path isn't already
+        // lowercase could never be found by clients.
+        let expected_name = match PackageName::new(&components[0], &components[1]) {
+            Ok(name) if name.to_string() == field => name,
+            _ => {
+                problems.push(Problem::new(field, "is not named like a package"));
+                continue;
+            }
+        };
+
+        let entries = fs_err::read_to_string(entry.path())?;
+        let mut versions: HashSet<Version> = HashSet::new();
+
+        for (number, line) in entries.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let line_field = format!("{}:{}", field, number + 1);
+
+            let manifest: Manifest = match serde_json::from_str(line) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    problems.push(Problem::new(
+                        line_field,
+                        format!("is not a valid entry: {}", err),
+                    ));
+                    continue;
+                }
+            };
+
+            if manifest.package.name != expected_name {
+                problems.push(Problem::new(
+                    line_field,
+                    format!(
+                        "is an entry for {}, which belongs in a different file",
+                        manifest.package.name
+                    ),
+                ));
+                continue;
+            }
+
+            if !versions.insert(manifest.package.version.clone()) {
+                problems.push(Problem::new(
+                    line_field,
+                    format!("{} is listed more than once", manifest.package_id()),
+                ));
+            }
+        }
+    }
+
+    for package_id in with_contents {
+        let contents_path = registry_path
+            .join("contents")
+            .join(package_id.name().scope())
+            .join(package_id.name().name())
+            .join(format!("{}.zip", package_id.version()));
+
+        let present = fs_err::metadata(&contents_path)
+            .map(|metadata| metadata.len() > 0)
+            .unwrap_or(false);
+
+        if !present {
+            problems.push(Problem::new(
+                format!("contents/{}", package_id.name()),
+                format!("the contents of {} are missing", package_id),
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Copies an index checkout, leaving out its Git directory.
+fn copy_index(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let walker = WalkDir::new(from)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git");
+
+    for entry in walker {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+
+        if entry.file_type().is_dir() {
+            fs_err::create_dir_all(&target)?;
+        } else {
+            fs_err::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::test_package::PackageBuilder;
+
+    fn primary_index() -> &'static Path {
+        Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry/index"
+        ))
+    }
+
+    #[test]
+    fn new_version_is_accepted() {
+        let (manifest, contents) = PackageBuilder::new("biff/minimal@0.2.0").package();
+
+        let problems = simulate_ingest(primary_index(), &manifest, &contents).unwrap();
+
+        assert_eq!(problems, Vec::new());
+    }
+
+    #[test]
+    fn conflicting_version_is_rejected() {
+        let (manifest, contents) = PackageBuilder::new("biff/minimal@0.1.0").package();
+
+        let problems = simulate_ingest(primary_index(), &manifest, &contents).unwrap();
+
+        assert_eq!(
+            problems,
+            [Problem::new(
+                "biff/minimal:2",
+                "biff/minimal@0.1.0 is listed more than once"
+            )]
+        );
+    }
+}
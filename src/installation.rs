@@ -1,26 +1,110 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fmt::Display,
     io,
     path::{Path, PathBuf},
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use anyhow::{bail, format_err};
+use anyhow::{bail, format_err, Context};
 use crossterm::style::{Color, SetForegroundColor};
 use fs_err as fs;
 use indicatif::{ProgressBar, ProgressStyle};
-use indoc::{formatdoc, indoc};
+use indoc::indoc;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use tempfile::TempDir;
+use walkdir::WalkDir;
 
 use crate::{
-    manifest::Realm,
+    checksum::Checksum,
+    integrity::IntegrityStore,
+    manifest::{LinkStyle, Manifest, Realm, MANIFEST_FILE_NAME},
+    package_cache::PackageCache,
     package_contents::PackageContents,
     package_id::PackageId,
-    package_source::{PackageSourceMap, PackageSourceProvider},
+    package_name::PackageName,
+    package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider},
+    pipeline::{Pipeline, DEFAULT_DOWNLOAD_CONCURRENCY, DEFAULT_EXTRACT_CONCURRENCY},
     resolution::Resolve,
+    warnings,
 };
 
+/// What `InstallationContext::install` did.
+#[derive(Debug, Clone)]
+pub struct Installed {
+    /// The checksums of every installed package.
+    pub checksums: BTreeMap<PackageId, String>,
+
+    /// Where the contents of downloaded packages were redirected to, for the
+    /// packages whose registry sent them somewhere else.
+    pub sources: BTreeMap<PackageId, String>,
+
+    /// The packages that were already installed and up to date, so they
+    /// weren't downloaded again.
+    pub reused: BTreeSet<PackageId>,
+
+    /// The path dependencies that were symlinked into place instead of being
+    /// copied.
+    pub linked: BTreeSet<PackageId>,
+
+    pub timings: InstallTimings,
+}
+
+/// How long the parts of an install took.
+#[derive(Debug, Clone, Default)]
+pub struct InstallTimings {
+    /// How long each package that was downloaded took to install.
+    pub packages: BTreeMap<PackageId, PackageTimings>,
+
+    /// How long writing the link files and symlinks took altogether.
+    pub links: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PackageTimings {
+    pub download: Duration,
+
+    /// Checking the contents against their checksum and, if asked to, their
+    /// manifest against the index.
+    pub verification: Duration,
+
+    pub extraction: Duration,
+}
+
+/// What's installed in a project's package folders, as read back by
+/// `InstallationContext::read_installed`.
+#[derive(Debug, Clone, Default)]
+pub struct InstalledTree {
+    /// The links at the top of the package folders, which are the project's
+    /// own dependencies.
+    pub root_dependencies: Vec<(String, PackageId)>,
+
+    /// Every package found in an `_Index` folder.
+    pub packages: BTreeMap<PackageId, InstalledPackage>,
+
+    /// Problems with what's installed, like folders that aren't any package,
+    /// described for the user.
+    pub problems: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InstalledPackage {
+    /// The checksum the package was installed with, if the install recorded
+    /// one.
+    pub checksum: Option<String>,
+
+    /// Whether the package is a symlink to a path dependency.
+    pub link: bool,
+
+    /// The package's links to its own dependencies.
+    pub dependencies: Vec<(String, PackageId)>,
+}
+
 #[derive(Clone)]
 pub struct InstallationContext {
+    project_path: PathBuf,
     shared_dir: PathBuf,
     shared_index_dir: PathBuf,
     shared_path: Option<String>,
@@ -29,6 +113,17 @@ pub struct InstallationContext {
     server_path: Option<String>,
     dev_dir: PathBuf,
     dev_index_dir: PathBuf,
+    build_dir: PathBuf,
+    build_index_dir: PathBuf,
+    link_path_dependencies: bool,
+    destinations: BTreeMap<PackageName, PathBuf>,
+    package_cache: Option<PackageCache>,
+    link_style: LinkStyle,
+    link_loader: Option<String>,
+    download_concurrency: usize,
+    extract_concurrency: usize,
+    verify_manifests: bool,
+    temp_dir: Option<PathBuf>,
 }
 
 impl InstallationContext {
@@ -41,12 +136,15 @@ impl InstallationContext {
         let shared_dir = project_path.join("Packages");
         let server_dir = project_path.join("ServerPackages");
         let dev_dir = project_path.join("DevPackages");
+        let build_dir = project_path.join("BuildPackages");
 
         let shared_index_dir = shared_dir.join("_Index");
         let server_index_dir = server_dir.join("_Index");
         let dev_index_dir = dev_dir.join("_Index");
+        let build_index_dir = build_dir.join("_Index");
 
         Self {
+            project_path: project_path.to_owned(),
             shared_dir,
             shared_index_dir,
             shared_path,
@@ -55,36 +153,388 @@ impl InstallationContext {
             server_path,
             dev_dir,
             dev_index_dir,
+            build_dir,
+            build_index_dir,
+            link_path_dependencies: false,
+            destinations: BTreeMap::new(),
+            package_cache: None,
+            link_style: LinkStyle::default(),
+            link_loader: None,
+            download_concurrency: DEFAULT_DOWNLOAD_CONCURRENCY,
+            extract_concurrency: DEFAULT_EXTRACT_CONCURRENCY,
+            verify_manifests: false,
+            temp_dir: None,
+        }
+    }
+
+    /// Symlink path dependencies into the package folders instead of copying
+    /// them, so that edits to them show up without installing again.
+    pub fn with_linked_path_dependencies(self, link_path_dependencies: bool) -> Self {
+        Self {
+            link_path_dependencies,
+            ..self
+        }
+    }
+
+    /// Install specific packages into the given folders, relative to the
+    /// project, instead of the usual package folders. These packages get no
+    /// link in the package folders, so they can't have dependencies or be
+    /// depended on by other packages.
+    pub fn with_destinations(self, destinations: BTreeMap<PackageName, PathBuf>) -> Self {
+        Self {
+            destinations,
+            ..self
         }
     }
 
-    /// Delete the existing index, if it exists.
-    pub fn clean(&self) -> anyhow::Result<()> {
-        fn remove_ignore_not_found(path: &Path) -> io::Result<()> {
-            if let Err(err) = fs::remove_dir_all(path) {
-                if err.kind() != io::ErrorKind::NotFound {
-                    return Err(err);
+    /// Reuse the contents of packages kept in the cache instead of downloading
+    /// them, and cache the contents of packages that are downloaded.
+    pub fn with_package_cache(self, package_cache: Option<PackageCache>) -> Self {
+        Self {
+            package_cache,
+            ..self
+        }
+    }
+
+    /// Generate link files in the given style. `link_loader` is the module
+    /// that packages are passed through with `LinkStyle::Loader`.
+    pub fn with_link_style(self, link_style: LinkStyle, link_loader: Option<String>) -> Self {
+        Self {
+            link_style,
+            link_loader,
+            ..self
+        }
+    }
+
+    /// How many packages are downloaded at once, and separately, how many
+    /// downloaded packages are extracted at once.
+    pub fn with_concurrency(self, download_concurrency: usize, extract_concurrency: usize) -> Self {
+        Self {
+            download_concurrency,
+            extract_concurrency,
+            ..self
+        }
+    }
+
+    /// Check the index entry of every package downloaded from a registry
+    /// against the manifest packed into it, failing if they disagree.
+    pub fn with_manifest_verification(self, verify_manifests: bool) -> Self {
+        Self {
+            verify_manifests,
+            ..self
+        }
+    }
+
+    /// Extract packages in the given folder before moving them into the
+    /// package folders, instead of in the project.
+    pub fn with_temp_dir(self, temp_dir: Option<PathBuf>) -> Self {
+        Self { temp_dir, ..self }
+    }
+
+    /// Delete the package folders that Wally generated, leaving alone any
+    /// folders that share their name but weren't created by Wally. A folder is
+    /// considered to be managed by Wally if it contains an `_Index` folder.
+    ///
+    /// Returns the folders that were removed.
+    pub fn clean_managed(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+
+        let folders = [
+            (&self.shared_dir, &self.shared_index_dir),
+            (&self.server_dir, &self.server_index_dir),
+            (&self.dev_dir, &self.dev_index_dir),
+            (&self.build_dir, &self.build_index_dir),
+        ];
+
+        for (dir, index_dir) in folders {
+            if !dir.exists() {
+                continue;
+            }
+
+            if !index_dir.is_dir() {
+                warnings::warn(format!(
+                    "Skipping {} as it does not look like it was created by Wally",
+                    dir.display()
+                ));
+                continue;
+            }
+
+            log::debug!("Removing {}", dir.display());
+            fs::remove_dir_all(dir)?;
+            removed.push(dir.clone());
+        }
+
+        Ok(removed)
+    }
+
+    /// The packages that earlier installs recorded in the package folders,
+    /// which an install can reuse instead of downloading them again.
+    pub fn recorded_packages(&self) -> anyhow::Result<BTreeSet<PackageId>> {
+        let mut packages = BTreeSet::new();
+
+        for index_dir in [
+            &self.shared_index_dir,
+            &self.server_index_dir,
+            &self.dev_index_dir,
+            &self.build_index_dir,
+        ] {
+            packages.extend(InstallRecord::load(index_dir)?.packages.into_keys());
+        }
+
+        Ok(packages)
+    }
+
+    /// Read back what earlier installs left in the package folders: each
+    /// package in an `_Index` folder with its manifest and recorded checksum,
+    /// and the links between them. Packages installed into a custom
+    /// destination can't be found this way and are reported as problems.
+    pub fn read_installed(&self) -> anyhow::Result<InstalledTree> {
+        let mut tree = InstalledTree::default();
+
+        let folders = [
+            (&self.shared_dir, &self.shared_index_dir),
+            (&self.server_dir, &self.server_index_dir),
+            (&self.dev_dir, &self.dev_index_dir),
+            (&self.build_dir, &self.build_index_dir),
+        ];
+
+        for (dir, index_dir) in folders {
+            if !index_dir.is_dir() {
+                continue;
+            }
+
+            tree.root_dependencies
+                .extend(read_links(dir, &mut tree.problems)?);
+
+            let record = InstallRecord::load(index_dir)?;
+
+            for entry in fs::read_dir(index_dir)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+
+                let package_id = match entry
+                    .file_name()
+                    .to_str()
+                    .and_then(parse_package_id_file_name)
+                {
+                    Some(package_id) => package_id,
+                    None => {
+                        tree.problems.push(format!(
+                            "{} isn't named like an installed package",
+                            entry.path().display()
+                        ));
+                        continue;
+                    }
+                };
+
+                let contents_dir = entry.path().join(package_id.name().name());
+                let link = fs::symlink_metadata(&contents_dir)
+                    .map(|metadata| metadata.file_type().is_symlink())
+                    .unwrap_or(false);
+
+                // Packages published before manifests were packed with them
+                // only have their folder name to go on.
+                if contents_dir.join(MANIFEST_FILE_NAME).is_file() {
+                    let manifest = Manifest::load(&contents_dir)?;
+                    if manifest.package_id() != package_id {
+                        tree.problems.push(format!(
+                            "{} holds {} instead of {}",
+                            entry.path().display(),
+                            manifest.package_id(),
+                            package_id
+                        ));
+                        continue;
+                    }
                 }
+
+                let dependencies = read_links(&entry.path(), &mut tree.problems)?;
+
+                tree.packages.insert(
+                    package_id.clone(),
+                    InstalledPackage {
+                        checksum: record.packages.get(&package_id).cloned(),
+                        link,
+                        dependencies,
+                    },
+                );
             }
+        }
 
-            Ok(())
+        for name in self.destinations.keys() {
+            tree.problems.push(format!(
+                "{} is installed into a custom destination, which doesn't record its version",
+                name
+            ));
         }
 
-        remove_ignore_not_found(&self.shared_dir)?;
-        remove_ignore_not_found(&self.server_dir)?;
-        remove_ignore_not_found(&self.dev_dir)?;
+        let all_dependencies = tree.root_dependencies.iter().chain(
+            tree.packages
+                .values()
+                .flat_map(|package| package.dependencies.iter()),
+        );
+        let missing: BTreeSet<&PackageId> = all_dependencies
+            .map(|(_, package_id)| package_id)
+            .filter(|package_id| !tree.packages.contains_key(package_id))
+            .collect();
+        let missing: Vec<String> = missing
+            .into_iter()
+            .map(|package_id| format!("{} is linked to, but isn't installed", package_id))
+            .collect();
+        tree.problems.extend(missing);
 
-        Ok(())
+        Ok(tree)
+    }
+
+    /// Remove everything from the package folders that this installation
+    /// won't reuse: root package links, packages that are no longer part of
+    /// the resolve, path dependencies, and packages whose recorded checksum
+    /// doesn't match the one in the integrity store. Packages we don't expect a checksum
+    /// for yet were extracted by an earlier install that was interrupted, so
+    /// they're reused with the checksum they were recorded with.
+    ///
+    /// Returns the packages that are already installed and up to date.
+    fn prune(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+        integrity: &dyn IntegrityStore,
+    ) -> anyhow::Result<BTreeMap<PackageId, String>> {
+        let mut up_to_date = BTreeMap::new();
+
+        let folders = [
+            (Realm::Shared, &self.shared_dir, &self.shared_index_dir),
+            (Realm::Server, &self.server_dir, &self.server_index_dir),
+            (Realm::Dev, &self.dev_dir, &self.dev_index_dir),
+            (Realm::Build, &self.build_dir, &self.build_index_dir),
+        ];
+
+        for (realm, dir, index_dir) in folders {
+            if !dir.exists() {
+                continue;
+            }
+
+            let record = InstallRecord::load(index_dir)?;
+            let mut keep = BTreeSet::new();
+
+            for (package_id, installed_checksum) in record.packages {
+                let file_name = package_id_file_name(&package_id);
+
+                let wanted = package_id != *root_package_id
+                    && resolved.activated.contains(&package_id)
+                    && resolved.metadata[&package_id].origin_realm == realm
+                    && !matches!(
+                        resolved.metadata[&package_id].source_registry,
+                        PackageSourceId::Local(_)
+                    )
+                    && self.destination(&package_id).is_none();
+
+                // Checksums are compared by value, so that one recorded before
+                // checksums had an algorithm prefix still counts.
+                let installed_checksum = installed_checksum.parse::<Checksum>().ok();
+                let expected_checksum = if wanted {
+                    integrity.recorded(&package_id)?
+                } else {
+                    None
+                };
+
+                let checksum_matches = match &expected_checksum {
+                    Some(_) => installed_checksum == expected_checksum,
+                    None => true,
+                };
+
+                if wanted
+                    && installed_checksum.is_some()
+                    && checksum_matches
+                    && index_dir.join(&file_name).is_dir()
+                {
+                    log::trace!("{} is already installed", package_id);
+                    keep.insert(file_name);
+                    up_to_date.insert(package_id, installed_checksum.unwrap().to_string());
+                }
+            }
+
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+
+                if entry.path() != *index_dir {
+                    remove_entry(&entry)?;
+                }
+            }
+
+            if index_dir.is_dir() {
+                for entry in fs::read_dir(index_dir)? {
+                    let entry = entry?;
+
+                    let is_kept = match entry.file_name().to_str() {
+                        Some(name) => keep.contains(name),
+                        None => false,
+                    };
+
+                    if !is_kept {
+                        remove_entry(&entry)?;
+                    }
+                }
+            }
+
+            // Leave no trace of realms that have nothing left in them, just
+            // like a fresh install.
+            remove_dir_if_empty(index_dir)?;
+            remove_dir_if_empty(dir)?;
+        }
+
+        Ok(up_to_date)
     }
 
     /// Install all packages from the given `Resolve` into the package that this
     /// `InstallationContext` was built for.
+    ///
+    /// Packages that are already installed with the checksum recorded in
+    /// `integrity` are left alone. Any other package is downloaded, verified
+    /// by `integrity`, extracted, and then recorded in `integrity`.
+    ///
+    /// Returns the checksums of every installed package, along with which of
+    /// them were already installed.
     pub fn install(
         self,
         sources: PackageSourceMap,
         root_package_id: PackageId,
         resolved: Resolve,
-    ) -> anyhow::Result<()> {
+        integrity: Arc<dyn IntegrityStore>,
+    ) -> anyhow::Result<Installed> {
+        // Packages from folders on disk can change at any time, so they're
+        // always extracted again and their checksums are never kept.
+        let is_local = |package_id: &PackageId| {
+            matches!(
+                resolved.metadata.get(package_id),
+                Some(metadata) if matches!(metadata.source_registry, PackageSourceId::Local(_))
+            )
+        };
+
+        self.check_destinations(&root_package_id, &resolved)?;
+        self.check_manifest_verification(&root_package_id, &resolved, &sources)?;
+
+        let mut installed = self.prune(&root_package_id, &resolved, integrity.as_ref())?;
+        let reused: BTreeSet<PackageId> = installed.keys().cloned().collect();
+
+        let mut records: BTreeMap<PathBuf, InstallRecord> = BTreeMap::new();
+        for (package_id, checksum) in &installed {
+            let index_dir = self.index_dir(resolved.metadata[package_id].origin_realm);
+            records
+                .entry(index_dir.clone())
+                .or_default()
+                .packages
+                .insert(package_id.clone(), checksum.clone());
+        }
+        let checkpoint = Checkpoint::new(records);
+        // The staging folder is only made once there's something to extract,
+        // so an install that's already up to date doesn't touch the disk.
+        let staging: Arc<OnceCell<Staging>> = Arc::new(OnceCell::new());
+        let mut linked = BTreeSet::new();
+        let mut origins = BTreeMap::new();
+        let mut timings = InstallTimings::default();
+
         let mut handles = Vec::new();
         let resolved_copy = resolved.clone();
         let bar = ProgressBar::new((resolved_copy.activated.len() - 1) as u64).with_style(
@@ -102,6 +552,7 @@ impl InstallationContext {
             .enable_all()
             .build()
             .unwrap();
+        let pipeline = Pipeline::new(self.download_concurrency, self.extract_concurrency);
 
         for package_id in resolved_copy.activated {
             log::debug!("Installing {}...", package_id);
@@ -109,6 +560,9 @@ impl InstallationContext {
             let shared_deps = resolved.shared_dependencies.get(&package_id);
             let server_deps = resolved.server_dependencies.get(&package_id);
             let dev_deps = resolved.dev_dependencies.get(&package_id);
+            let build_deps = resolved.build_dependencies.get(&package_id);
+
+            let links_start = Instant::now();
 
             // We do not need to install the root package, but we should create
             // package links for its dependencies.
@@ -124,6 +578,12 @@ impl InstallationContext {
                 if let Some(deps) = dev_deps {
                     self.write_root_package_links(Realm::Dev, deps, &resolved)?;
                 }
+
+                if let Some(deps) = build_deps {
+                    self.write_root_package_links(Realm::Build, deps, &resolved)?;
+                }
+
+                timings.links += links_start.elapsed();
             } else {
                 let metadata = resolved.metadata.get(&package_id).unwrap();
                 let package_realm = metadata.origin_realm;
@@ -140,24 +600,162 @@ impl InstallationContext {
                     self.write_package_links(&package_id, package_realm, deps, &resolved)?;
                 }
 
+                if let Some(deps) = build_deps {
+                    self.write_package_links(&package_id, package_realm, deps, &resolved)?;
+                }
+
+                if let PackageSourceId::Local(path) = &metadata.source_registry {
+                    if self.link_path_dependencies {
+                        self.write_symlink(&package_id, path, package_realm)?;
+                        timings.links += links_start.elapsed();
+                        linked.insert(package_id);
+                        bar.inc(1);
+                        continue;
+                    }
+                }
+
+                timings.links += links_start.elapsed();
+
+                if installed.contains_key(&package_id) {
+                    log::debug!("{} is up to date, skipping download", package_id);
+                    bar.inc(1);
+                    continue;
+                }
+
                 let source_registry = resolved_copy.metadata[&package_id].source_registry.clone();
                 let source_copy = sources.clone();
                 let context = self.clone();
+                let checkpoint = checkpoint.clone();
+                let staging = Arc::clone(&staging);
                 let b = bar.clone();
 
-                let handle = runtime.spawn_blocking(move || {
-                    let package_source = source_copy.get(&source_registry).unwrap();
-                    let contents = package_source.download_package(&package_id)?;
-                    b.println(format!(
-                        "{} Downloaded {}{}",
-                        SetForegroundColor(Color::DarkGreen),
-                        SetForegroundColor(Color::Reset),
-                        package_id,
-                    ));
+                // Path dependencies change all the time, so there's nothing
+                // to hold their contents to, and they're never cached.
+                let is_local = matches!(source_registry, PackageSourceId::Local(_));
+
+                let package_cache = self.package_cache.clone().filter(|_| !is_local);
+                let verify_manifests = self.verify_manifests;
+                let download = {
+                    let integrity = Arc::clone(&integrity);
+                    let bar = bar.clone();
+
+                    move || {
+                        let package_source = source_copy.get(&source_registry).unwrap();
+
+                        // Cached contents are only found by the checksum the
+                        // package is pinned to, and the cache checks them
+                        // against it, so they don't need verifying again.
+                        let cached = match &package_cache {
+                            Some(package_cache) => {
+                                integrity.recorded(&package_id)?.and_then(|checksum| {
+                                    Some((package_cache.get(&checksum)?, checksum))
+                                })
+                            }
+                            None => None,
+                        };
+
+                        let mut package_timings = PackageTimings::default();
+                        let (contents, checksum) = match cached {
+                            Some(cached) => {
+                                log::debug!("Using the cached contents of {}", package_id);
+                                cached
+                            }
+                            None => {
+                                log::debug!(
+                                    "Downloading {} from {:?}",
+                                    package_id,
+                                    source_registry
+                                );
+                                let download_start = Instant::now();
+                                let contents = package_source.download_package(&package_id)?;
+                                package_timings.download = download_start.elapsed();
+                                log::debug!(
+                                    "Finished downloading {} ({} bytes)",
+                                    package_id,
+                                    contents.data().len()
+                                );
+
+                                let verify_start = Instant::now();
+                                if !is_local {
+                                    integrity.verify(&package_id, contents.data())?;
+                                }
+                                package_timings.verification = verify_start.elapsed();
+
+                                if let Some(package_cache) = &package_cache {
+                                    if let Err(err) = package_cache.insert(&contents) {
+                                        log::debug!("Could not cache {}: {:#}", package_id, err);
+                                    }
+                                }
+
+                                bar.println(format!(
+                                    "{} Downloaded {}{}",
+                                    SetForegroundColor(Color::DarkGreen),
+                                    SetForegroundColor(Color::Reset),
+                                    package_id,
+                                ));
+
+                                // Always record the checksum with the default
+                                // algorithm, whichever one the recorded
+                                // checksum used.
+                                let checksum = Checksum::compute(contents.data());
+                                (contents, checksum)
+                            }
+                        };
+
+                        let verify_start = Instant::now();
+                        if verify_manifests && !is_local {
+                            let manifest = package_source.get(&package_id)?.ok_or_else(|| {
+                                format_err!("{} is no longer in its registry's index", package_id)
+                            })?;
+
+                            if !contents.verify_manifest(&manifest)? {
+                                warnings::warn(format!(
+                                    "{} has no manifest in its contents to check its index \
+                                     entry against",
+                                    package_id
+                                ));
+                            }
+                        }
+                        package_timings.verification += verify_start.elapsed();
+
+                        Ok((package_id, contents, checksum, package_timings))
+                    }
+                };
+
+                let integrity = Arc::clone(&integrity);
+                let extract = move |(package_id, contents, checksum, mut package_timings): (
+                    PackageId,
+                    PackageContents,
+                    Checksum,
+                    PackageTimings,
+                )| {
+                    let origin = contents.origin().map(str::to_owned);
+
+                    let extract_start = Instant::now();
+                    let staging = staging.get_or_try_init(|| context.staging())?;
+                    context.write_contents(&package_id, &contents, package_realm, staging)?;
+                    package_timings.extraction = extract_start.elapsed();
                     b.inc(1);
-                    context.write_contents(&package_id, &contents, package_realm)
-                });
 
+                    if !is_local {
+                        integrity.record(&package_id, &checksum)?;
+                    }
+
+                    // Path dependencies are always extracted again, and
+                    // packages with a destination aren't in any index.
+                    let checksum = checksum.to_string();
+                    if !is_local && context.destination(&package_id).is_none() {
+                        checkpoint.record(
+                            context.index_dir(package_realm),
+                            &package_id,
+                            &checksum,
+                        )?;
+                    }
+
+                    Ok((package_id, checksum, origin, package_timings))
+                };
+
+                let handle = pipeline.spawn(&runtime, download, extract);
                 handles.push(handle);
             }
         }
@@ -165,38 +763,128 @@ impl InstallationContext {
         let num_packages = handles.len();
 
         for handle in handles {
-            runtime
+            let (package_id, checksum, origin, package_timings) = runtime
                 .block_on(handle)
                 .expect("Package failed to be installed.")?;
+
+            timings.packages.insert(package_id.clone(), package_timings);
+            if let Some(origin) = origin {
+                origins.insert(package_id.clone(), origin);
+            }
+            installed.insert(package_id, checksum);
         }
 
         bar.finish_and_clear();
         log::info!("Downloaded {} packages!", num_packages);
 
-        Ok(())
+        let index_dirs = [
+            (Realm::Shared, &self.shared_index_dir),
+            (Realm::Server, &self.server_index_dir),
+            (Realm::Dev, &self.dev_index_dir),
+            (Realm::Build, &self.build_index_dir),
+        ];
+
+        for (realm, index_dir) in index_dirs {
+            // Packages with a destination aren't in any index, so they're
+            // always extracted again.
+            let packages: BTreeMap<PackageId, String> = installed
+                .iter()
+                .filter(|(package_id, _)| resolved.metadata[*package_id].origin_realm == realm)
+                .filter(|(package_id, _)| self.destination(package_id).is_none())
+                .map(|(package_id, checksum)| (package_id.clone(), checksum.clone()))
+                .collect();
+
+            if !packages.is_empty() {
+                InstallRecord { packages }.save(index_dir)?;
+            }
+        }
+
+        self.check_links(&root_package_id, &resolved)?;
+
+        installed.retain(|package_id, _| !is_local(package_id));
+
+        Ok(Installed {
+            checksums: installed,
+            sources: origins,
+            reused,
+            linked,
+            timings,
+        })
+    }
+
+    /// The module a package-to-package link within the same index requires.
+    fn link_sibling_same_index(&self, realm: Realm, id: &PackageId) -> String {
+        match self.anchored_index(realm) {
+            Some(index) => index_module(&index, id),
+            None => index_module("script.Parent.Parent", id),
+        }
+    }
+
+    /// The module a root-to-package link within the same index requires.
+    fn link_root_same_index(&self, realm: Realm, id: &PackageId) -> String {
+        match self.anchored_index(realm) {
+            Some(index) => index_module(&index, id),
+            None => index_module("script.Parent._Index", id),
+        }
     }
 
-    /// Contents of a package-to-package link within the same index.
-    fn link_sibling_same_index(&self, id: &PackageId) -> String {
-        formatdoc! {r#"
-            return require(script.Parent.Parent["{full_name}"]["{short_name}"])
-            "#,
-            full_name = package_id_file_name(id),
-            short_name = id.name().name()
+    /// The `_Index` folder of `realm` found through `game:GetService`, for the
+    /// `service` link style. Folders without a place fall back to relative
+    /// links.
+    fn anchored_index(&self, realm: Realm) -> Option<String> {
+        if self.link_style != LinkStyle::Service {
+            return None;
         }
+
+        let packages = match realm {
+            Realm::Shared => self.shared_path.as_ref()?,
+            Realm::Server => self.server_path.as_ref()?,
+            Realm::Dev | Realm::Build => return None,
+        };
+
+        Some(format!("{}._Index", self.place_path(packages)))
     }
 
-    /// Contents of a root-to-package link within the same index.
-    fn link_root_same_index(&self, id: &PackageId) -> String {
-        formatdoc! {r#"
-            return require(script.Parent._Index["{full_name}"]["{short_name}"])
-            "#,
-            full_name = package_id_file_name(id),
-            short_name = id.name().name()
+    /// A path from `[place]`, written the way the link style needs it.
+    fn place_path(&self, path: &str) -> String {
+        if self.link_style != LinkStyle::Service {
+            return path.to_owned();
+        }
+
+        match path.strip_prefix("game.") {
+            Some(rest) => match rest.split_once('.') {
+                Some((service, rest)) => format!("game:GetService(\"{}\").{}", service, rest),
+                None => format!("game:GetService(\"{}\")", rest),
+            },
+            None => path.to_owned(),
         }
     }
 
-    /// Contents of a link into the shared index from outside the shared index.
+    /// Contents of a link file that requires `module`, in the link style.
+    fn link_contents(&self, module: &str) -> anyhow::Result<String> {
+        if self.link_style != LinkStyle::Loader {
+            return Ok(format!("return require({})\n", module));
+        }
+
+        let loader = self.link_loader.as_ref().ok_or_else(|| {
+            format_err!(indoc! {r#"
+                The loader link style passes packages through a loader module,
+                but no loader module was given. Declare where it is in the
+                roblox datamodel in your wally.toml.
+
+                This typically looks like:
+
+                [place]
+                link-style = "loader"
+                link-loader = "game.ReplicatedStorage.WallyLoader"
+            "#})
+        })?;
+
+        Ok(format!("return require({})({})\n", loader, module))
+    }
+
+    /// The module a link into the shared index from outside the shared index
+    /// requires.
     fn link_shared_index(&self, id: &PackageId) -> anyhow::Result<String> {
         let shared_path = self.shared_path.as_ref().ok_or_else(|| {
             format_err!(indoc! {r#"
@@ -211,18 +899,12 @@ impl InstallationContext {
             "#})
         })?;
 
-        let contents = formatdoc! {r#"
-            return require({packages}._Index["{full_name}"]["{short_name}"])
-            "#,
-            packages = shared_path,
-            full_name = package_id_file_name(id),
-            short_name = id.name().name()
-        };
-
-        Ok(contents)
+        let index = format!("{}._Index", self.place_path(shared_path));
+        Ok(index_module(&index, id))
     }
 
-    /// Contents of a link into the server index from outside the server index.
+    /// The module a link into the server index from outside the server index
+    /// requires.
     fn link_server_index(&self, id: &PackageId) -> anyhow::Result<String> {
         let server_path = self.server_path.as_ref().ok_or_else(|| {
             format_err!(indoc! {r#"
@@ -237,15 +919,8 @@ impl InstallationContext {
             "#})
         })?;
 
-        let contents = formatdoc! {r#"
-            return require({packages}._Index["{full_name}"]["{short_name}"])
-            "#,
-            packages = server_path,
-            full_name = package_id_file_name(id),
-            short_name = id.name().name()
-        };
-
-        Ok(contents)
+        let index = format!("{}._Index", self.place_path(server_path));
+        Ok(index_module(&index, id))
     }
 
     fn write_root_package_links<'a, K: Display>(
@@ -256,28 +931,28 @@ impl InstallationContext {
     ) -> anyhow::Result<()> {
         log::debug!("Writing root package links");
 
-        let base_path = match root_realm {
-            Realm::Shared => &self.shared_dir,
-            Realm::Server => &self.server_dir,
-            Realm::Dev => &self.dev_dir,
-        };
+        // Packages with a destination are used from there instead of through a
+        // link.
+        let dependencies = dependencies
+            .into_iter()
+            .filter(|(_, dep_package_id)| self.destination(dep_package_id).is_none());
+        let links = self.links(
+            root_realm,
+            Self::link_root_same_index,
+            dependencies,
+            resolved,
+        )?;
+        if links.is_empty() {
+            return Ok(());
+        }
+
+        let base_path = self.root_dir(root_realm);
 
         log::trace!("Creating directory {}", base_path.display());
         fs::create_dir_all(base_path)?;
 
-        for (dep_name, dep_package_id) in dependencies {
-            let dependencies_realm = resolved.metadata.get(dep_package_id).unwrap().origin_realm;
-            let path = base_path.join(format!("{}.lua", dep_name));
-
-            let contents = match (root_realm, dependencies_realm) {
-                (source, dest) if source == dest => self.link_root_same_index(dep_package_id),
-                (_, Realm::Server) => self.link_server_index(dep_package_id)?,
-                (_, Realm::Shared) => self.link_shared_index(dep_package_id)?,
-                (_, Realm::Dev) => {
-                    bail!("A dev dependency cannot be depended upon by a non-dev dependency")
-                }
-            };
-
+        for (file_name, contents) in links {
+            let path = base_path.join(file_name);
             log::trace!("Writing {}", path.display());
             fs::write(path, contents)?;
         }
@@ -294,10 +969,23 @@ impl InstallationContext {
     ) -> anyhow::Result<()> {
         log::debug!("Writing package links for {}", package_id);
 
+        // Packages without dependencies need no links, and may not even live
+        // in an index.
+        let links = self.links(
+            package_realm,
+            Self::link_sibling_same_index,
+            dependencies,
+            resolved,
+        )?;
+        if links.is_empty() {
+            return Ok(());
+        }
+
         let mut base_path = match package_realm {
             Realm::Shared => self.shared_index_dir.clone(),
             Realm::Server => self.server_index_dir.clone(),
             Realm::Dev => self.dev_index_dir.clone(),
+            Realm::Build => self.build_index_dir.clone(),
         };
 
         base_path.push(package_id_file_name(package_id));
@@ -305,46 +993,567 @@ impl InstallationContext {
         log::trace!("Creating directory {}", base_path.display());
         fs::create_dir_all(&base_path)?;
 
-        for (dep_name, dep_package_id) in dependencies {
-            let dependencies_realm = resolved.metadata.get(dep_package_id).unwrap().origin_realm;
-            let path = base_path.join(format!("{}.lua", dep_name));
+        for (file_name, contents) in links {
+            let path = base_path.join(file_name);
+            log::trace!("Writing {}", path.display());
+            fs::write(path, contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// The file names and contents of the links to `dependencies` from a
+    /// package in `realm`, sorted by alias so that they're generated the same
+    /// way every time, whatever order the dependencies came in.
+    /// `link_same_index` picks the module that links to packages in the same
+    /// index require.
+    fn links<'a, K: Display>(
+        &self,
+        realm: Realm,
+        link_same_index: fn(&Self, Realm, &PackageId) -> String,
+        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        resolved: &Resolve,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let mut dependencies: Vec<_> = dependencies
+            .into_iter()
+            .map(|(dep_name, dep_package_id)| (dep_name.to_string(), dep_package_id))
+            .collect();
+        dependencies.sort();
+
+        dependencies
+            .into_iter()
+            .map(|(dep_name, dep_package_id)| -> anyhow::Result<_> {
+                let dependencies_realm = resolved.metadata[dep_package_id].origin_realm;
 
-            let contents = match (package_realm, dependencies_realm) {
-                (source, dest) if source == dest => self.link_sibling_same_index(dep_package_id),
-                (_, Realm::Server) => self.link_server_index(dep_package_id)?,
-                (_, Realm::Shared) => self.link_shared_index(dep_package_id)?,
-                (_, Realm::Dev) => {
-                    bail!("A dev dependency cannot be depended upon by a non-dev dependency")
+                let module = match (realm, dependencies_realm) {
+                    (source, dest) if source == dest => {
+                        link_same_index(self, realm, dep_package_id)
+                    }
+                    (_, Realm::Server) => self.link_server_index(dep_package_id)?,
+                    (_, Realm::Shared) => self.link_shared_index(dep_package_id)?,
+                    (_, Realm::Dev) => {
+                        bail!("A dev dependency cannot be depended upon by a non-dev dependency")
+                    }
+                    (_, Realm::Build) => {
+                        bail!(
+                            "A build dependency cannot be depended upon by a non-build dependency"
+                        )
+                    }
+                };
+
+                Ok((format!("{}.lua", dep_name), self.link_contents(&module)?))
+            })
+            .collect()
+    }
+
+    /// The folder that the root's links to its dependencies in `realm` are
+    /// written to.
+    fn root_dir(&self, realm: Realm) -> &PathBuf {
+        match realm {
+            Realm::Shared => &self.shared_dir,
+            Realm::Server => &self.server_dir,
+            Realm::Dev => &self.dev_dir,
+            Realm::Build => &self.build_dir,
+        }
+    }
+
+    /// Makes sure that every link written for `resolved` requires a package
+    /// that was actually extracted. A dangling link would otherwise only fail
+    /// once it's required in Roblox.
+    fn check_links(&self, root_package_id: &PackageId, resolved: &Resolve) -> anyhow::Result<()> {
+        let dependency_maps = [
+            (Realm::Shared, &resolved.shared_dependencies),
+            (Realm::Server, &resolved.server_dependencies),
+            (Realm::Dev, &resolved.dev_dependencies),
+            (Realm::Build, &resolved.build_dependencies),
+        ];
+
+        let mut dangling = Vec::new();
+
+        for (realm, graph) in dependency_maps {
+            for (package_id, dependencies) in graph {
+                let links_dir = if package_id == root_package_id {
+                    self.root_dir(realm).clone()
+                } else {
+                    let package_realm = resolved.metadata[package_id].origin_realm;
+                    self.index_dir(package_realm)
+                        .join(package_id_file_name(package_id))
+                };
+
+                for (alias, dependency) in dependencies {
+                    // Packages with a destination are used from there, and
+                    // nothing links to them.
+                    if self.destination(dependency).is_some() {
+                        continue;
+                    }
+
+                    let target =
+                        self.contents_dir(dependency, resolved.metadata[dependency].origin_realm);
+                    if !is_extracted(&target) {
+                        dangling.push(format!(
+                            "{} links to {}, but {} doesn't exist",
+                            links_dir.join(format!("{}.lua", alias)).display(),
+                            dependency,
+                            target.display()
+                        ));
+                    }
                 }
-            };
+            }
+        }
 
-            log::trace!("Writing {}", path.display());
-            fs::write(path, contents)?;
+        if !dangling.is_empty() {
+            bail!(
+                "Some package links point at modules that weren't installed:\n{}",
+                dangling.join("\n")
+            );
         }
 
         Ok(())
     }
 
+    fn index_dir(&self, realm: Realm) -> &PathBuf {
+        match realm {
+            Realm::Shared => &self.shared_index_dir,
+            Realm::Server => &self.server_index_dir,
+            Realm::Dev => &self.dev_index_dir,
+            Realm::Build => &self.build_index_dir,
+        }
+    }
+
+    /// The folder that a package's contents are installed into.
+    fn contents_dir(&self, package_id: &PackageId, realm: Realm) -> PathBuf {
+        if let Some(dest) = self.destination(package_id) {
+            return dest;
+        }
+
+        let mut path = self.index_dir(realm).clone();
+        path.push(package_id_file_name(package_id));
+        path.push(package_id.name().name());
+        path
+    }
+
+    /// Creates the folder that this install extracts packages into.
+    fn staging(&self) -> anyhow::Result<Staging> {
+        let parent = self.temp_dir.as_ref().unwrap_or(&self.project_path);
+
+        let create = || -> anyhow::Result<TempDir> {
+            fs::create_dir_all(parent)?;
+            Ok(tempfile::Builder::new()
+                .prefix(".wally-staging-")
+                .tempdir_in(parent)?)
+        };
+        let dir = create()
+            .with_context(|| format!("Could not stage packages in {}", parent.display()))?;
+
+        let same_filesystem = same_filesystem(dir.path(), &self.project_path)?;
+        if !same_filesystem {
+            warnings::warn(format!(
+                "{} is on a different filesystem than {}, so packages are copied into \
+                 place instead of being moved there at once",
+                parent.display(),
+                self.project_path.display()
+            ));
+        }
+
+        log::debug!("Staging packages in {}", dir.path().display());
+        Ok(Staging {
+            dir,
+            same_filesystem,
+        })
+    }
+
+    /// Extracts a package into the staging folder, then moves it into place,
+    /// so that its folder never holds only part of it.
     fn write_contents(
         &self,
         package_id: &PackageId,
         contents: &PackageContents,
         realm: Realm,
+        staging: &Staging,
     ) -> anyhow::Result<()> {
-        let mut path = match realm {
-            Realm::Shared => self.shared_index_dir.clone(),
-            Realm::Server => self.server_index_dir.clone(),
-            Realm::Dev => self.dev_index_dir.clone(),
-        };
+        let path = self.contents_dir(package_id, realm);
 
-        path.push(package_id_file_name(package_id));
-        path.push(package_id.name().name());
+        let staged = staging.dir.path().join(package_id_file_name(package_id));
+        fs::create_dir_all(&staged)?;
+        let written = contents.extract_to(&staged)?;
+        log::trace!(
+            "Extracted {} paths into {}",
+            written.len(),
+            staged.display()
+        );
 
-        fs::create_dir_all(&path)?;
-        contents.unpack_into_path(&path)?;
+        clear_destination(&path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if staging.same_filesystem {
+            fs::rename(&staged, &path)?;
+        } else {
+            copy_dir(&staged, &path)?;
+            fs::remove_dir_all(&staged)?;
+        }
 
         Ok(())
     }
+
+    /// Installs a path dependency as a symlink to the folder it lives in.
+    /// Removing the package folders later only removes the link, never the
+    /// folder it points to.
+    fn write_symlink(
+        &self,
+        package_id: &PackageId,
+        source: &Path,
+        realm: Realm,
+    ) -> anyhow::Result<()> {
+        let path = self.contents_dir(package_id, realm);
+        let source = fs::canonicalize(source)?;
+
+        if self.destination(package_id).is_some() {
+            clear_destination(&path)?;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        log::trace!("Linking {} to {}", path.display(), source.display());
+        symlink_dir(&source, &path).with_context(|| {
+            format!("could not link {} to {}", path.display(), source.display())
+        })?;
+
+        Ok(())
+    }
+
+    /// Where a package is installed if it has a custom destination.
+    fn destination(&self, package_id: &PackageId) -> Option<PathBuf> {
+        self.destinations
+            .get(package_id.name())
+            .map(|dest| self.project_path.join(dest))
+    }
+
+    /// Makes sure that every registry a package will be downloaded from can
+    /// look up its index entry, when manifests are being verified, so that
+    /// the install fails before anything is downloaded instead of partway
+    /// through.
+    fn check_manifest_verification(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+        sources: &PackageSourceMap,
+    ) -> anyhow::Result<()> {
+        if !self.verify_manifests {
+            return Ok(());
+        }
+
+        for (package_id, metadata) in &resolved.metadata {
+            // Path dependencies are never checked against an index.
+            let is_local = matches!(metadata.source_registry, PackageSourceId::Local(_));
+            if package_id == root_package_id || is_local {
+                continue;
+            }
+
+            let supports_get = sources
+                .get(&metadata.source_registry)
+                .map_or(true, |source| source.capabilities().supports_get);
+            if !supports_get {
+                bail!(
+                    "The registry {} comes from doesn't support looking up a single version of \
+                     a package, which --verify-manifests needs to check its index entry",
+                    package_id
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure that the packages with a custom destination can be installed
+    /// there: they can't be linked to or from other packages, and their
+    /// destination can't overlap a path dependency, since it's replaced on
+    /// every install.
+    fn check_destinations(
+        &self,
+        root_package_id: &PackageId,
+        resolved: &Resolve,
+    ) -> anyhow::Result<()> {
+        let dependency_maps = [
+            &resolved.shared_dependencies,
+            &resolved.server_dependencies,
+            &resolved.dev_dependencies,
+            &resolved.build_dependencies,
+        ];
+
+        for package_id in &resolved.activated {
+            let dest = match self.destination(package_id) {
+                Some(dest) => dest,
+                None => continue,
+            };
+
+            let has_dependencies = dependency_maps.iter().any(|dependencies| {
+                dependencies
+                    .get(package_id)
+                    .map_or(false, |dependencies| !dependencies.is_empty())
+            });
+            if has_dependencies {
+                bail!(
+                    "{} can't be installed into {} because it has dependencies of its own",
+                    package_id,
+                    dest.display()
+                );
+            }
+
+            for graph in &dependency_maps {
+                for (dependent, dependencies) in graph.iter() {
+                    if dependent != root_package_id
+                        && dependencies
+                            .values()
+                            .any(|dependency| dependency == package_id)
+                    {
+                        bail!(
+                            "{} can't be installed into {} because {} depends on it",
+                            package_id,
+                            dest.display(),
+                            dependent
+                        );
+                    }
+                }
+            }
+        }
+
+        let project_path = fs::canonicalize(&self.project_path)?;
+
+        for metadata in resolved.metadata.values() {
+            let source = match &metadata.source_registry {
+                PackageSourceId::Local(path) => fs::canonicalize(path)?,
+                _ => continue,
+            };
+
+            for (name, dest) in &self.destinations {
+                let dest = project_path.join(dest);
+
+                if dest.starts_with(&source) || source.starts_with(&dest) {
+                    bail!(
+                        "{} can't be installed into {} because it overlaps the path dependency at {}",
+                        name,
+                        dest.display(),
+                        source.display()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a package's contents are at `path`. An empty folder is what's left
+/// when extracting it went wrong, so it doesn't count.
+fn is_extracted(path: &Path) -> bool {
+    fs::read_dir(path)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false)
+}
+
+/// The folder an install extracts packages into, which is removed along with
+/// anything left in it once the install is done.
+struct Staging {
+    dir: TempDir,
+
+    /// Whether packages can be renamed into place. Otherwise they have to be
+    /// copied.
+    same_filesystem: bool,
+}
+
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs::metadata(a)?.dev() == fs::metadata(b)?.dev())
+}
+
+#[cfg(windows)]
+fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    let a = fs::canonicalize(a)?;
+    let b = fs::canonicalize(b)?;
+
+    Ok(a.components().next() == b.components().next())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn clear_destination(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+/// Name of the file in each `_Index` folder that records which packages were
+/// installed into it.
+const INSTALL_RECORD_NAME: &str = ".wally-installed";
+
+/// The packages installed into an `_Index` folder, along with the checksums of
+/// their contents.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct InstallRecord {
+    #[serde(default)]
+    packages: BTreeMap<PackageId, String>,
+}
+
+impl InstallRecord {
+    fn load(index_dir: &Path) -> anyhow::Result<Self> {
+        let path = index_dir.join(INSTALL_RECORD_NAME);
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, index_dir: &Path) -> anyhow::Result<()> {
+        let path = index_dir.join(INSTALL_RECORD_NAME);
+        let contents = format!(
+            "# This file is automatically @generated by Wally.\n{}",
+            toml::to_string(self)?
+        );
+
+        log::trace!("Writing {}", path.display());
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+}
+
+/// The install records of every `_Index` folder being installed into, which
+/// are saved each time a package finishes extracting. An install that's
+/// interrupted leaves behind a record of the packages it finished, so they
+/// aren't downloaded and extracted again by the next install.
+#[derive(Clone)]
+struct Checkpoint {
+    records: Arc<Mutex<BTreeMap<PathBuf, InstallRecord>>>,
+}
+
+impl Checkpoint {
+    /// Starts from the packages that are already installed into each folder.
+    fn new(records: BTreeMap<PathBuf, InstallRecord>) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(records)),
+        }
+    }
+
+    fn record(
+        &self,
+        index_dir: &Path,
+        package_id: &PackageId,
+        checksum: &str,
+    ) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        let record = records.entry(index_dir.to_owned()).or_default();
+
+        record
+            .packages
+            .insert(package_id.clone(), checksum.to_owned());
+        record.save(index_dir)
+    }
+}
+
+fn remove_entry(entry: &fs::DirEntry) -> io::Result<()> {
+    if entry.file_type()?.is_dir() {
+        fs::remove_dir_all(entry.path())
+    } else {
+        fs::remove_file(entry.path())
+    }
+}
+
+fn remove_dir_if_empty(path: &Path) -> io::Result<()> {
+    if path.is_dir() && fs::read_dir(path)?.next().is_none() {
+        fs::remove_dir(path)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the package links in a folder, keyed by alias. Links that don't
+/// point at a package are reported in `problems`.
+fn read_links(dir: &Path, problems: &mut Vec<String>) -> anyhow::Result<Vec<(String, PackageId)>> {
+    let mut links = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        let alias = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(alias) if path.extension() == Some("lua".as_ref()) => alias.to_owned(),
+            _ => continue,
+        };
+
+        match link_target(&fs::read_to_string(&path)?) {
+            Some(package_id) => links.push((alias, package_id)),
+            None => problems.push(format!("{} isn't a link to a package", path.display())),
+        }
+    }
+
+    links.sort();
+    Ok(links)
+}
+
+/// The package a link requires. Links end in
+/// `["<package_id_file_name>"]["<name>"]`, whatever comes before that.
+fn link_target(contents: &str) -> Option<PackageId> {
+    let keys: Vec<&str> = contents
+        .split("[\"")
+        .skip(1)
+        .filter_map(|key| key.split("\"]").next())
+        .collect();
+
+    let full_name = keys.get(keys.len().checked_sub(2)?)?;
+    parse_package_id_file_name(full_name)
+}
+
+/// The inverse of `package_id_file_name`. Scopes and names can't contain
+/// underscores, so the first one always splits them.
+fn parse_package_id_file_name(file_name: &str) -> Option<PackageId> {
+    let (full_name, version) = file_name.split_once('@')?;
+    let (scope, name) = full_name.split_once('_')?;
+
+    format!("{}/{}@{}", scope, name, version).parse().ok()
+}
+
+/// The module of a package installed into the `_Index` folder at `index`.
+fn index_module(index: &str, id: &PackageId) -> String {
+    format!(
+        "{}[\"{}\"][\"{}\"]",
+        index,
+        package_id_file_name(id),
+        id.name().name()
+    )
 }
 
 /// Creates a suitable name for use in file paths that refer to this package.
@@ -356,3 +1565,526 @@ fn package_id_file_name(id: &PackageId) -> String {
         id.version()
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::integrity::LockfileStore;
+    use crate::lockfile::Lockfile;
+    use crate::package_source::InMemoryRegistry;
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    fn sources(packages: &[&str]) -> PackageSourceMap {
+        let registry = InMemoryRegistry::new();
+        for package in packages {
+            registry.publish(PackageBuilder::new(package).with_file("init.lua", "return nil"));
+        }
+
+        PackageSourceMap::new(Box::new(registry.source()))
+    }
+
+    #[test]
+    fn cached_packages_are_not_downloaded_again() {
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_file("init.lua", "return nil"));
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let package_cache = PackageCache::new(cache_dir.path().to_owned());
+
+        let first = tempfile::tempdir().unwrap();
+        let installed = InstallationContext::new(first.path(), None, None)
+            .with_package_cache(Some(package_cache.clone()))
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+        assert_eq!(registry.download_count(), 1);
+
+        // Another project pinning the same contents gets them from the cache.
+        let second = tempfile::tempdir().unwrap();
+        InstallationContext::new(second.path(), None, None)
+            .with_package_cache(Some(package_cache))
+            .install(
+                sources,
+                root_id,
+                resolved,
+                Arc::new(LockfileStore::new(&installed.checksums).unwrap()),
+            )
+            .unwrap();
+        assert_eq!(registry.download_count(), 1);
+        assert_eq!(
+            fs::read_to_string(
+                second
+                    .path()
+                    .join("Packages/_Index/biff_a@1.0.0/a/init.lua")
+            )
+            .unwrap(),
+            "return nil"
+        );
+    }
+
+    #[test]
+    fn deduplicated_cache_entries_keep_their_checksum() {
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_file("init.lua", "return nil"));
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let package_cache = PackageCache::new(cache_dir.path().to_owned()).with_dedupe(true);
+
+        let first = tempfile::tempdir().unwrap();
+        let installed = InstallationContext::new(first.path(), None, None)
+            .with_package_cache(Some(package_cache.clone()))
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+
+        // The cache puts the package back together as a different zip, but
+        // the lockfile should keep the checksum it pinned.
+        let second = tempfile::tempdir().unwrap();
+        let reinstalled = InstallationContext::new(second.path(), None, None)
+            .with_package_cache(Some(package_cache))
+            .install(
+                sources,
+                root_id,
+                resolved,
+                Arc::new(LockfileStore::new(&installed.checksums).unwrap()),
+            )
+            .unwrap();
+        assert_eq!(registry.download_count(), 1);
+        assert_eq!(reinstalled.checksums, installed.checksums);
+    }
+
+    #[test]
+    fn corrupted_cache_entries_are_downloaded_again() {
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_file("init.lua", "return nil"));
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+
+        // Every entry is due to be checked again as soon as it's cached.
+        let cache_dir = tempfile::tempdir().unwrap();
+        let package_cache = PackageCache::new(cache_dir.path().to_owned())
+            .with_revalidate_after(Some(Duration::ZERO));
+
+        let first = tempfile::tempdir().unwrap();
+        let installed = InstallationContext::new(first.path(), None, None)
+            .with_package_cache(Some(package_cache.clone()))
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+
+        for zip in fs::read_dir(cache_dir.path().join("zips")).unwrap() {
+            fs::write(zip.unwrap().path(), "corrupted").unwrap();
+        }
+
+        let second = tempfile::tempdir().unwrap();
+        InstallationContext::new(second.path(), None, None)
+            .with_package_cache(Some(package_cache.clone()))
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::new(&installed.checksums).unwrap()),
+            )
+            .unwrap();
+        assert_eq!(registry.download_count(), 2);
+        assert_eq!(
+            fs::read_to_string(
+                second
+                    .path()
+                    .join("Packages/_Index/biff_a@1.0.0/a/init.lua")
+            )
+            .unwrap(),
+            "return nil"
+        );
+
+        // The download replaced the corrupted entry.
+        let third = tempfile::tempdir().unwrap();
+        InstallationContext::new(third.path(), None, None)
+            .with_package_cache(Some(package_cache))
+            .install(
+                sources,
+                root_id,
+                resolved,
+                Arc::new(LockfileStore::new(&installed.checksums).unwrap()),
+            )
+            .unwrap();
+        assert_eq!(registry.download_count(), 2);
+    }
+
+    #[test]
+    fn manifest_verification_needs_registries_with_get() {
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+
+        // The in-memory registry only finds versions by querying for them.
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0"));
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+
+        let project = tempfile::tempdir().unwrap();
+        let err = InstallationContext::new(project.path(), None, None)
+            .with_manifest_verification(true)
+            .install(
+                sources,
+                root_id,
+                resolved,
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap_err();
+
+        assert!(err.to_string().contains("doesn't support looking up"));
+        assert_eq!(registry.download_count(), 0);
+    }
+
+    #[test]
+    fn links_are_sorted_by_alias() {
+        let all = [
+            "biff/a@1.0.0",
+            "biff/b@1.0.0",
+            "biff/c@1.0.0",
+            "biff/d@1.0.0",
+        ];
+        let mut root = PackageBuilder::new("biff/root@1.0.0");
+        for (alias, package) in ["Delta", "alpha", "Charlie", "Bravo"].iter().zip(&all) {
+            root = root.with_dep(*alias, *package);
+        }
+        let resolved = resolve(root.manifest(), &Default::default(), &sources(&all)).unwrap();
+        let dependencies = &resolved.shared_dependencies[&root.manifest().package_id()];
+
+        let project = tempfile::tempdir().unwrap();
+        let context = InstallationContext::new(project.path(), None, None);
+
+        // A HashMap iterates in a different order each time it's built.
+        let links = || {
+            let dependencies: HashMap<_, _> = dependencies.iter().collect();
+            context
+                .links(
+                    Realm::Shared,
+                    InstallationContext::link_root_same_index,
+                    dependencies,
+                    &resolved,
+                )
+                .unwrap()
+        };
+
+        let first = links();
+        let names: Vec<_> = first.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Bravo.lua", "Charlie.lua", "Delta.lua", "alpha.lua"]
+        );
+        assert_eq!(
+            first[0].1,
+            "return require(script.Parent._Index[\"biff_b@1.0.0\"][\"b\"])\n"
+        );
+
+        for _ in 0..10 {
+            assert_eq!(links(), first);
+        }
+    }
+
+    #[test]
+    fn link_styles() {
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let resolved = resolve(
+            root.manifest(),
+            &Default::default(),
+            &sources(&["biff/a@1.0.0"]),
+        )
+        .unwrap();
+        let dependencies = &resolved.shared_dependencies[&root.manifest().package_id()];
+
+        let project = tempfile::tempdir().unwrap();
+        let styles = [
+            ("relative", LinkStyle::Relative),
+            ("service", LinkStyle::Service),
+            ("loader", LinkStyle::Loader),
+        ];
+
+        for (name, link_style) in styles {
+            let context = InstallationContext::new(
+                project.path(),
+                Some("game.ReplicatedStorage.Packages".to_owned()),
+                None,
+            )
+            .with_link_style(
+                link_style,
+                Some("game.ReplicatedStorage.WallyLoader".to_owned()),
+            );
+
+            // The root's links and another package's links to the same
+            // dependency.
+            let root_links = context
+                .links(
+                    Realm::Shared,
+                    InstallationContext::link_root_same_index,
+                    dependencies,
+                    &resolved,
+                )
+                .unwrap();
+            let package_links = context
+                .links(
+                    Realm::Shared,
+                    InstallationContext::link_sibling_same_index,
+                    dependencies,
+                    &resolved,
+                )
+                .unwrap();
+
+            let output = format!(
+                "-- Packages/{}\n{}-- Packages/_Index/biff_root@1.0.0/{}\n{}",
+                root_links[0].0, root_links[0].1, package_links[0].0, package_links[0].1
+            );
+            insta::assert_snapshot!(format!("link_style_{}", name), output);
+        }
+    }
+
+    #[test]
+    fn upgrade_removes_orphaned_dependency() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_dep("B", "biff/b@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/a@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0"));
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+
+        let project = tempfile::tempdir().unwrap();
+        let context = InstallationContext::new(project.path(), None, None);
+        let shared_index = project.path().join("Packages/_Index");
+
+        // The lockfile pins biff/a to 1.0.0, which depends on biff/b.
+        let pinned: BTreeSet<PackageId> = ["biff/a@1.0.0", "biff/b@1.0.0"]
+            .iter()
+            .map(|package_id| package_id.parse().unwrap())
+            .collect();
+        let resolved = resolve(root.manifest(), &pinned, &sources).unwrap();
+        context
+            .clone()
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved,
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+        assert!(shared_index.join("biff_b@1.0.0").is_dir());
+
+        // Updating moves biff/a to 1.1.0, which doesn't need biff/b anymore.
+        let upgraded = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+        let lockfile = Lockfile::from_resolve(&upgraded, "test");
+        let locked: Vec<String> = lockfile.as_ids().map(|id| id.to_string()).collect();
+        assert_eq!(locked, vec!["biff/a@1.1.0", "biff/root@1.0.0"]);
+
+        let installed = context
+            .install(
+                sources,
+                root_id,
+                upgraded,
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+
+        let installed: Vec<String> = installed
+            .checksums
+            .keys()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(installed, vec!["biff/a@1.1.0"]);
+        assert!(shared_index.join("biff_a@1.1.0").is_dir());
+        assert!(!shared_index.join("biff_a@1.0.0").exists());
+        assert!(!shared_index.join("biff_b@1.0.0").exists());
+
+        let record = InstallRecord::load(&shared_index).unwrap();
+        let recorded: Vec<String> = record.packages.keys().map(ToString::to_string).collect();
+        assert_eq!(recorded, vec!["biff/a@1.1.0"]);
+    }
+
+    #[test]
+    fn dangling_links_are_reported() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_dep("B", "biff/b@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_file("init.lua", "return nil"));
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+        let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+
+        let project = tempfile::tempdir().unwrap();
+        let context = InstallationContext::new(project.path(), None, None);
+        context
+            .clone()
+            .install(
+                sources,
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+        context.check_links(&root_id, &resolved).unwrap();
+
+        // Extracting biff/b went wrong and left its folder empty.
+        let index = project.path().join("Packages/_Index");
+        let contents = index.join("biff_b@1.0.0/b");
+        fs::remove_dir_all(&contents).unwrap();
+        fs::create_dir(&contents).unwrap();
+
+        let err = context.check_links(&root_id, &resolved).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Some package links point at modules that weren't installed:\n\
+                 {} links to biff/b@1.0.0, but {} doesn't exist",
+                index.join("biff_a@1.0.0/B.lua").display(),
+                contents.display()
+            )
+        );
+    }
+
+    #[test]
+    fn packages_are_staged_in_temp_dir() {
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("A", "biff/a@1.0.0");
+        let root_id = root.manifest().package_id();
+        let sources = sources(&["biff/a@1.0.0"]);
+        let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+
+        let project = tempfile::tempdir().unwrap();
+        let temp_dir = project.path().join("staging");
+
+        // Nothing can be staged in a file, so the install has to stop there.
+        fs::write(&temp_dir, "").unwrap();
+        let err = InstallationContext::new(project.path(), None, None)
+            .with_temp_dir(Some(temp_dir.clone()))
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Could not stage packages in {}", temp_dir.display())
+        );
+        assert!(!project.path().join("Packages/_Index/biff_a@1.0.0").exists());
+
+        fs::remove_file(&temp_dir).unwrap();
+        InstallationContext::new(project.path(), None, None)
+            .with_temp_dir(Some(temp_dir.clone()))
+            .install(
+                sources.clone(),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+
+        let contents = project.path().join("Packages/_Index/biff_a@1.0.0/a");
+        assert_eq!(
+            fs::read_to_string(contents.join("init.lua")).unwrap(),
+            "return nil"
+        );
+
+        // The staging folder is cleaned up, and was the only thing made in
+        // the temp dir or the project.
+        assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+        let mut project_entries: Vec<_> = fs::read_dir(project.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        project_entries.sort();
+        assert_eq!(project_entries, vec!["Packages", "staging"]);
+
+        // Installing again has nothing to extract, so nothing is staged, even
+        // where nothing could be.
+        fs::remove_dir(&temp_dir).unwrap();
+        fs::write(&temp_dir, "").unwrap();
+        InstallationContext::new(project.path(), None, None)
+            .with_temp_dir(Some(temp_dir.clone()))
+            .install(
+                sources,
+                root_id,
+                resolved,
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn resume_interrupted_install() {
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("Z", "biff/z@1.0.0");
+        let root_id = root.manifest().package_id();
+
+        let all = ["biff/a@1.0.0", "biff/b@1.0.0", "biff/z@1.0.0"];
+        let resolved = resolve(root.manifest(), &Default::default(), &sources(&all)).unwrap();
+
+        let project = tempfile::tempdir().unwrap();
+        let context = InstallationContext::new(project.path(), None, None);
+
+        // The first install is cut short when biff/z can't be downloaded,
+        // after biff/a and biff/b were extracted.
+        let err = context
+            .clone()
+            .install(
+                sources(&["biff/a@1.0.0", "biff/b@1.0.0"]),
+                root_id.clone(),
+                resolved.clone(),
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Package biff/z@1.0.0 does not exist");
+
+        // Only biff/z is left to download, so the rerun succeeds even though
+        // nothing else can be downloaded anymore.
+        let installed = context
+            .install(
+                sources(&["biff/z@1.0.0"]),
+                root_id,
+                resolved,
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap();
+
+        let reused: Vec<String> = installed.reused.iter().map(ToString::to_string).collect();
+        assert_eq!(reused, vec!["biff/a@1.0.0", "biff/b@1.0.0"]);
+        assert_eq!(installed.checksums.len(), 3);
+
+        let shared_index = project.path().join("Packages/_Index");
+        assert!(shared_index.join("biff_z@1.0.0/z/init.lua").is_file());
+    }
+}
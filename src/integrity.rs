@@ -0,0 +1,262 @@
+//! Where the checksums of installed packages are kept and checked. Wally keeps
+//! them in the lockfile, but deployments can plug in their own store, like a
+//! remote attestation service, by implementing `IntegrityStore`.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context};
+
+use crate::checksum::Checksum;
+use crate::package_id::PackageId;
+use crate::warnings;
+
+pub trait IntegrityStore: Send + Sync {
+    /// The checksum recorded for a package, if there is one.
+    fn recorded(&self, package_id: &PackageId) -> anyhow::Result<Option<Checksum>>;
+
+    /// Records the checksum of a package that was just installed.
+    fn record(&self, package_id: &PackageId, checksum: &Checksum) -> anyhow::Result<()>;
+
+    /// Checks a package's downloaded contents against its recorded checksum,
+    /// using whichever algorithm that checksum was made with. Packages that
+    /// have nothing recorded yet pass.
+    fn verify(&self, package_id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
+        if let Some(expected) = self.recorded(package_id)? {
+            if !expected.matches(contents) {
+                bail!(
+                    "The contents of {} do not match the recorded checksum (expected {}, got {})",
+                    package_id,
+                    expected,
+                    Checksum::compute_with(expected.algorithm(), contents)
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The default store, which starts out with the checksums pinned in the
+/// lockfile. Newly recorded checksums are kept in memory until the install
+/// writes them back to the lockfile.
+#[derive(Debug, Default)]
+pub struct LockfileStore {
+    checksums: Mutex<BTreeMap<PackageId, Checksum>>,
+}
+
+impl LockfileStore {
+    pub fn new(checksums: &BTreeMap<PackageId, String>) -> anyhow::Result<Self> {
+        let checksums = checksums
+            .iter()
+            .map(|(package_id, checksum)| {
+                let checksum = checksum.parse().with_context(|| {
+                    format!("The lockfile has an invalid checksum for {}", package_id)
+                })?;
+
+                Ok((package_id.clone(), checksum))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            checksums: Mutex::new(checksums),
+        })
+    }
+}
+
+impl IntegrityStore for LockfileStore {
+    fn recorded(&self, package_id: &PackageId) -> anyhow::Result<Option<Checksum>> {
+        Ok(self.checksums.lock().unwrap().get(package_id).cloned())
+    }
+
+    fn record(&self, package_id: &PackageId, checksum: &Checksum) -> anyhow::Result<()> {
+        self.checksums
+            .lock()
+            .unwrap()
+            .insert(package_id.clone(), checksum.clone());
+
+        Ok(())
+    }
+}
+
+/// Wraps another store so that packages pinned to a checksum in the manifest
+/// must have exactly that checksum, whatever the wrapped store recorded. This
+/// holds even if the registry sends different contents for the same version.
+/// Other versions of a pinned package are checked by the wrapped store.
+pub struct PinnedStore<S> {
+    store: S,
+    pins: BTreeMap<PackageId, Checksum>,
+}
+
+impl<S: IntegrityStore> PinnedStore<S> {
+    pub fn new(store: S, pins: BTreeMap<PackageId, Checksum>) -> Self {
+        Self { store, pins }
+    }
+}
+
+impl<S: IntegrityStore> IntegrityStore for PinnedStore<S> {
+    fn recorded(&self, package_id: &PackageId) -> anyhow::Result<Option<Checksum>> {
+        match self.pins.get(package_id) {
+            Some(pinned) => Ok(Some(pinned.clone())),
+            None => self.store.recorded(package_id),
+        }
+    }
+
+    fn record(&self, package_id: &PackageId, checksum: &Checksum) -> anyhow::Result<()> {
+        self.store.record(package_id, checksum)
+    }
+
+    fn verify(&self, package_id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
+        match self.pins.get(package_id) {
+            Some(pinned) if !pinned.matches(contents) => bail!(
+                "The contents of {} do not match the checksum pinned in the manifest \
+                 (expected {}, got {})",
+                package_id,
+                pinned,
+                Checksum::compute_with(pinned.algorithm(), contents)
+            ),
+            Some(_) => Ok(()),
+            None => self.store.verify(package_id, contents),
+        }
+    }
+}
+
+/// Wraps another store so that contents that don't match their recorded
+/// checksum are installed anyway, with a warning, for inspecting what a
+/// registry actually sent while debugging a mismatch.
+pub struct UnverifiedStore<S> {
+    store: S,
+}
+
+impl<S: IntegrityStore> UnverifiedStore<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+}
+
+impl<S: IntegrityStore> IntegrityStore for UnverifiedStore<S> {
+    fn recorded(&self, package_id: &PackageId) -> anyhow::Result<Option<Checksum>> {
+        self.store.recorded(package_id)
+    }
+
+    fn record(&self, package_id: &PackageId, checksum: &Checksum) -> anyhow::Result<()> {
+        self.store.record(package_id, checksum)
+    }
+
+    fn verify(&self, package_id: &PackageId, contents: &[u8]) -> anyhow::Result<()> {
+        if let Err(err) = self.store.verify(package_id, contents) {
+            warnings::warn(format!(
+                "{}. It was installed anyway because of --no-verify",
+                err
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A store that only uses the default `verify`, like a custom backend
+    /// would.
+    #[derive(Default)]
+    struct InMemoryStore {
+        checksums: Mutex<BTreeMap<PackageId, Checksum>>,
+    }
+
+    impl IntegrityStore for InMemoryStore {
+        fn recorded(&self, package_id: &PackageId) -> anyhow::Result<Option<Checksum>> {
+            Ok(self.checksums.lock().unwrap().get(package_id).cloned())
+        }
+
+        fn record(&self, package_id: &PackageId, checksum: &Checksum) -> anyhow::Result<()> {
+            self.checksums
+                .lock()
+                .unwrap()
+                .insert(package_id.clone(), checksum.clone());
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_and_verify() {
+        let store = InMemoryStore::default();
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        // Nothing is recorded yet, so anything passes.
+        store.verify(&package_id, b"return nil").unwrap();
+
+        store
+            .record(&package_id, &Checksum::compute(b"return \"hey\""))
+            .unwrap();
+        assert_eq!(
+            store.recorded(&package_id).unwrap(),
+            Some(Checksum::compute(b"return \"hey\""))
+        );
+        store.verify(&package_id, b"return \"hey\"").unwrap();
+
+        let err = store.verify(&package_id, b"return nil").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The contents of biff/minimal@0.1.0 do not match the recorded checksum \
+                 (expected {}, got {})",
+                Checksum::compute(b"return \"hey\""),
+                Checksum::compute(b"return nil")
+            )
+        );
+    }
+
+    #[test]
+    fn lockfile_store_rejects_invalid_checksums() {
+        let mut checksums = BTreeMap::new();
+        checksums.insert("biff/minimal@0.1.0".parse().unwrap(), "md5:abc".to_owned());
+
+        let err = LockfileStore::new(&checksums).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The lockfile has an invalid checksum for biff/minimal@0.1.0"
+        );
+    }
+
+    #[test]
+    fn pinned_checksums_override_recorded_ones() {
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        // The lockfile agrees with the registry, but the manifest doesn't.
+        let mut checksums = BTreeMap::new();
+        checksums.insert(
+            package_id.clone(),
+            Checksum::compute(b"return nil").to_string(),
+        );
+        let mut pins = BTreeMap::new();
+        pins.insert(package_id.clone(), Checksum::compute(b"return \"hey\""));
+
+        let store = PinnedStore::new(LockfileStore::new(&checksums).unwrap(), pins);
+
+        store.verify(&package_id, b"return \"hey\"").unwrap();
+
+        let err = store.verify(&package_id, b"return nil").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The contents of biff/minimal@0.1.0 do not match the checksum pinned in the \
+                 manifest (expected {}, got {})",
+                Checksum::compute(b"return \"hey\""),
+                Checksum::compute(b"return nil")
+            )
+        );
+
+        // Packages that aren't pinned are checked against the lockfile, even
+        // other versions of a pinned package.
+        let other: PackageId = "biff/other@1.0.0".parse().unwrap();
+        store.verify(&other, b"anything").unwrap();
+
+        let other_version: PackageId = "biff/minimal@0.2.0".parse().unwrap();
+        assert_eq!(store.recorded(&other_version).unwrap(), None);
+        store.verify(&other_version, b"anything").unwrap();
+    }
+}
@@ -1,16 +1,38 @@
+pub mod audit;
 pub mod auth;
+pub mod checksum;
 pub mod commands;
 pub mod git_util;
+pub mod graph;
+pub mod http_config;
+pub mod ingest;
 pub mod installation;
+pub mod integrity;
+pub mod license_policy;
 pub mod lockfile;
+pub mod lockfile_diff;
+pub mod lockfile_merge;
 pub mod manifest;
+pub mod mirror;
+pub mod package_cache;
 pub mod package_contents;
 pub mod package_id;
 pub mod package_index;
+pub mod package_info;
 pub mod package_name;
 pub mod package_req;
 pub mod package_source;
+pub mod pipeline;
+pub mod preflight;
+pub mod report;
 pub mod resolution;
+pub mod resolution_cache;
+pub mod resolution_conflict;
+pub mod resolution_trace;
+pub mod retry;
+pub mod sbom;
 pub mod test_package;
+pub mod validation;
+pub mod warnings;
 
 pub use commands::*;
@@ -0,0 +1,278 @@
+//! Checks the licenses of every package in a project's dependency graph
+//! against the policy in the project's manifest, so that an organization can
+//! keep licenses it forbids out of what it ships.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSourceMap, PackageSourceProvider};
+use crate::resolution::Resolve;
+use crate::warnings;
+
+/// The `[license-policy]` table of a manifest, which looks like:
+///
+/// ```toml
+/// [license-policy]
+/// allow = ["MIT", "Apache-2.0"]
+/// deny = ["GPL-3.0"]
+/// unlicensed = "deny"
+/// ```
+///
+/// Licenses are SPDX identifiers and are compared without regard to case. A
+/// package whose license is an expression like `MIT OR Apache-2.0` must be
+/// fine with every license it names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LicensePolicy {
+    /// If given, only these licenses are allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+
+    /// Licenses that are never allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+
+    /// What to do about packages that don't declare a license.
+    #[serde(default)]
+    pub unlicensed: UnlicensedPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnlicensedPolicy {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Default for UnlicensedPolicy {
+    fn default() -> Self {
+        UnlicensedPolicy::Warn
+    }
+}
+
+/// The packages that a license policy doesn't allow.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Violations {
+    /// Packages with a license that isn't allowed, along with that license.
+    pub denied: Vec<(PackageId, String)>,
+
+    /// Packages that don't declare a license at all.
+    pub unlicensed: Vec<PackageId>,
+}
+
+impl LicensePolicy {
+    /// Whether the manifest has no license policy at all.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_none()
+            && self.deny.is_empty()
+            && self.unlicensed == UnlicensedPolicy::default()
+    }
+
+    /// Checks the license of each package, where `None` means the package
+    /// doesn't declare one.
+    pub fn check(&self, licenses: &BTreeMap<PackageId, Option<String>>) -> Violations {
+        let mut violations = Violations::default();
+
+        for (package_id, license) in licenses {
+            match license {
+                Some(license) if !self.allows(license) => {
+                    violations
+                        .denied
+                        .push((package_id.clone(), license.clone()));
+                }
+                Some(_) => {}
+                None => violations.unlicensed.push(package_id.clone()),
+            }
+        }
+
+        violations
+    }
+
+    fn allows(&self, license: &str) -> bool {
+        let contains = |list: &[String], identifier: &str| {
+            list.iter()
+                .any(|listed| listed.eq_ignore_ascii_case(identifier))
+        };
+
+        license_identifiers(license).all(|identifier| {
+            let allowed = match &self.allow {
+                Some(allow) => contains(allow, identifier),
+                None => true,
+            };
+
+            allowed && !contains(&self.deny, identifier)
+        })
+    }
+
+    /// Checks every package in `resolve` other than `root`, looking up their
+    /// licenses from the sources they were resolved from. Fails with every
+    /// package that violates the policy.
+    pub fn enforce(
+        &self,
+        root: &PackageId,
+        resolve: &Resolve,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let mut licenses = BTreeMap::new();
+
+        for package_id in resolve.activated.iter().filter(|id| *id != root) {
+            let source_id = &resolve.metadata[package_id].source_registry;
+            let source = package_sources
+                .get(source_id)
+                .with_context(|| format!("no source for package {}", package_id))?;
+
+            let manifest = source
+                .get(package_id)?
+                .with_context(|| format!("could not find the manifest of {}", package_id))?;
+
+            licenses.insert(package_id.clone(), manifest.package.license);
+        }
+
+        let violations = self.check(&licenses);
+        let mut problems: Vec<String> = violations
+            .denied
+            .iter()
+            .map(|(package_id, license)| format!("{} is licensed {}", package_id, license))
+            .collect();
+
+        match self.unlicensed {
+            UnlicensedPolicy::Allow => {}
+            UnlicensedPolicy::Warn => {
+                for package_id in &violations.unlicensed {
+                    warnings::warn(format!("{} does not declare a license", package_id));
+                }
+            }
+            UnlicensedPolicy::Deny => {
+                problems.extend(
+                    violations
+                        .unlicensed
+                        .iter()
+                        .map(|package_id| format!("{} does not declare a license", package_id)),
+                );
+            }
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "These packages are not allowed by the project's license policy:\n{}",
+                problems.join("\n")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The license identifiers named by an SPDX license expression, leaving out
+/// its operators and parentheses.
+fn license_identifiers(license: &str) -> impl Iterator<Item = &str> {
+    license
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+        .filter(|token| !matches!(*token, "AND" | "OR" | "WITH"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    /// Resolves a project that depends on biff/a, which depends on biff/b
+    /// licensed with `transitive_license`, if any, and enforces `policy` on
+    /// it.
+    fn enforce(policy: LicensePolicy, transitive_license: Option<&str>) -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(
+            PackageBuilder::new("biff/a@1.0.0")
+                .with_license("MIT")
+                .with_dep("B", "biff/b@1.0.0"),
+        );
+        let b = PackageBuilder::new("biff/b@1.0.0");
+        registry.publish(match transitive_license {
+            Some(license) => b.with_license(license),
+            None => b,
+        });
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .into_manifest();
+        let resolve = resolve(&manifest, &Default::default(), &package_sources)?;
+
+        policy.enforce(&manifest.package_id(), &resolve, &package_sources)
+    }
+
+    #[test]
+    fn allowed_licenses_pass() {
+        let policy = LicensePolicy {
+            allow: Some(vec!["MIT".to_owned(), "Apache-2.0".to_owned()]),
+            ..Default::default()
+        };
+
+        enforce(policy, Some("MIT OR apache-2.0")).unwrap();
+    }
+
+    #[test]
+    fn denied_transitive_license_fails() {
+        let policy = LicensePolicy {
+            deny: vec!["GPL-3.0".to_owned()],
+            ..Default::default()
+        };
+
+        let err = enforce(policy, Some("GPL-3.0")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "These packages are not allowed by the project's license policy:\n\
+             biff/b@1.0.0 is licensed GPL-3.0"
+        );
+    }
+
+    #[test]
+    fn unlicensed_packages() {
+        let policy = LicensePolicy {
+            deny: vec!["GPL-3.0".to_owned()],
+            ..Default::default()
+        };
+
+        let mut licenses = BTreeMap::new();
+        licenses.insert("biff/a@1.0.0".parse().unwrap(), Some("MIT".to_owned()));
+        licenses.insert("biff/b@1.0.0".parse().unwrap(), None);
+
+        assert_eq!(
+            policy.check(&licenses),
+            Violations {
+                denied: Vec::new(),
+                unlicensed: vec!["biff/b@1.0.0".parse().unwrap()],
+            }
+        );
+    }
+
+    #[test]
+    fn denying_only_unlicensed_packages_is_a_policy() {
+        let policy = LicensePolicy {
+            unlicensed: UnlicensedPolicy::Deny,
+            ..Default::default()
+        };
+        assert!(!policy.is_empty());
+
+        let err = enforce(policy.clone(), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "These packages are not allowed by the project's license policy:\n\
+             biff/b@1.0.0 does not declare a license"
+        );
+
+        enforce(policy, Some("MIT")).unwrap();
+    }
+}
@@ -1,27 +1,94 @@
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{
     fs::read_to_string,
     io::{self, BufWriter, Write},
 };
 
+use anyhow::{bail, Context};
 use fs_err::File;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
+use crate::checksum::Checksum;
+use crate::installation::InstalledTree;
 use crate::package_id;
+use crate::warnings;
 use crate::{
-    manifest::Manifest, package_id::PackageId, package_name::PackageName, resolution::Resolve,
+    manifest::{ConflictPolicy, Manifest, Realm},
+    package_id::PackageId,
+    package_name::PackageName,
+    package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider},
+    resolution::{Resolve, ResolvePackageMetadata},
 };
 
 pub const LOCKFILE_NAME: &str = "wally.lock";
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The version of the lockfile format this version of Wally writes, which is
+/// recorded as `version` at the top of the lockfile. Lockfiles from before
+/// the format had versions are version 1, and may have checksums without an
+/// algorithm prefix.
+pub const LOCKFILE_VERSION: u32 = 2;
+
+const UNVERSIONED: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Lockfile {
+    /// The version of the lockfile format. Version 1 lockfiles are written
+    /// without it, like they always were.
+    #[serde(default = "unversioned", skip_serializing_if = "is_unversioned")]
+    pub version: u32,
+
     pub registry: String,
 
+    /// The conflict policy of the manifest this lockfile was generated for.
+    /// It's left out when it's the default.
+    #[serde(
+        rename = "conflict-policy",
+        default,
+        skip_serializing_if = "ConflictPolicy::is_default"
+    )]
+    pub conflict_policy: ConflictPolicy,
+
+    /// When the lockfile was last refreshed by `wally update`, in seconds
+    /// since the Unix epoch. It's only recorded for manifests that set
+    /// `lockfile-max-age`.
+    #[serde(
+        rename = "resolved-at",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resolved_at: Option<u64>,
+
+    /// The commit of the registry's Git index that packages were resolved
+    /// against. `wally install --locked` resolves against the same commit.
+    #[serde(rename = "index-rev", default, skip_serializing_if = "Option::is_none")]
+    pub index_rev: Option<String>,
+
     #[serde(rename = "package")]
     pub packages: Vec<LockPackage>,
+
+    /// The pins of each target's graph, for installing with `--target`. The
+    /// pins above are the default graph's.
+    #[serde(rename = "target", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub targets: BTreeMap<String, TargetLock>,
+}
+
+/// The pins of one target's graph. Pins that are exactly the same in the
+/// default graph are only named, so that they're only stored once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetLock {
+    /// Packages pinned the same way as in the default graph.
+    #[serde(default)]
+    pub shared: Vec<PackageId>,
+
+    /// Packages pinned differently than in the default graph, or not in it
+    /// at all, like the project itself with the target's dependencies.
+    #[serde(rename = "package", default)]
+    pub packages: Vec<LockPackage>,
 }
 
 fn grab_dependencies(
@@ -45,12 +112,17 @@ fn grab_dependencies(
 impl Lockfile {
     pub fn from_manifest(manifest: &Manifest) -> Self {
         Self {
+            version: LOCKFILE_VERSION,
             registry: manifest.package.registry.clone(),
+            conflict_policy: manifest.package.conflict_policy,
+            resolved_at: None,
+            index_rev: None,
             packages: Vec::new(),
+            targets: BTreeMap::new(),
         }
     }
 
-    pub fn from_resolve(resolve: &Resolve) -> Self {
+    pub fn from_resolve(resolve: &Resolve, registry: &str) -> Self {
         let mut packages = Vec::new();
 
         for package_id in &resolve.activated {
@@ -58,6 +130,7 @@ impl Lockfile {
                 grab_dependencies(&package_id, &resolve.shared_dependencies),
                 grab_dependencies(&package_id, &resolve.server_dependencies),
                 grab_dependencies(&package_id, &resolve.dev_dependencies),
+                grab_dependencies(&package_id, &resolve.build_dependencies),
             ]
             .concat();
 
@@ -65,19 +138,137 @@ impl Lockfile {
                 name: package_id.name().clone(),
                 version: package_id.version().clone(),
                 checksum: None,
+                source: None,
+                link: false,
                 dependencies,
             }));
         }
 
         Self {
-            registry: "test".to_owned(),
+            version: LOCKFILE_VERSION,
+            registry: registry.to_owned(),
+            conflict_policy: ConflictPolicy::default(),
+            resolved_at: None,
+            index_rev: None,
             packages,
+            targets: BTreeMap::new(),
         }
     }
 
+    /// The lockfile as seen when installing for `target`, with the pins of
+    /// that target's graph, or of the default graph without a target. A
+    /// target that hasn't been installed yet starts from the default graph's
+    /// pins.
+    pub fn for_target(&self, target: Option<&str>) -> Self {
+        let mut lockfile = self.clone();
+        lockfile.targets = BTreeMap::new();
+
+        if let Some(target_lock) = target.and_then(|target| self.targets.get(target)) {
+            lockfile.packages = target_lock.expand(&self.packages);
+        }
+
+        lockfile
+    }
+
+    /// Stores the pins of `locked` as the pins of `target`'s graph, or of
+    /// the default graph without a target. Everything else about the
+    /// lockfile, like the index commit, is taken from `locked` too, since it
+    /// was just resolved.
+    pub fn set_target(&mut self, target: Option<&str>, locked: Lockfile) {
+        let default_packages = std::mem::take(&mut self.packages);
+        let mut targets: BTreeMap<String, Vec<LockPackage>> = std::mem::take(&mut self.targets)
+            .into_iter()
+            .map(|(name, target_lock)| {
+                let packages = target_lock.expand(&default_packages);
+                (name, packages)
+            })
+            .collect();
+
+        let packages = match target {
+            Some(target) => {
+                targets.insert(target.to_owned(), locked.packages.clone());
+                default_packages
+            }
+            None => locked.packages.clone(),
+        };
+
+        *self = Self { packages, ..locked };
+        self.canonicalize();
+
+        // The default graph may have changed, so every target is split up
+        // against it again. Targets that were kept as they were may have been
+        // written by an older version of the format.
+        self.targets = targets
+            .into_iter()
+            .map(|(name, mut packages)| {
+                prefix_legacy_checksums(&mut packages);
+                (name, TargetLock::new(packages, &self.packages))
+            })
+            .collect();
+    }
+
+    /// Rebuilds the lockfile of `manifest`'s project from the packages its
+    /// installs left behind, for recovering a lost lockfile. The version of
+    /// the registry's index that was used isn't recorded anywhere, so it's
+    /// left out.
+    ///
+    /// Returns the lockfile along with the packages whose source can't be
+    /// determined, described for the user.
+    pub fn from_installed(manifest: &Manifest, installed: &InstalledTree) -> (Self, Vec<String>) {
+        let mut lockfile = Self::from_manifest(manifest);
+        let mut unknown = installed.problems.clone();
+
+        lockfile
+            .packages
+            .push(LockPackage::Registry(RegistryLockPackage {
+                name: manifest.package.name.clone(),
+                version: manifest.package.version.clone(),
+                checksum: None,
+                source: None,
+                link: false,
+                dependencies: installed.root_dependencies.clone(),
+            }));
+
+        for (package_id, package) in &installed.packages {
+            // Path dependencies are copied with a checksum like any other
+            // package, but the lockfile never pins their contents.
+            let is_path = manifest.dependency_paths.contains_key(package_id.name());
+            let checksum = if is_path || package.link {
+                None
+            } else {
+                package.checksum.clone()
+            };
+
+            if checksum.is_none() && !is_path && !package.link {
+                unknown.push(format!(
+                    "{} has no recorded checksum, so where it came from can't be verified",
+                    package_id
+                ));
+            }
+
+            lockfile
+                .packages
+                .push(LockPackage::Registry(RegistryLockPackage {
+                    name: package_id.name().clone(),
+                    version: package_id.version().clone(),
+                    checksum,
+                    source: None,
+                    link: package.link,
+                    dependencies: package.dependencies.clone(),
+                }));
+        }
+
+        (lockfile, unknown)
+    }
+
     pub fn load(project_path: &Path) -> anyhow::Result<Option<Self>> {
-        let lockfile_path = project_path.join(LOCKFILE_NAME);
-        let contents = match read_to_string(&lockfile_path) {
+        Self::load_file(&project_path.join(LOCKFILE_NAME))
+    }
+
+    /// Loads the lockfile at `lockfile_path`, which doesn't have to belong
+    /// to any project, returning `None` if there's no file there.
+    pub fn load_file(lockfile_path: &Path) -> anyhow::Result<Option<Self>> {
+        let contents = match read_to_string(lockfile_path) {
             Ok(contents) => contents,
             Err(err) => {
                 if err.kind() == io::ErrorKind::NotFound {
@@ -87,22 +278,244 @@ impl Lockfile {
                 }
             }
         };
-        Ok(Some(toml::from_str(&contents)?))
+        let lockfile: Lockfile = contents
+            .parse()
+            .with_context(|| format!("Could not read {}", lockfile_path.display()))?;
+
+        let has_legacy_checksums = lockfile
+            .checksums()
+            .values()
+            .any(|checksum| Checksum::is_legacy(checksum));
+        if has_legacy_checksums {
+            warnings::warn(format!(
+                "{} has checksums without an algorithm prefix, which are treated as SHA-256. \
+                 This format is deprecated; the next install or wally migrate-lockfile will \
+                 rewrite them like `sha256:<hex>`.",
+                lockfile_path.display()
+            ));
+        }
+
+        Ok(Some(lockfile))
     }
 
     pub fn save(&self, project_path: &Path) -> anyhow::Result<()> {
         let lockfile_path = project_path.join(LOCKFILE_NAME);
-        let serialized = toml::to_string(self)?;
+        let serialized = self.to_canonical_string()?;
 
         let mut file = BufWriter::new(File::create(lockfile_path)?);
-        writeln!(file, "# This file is automatically @generated by Wally.")?;
-        writeln!(file, "# It is not intended for manual editing.")?;
         write!(file, "{}", serialized)?;
         file.flush()?;
 
         Ok(())
     }
 
+    /// Serializes the lockfile in its canonical form: packages sorted by name
+    /// and version, and each package's dependencies sorted by alias. Two
+    /// lockfiles with the same contents always serialize the same way, which
+    /// keeps diffs of the lockfile down to real changes.
+    pub fn to_canonical_string(&self) -> anyhow::Result<String> {
+        let mut lockfile = self.clone();
+        lockfile.canonicalize();
+
+        for target_lock in lockfile.targets.values_mut() {
+            target_lock.shared.sort();
+            canonicalize_packages(&mut target_lock.packages);
+        }
+
+        let mut output = String::new();
+        output.push_str("# This file is automatically @generated by Wally.\n");
+        output.push_str("# It is not intended for manual editing.\n");
+        output.push_str(&toml::to_string(&lockfile)?);
+
+        Ok(output)
+    }
+
+    fn canonicalize(&mut self) {
+        canonicalize_packages(&mut self.packages);
+    }
+
+    /// Upgrades the lockfile to the current version of the format without
+    /// changing any pins or checksums, only how they're written. Returns the
+    /// version it was upgraded from.
+    pub fn migrate(&mut self) -> anyhow::Result<u32> {
+        let from = self.version;
+        check_version(from)?;
+
+        // Version 2 writes every checksum with its algorithm.
+        if from < 2 {
+            self.prefix_legacy_checksums();
+        }
+
+        self.version = LOCKFILE_VERSION;
+        Ok(from)
+    }
+
+    fn prefix_legacy_checksums(&mut self) {
+        prefix_legacy_checksums(&mut self.packages);
+
+        for target_lock in self.targets.values_mut() {
+            prefix_legacy_checksums(&mut target_lock.packages);
+        }
+    }
+
+    /// Records when the lockfile was refreshed for manifests that ask for it,
+    /// keeping the time from `previous` unless `refreshed` is set.
+    pub fn set_resolved_at(&mut self, manifest: &Manifest, previous: &Lockfile, refreshed: bool) {
+        if manifest.package.lockfile_max_age.is_none() {
+            return;
+        }
+
+        self.resolved_at = match previous.resolved_at {
+            Some(resolved_at) if !refreshed => Some(resolved_at),
+            _ => Some(unix_time(SystemTime::now())),
+        };
+    }
+
+    /// A warning to show if the lockfile hasn't been refreshed within
+    /// `max_age_days` days of `now`.
+    pub fn staleness_warning(&self, max_age_days: u64, now: SystemTime) -> Option<String> {
+        let resolved_at = UNIX_EPOCH + Duration::from_secs(self.resolved_at?);
+        let age_days = now.duration_since(resolved_at).ok()?.as_secs() / (60 * 60 * 24);
+
+        if age_days > max_age_days {
+            Some(format!(
+                "The lockfile was last refreshed {} days ago, which is more than the {} days \
+                 allowed by lockfile-max-age. Try running wally update.",
+                age_days, max_age_days
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The project this lockfile belongs to, which is the one package that
+    /// nothing else depends on.
+    pub fn root(&self) -> anyhow::Result<PackageId> {
+        let registry_packages =
+            self.packages
+                .iter()
+                .filter_map(|lock_package| match lock_package {
+                    LockPackage::Registry(lock_package) => Some(lock_package),
+                    LockPackage::Git(_) => None,
+                });
+
+        let mut ids = Vec::new();
+        let mut depended_on = BTreeSet::new();
+        for lock_package in registry_packages {
+            ids.push(PackageId::new(
+                lock_package.name.clone(),
+                lock_package.version.clone(),
+            ));
+            depended_on.extend(lock_package.dependencies.iter().map(|(_, id)| id));
+        }
+
+        let mut roots: Vec<PackageId> = ids
+            .into_iter()
+            .filter(|package_id| !depended_on.contains(package_id))
+            .collect();
+
+        match roots.len() {
+            1 => Ok(roots.remove(0)),
+            count => bail!(
+                "The lockfile should have exactly one package that nothing depends on, \
+                 the project itself, but it has {}",
+                count
+            ),
+        }
+    }
+
+    /// Rebuilds the resolved graph from the lockfile alone, for installing
+    /// without a manifest. Returns the graph along with its root, which is
+    /// the one package that nothing else depends on.
+    ///
+    /// The lockfile doesn't record which realm the root depends on each
+    /// package from, so each of the root's dependencies is placed in the realm
+    /// its own manifest declares, and its dependencies follow it there like
+    /// they do during resolution.
+    pub fn to_resolve(
+        &self,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<(PackageId, Resolve)> {
+        let mut packages = BTreeMap::new();
+
+        for lock_package in &self.packages {
+            match lock_package {
+                LockPackage::Registry(lock_package) if lock_package.link => bail!(
+                    "{}@{} is a path dependency, which can't be installed without the manifest",
+                    lock_package.name,
+                    lock_package.version
+                ),
+                LockPackage::Registry(lock_package) => {
+                    let package_id =
+                        PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+                    packages.insert(package_id, &lock_package.dependencies);
+                }
+                LockPackage::Git(lock_package) => bail!(
+                    "{} is a Git dependency, which can't be installed without the manifest",
+                    lock_package.name
+                ),
+            }
+        }
+
+        let root = self.root()?;
+
+        let mut resolve = Resolve::default();
+        resolve.activated.insert(root.clone());
+        resolve.metadata.insert(
+            root.clone(),
+            ResolvePackageMetadata {
+                realm: Realm::Shared,
+                origin_realm: Realm::Shared,
+                source_registry: PackageSourceId::DefaultRegistry,
+            },
+        );
+
+        let mut found = BTreeMap::new();
+        for package_id in packages.keys().filter(|package_id| **package_id != root) {
+            found.insert(package_id, find_package(package_sources, package_id)?);
+        }
+
+        // Packages get the least restrictive realm they're reached from, so
+        // the root's dependencies are walked from shared through to build.
+        let mut root_dependencies: Vec<&(String, PackageId)> = packages[&root].iter().collect();
+        root_dependencies.sort_by_key(|(_, package_id)| match found[package_id].1 {
+            Realm::Shared => 0,
+            Realm::Server => 1,
+            Realm::Dev => 2,
+            Realm::Build => 3,
+        });
+
+        let mut to_visit = VecDeque::new();
+        for (alias, package_id) in root_dependencies {
+            let realm = found[package_id].1;
+            add_dependency(&mut resolve, &root, alias, realm, package_id);
+            to_visit.push_back((package_id, realm));
+        }
+
+        while let Some((package_id, origin_realm)) = to_visit.pop_front() {
+            if resolve.metadata.contains_key(package_id) {
+                continue;
+            }
+
+            let (source_registry, realm) = found[package_id].clone();
+            resolve.metadata.insert(
+                package_id.clone(),
+                ResolvePackageMetadata {
+                    realm,
+                    origin_realm,
+                    source_registry,
+                },
+            );
+
+            for (alias, dependency) in packages[package_id] {
+                add_dependency(&mut resolve, package_id, alias, origin_realm, dependency);
+                to_visit.push_back((dependency, origin_realm));
+            }
+        }
+
+        Ok((root, resolve))
+    }
+
     pub fn as_ids(&self) -> impl Iterator<Item = PackageId> + '_ {
         self.packages.iter().map(|lock_package| match lock_package {
             LockPackage::Registry(lock_package) => {
@@ -111,26 +524,315 @@ impl Lockfile {
             LockPackage::Git(_) => todo!(),
         })
     }
+
+    /// A fingerprint of the pinned graph, made from the ID and checksum of
+    /// every package in it. Lockfiles pinning the same packages with the same
+    /// contents have the same graph hash however they're written, and
+    /// anything else, like another version or contents, changes it. Targets
+    /// aren't part of it.
+    pub fn graph_hash(&self) -> String {
+        let mut lockfile = self.clone();
+        lockfile.canonicalize();
+        lockfile.prefix_legacy_checksums();
+
+        let lines: Vec<String> = lockfile
+            .packages
+            .iter()
+            .map(|lock_package| match lock_package {
+                LockPackage::Registry(package) => format!(
+                    "{}@{} {}\n",
+                    package.name,
+                    package.version,
+                    package.checksum.as_deref().unwrap_or("-")
+                ),
+                LockPackage::Git(package) => format!("{} {}\n", package.name, package.commit),
+            })
+            .collect();
+
+        Checksum::compute(lines.concat().as_bytes()).to_string()
+    }
+
+    /// The checksums of every package in the lockfile that has one recorded.
+    pub fn checksums(&self) -> BTreeMap<PackageId, String> {
+        self.packages
+            .iter()
+            .filter_map(|lock_package| match lock_package {
+                LockPackage::Registry(lock_package) => {
+                    let checksum = lock_package.checksum.clone()?;
+                    let package_id =
+                        PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                    Some((package_id, checksum))
+                }
+                LockPackage::Git(_) => None,
+            })
+            .collect()
+    }
+
+    /// Record checksums for the packages in the lockfile. Packages that aren't
+    /// in the lockfile are ignored.
+    pub fn set_checksums(&mut self, checksums: &BTreeMap<PackageId, String>) {
+        for lock_package in &mut self.packages {
+            if let LockPackage::Registry(lock_package) = lock_package {
+                let package_id =
+                    PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                if let Some(checksum) = checksums.get(&package_id) {
+                    lock_package.checksum = Some(prefixed_checksum(checksum));
+                }
+            }
+        }
+    }
+
+    /// Where the contents of every package in the lockfile that was
+    /// redirected were actually downloaded from.
+    pub fn sources(&self) -> BTreeMap<PackageId, String> {
+        self.packages
+            .iter()
+            .filter_map(|lock_package| match lock_package {
+                LockPackage::Registry(lock_package) => {
+                    let source = lock_package.source.clone()?;
+                    let package_id =
+                        PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                    Some((package_id, source))
+                }
+                LockPackage::Git(_) => None,
+            })
+            .collect()
+    }
+
+    /// Record where packages' contents were redirected to. Packages that
+    /// aren't in the lockfile are ignored.
+    pub fn set_sources(&mut self, sources: &BTreeMap<PackageId, String>) {
+        for lock_package in &mut self.packages {
+            if let LockPackage::Registry(lock_package) = lock_package {
+                let package_id =
+                    PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                if let Some(source) = sources.get(&package_id) {
+                    lock_package.source = Some(source.clone());
+                }
+            }
+        }
+    }
+
+    /// Mark the packages that were symlinked into place instead of being
+    /// installed from their contents.
+    pub fn set_links(&mut self, linked: &BTreeSet<PackageId>) {
+        for lock_package in &mut self.packages {
+            if let LockPackage::Registry(lock_package) = lock_package {
+                let package_id =
+                    PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+                lock_package.link = linked.contains(&package_id);
+            }
+        }
+    }
+}
+
+impl FromStr for Lockfile {
+    type Err = anyhow::Error;
+
+    /// Parses a lockfile of any version this version of Wally understands.
+    /// The version is checked first, so that a lockfile from a newer version
+    /// of Wally is reported as such instead of failing to parse.
+    fn from_str(contents: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            #[serde(default = "unversioned")]
+            version: u32,
+        }
+
+        let Versioned { version } = toml::from_str(contents)?;
+        check_version(version)?;
+
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+fn unversioned() -> u32 {
+    UNVERSIONED
+}
+
+fn is_unversioned(version: &u32) -> bool {
+    *version == UNVERSIONED
+}
+
+fn check_version(version: u32) -> anyhow::Result<()> {
+    if version < UNVERSIONED || version > LOCKFILE_VERSION {
+        bail!(
+            "Lockfile version {} isn't supported. This version of Wally understands lockfile \
+             versions {} through {}, so try upgrading Wally.",
+            version,
+            UNVERSIONED,
+            LOCKFILE_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+fn prefix_legacy_checksums(packages: &mut [LockPackage]) {
+    for lock_package in packages {
+        if let LockPackage::Registry(lock_package) = lock_package {
+            if let Some(checksum) = &mut lock_package.checksum {
+                *checksum = prefixed_checksum(checksum);
+            }
+        }
+    }
+}
+
+/// Writes a legacy checksum with its algorithm prefix. Checksums that can't
+/// be read are left as they are.
+fn prefixed_checksum(checksum: &str) -> String {
+    checksum
+        .parse::<Checksum>()
+        .map(|parsed| parsed.to_string())
+        .unwrap_or_else(|_| checksum.to_owned())
+}
+
+fn canonicalize_packages(packages: &mut [LockPackage]) {
+    packages.sort_by(LockPackage::canonical_cmp);
+
+    for lock_package in packages {
+        match lock_package {
+            LockPackage::Registry(lock_package) => lock_package.dependencies.sort(),
+            LockPackage::Git(lock_package) => lock_package.dependencies.sort(),
+        }
+    }
+}
+
+impl TargetLock {
+    /// Splits a target's pins into the ones that are the same in the default
+    /// graph's `default_packages` and the ones that aren't.
+    fn new(mut packages: Vec<LockPackage>, default_packages: &[LockPackage]) -> Self {
+        canonicalize_packages(&mut packages);
+        let mut target_lock = Self::default();
+
+        for lock_package in packages {
+            match &lock_package {
+                LockPackage::Registry(registry_package)
+                    if default_packages.contains(&lock_package) =>
+                {
+                    target_lock.shared.push(PackageId::new(
+                        registry_package.name.clone(),
+                        registry_package.version.clone(),
+                    ));
+                }
+                _ => target_lock.packages.push(lock_package),
+            }
+        }
+
+        target_lock
+    }
+
+    /// Every pin of the target, taking shared ones from `default_packages`.
+    fn expand(&self, default_packages: &[LockPackage]) -> Vec<LockPackage> {
+        let shared = default_packages
+            .iter()
+            .filter(|lock_package| match lock_package {
+                LockPackage::Registry(lock_package) => self.shared.contains(&PackageId::new(
+                    lock_package.name.clone(),
+                    lock_package.version.clone(),
+                )),
+                LockPackage::Git(_) => false,
+            });
+
+        shared.chain(&self.packages).cloned().collect()
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Finds the first source with the package, returning the source and the
+/// realm the package declares.
+fn find_package(
+    package_sources: &PackageSourceMap,
+    package_id: &PackageId,
+) -> anyhow::Result<(PackageSourceId, Realm)> {
+    for source_id in package_sources.source_order() {
+        let source = package_sources.get(source_id).unwrap();
+
+        if let Some(manifest) = source.get(package_id)? {
+            return Ok((source_id.clone(), manifest.package.realm));
+        }
+    }
+
+    bail!(
+        "{} is pinned in the lockfile, but no registry has it",
+        package_id
+    )
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn add_dependency(
+    resolve: &mut Resolve,
+    source: &PackageId,
+    alias: &str,
+    realm: Realm,
+    dependency: &PackageId,
+) {
+    resolve.activated.insert(dependency.clone());
+
+    let dependencies = match realm {
+        Realm::Shared => &mut resolve.shared_dependencies,
+        Realm::Server => &mut resolve.server_dependencies,
+        Realm::Dev => &mut resolve.dev_dependencies,
+        Realm::Build => &mut resolve.build_dependencies,
+    };
+
+    dependencies
+        .entry(source.clone())
+        .or_default()
+        .insert(alias.to_owned(), dependency.clone());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum LockPackage {
     Registry(RegistryLockPackage),
     Git(GitLockPackage),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl LockPackage {
+    /// The order packages are listed in within a canonical lockfile.
+    fn canonical_cmp(a: &Self, b: &Self) -> Ordering {
+        match (a, b) {
+            (LockPackage::Registry(a), LockPackage::Registry(b)) => {
+                (&a.name, &a.version).cmp(&(&b.name, &b.version))
+            }
+            (LockPackage::Git(a), LockPackage::Git(b)) => a.name.cmp(&b.name),
+            (LockPackage::Registry(_), LockPackage::Git(_)) => Ordering::Less,
+            (LockPackage::Git(_), LockPackage::Registry(_)) => Ordering::Greater,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RegistryLockPackage {
     pub name: PackageName,
     pub version: Version,
     pub checksum: Option<String>,
 
+    /// Where the package's contents were downloaded from, if its registry
+    /// redirected the download somewhere other than where its index said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// Whether this is a path dependency that `wally install --link` linked
+    /// to, which has no checksum because its contents are live.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub link: bool,
+
     #[serde(default)]
     pub dependencies: Vec<(String, PackageId)>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GitLockPackage {
     pub name: String,
     pub rev: String,
@@ -139,3 +841,134 @@ pub struct GitLockPackage {
     #[serde(default)]
     pub dependencies: Vec<PackageId>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn graph_hash() {
+        let lockfile: Lockfile = toml::from_str(
+            r#"
+registry = "test"
+
+[[package]]
+name = "biff/root"
+version = "1.0.0"
+dependencies = [["Minimal", "biff/minimal@0.1.0"]]
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "sha256:aaa"
+"#,
+        )
+        .unwrap();
+        let graph_hash = lockfile.graph_hash();
+
+        // The order packages are written in doesn't matter.
+        let mut reordered = lockfile.clone();
+        reordered.packages.reverse();
+        assert_eq!(reordered.graph_hash(), graph_hash);
+
+        let mut bumped = lockfile.clone();
+        if let LockPackage::Registry(package) = &mut bumped.packages[1] {
+            package.version = Version::new(0, 1, 1);
+        }
+        assert_ne!(bumped.graph_hash(), graph_hash);
+
+        let mut changed = lockfile;
+        let mut checksums = BTreeMap::new();
+        checksums.insert(
+            "biff/minimal@0.1.0".parse().unwrap(),
+            "sha256:bbb".to_owned(),
+        );
+        changed.set_checksums(&checksums);
+        assert_ne!(changed.graph_hash(), graph_hash);
+    }
+
+    #[test]
+    fn canonical_round_trip() {
+        let unordered = r#"
+registry = "test"
+
+[[package]]
+name = "biff/root"
+version = "1.0.0"
+dependencies = [["Shared", "biff/shared@1.0.0"], ["Minimal", "biff/minimal@0.2.0"]]
+
+[[package]]
+checksum = "abc123"
+version = "0.2.0"
+name = "biff/minimal"
+dependencies = []
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "def456"
+
+[[package]]
+dependencies = []
+name = "biff/shared"
+version = "1.0.0"
+"#;
+
+        let lockfile: Lockfile = toml::from_str(unordered).unwrap();
+        let canonical = lockfile.to_canonical_string().unwrap();
+
+        let expected = r#"# This file is automatically @generated by Wally.
+# It is not intended for manual editing.
+registry = "test"
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "def456"
+dependencies = []
+
+[[package]]
+name = "biff/minimal"
+version = "0.2.0"
+checksum = "abc123"
+dependencies = []
+
+[[package]]
+name = "biff/root"
+version = "1.0.0"
+dependencies = [["Minimal", "biff/minimal@0.2.0"], ["Shared", "biff/shared@1.0.0"]]
+
+[[package]]
+name = "biff/shared"
+version = "1.0.0"
+dependencies = []
+"#;
+
+        assert_eq!(canonical, expected);
+
+        // Formatting a canonical lockfile again must not change it.
+        let reparsed: Lockfile = toml::from_str(&canonical).unwrap();
+        assert_eq!(reparsed.to_canonical_string().unwrap(), canonical);
+    }
+
+    #[test]
+    fn stale_lockfile() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let day = 60 * 60 * 24;
+
+        let lockfile: Lockfile = toml::from_str(&format!(
+            "registry = \"test\"\nresolved-at = {}\npackage = []\n",
+            1_700_000_000 - 100 * day
+        ))
+        .unwrap();
+
+        let warning = lockfile.staleness_warning(90, now).unwrap();
+        assert!(
+            warning.contains("100 days ago"),
+            "Unexpected warning: {}",
+            warning
+        );
+
+        assert_eq!(lockfile.staleness_warning(120, now), None);
+    }
+}
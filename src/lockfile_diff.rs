@@ -0,0 +1,438 @@
+//! A summary of how the packages pinned by one lockfile differ from another,
+//! meant for reviewing dependency changes in a pull request.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+use semver::Version;
+use serde::Serialize;
+
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::{Manifest, Realm};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LockfileDiff {
+    /// The changes in each realm that has any, from shared through to build.
+    /// Packages whose realm can't be worked out come last, with no realm.
+    pub realms: Vec<RealmChanges>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RealmChanges {
+    pub realm: Option<Realm>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<PackageId>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<PackageId>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<VersionChange>,
+
+    /// Packages pinned to the same version whose checksum is different,
+    /// which means their registry changed their contents.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checksum_changed: Vec<ChecksumChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionChange {
+    pub name: PackageName,
+    pub old: Version,
+    pub new: Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChecksumChange {
+    pub package: PackageId,
+    pub old: String,
+    pub new: String,
+}
+
+impl LockfileDiff {
+    /// Compares the packages pinned by `old` and `new`, leaving out the
+    /// project itself.
+    ///
+    /// Lockfiles don't record which realm each package is used from, so it's
+    /// worked out from the tables the project's `manifest` lists the root's
+    /// dependencies in, like resolution does. Without a manifest, or for
+    /// dependencies it no longer lists, packages are grouped with no realm.
+    pub fn new(old: &Lockfile, new: &Lockfile, manifest: Option<&Manifest>) -> Self {
+        let old_packages = pinned_packages(old);
+        let new_packages = pinned_packages(new);
+
+        // Packages are grouped by where the new lockfile uses them, or where
+        // the old one did if they were removed.
+        let mut realms = origin_realms(old, manifest);
+        realms.extend(origin_realms(new, manifest));
+
+        let mut changes: BTreeMap<u8, RealmChanges> = BTreeMap::new();
+        let mut changes_in = |package_id: &PackageId| {
+            let realm = realms.get(package_id).copied();
+            changes
+                .entry(realm_order(realm))
+                .or_insert_with(|| RealmChanges::new(realm))
+        };
+
+        let names: BTreeSet<&PackageName> = old_packages
+            .keys()
+            .chain(new_packages.keys())
+            .map(|package_id| package_id.name())
+            .collect();
+
+        for name in names {
+            let versions = |packages: &BTreeMap<PackageId, Option<String>>| -> Vec<PackageId> {
+                packages
+                    .keys()
+                    .filter(|package_id| package_id.name() == name)
+                    .cloned()
+                    .collect()
+            };
+            let old_versions = versions(&old_packages);
+            let new_versions = versions(&new_packages);
+
+            for package_id in old_versions.iter().filter(|id| new_versions.contains(id)) {
+                if let (Some(old_checksum), Some(new_checksum)) =
+                    (&old_packages[package_id], &new_packages[package_id])
+                {
+                    if old_checksum != new_checksum {
+                        changes_in(package_id)
+                            .checksum_changed
+                            .push(ChecksumChange {
+                                package: package_id.clone(),
+                                old: old_checksum.clone(),
+                                new: new_checksum.clone(),
+                            });
+                    }
+                }
+            }
+
+            let removed: Vec<&PackageId> = old_versions
+                .iter()
+                .filter(|id| !new_versions.contains(id))
+                .collect();
+            let added: Vec<&PackageId> = new_versions
+                .iter()
+                .filter(|id| !old_versions.contains(id))
+                .collect();
+
+            // Versions that were swapped for another version of the same
+            // package are paired up, oldest with oldest, and anything left
+            // over was added or removed outright.
+            for (&old_id, &new_id) in removed.iter().zip(added.iter()) {
+                changes_in(new_id).changed.push(VersionChange {
+                    name: name.clone(),
+                    old: old_id.version().clone(),
+                    new: new_id.version().clone(),
+                });
+            }
+
+            for &package_id in removed.iter().skip(added.len()) {
+                changes_in(package_id).removed.push(package_id.clone());
+            }
+
+            for &package_id in added.iter().skip(removed.len()) {
+                changes_in(package_id).added.push(package_id.clone());
+            }
+        }
+
+        Self {
+            realms: changes.into_iter().map(|(_, changes)| changes).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.realms.is_empty()
+    }
+}
+
+impl RealmChanges {
+    fn new(realm: Option<Realm>) -> Self {
+        Self {
+            realm,
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+            checksum_changed: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for LockfileDiff {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(formatter, "No packages changed");
+        }
+
+        for changes in &self.realms {
+            match changes.realm {
+                Some(realm) => writeln!(formatter, "{}:", realm)?,
+                None => writeln!(formatter, "unknown realm:")?,
+            }
+
+            for package_id in &changes.added {
+                writeln!(formatter, "  + {}", package_id)?;
+            }
+
+            for package_id in &changes.removed {
+                writeln!(formatter, "  - {}", package_id)?;
+            }
+
+            for change in &changes.changed {
+                writeln!(
+                    formatter,
+                    "  ~ {} {} -> {}",
+                    change.name, change.old, change.new
+                )?;
+            }
+
+            for change in &changes.checksum_changed {
+                writeln!(
+                    formatter,
+                    "  ! {} checksum changed from {} to {}",
+                    change.package, change.old, change.new
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sorts realms in the order they're listed in a manifest, with packages
+/// whose realm isn't known last.
+fn realm_order(realm: Option<Realm>) -> u8 {
+    match realm {
+        Some(Realm::Shared) => 0,
+        Some(Realm::Server) => 1,
+        Some(Realm::Dev) => 2,
+        Some(Realm::Build) => 3,
+        None => 4,
+    }
+}
+
+/// Every registry package the lockfile pins, other than the project itself,
+/// with its checksum.
+fn pinned_packages(lockfile: &Lockfile) -> BTreeMap<PackageId, Option<String>> {
+    let root = lockfile.root().ok();
+
+    lockfile
+        .packages
+        .iter()
+        .filter_map(|lock_package| match lock_package {
+            LockPackage::Registry(lock_package) => Some((
+                PackageId::new(lock_package.name.clone(), lock_package.version.clone()),
+                lock_package.checksum.clone(),
+            )),
+            LockPackage::Git(_) => None,
+        })
+        .filter(|(package_id, _)| Some(package_id) != root.as_ref())
+        .collect()
+}
+
+/// The realm each package is used from, found by following the root's
+/// dependencies from the tables `manifest` lists them in. Like during
+/// resolution, packages get the least restrictive realm they're reached from.
+fn origin_realms(lockfile: &Lockfile, manifest: Option<&Manifest>) -> BTreeMap<PackageId, Realm> {
+    let mut realms = BTreeMap::new();
+
+    let (manifest, root) = match (manifest, lockfile.root()) {
+        (Some(manifest), Ok(root)) => (manifest, root),
+        _ => return realms,
+    };
+
+    let dependencies: BTreeMap<PackageId, &Vec<(String, PackageId)>> = lockfile
+        .packages
+        .iter()
+        .filter_map(|lock_package| match lock_package {
+            LockPackage::Registry(lock_package) => Some((
+                PackageId::new(lock_package.name.clone(), lock_package.version.clone()),
+                &lock_package.dependencies,
+            )),
+            LockPackage::Git(_) => None,
+        })
+        .collect();
+
+    let tables = [
+        (Realm::Shared, &manifest.dependencies),
+        (Realm::Server, &manifest.server_dependencies),
+        (Realm::Dev, &manifest.dev_dependencies),
+        (Realm::Build, &manifest.build_dependencies),
+    ];
+
+    let mut to_visit = VecDeque::new();
+    for (realm, table) in tables {
+        for (alias, package_id) in dependencies[&root].iter() {
+            if table.contains_key(alias) {
+                to_visit.push_back((package_id, realm));
+            }
+        }
+    }
+
+    while let Some((package_id, realm)) = to_visit.pop_front() {
+        if realms.contains_key(package_id) {
+            continue;
+        }
+
+        realms.insert(package_id.clone(), realm);
+
+        if let Some(package_dependencies) = dependencies.get(package_id) {
+            for (_, dependency) in package_dependencies.iter() {
+                to_visit.push_back((dependency, realm));
+            }
+        }
+    }
+
+    realms
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const OLD: &str = r#"
+registry = "test"
+
+[[package]]
+name = "biff/root"
+version = "1.0.0"
+dependencies = [["Minimal", "biff/minimal@0.1.0"], ["Old", "biff/old@1.0.0"], ["Server", "biff/server@1.0.0"]]
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "sha256:aaa"
+
+[[package]]
+name = "biff/old"
+version = "1.0.0"
+checksum = "sha256:bbb"
+
+[[package]]
+name = "biff/server"
+version = "1.0.0"
+checksum = "sha256:ccc"
+dependencies = [["Helper", "biff/helper@1.0.0"]]
+
+[[package]]
+name = "biff/helper"
+version = "1.0.0"
+checksum = "sha256:ddd"
+"#;
+
+    const NEW: &str = r#"
+registry = "test"
+
+[[package]]
+name = "biff/root"
+version = "1.1.0"
+dependencies = [["Minimal", "biff/minimal@0.2.0"], ["New", "biff/new@2.0.0"], ["Server", "biff/server@1.0.0"]]
+
+[[package]]
+name = "biff/minimal"
+version = "0.2.0"
+checksum = "sha256:eee"
+
+[[package]]
+name = "biff/new"
+version = "2.0.0"
+checksum = "sha256:fff"
+
+[[package]]
+name = "biff/server"
+version = "1.0.0"
+checksum = "sha256:ccc"
+dependencies = [["Helper", "biff/helper@1.0.0"]]
+
+[[package]]
+name = "biff/helper"
+version = "1.0.0"
+checksum = "sha256:999"
+"#;
+
+    const MANIFEST: &str = r#"
+[package]
+name = "biff/root"
+version = "1.1.0"
+registry = "test"
+realm = "shared"
+
+[dependencies]
+Minimal = "biff/minimal@0.2.0"
+New = "biff/new@2.0.0"
+
+[server-dependencies]
+Server = "biff/server@1.0.0"
+"#;
+
+    fn lockfile(contents: &str) -> Lockfile {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn grouped_by_realm() {
+        let manifest = Manifest::from_slice(MANIFEST.as_bytes()).unwrap();
+        let diff = LockfileDiff::new(&lockfile(OLD), &lockfile(NEW), Some(&manifest));
+
+        let id = |id: &str| id.parse::<PackageId>().unwrap();
+
+        assert_eq!(
+            diff.realms,
+            vec![
+                RealmChanges {
+                    realm: Some(Realm::Shared),
+                    added: vec![id("biff/new@2.0.0")],
+                    removed: Vec::new(),
+                    changed: vec![VersionChange {
+                        name: "biff/minimal".parse().unwrap(),
+                        old: Version::new(0, 1, 0),
+                        new: Version::new(0, 2, 0),
+                    }],
+                    checksum_changed: Vec::new(),
+                },
+                RealmChanges {
+                    realm: Some(Realm::Server),
+                    added: Vec::new(),
+                    removed: Vec::new(),
+                    changed: Vec::new(),
+                    checksum_changed: vec![ChecksumChange {
+                        package: id("biff/helper@1.0.0"),
+                        old: "sha256:ddd".to_owned(),
+                        new: "sha256:999".to_owned(),
+                    }],
+                },
+                // The manifest no longer lists Old, so there's no telling
+                // which realm it was used from.
+                RealmChanges {
+                    realm: None,
+                    added: Vec::new(),
+                    removed: vec![id("biff/old@1.0.0")],
+                    changed: Vec::new(),
+                    checksum_changed: Vec::new(),
+                },
+            ]
+        );
+
+        assert_eq!(
+            diff.to_string(),
+            "shared:\n  \
+             + biff/new@2.0.0\n  \
+             ~ biff/minimal 0.1.0 -> 0.2.0\n\
+             server:\n  \
+             ! biff/helper@1.0.0 checksum changed from sha256:ddd to sha256:999\n\
+             unknown realm:\n  \
+             - biff/old@1.0.0\n"
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        let diff = LockfileDiff::new(&lockfile(NEW), &lockfile(NEW), None);
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "No packages changed\n");
+    }
+}
@@ -0,0 +1,354 @@
+//! Combines the lockfiles of several projects that are built into one
+//! artifact, so that every package that ends up in it can be audited at once.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::bail;
+use semver::Version;
+
+use crate::lockfile::{GitLockPackage, LockPackage, Lockfile, RegistryLockPackage};
+use crate::manifest::ZeroVersionCompat;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::resolution::compatible;
+
+/// The lockfiles being merged, each labelled with where it came from.
+pub type LabelledLockfile = (String, Lockfile);
+
+/// Every way the merged lockfiles disagree. This is the error `merge` fails
+/// with, and it displays as one line for each conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflicts {
+    pub conflicts: Vec<MergeConflict>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// A package pinned at different versions that would have been resolved
+    /// to the same one if the projects were a single graph. Each version is
+    /// listed with the lockfiles that pinned it.
+    Version {
+        package: PackageName,
+        pins: Vec<(Version, Vec<String>)>,
+    },
+
+    /// A package pinned at the same version but with different contents.
+    Checksum {
+        package: PackageId,
+        pins: Vec<(String, Vec<String>)>,
+    },
+
+    /// A Git dependency pinned at different commits.
+    Commit {
+        name: String,
+        pins: Vec<(String, Vec<String>)>,
+    },
+}
+
+/// Merges `lockfiles` into one that pins every package any of them pins.
+/// Versions of a package that aren't compatible can sit side by side like they
+/// do in one project, but the lockfiles must otherwise agree, and must all be
+/// for the same registry.
+///
+/// Only the default graphs are merged, not targets. Each project's own
+/// package is kept, so the merged lockfile has one root for each project.
+pub fn merge(lockfiles: &[LabelledLockfile]) -> anyhow::Result<Lockfile> {
+    let (first_label, first) = match lockfiles.first() {
+        Some(first) => first,
+        None => bail!("There are no lockfiles to merge"),
+    };
+
+    for (label, lockfile) in lockfiles {
+        if lockfile.registry != first.registry {
+            bail!(
+                "{} uses the registry {}, but {} uses {}, so they can't be merged",
+                first_label,
+                first.registry,
+                label,
+                lockfile.registry
+            );
+        }
+    }
+
+    let mut registry_packages: BTreeMap<PackageId, Vec<(&str, &RegistryLockPackage)>> =
+        BTreeMap::new();
+    let mut git_packages: BTreeMap<&str, Vec<(&str, &GitLockPackage)>> = BTreeMap::new();
+
+    for (label, lockfile) in lockfiles {
+        for lock_package in &lockfile.packages {
+            match lock_package {
+                LockPackage::Registry(package) => registry_packages
+                    .entry(PackageId::new(
+                        package.name.clone(),
+                        package.version.clone(),
+                    ))
+                    .or_default()
+                    .push((label.as_str(), package)),
+                LockPackage::Git(package) => git_packages
+                    .entry(package.name.as_str())
+                    .or_default()
+                    .push((label.as_str(), package)),
+            }
+        }
+    }
+
+    let mut conflicts = version_conflicts(&registry_packages);
+    let mut packages = Vec::new();
+
+    for (package_id, pins) in &registry_packages {
+        let checksums = group_pins(
+            pins.iter()
+                .filter_map(|(label, package)| Some((package.checksum.clone()?, *label))),
+        );
+        if checksums.len() > 1 {
+            conflicts.push(MergeConflict::Checksum {
+                package: package_id.clone(),
+                pins: checksums,
+            });
+        }
+
+        // Any lockfile's pin will do once they agree, but one with a
+        // checksum is the most useful to keep.
+        let (_, package) = pins
+            .iter()
+            .find(|(_, package)| package.checksum.is_some())
+            .unwrap_or(&pins[0]);
+        packages.push(LockPackage::Registry((*package).clone()));
+    }
+
+    for (name, pins) in &git_packages {
+        let commits = group_pins(
+            pins.iter()
+                .map(|(label, package)| (package.commit.clone(), *label)),
+        );
+        if commits.len() > 1 {
+            conflicts.push(MergeConflict::Commit {
+                name: name.to_string(),
+                pins: commits,
+            });
+        }
+
+        packages.push(LockPackage::Git(pins[0].1.clone()));
+    }
+
+    if !conflicts.is_empty() {
+        return Err(MergeConflicts { conflicts }.into());
+    }
+
+    let same_index_rev = lockfiles
+        .iter()
+        .all(|(_, lockfile)| lockfile.index_rev == first.index_rev);
+
+    // Checksums are kept as they're written, so the merged lockfile is only
+    // as new as the oldest one.
+    let version = lockfiles
+        .iter()
+        .map(|(_, lockfile)| lockfile.version)
+        .min()
+        .unwrap_or(first.version);
+
+    Ok(Lockfile {
+        version,
+        registry: first.registry.clone(),
+        conflict_policy: Default::default(),
+        resolved_at: None,
+        index_rev: first.index_rev.clone().filter(|_| same_index_rev),
+        packages,
+        targets: BTreeMap::new(),
+    })
+}
+
+/// Packages that are pinned at more than one compatible version across the
+/// lockfiles. Packages are listed in order, so the versions of each package
+/// are too, and compatible versions sit next to each other.
+fn version_conflicts(
+    registry_packages: &BTreeMap<PackageId, Vec<(&str, &RegistryLockPackage)>>,
+) -> Vec<MergeConflict> {
+    let mut by_name: BTreeMap<&PackageName, Vec<(&Version, Vec<String>)>> = BTreeMap::new();
+    for (package_id, pins) in registry_packages {
+        let labels = pins.iter().map(|(label, _)| label.to_string()).collect();
+        by_name
+            .entry(package_id.name())
+            .or_default()
+            .push((package_id.version(), labels));
+    }
+
+    let mut conflicts = Vec::new();
+
+    for (name, versions) in by_name {
+        let mut groups: Vec<Vec<(Version, Vec<String>)>> = Vec::new();
+
+        for (version, labels) in versions {
+            match groups.last_mut() {
+                Some(group) if compatible(&group[0].0, version, ZeroVersionCompat::Strict) => {
+                    group.push((version.clone(), labels))
+                }
+                _ => groups.push(vec![(version.clone(), labels)]),
+            }
+        }
+
+        for pins in groups {
+            if pins.len() > 1 {
+                conflicts.push(MergeConflict::Version {
+                    package: name.clone(),
+                    pins,
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+/// Groups the lockfiles by what they pinned, in order of what was pinned.
+fn group_pins<'a>(pins: impl Iterator<Item = (String, &'a str)>) -> Vec<(String, Vec<String>)> {
+    let mut grouped: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (pin, label) in pins {
+        grouped.entry(pin).or_default().push(label.to_owned());
+    }
+
+    grouped.into_iter().collect()
+}
+
+fn write_pins<T: fmt::Display>(
+    formatter: &mut fmt::Formatter,
+    pins: &[(T, Vec<String>)],
+) -> fmt::Result {
+    let pins: Vec<String> = pins
+        .iter()
+        .map(|(pin, labels)| format!("{} ({})", pin, labels.join(", ")))
+        .collect();
+
+    write!(formatter, "{}", pins.join(", "))
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeConflict::Version { package, pins } => {
+                write!(formatter, "{} is pinned at conflicting versions: ", package)?;
+                write_pins(formatter, pins)
+            }
+            MergeConflict::Checksum { package, pins } => {
+                write!(
+                    formatter,
+                    "{} is pinned with different checksums: ",
+                    package
+                )?;
+                write_pins(formatter, pins)
+            }
+            MergeConflict::Commit { name, pins } => {
+                write!(formatter, "{} is pinned at different commits: ", name)?;
+                write_pins(formatter, pins)
+            }
+        }
+    }
+}
+
+impl fmt::Display for MergeConflicts {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(formatter, "The lockfiles can't be merged:")?;
+
+        for conflict in &self.conflicts {
+            writeln!(formatter, "  {}", conflict)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for MergeConflicts {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GAME: &str = r#"
+registry = "test"
+
+[[package]]
+name = "biff/game"
+version = "1.0.0"
+dependencies = [["Minimal", "biff/minimal@0.1.0"], ["Shared", "biff/shared@1.0.0"]]
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "sha256:aaa"
+
+[[package]]
+name = "biff/shared"
+version = "1.0.0"
+checksum = "sha256:bbb"
+"#;
+
+    const LOBBY: &str = r#"
+registry = "test"
+
+[[package]]
+name = "biff/lobby"
+version = "1.0.0"
+dependencies = [["Minimal", "biff/minimal@0.2.0"], ["Shared", "biff/shared@1.0.0"]]
+
+[[package]]
+name = "biff/minimal"
+version = "0.2.0"
+checksum = "sha256:ccc"
+
+[[package]]
+name = "biff/shared"
+version = "1.0.0"
+checksum = "sha256:bbb"
+"#;
+
+    fn labelled(label: &str, contents: &str) -> LabelledLockfile {
+        (label.to_owned(), toml::from_str(contents).unwrap())
+    }
+
+    #[test]
+    fn compatible_lockfiles() {
+        let merged = merge(&[labelled("game", GAME), labelled("lobby", LOBBY)]).unwrap();
+
+        let ids: Vec<String> = merged.as_ids().map(|id| id.to_string()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "biff/game@1.0.0",
+                "biff/lobby@1.0.0",
+                "biff/minimal@0.1.0",
+                "biff/minimal@0.2.0",
+                "biff/shared@1.0.0",
+            ]
+        );
+
+        let checksums = merged.checksums();
+        assert_eq!(
+            checksums[&"biff/shared@1.0.0".parse().unwrap()],
+            "sha256:bbb"
+        );
+    }
+
+    #[test]
+    fn conflicting_versions() {
+        let lobby = LOBBY.replace("0.2.0", "0.1.1");
+        let err = merge(&[labelled("game", GAME), labelled("lobby", &lobby)]).unwrap_err();
+
+        let conflicts = err.downcast_ref::<MergeConflicts>().unwrap();
+        assert_eq!(
+            conflicts.conflicts,
+            vec![MergeConflict::Version {
+                package: PackageName::new("biff", "minimal").unwrap(),
+                pins: vec![
+                    (Version::new(0, 1, 0), vec!["game".to_owned()]),
+                    (Version::new(0, 1, 1), vec!["lobby".to_owned()]),
+                ],
+            }]
+        );
+        assert_eq!(
+            err.to_string(),
+            "The lockfiles can't be merged:\n  \
+             biff/minimal is pinned at conflicting versions: 0.1.0 (game), 0.1.1 (lobby)\n"
+        );
+    }
+}
@@ -2,10 +2,13 @@ use std::process::exit;
 
 use structopt::StructOpt;
 
-use libwally::Args;
+use libwally::{default_cache_dir, Args};
 
 fn main() {
-    let args = Args::from_args();
+    let mut args = Args::from_args();
+    if args.global.cache_dir.is_none() {
+        args.global.cache_dir = default_cache_dir();
+    }
 
     let log_filter = match args.global.verbosity {
         0 => "libwally=info",
@@ -1,13 +1,17 @@
 use std::collections::BTreeMap;
-use std::path::Path;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
+use crate::checksum::Checksum;
+use crate::license_policy::LicensePolicy;
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
+use crate::warnings;
 
 pub const MANIFEST_FILE_NAME: &str = "wally.toml";
 
@@ -28,23 +32,193 @@ pub struct Manifest {
 
     #[serde(default)]
     pub dev_dependencies: BTreeMap<String, PackageReq>,
+
+    /// Tooling that's only needed to build the project, like code generators.
+    /// Build dependencies are installed into their own folder outside of the
+    /// runtime package tree, and are never installed for consumers of this
+    /// package.
+    #[serde(default)]
+    pub build_dependencies: BTreeMap<String, PackageReq>,
+
+    /// Packages that a package that depends on this one has to provide, like
+    /// the framework a plugin extends, instead of being installed for this
+    /// package. They're never installed by the resolver, which only checks
+    /// that a package further up the graph depends on a satisfying version.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_dependencies: BTreeMap<String, PackageReq>,
+
+    /// Requirements that replace every request for a package anywhere in the
+    /// dependency graph, no matter which package made the request. Only the
+    /// root manifest's overrides are used during resolution.
+    ///
+    /// Example: `"roblox/roact" = "roblox/roact@1.4.2"`
+    #[serde(default)]
+    pub overrides: BTreeMap<PackageName, PackageReq>,
+
+    /// Dependencies that are only used when installing for one of the targets
+    /// declared in `package.targets`, like `[target.plugin.dependencies]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target: BTreeMap<String, TargetDependencies>,
+
+    /// The licenses that packages in the dependency graph may have. Only the
+    /// root manifest's policy is enforced, by `wally install`.
+    #[serde(default, skip_serializing_if = "LicensePolicy::is_empty")]
+    pub license_policy: LicensePolicy,
+
+    /// Commands that can be run in the project's folder with `wally run`,
+    /// like `lint = "selene src"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scripts: BTreeMap<String, String>,
+
+    /// Dependencies that can be turned on or off, by alias, and whether
+    /// they're installed by default. These come from the `optional` and
+    /// `default` keys of dependencies written in their table form. Unlike the
+    /// other keys, they're kept in the index so that packages further down
+    /// the graph only install their optional dependencies that are on by
+    /// default.
+    ///
+    /// Example: `Foo = { name = "biff/foo", version = "^1", optional = true, default = true }`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub optional_dependencies: BTreeMap<String, bool>,
+
+    /// Registries that specific packages must be pulled from instead of this
+    /// package's registry and its fallbacks. These come from dependencies
+    /// written in their table form, and only the root manifest's are used
+    /// during resolution.
+    ///
+    /// Example: `Foo = { name = "biff/foo", version = "^1", registry = "https://..." }`
+    #[serde(skip)]
+    pub dependency_registries: BTreeMap<PackageName, String>,
+
+    /// Folders on disk that specific packages are installed from instead of a
+    /// registry. These come from dependencies written in their table form.
+    /// Like `dependency_registries`, only the root manifest's are used.
+    ///
+    /// Example: `Foo = { path = "../monorepo", subdir = "packages/foo" }`
+    #[serde(skip)]
+    pub dependency_paths: BTreeMap<PackageName, PathBuf>,
+
+    /// Folders, relative to the project, that specific packages are installed
+    /// into instead of the usual package folders. These come from the `dest`
+    /// key of dependencies written in their table form, and only the root
+    /// manifest's are used.
+    ///
+    /// Example: `Foo = { name = "biff/foo", version = "^1", dest = "Plugins/Foo" }`
+    #[serde(skip)]
+    pub dependency_destinations: BTreeMap<PackageName, PathBuf>,
+
+    /// Checksums that the contents of specific packages must have, whatever
+    /// the registry or lockfile says. These come from the `checksum` key of
+    /// dependencies written in their table form, which must require an exact
+    /// version, and only the root manifest's are used.
+    ///
+    /// Example: `Foo = { name = "biff/foo", version = "=1.0.0", checksum = "sha256:..." }`
+    #[serde(skip)]
+    pub dependency_checksums: BTreeMap<PackageId, Checksum>,
+
+    /// How many bytes the package's contents take up once extracted. The
+    /// registry records this in the index when the package is published, so
+    /// it's never written in a `wally.toml`, and older index entries don't
+    /// have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extracted_size: Option<u64>,
+
+    /// The checksum of the package's contents as they were published, like
+    /// `sha256:...`. Like `extracted_size`, only the registry records this,
+    /// and older index entries don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+
+    /// Whether the registry recommends this version over others that satisfy
+    /// the same requirements, like when a newer compatible release is known
+    /// to be slow. Resolving with `--prefer-recommended` picks recommended
+    /// versions first. Like `checksum`, only the registry sets this.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub recommended: bool,
 }
 
+/// The tables that dependencies can be listed in, both at the top level of a
+/// manifest and within each target.
+pub(crate) const DEPENDENCY_TABLES: &[&str] = &[
+    "dependencies",
+    "server-dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+];
+
 impl Manifest {
     /// Load a manifest from a project directory containing a `wally.toml` file.
+    ///
+    /// If the manifest `extends` another manifest, the inherited manifests are
+    /// flattened into this one first, with local entries taking precedence.
     pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        Self::load_file(&dir.join(MANIFEST_FILE_NAME))
+    }
+
+    /// Load a manifest from a file that may have any name, like one that's
+    /// generated by other tooling. Paths in it are relative to its folder.
+    pub fn load_file(file_path: &Path) -> anyhow::Result<Self> {
+        let dir = file_path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut value = load_flattened(file_path, &mut Vec::new())?;
+        assign_dependency_realms(&mut value)
+            .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
+        let ambiguities = apply_default_scope(&mut value)
+            .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
+        for ambiguity in ambiguities {
+            warnings::warn(ambiguity);
+        }
+        let sources = take_dependency_sources(&mut value, Some(dir))
+            .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
+
+        let mut manifest: Manifest = value
+            .try_into()
+            .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
+        manifest.dependency_registries = sources.registries;
+        manifest.dependency_paths = sources.paths;
+        manifest.dependency_destinations = sources.destinations;
+        manifest.dependency_checksums = sources.checksums;
+        manifest.optional_dependencies.extend(sources.optional);
+
+        manifest
+            .validate()
+            .with_context(|| format!("invalid manifest at path {}", file_path.display()))?;
+
+        Ok(manifest)
+    }
+
+    /// Tells whether the manifest in the given project directory extends
+    /// another manifest, without flattening it.
+    pub fn is_extended(dir: &Path) -> anyhow::Result<bool> {
         let file_path = dir.join(MANIFEST_FILE_NAME);
 
         let content = fs_err::read_to_string(&file_path)?;
-        let manifest: Manifest = toml::from_str(&content)
+        let value: toml::Value = toml::from_str(&content)
             .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
 
-        Ok(manifest)
+        Ok(value.get("extends").is_some())
     }
 
     pub fn from_slice(slice: &[u8]) -> anyhow::Result<Self> {
-        let manifest: Manifest =
+        let mut value: toml::Value =
             toml::from_slice(slice).with_context(|| format!("failed to parse manifest"))?;
+        assign_dependency_realms(&mut value).context("failed to parse manifest")?;
+        for ambiguity in apply_default_scope(&mut value).context("failed to parse manifest")? {
+            warnings::warn(ambiguity);
+        }
+        let sources =
+            take_dependency_sources(&mut value, None).context("failed to parse manifest")?;
+
+        let mut manifest: Manifest = value
+            .try_into()
+            .with_context(|| format!("failed to parse manifest"))?;
+        manifest.dependency_registries = sources.registries;
+        manifest.dependency_paths = sources.paths;
+        manifest.dependency_destinations = sources.destinations;
+        manifest.dependency_checksums = sources.checksums;
+        manifest.optional_dependencies.extend(sources.optional);
+
+        manifest.validate()?;
 
         Ok(manifest)
     }
@@ -52,6 +226,778 @@ impl Manifest {
     pub fn package_id(&self) -> PackageId {
         PackageId::new(self.package.name.clone(), self.package.version.clone())
     }
+
+    /// Returns the manifest as seen when installing for the given target, with
+    /// that target's dependencies added to the regular dependency tables.
+    /// Target-specific entries take precedence over ones with the same alias.
+    pub fn for_target(&self, target: Option<&str>) -> anyhow::Result<Manifest> {
+        let mut manifest = self.clone();
+
+        let target = match target {
+            Some(target) => target,
+            None => return Ok(manifest),
+        };
+
+        if !self
+            .package
+            .targets
+            .iter()
+            .any(|declared| declared == target)
+        {
+            bail!(
+                "{} does not declare a target named `{}`",
+                self.package.name,
+                target
+            );
+        }
+
+        if let Some(dependencies) = self.target.get(target) {
+            manifest
+                .dependencies
+                .extend(dependencies.dependencies.clone());
+            manifest
+                .server_dependencies
+                .extend(dependencies.server_dependencies.clone());
+            manifest
+                .dev_dependencies
+                .extend(dependencies.dev_dependencies.clone());
+            manifest
+                .build_dependencies
+                .extend(dependencies.build_dependencies.clone());
+        }
+
+        Ok(manifest)
+    }
+
+    /// Returns the manifest with the optional dependencies that aren't turned
+    /// on left out. Optional dependencies that are on by default stay unless
+    /// `no_default_features` is set, and the ones named in `features` are
+    /// always turned on.
+    pub fn with_features(
+        &self,
+        no_default_features: bool,
+        features: &[String],
+    ) -> anyhow::Result<Manifest> {
+        for feature in features {
+            if !self.optional_dependencies.contains_key(feature) {
+                bail!(
+                    "{} does not have an optional dependency named `{}`",
+                    self.package.name,
+                    feature
+                );
+            }
+        }
+
+        let mut manifest = self.clone();
+
+        for (alias, &default) in &self.optional_dependencies {
+            if features.contains(alias) || (default && !no_default_features) {
+                continue;
+            }
+
+            manifest.dependencies.remove(alias);
+            manifest.server_dependencies.remove(alias);
+            manifest.dev_dependencies.remove(alias);
+            manifest.build_dependencies.remove(alias);
+        }
+
+        Ok(manifest)
+    }
+
+    /// Tells whether a dependency is installed when nothing turns it on or
+    /// off, which is how every package below the root is installed.
+    pub fn is_enabled_by_default(&self, alias: &str) -> bool {
+        self.optional_dependencies
+            .get(alias)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Whether this package can be used as a dependency of `request_realm`.
+    /// Dev and build dependencies never run in a realm of their own, so they
+    /// can use any package.
+    pub fn supports_realm(&self, request_realm: Realm) -> bool {
+        match (&self.package.realms, request_realm) {
+            (_, Realm::Dev) | (_, Realm::Build) => true,
+            (Some(realms), realm) => realms.contains(&realm),
+            (None, realm) => Realm::is_dependency_valid(realm, self.package.realm),
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(realms) = &self.package.realms {
+            if realms.is_empty() {
+                bail!("package.realms must list at least one realm");
+            }
+
+            if let Some(realm) = realms
+                .iter()
+                .find(|realm| !matches!(realm, Realm::Shared | Realm::Server))
+            {
+                bail!(
+                    "package.realms can only list \"shared\" or \"server\", but found \"{}\"",
+                    realm
+                );
+            }
+        }
+
+        let destinations: Vec<_> = self.dependency_destinations.iter().collect();
+        for (index, (name, dest)) in destinations.iter().enumerate() {
+            for (other_name, other_dest) in &destinations[index + 1..] {
+                if dest.starts_with(other_dest) || other_dest.starts_with(dest) {
+                    bail!(
+                        "{} and {} are installed into overlapping destinations ({} and {})",
+                        name,
+                        other_name,
+                        dest.display(),
+                        other_dest.display()
+                    );
+                }
+            }
+        }
+
+        let tables = [
+            ("dependencies", &self.dependencies),
+            ("server-dependencies", &self.server_dependencies),
+            ("dev-dependencies", &self.dev_dependencies),
+            ("build-dependencies", &self.build_dependencies),
+        ];
+        for (table, dependencies) in &tables {
+            check_aliases(table, dependencies.keys())?;
+        }
+
+        for target in self.target.keys() {
+            if !self.package.targets.contains(target) {
+                bail!(
+                    "dependencies are listed for target `{}`, which is not one of the targets \
+                     declared in package.targets",
+                    target
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Names that can't be used as dependency aliases, since the links Wally
+/// generates for dependencies sit next to folders with these names.
+const RESERVED_ALIASES: &[&str] = &[
+    "_Index",
+    "Packages",
+    "ServerPackages",
+    "DevPackages",
+    "BuildPackages",
+];
+
+/// Checks that every alias in a dependency table gets a link file of its own.
+/// Aliases are compared without case, since links that only differ by case
+/// overwrite each other on case-insensitive file systems.
+fn check_aliases<'a>(
+    table: &str,
+    aliases: impl IntoIterator<Item = &'a String>,
+) -> anyhow::Result<()> {
+    let mut seen: BTreeMap<String, &String> = BTreeMap::new();
+
+    for alias in aliases {
+        let reserved = RESERVED_ALIASES
+            .iter()
+            .find(|reserved| reserved.eq_ignore_ascii_case(alias));
+        if let Some(reserved) = reserved {
+            bail!(
+                "dependency alias {} in {} conflicts with the {} folder Wally installs into",
+                alias,
+                table,
+                reserved
+            );
+        }
+
+        if let Some(other) = seen.insert(alias.to_lowercase(), alias) {
+            bail!(
+                "dependency aliases {} and {} in {} only differ by case, so their links would \
+                 overwrite each other on case-insensitive file systems",
+                other,
+                alias,
+                table
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the manifest at `file_path` as a TOML value, following its `extends`
+/// key to any base manifests. `chain` holds the manifests visited so far and is
+/// used to reject cycles.
+pub(crate) fn load_flattened(
+    file_path: &Path,
+    chain: &mut Vec<PathBuf>,
+) -> anyhow::Result<toml::Value> {
+    let canonical_path = fs_err::canonicalize(file_path)?;
+
+    if chain.contains(&canonical_path) {
+        let cycle: Vec<_> = chain
+            .iter()
+            .chain(Some(&canonical_path))
+            .map(|path| path.display().to_string())
+            .collect();
+
+        bail!(
+            "manifest at path {} extends itself ({})",
+            file_path.display(),
+            cycle.join(" -> ")
+        );
+    }
+
+    chain.push(canonical_path);
+
+    let content = fs_err::read_to_string(file_path)?;
+    let mut value: toml::Value = toml::from_str(&content)
+        .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))?;
+
+    let extends = match value
+        .as_table_mut()
+        .and_then(|table| table.remove("extends"))
+    {
+        Some(toml::Value::String(extends)) => extends,
+        Some(_) => bail!(
+            "`extends` in manifest at path {} must be a path to another manifest",
+            file_path.display()
+        ),
+        None => return Ok(value),
+    };
+
+    // Paths in `extends` are relative to the manifest that contains them.
+    let base_path = file_path
+        .parent()
+        .map(|parent| parent.join(&extends))
+        .unwrap_or_else(|| PathBuf::from(&extends));
+
+    let mut base = load_flattened(&base_path, chain).with_context(|| {
+        format!(
+            "failed to load manifest {} extended by {}",
+            extends,
+            file_path.display()
+        )
+    })?;
+
+    merge_values(&mut base, value);
+    Ok(base)
+}
+
+/// Moves each entry of the `[dependencies]` tables into the table for its
+/// realm. Entries use `package.default-realm`, or `shared` if it isn't set,
+/// unless they're written as a table with a `realm` of their own.
+fn assign_dependency_realms(value: &mut toml::Value) -> anyhow::Result<()> {
+    let root = match value.as_table_mut() {
+        Some(root) => root,
+        None => return Ok(()),
+    };
+
+    let default_realm = match root
+        .get("package")
+        .and_then(|package| package.get("default-realm"))
+    {
+        Some(realm) => runtime_realm(realm).context("invalid package.default-realm")?,
+        None => Realm::Shared,
+    };
+
+    move_dependencies_to_realms(root, default_realm)?;
+
+    if let Some(targets) = root.get_mut("target").and_then(toml::Value::as_table_mut) {
+        for target in targets.values_mut().filter_map(toml::Value::as_table_mut) {
+            move_dependencies_to_realms(target, default_realm)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the server entries of a `[dependencies]` table, as decided by
+/// `assign_dependency_realms`, into the `[server-dependencies]` table next to
+/// it.
+fn move_dependencies_to_realms(
+    tables: &mut toml::value::Table,
+    default_realm: Realm,
+) -> anyhow::Result<()> {
+    let mut server = Vec::new();
+
+    if let Some(dependencies) = tables
+        .get_mut("dependencies")
+        .and_then(toml::Value::as_table_mut)
+    {
+        let mut aliases = Vec::new();
+
+        for (alias, dependency) in dependencies.iter_mut() {
+            let realm = match dependency
+                .as_table_mut()
+                .and_then(|spec| spec.remove("realm"))
+            {
+                Some(realm) => runtime_realm(&realm)
+                    .with_context(|| format!("dependency {} has an invalid realm", alias))?,
+                None => default_realm,
+            };
+
+            if realm == Realm::Server {
+                aliases.push(alias.clone());
+            }
+        }
+
+        for alias in aliases {
+            let dependency = dependencies.remove(&alias).unwrap();
+            server.push((alias, dependency));
+        }
+    }
+
+    if server.is_empty() {
+        return Ok(());
+    }
+
+    if !tables.contains_key("server-dependencies") {
+        tables.insert(
+            "server-dependencies".to_owned(),
+            toml::Value::Table(Default::default()),
+        );
+    }
+
+    let server_dependencies = tables
+        .get_mut("server-dependencies")
+        .and_then(toml::Value::as_table_mut)
+        .ok_or_else(|| anyhow::format_err!("server-dependencies must be a table"))?;
+
+    for (alias, dependency) in server {
+        if server_dependencies.contains_key(&alias) {
+            bail!(
+                "{} is listed in both dependencies and server-dependencies, and both are in \
+                 the server realm",
+                alias
+            );
+        }
+
+        server_dependencies.insert(alias, dependency);
+    }
+
+    Ok(())
+}
+
+/// Reads the realm of dependencies that are installed at runtime, which can
+/// only be `shared` or `server`.
+fn runtime_realm(value: &toml::Value) -> anyhow::Result<Realm> {
+    match value.as_str() {
+        Some("shared") => Ok(Realm::Shared),
+        Some("server") => Ok(Realm::Server),
+        _ => bail!("expected \"shared\" or \"server\", but found {}", value),
+    }
+}
+
+/// Every dependency table in the manifest, including the ones under
+/// `[target.<name>]`.
+fn dependency_tables(root: &mut toml::value::Table) -> Vec<&mut toml::value::Table> {
+    let mut tables = Vec::new();
+
+    for (key, table) in root.iter_mut() {
+        if key == "target" {
+            let targets = table
+                .as_table_mut()
+                .into_iter()
+                .flat_map(|targets| targets.values_mut());
+
+            for target in targets.filter_map(toml::Value::as_table_mut) {
+                for (key, table) in target.iter_mut() {
+                    if DEPENDENCY_TABLES.contains(&key.as_str()) {
+                        tables.extend(table.as_table_mut());
+                    }
+                }
+            }
+        } else if DEPENDENCY_TABLES.contains(&key.as_str()) {
+            tables.extend(table.as_table_mut());
+        }
+    }
+
+    tables
+}
+
+/// Puts dependency names written without a scope, like `utils@1.0.0` or a
+/// table with `name = "utils"`, in `package.default-scope`. Names that have a
+/// scope are left alone.
+///
+/// Returns a warning for each bare name whose package is also depended on
+/// from another scope, since it's easy to have meant that one.
+fn apply_default_scope(value: &mut toml::Value) -> anyhow::Result<Vec<String>> {
+    let root = match value.as_table_mut() {
+        Some(root) => root,
+        None => return Ok(Vec::new()),
+    };
+
+    let scope = match root
+        .get("package")
+        .and_then(|package| package.get("default-scope"))
+    {
+        Some(scope) => scope
+            .as_str()
+            .ok_or_else(|| anyhow::format_err!("package.default-scope must be a string"))?
+            .to_owned(),
+        None => return Ok(Vec::new()),
+    };
+
+    let mut expanded = Vec::new();
+    let mut qualified = Vec::new();
+
+    for table in dependency_tables(root) {
+        for (alias, dependency) in table.iter_mut() {
+            // Path dependencies have no name to expand.
+            let spec = match dependency {
+                toml::Value::String(spec) => spec,
+                toml::Value::Table(fields) => match fields.get_mut("name") {
+                    Some(toml::Value::String(name)) => name,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let name = spec.split('@').next().unwrap_or_default();
+            if name.contains('/') {
+                qualified.extend(name.parse::<PackageName>().ok());
+                continue;
+            }
+
+            let name = PackageName::new(&scope, name).with_context(|| {
+                format!(
+                    "dependency {} can't be put in package.default-scope {}",
+                    alias, scope
+                )
+            })?;
+
+            *spec = format!("{}/{}", scope, spec);
+            expanded.push((alias.clone(), name));
+        }
+    }
+
+    let ambiguities = expanded
+        .iter()
+        .filter_map(|(alias, name)| {
+            let other = qualified
+                .iter()
+                .find(|other| other.name() == name.name() && other.scope() != name.scope())?;
+
+            Some(format!(
+                "dependency {} is taken to be {} from package.default-scope, but {} is also a \
+                 dependency. Write out its scope to make it clear which one is meant.",
+                alias, name, other
+            ))
+        })
+        .collect();
+
+    Ok(ambiguities)
+}
+
+/// Where the dependencies written in their table form come from.
+#[derive(Default)]
+struct DependencySources {
+    registries: BTreeMap<PackageName, String>,
+    paths: BTreeMap<PackageName, PathBuf>,
+    destinations: BTreeMap<PackageName, PathBuf>,
+    checksums: BTreeMap<PackageId, Checksum>,
+    optional: BTreeMap<String, bool>,
+}
+
+/// The folders that packages are normally installed into, which a dependency's
+/// `dest` can't point inside of.
+const PACKAGE_FOLDERS: &[&str] = &["Packages", "ServerPackages", "DevPackages", "BuildPackages"];
+
+/// Rewrites dependencies written as tables into the usual
+/// `SCOPE/NAME@VERSION_REQ` form, returning where each of those packages
+/// should come from. `{ name, version, registry }` tables pull from a
+/// registry, or from the package's own registry if `registry` is left out,
+/// while `{ path, subdir }` tables point at a package on disk, relative to
+/// `project_dir`. Path dependencies are only allowed when `project_dir` is
+/// given. Either kind can have a `dest` to install the package somewhere else
+/// in the project, and registry dependencies can have a `checksum` that the
+/// exact version they require must match and an `exclude` list of versions to
+/// skip. Either kind can also be `optional`, and turned on by `default`.
+fn take_dependency_sources(
+    value: &mut toml::Value,
+    project_dir: Option<&Path>,
+) -> anyhow::Result<DependencySources> {
+    let mut sources = DependencySources::default();
+
+    let root = match value.as_table_mut() {
+        Some(root) => root,
+        None => return Ok(sources),
+    };
+
+    for table in dependency_tables(root) {
+        for (alias, dependency) in table.iter_mut() {
+            let spec = match dependency.as_table() {
+                Some(spec) => spec,
+                None => continue,
+            };
+
+            let field = |field: &str| {
+                spec.get(field)
+                    .and_then(toml::Value::as_str)
+                    .ok_or_else(|| {
+                        anyhow::format_err!(
+                            "dependency {} must have a `{}` string when written as a table",
+                            alias,
+                            field
+                        )
+                    })
+            };
+
+            let flag = |field: &str| -> anyhow::Result<bool> {
+                match spec.get(field) {
+                    None => Ok(false),
+                    Some(value) => value.as_bool().ok_or_else(|| {
+                        anyhow::format_err!(
+                            "dependency {} must have a `{}` boolean when written as a table",
+                            alias,
+                            field
+                        )
+                    }),
+                }
+            };
+
+            let default = flag("default")?;
+            if flag("optional")? {
+                sources.optional.insert(alias.clone(), default);
+            } else if spec.contains_key("default") {
+                bail!(
+                    "dependency {} has a `default`, which only applies to optional dependencies",
+                    alias
+                );
+            }
+
+            if spec.contains_key("git") {
+                bail!(
+                    "dependency {} is a git dependency, which isn't supported yet. Check the \
+                     repository out and use a path dependency, with a `subdir` if needed, \
+                     instead",
+                    alias
+                );
+            }
+
+            let dest = if spec.contains_key("dest") {
+                Some(destination(alias, field("dest")?)?)
+            } else {
+                None
+            };
+
+            let mut add_destination = |name: &PackageName| -> anyhow::Result<()> {
+                let dest = match &dest {
+                    Some(dest) => dest,
+                    None => return Ok(()),
+                };
+
+                if let Some(existing) = sources.destinations.get(name) {
+                    if existing != dest {
+                        bail!(
+                            "{} is listed with two different destinations ({} and {})",
+                            name,
+                            existing.display(),
+                            dest.display()
+                        );
+                    }
+                }
+
+                sources.destinations.insert(name.clone(), dest.clone());
+                Ok(())
+            };
+
+            if spec.contains_key("path") {
+                if spec.contains_key("checksum") {
+                    bail!(
+                        "dependency {} is a path dependency, which can't have a checksum since \
+                         its contents can change at any time",
+                        alias
+                    );
+                }
+
+                let project_dir = project_dir.ok_or_else(|| {
+                    anyhow::format_err!(
+                        "dependency {} is a path dependency, which can only be used in a \
+                         project's own manifest",
+                        alias
+                    )
+                })?;
+
+                let mut path = project_dir.join(field("path")?);
+                if spec.contains_key("subdir") {
+                    path.push(field("subdir")?);
+                }
+
+                let package = load_path_dependency(&path)
+                    .with_context(|| format!("failed to load path dependency {}", alias))?;
+
+                add_destination(&package.name)?;
+                *dependency = toml::Value::String(format!("{}@={}", package.name, package.version));
+                sources.paths.insert(package.name, path);
+                continue;
+            }
+
+            let name: PackageName = field("name")?.parse()?;
+            let mut package_req = format!("{}@{}", name, field("version")?);
+            if let Some(excluded) = spec.get("exclude") {
+                let excluded = excluded.as_array().ok_or_else(|| {
+                    anyhow::format_err!(
+                        "dependency {} must have an `exclude` list of versions",
+                        alias
+                    )
+                })?;
+
+                for version in excluded {
+                    let version = version
+                        .as_str()
+                        .and_then(|version| Version::parse(version).ok())
+                        .ok_or_else(|| {
+                            anyhow::format_err!(
+                                "dependency {} excludes {}, which isn't a version",
+                                alias,
+                                version
+                            )
+                        })?;
+
+                    package_req.push_str(&format!(", !={}", version));
+                }
+            }
+            package_req.parse::<PackageReq>()?;
+            add_destination(&name)?;
+
+            if spec.contains_key("registry") {
+                let registry = field("registry")?.to_owned();
+
+                if let Some(existing) = sources.registries.get(&name) {
+                    if existing != &registry {
+                        bail!(
+                            "{} is listed with two different registries ({} and {})",
+                            name,
+                            existing,
+                            registry
+                        );
+                    }
+                }
+
+                sources.registries.insert(name.clone(), registry);
+            }
+
+            if spec.contains_key("checksum") {
+                let checksum: Checksum = field("checksum")?
+                    .parse()
+                    .with_context(|| format!("dependency {} has an invalid checksum", alias))?;
+
+                // A checksum only describes one version's contents.
+                let version_req = field("version")?.trim();
+                let version = version_req
+                    .strip_prefix('=')
+                    .and_then(|version| Version::parse(version.trim()).ok())
+                    .ok_or_else(|| {
+                        anyhow::format_err!(
+                            "dependency {} has a checksum, so it must require an exact version \
+                             like `={}`",
+                            alias,
+                            version_req.trim_start_matches('^')
+                        )
+                    })?;
+                let package_id = PackageId::new(name.clone(), version);
+
+                if let Some(existing) = sources.checksums.get(&package_id) {
+                    if existing != &checksum {
+                        bail!(
+                            "{} is listed with two different checksums ({} and {})",
+                            package_id,
+                            existing,
+                            checksum
+                        );
+                    }
+                }
+
+                sources.checksums.insert(package_id, checksum);
+            }
+
+            *dependency = toml::Value::String(package_req);
+        }
+    }
+
+    Ok(sources)
+}
+
+/// Checks a dependency's `dest`, which must be a relative path that stays
+/// within the project and outside of the usual package folders.
+fn destination(alias: &str, dest: &str) -> anyhow::Result<PathBuf> {
+    let mut path = PathBuf::new();
+
+    for component in Path::new(dest).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => bail!(
+                "dependency {} has a dest of {}, which must be a relative path within the project",
+                alias,
+                dest
+            ),
+        }
+    }
+
+    let first = match path.components().next() {
+        Some(first) => first.as_os_str().to_string_lossy(),
+        None => bail!("dependency {} has an empty dest", alias),
+    };
+
+    if PACKAGE_FOLDERS
+        .iter()
+        .any(|folder| folder.eq_ignore_ascii_case(&first))
+    {
+        bail!(
+            "dependency {} has a dest of {}, which is inside a folder Wally installs packages into",
+            alias,
+            dest
+        );
+    }
+
+    Ok(path)
+}
+
+/// Reads the package metadata of a path dependency, which must be a folder
+/// containing a manifest. Its own dependencies are left alone, since only the
+/// root manifest's path dependencies are ever used.
+fn load_path_dependency(path: &Path) -> anyhow::Result<Package> {
+    let file_path = path.join(MANIFEST_FILE_NAME);
+
+    if !file_path.is_file() {
+        bail!(
+            "{} does not contain a {}",
+            path.display(),
+            MANIFEST_FILE_NAME
+        );
+    }
+
+    let value = load_flattened(&file_path, &mut Vec::new())?;
+    let package = value
+        .get("package")
+        .cloned()
+        .ok_or_else(|| anyhow::format_err!("{} has no [package] table", file_path.display()))?;
+
+    package
+        .try_into()
+        .with_context(|| format!("failed to parse manifest at path {}", file_path.display()))
+}
+
+/// Merges `local` on top of `base`. Tables are merged key by key so that local
+/// entries override inherited ones, while any other value is replaced outright.
+fn merge_values(base: &mut toml::Value, local: toml::Value) {
+    match (base, local) {
+        (toml::Value::Table(base), toml::Value::Table(local)) => {
+            for (key, value) in local {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, local) => *base = local,
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -80,6 +1026,30 @@ pub struct Package {
     /// Example: `shared`, `server`
     pub realm: Realm,
 
+    /// The realms that other packages can depend on this package from. If
+    /// it's left out, the package can be used wherever its `realm` allows:
+    /// `shared` packages from either realm, and `server` packages only from
+    /// the server.
+    ///
+    /// Example: ["shared", "server"]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realms: Option<Vec<Realm>>,
+
+    /// The realm that entries of `[dependencies]` are put in, unless they're
+    /// written as a table with a `realm` of their own. It's applied while the
+    /// manifest is parsed, so it's never written back out.
+    ///
+    /// Example: `shared`, `server`
+    #[serde(rename = "default-realm", default, skip_serializing)]
+    pub default_realm: Option<Realm>,
+
+    /// The scope that dependency names written without one are taken to be
+    /// in. Like `default-realm`, it's applied while the manifest is parsed.
+    ///
+    /// Example: `biff`
+    #[serde(rename = "default-scope", default, skip_serializing)]
+    pub default_scope: Option<String>,
+
     /// A short description of the package.
     ///
     /// Example: `A game about adopting things.`
@@ -96,6 +1066,12 @@ pub struct Package {
     #[serde(default)]
     pub authors: Vec<String>,
 
+    /// Where the package's source code can be found.
+    ///
+    /// Example: `https://github.com/UpliftGames/wally`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+
     /// A list of paths to include in the package. Glob patterns are supported.
     ///
     /// By default all directories and files are included except files generated
@@ -126,6 +1102,75 @@ pub struct Package {
     /// Example: true
     #[serde(default)]
     pub private: bool,
+
+    /// How the resolver settles a request for a package that conflicts with a
+    /// version it already picked. Only the root manifest's policy is used.
+    ///
+    /// Example: `error`, `newest`
+    #[serde(
+        rename = "conflict-policy",
+        default,
+        skip_serializing_if = "ConflictPolicy::is_default"
+    )]
+    pub conflict_policy: ConflictPolicy,
+
+    /// Whether a requirement on a `0.x` version also allows later `0.x`
+    /// versions. Only the root manifest's setting is used.
+    ///
+    /// Example: `strict`, `relaxed`
+    #[serde(
+        rename = "zero-version-compat",
+        default,
+        skip_serializing_if = "ZeroVersionCompat::is_default"
+    )]
+    pub zero_version_compat: ZeroVersionCompat,
+
+    /// The environments this package can be installed for, in addition to the
+    /// default one. Each target can list extra dependencies under
+    /// `[target.<name>.dependencies]`.
+    ///
+    /// Example: ["plugin"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+
+    /// How many days the lockfile can go without being refreshed by `wally
+    /// update` before `wally install` warns that it's stale. Setting this makes
+    /// the lockfile record when it was last refreshed. Only the root
+    /// manifest's setting is used.
+    ///
+    /// Example: 90
+    #[serde(
+        rename = "lockfile-max-age",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub lockfile_max_age: Option<u64>,
+
+    /// The folder, relative to the project, that installs extract packages
+    /// into before moving them into the package folders. It should be on the
+    /// same filesystem as the project so that moving a package is a rename.
+    /// Only the root manifest's setting is used.
+    ///
+    /// Example: ".wally-tmp"
+    #[serde(rename = "temp-dir", default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+}
+
+/// The dependency tables that can be listed under a `[target.<name>]` section.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetDependencies {
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, PackageReq>,
+
+    #[serde(default)]
+    pub server_dependencies: BTreeMap<String, PackageReq>,
+
+    #[serde(default)]
+    pub dev_dependencies: BTreeMap<String, PackageReq>,
+
+    #[serde(default)]
+    pub build_dependencies: BTreeMap<String, PackageReq>,
 }
 
 // Metadata we require when this manifest will be used to generate package folders
@@ -144,6 +1189,17 @@ pub struct PlaceInfo {
     /// Example: `game.ServerScriptStorage.Packages`
     #[serde(default)]
     pub server_packages: Option<String>,
+
+    /// How generated link files require the packages they link to.
+    #[serde(default, skip_serializing_if = "LinkStyle::is_default")]
+    pub link_style: LinkStyle,
+
+    /// The module that link files pass packages through with the `loader`
+    /// link style.
+    ///
+    /// Example: `game.ReplicatedStorage.WallyLoader`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_loader: Option<String>,
 }
 
 impl Default for PlaceInfo {
@@ -151,16 +1207,111 @@ impl Default for PlaceInfo {
         Self {
             shared_packages: None,
             server_packages: None,
+            link_style: LinkStyle::default(),
+            link_loader: None,
         }
     }
 }
 
+/// How generated link files require the packages they link to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// Require packages relative to the link, through `script.Parent`, and
+    /// through the paths in `[place]` between package folders.
+    Relative,
+
+    /// Require packages through `game:GetService`, using the paths in
+    /// `[place]` for every package folder that has one.
+    Service,
+
+    /// Pass each package's module to the function returned by the
+    /// `link-loader` module instead of requiring it directly.
+    Loader,
+}
+
+impl LinkStyle {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for LinkStyle {
+    fn default() -> Self {
+        LinkStyle::Relative
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Realm {
     Server,
     Shared,
     Dev,
+    Build,
+}
+
+/// What the resolver does when a request for a package conflicts with a
+/// semver-compatible version of that package that was already picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Report the conflict as an error.
+    Error,
+
+    /// Pick the newest version that satisfies every request for the package,
+    /// only reporting an error if there's no such version.
+    Newest,
+}
+
+impl ConflictPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> Self {
+        ConflictPolicy::Error
+    }
+}
+
+/// How requirements on `0.x` versions are matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZeroVersionCompat {
+    /// Follow SemVer, where each `0.x` minor version is incompatible with the
+    /// others, so `^0.2` only allows `0.2.x`.
+    Strict,
+
+    /// Treat later `0.x` versions as compatible, so `^0.2` allows `0.3.0` but
+    /// still not `1.0.0`.
+    Relaxed,
+}
+
+impl ZeroVersionCompat {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for ZeroVersionCompat {
+    fn default() -> Self {
+        ZeroVersionCompat::Strict
+    }
+}
+
+impl fmt::Display for Realm {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Realm::Server => "server",
+            Realm::Shared => "shared",
+            Realm::Dev => "dev",
+            Realm::Build => "build",
+        };
+
+        formatter.write_str(name)
+    }
 }
 
 impl Realm {
@@ -169,7 +1320,548 @@ impl Realm {
 
         matches!(
             (dep_type, dep_realm),
-            (Server, _) | (Shared, Shared) | (Dev, _)
+            (Server, _) | (Shared, Shared) | (Dev, _) | (Build, _)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn extends_inherits_and_overrides() {
+        let dir = tempdir().unwrap();
+        let project_dir = dir.path().join("project");
+        fs_err::create_dir(&project_dir).unwrap();
+
+        fs_err::write(
+            dir.path().join("base.toml"),
+            r#"
+            [package]
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+            license = "MIT"
+
+            [dependencies]
+            Promise = "evaera/promise@2.0.1"
+            Roact = "roblox/roact@1.2.0"
+            "#,
+        )
+        .unwrap();
+
+        fs_err::write(
+            project_dir.join(MANIFEST_FILE_NAME),
+            r#"
+            extends = "../base.toml"
+
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            license = "Apache-2.0"
+
+            [dependencies]
+            Roact = "roblox/roact@1.4.2"
+            "#,
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(&project_dir).unwrap();
+
+        assert_eq!(manifest.package.name.to_string(), "biff/project");
+        assert_eq!(
+            manifest.package.registry,
+            "https://github.com/UpliftGames/wally-index"
+        );
+        assert_eq!(manifest.package.realm, Realm::Shared);
+        assert_eq!(manifest.package.license.as_deref(), Some("Apache-2.0"));
+
+        let promise: PackageReq = "evaera/promise@2.0.1".parse().unwrap();
+        let roact: PackageReq = "roblox/roact@1.4.2".parse().unwrap();
+        assert_eq!(manifest.dependencies.get("Promise"), Some(&promise));
+        assert_eq!(manifest.dependencies.get("Roact"), Some(&roact));
+    }
+
+    #[test]
+    fn extends_cycle() {
+        let dir = tempdir().unwrap();
+
+        fs_err::write(
+            dir.path().join(MANIFEST_FILE_NAME),
+            r#"
+            extends = "base.toml"
+
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            "#,
         )
+        .unwrap();
+
+        fs_err::write(
+            dir.path().join("base.toml"),
+            r#"
+            extends = "wally.toml"
+            "#,
+        )
+        .unwrap();
+
+        let err = Manifest::load(dir.path()).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("extends itself"),
+            "Expected a cycle error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn unknown_target() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+            targets = ["plugin"]
+
+            [target.game.dependencies]
+            Promise = "evaera/promise@2.0.1"
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains("target `game`"),
+            "Expected an unknown target error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn target_build_dependencies() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+            targets = ["plugin"]
+
+            [build-dependencies]
+            Darklua = "biff/darklua@1.0.0"
+
+            [target.plugin.build-dependencies]
+            Bundler = "biff/bundler@1.0.0"
+            "#,
+        )
+        .unwrap();
+
+        let darklua: PackageReq = "biff/darklua@1.0.0".parse().unwrap();
+        let bundler: PackageReq = "biff/bundler@1.0.0".parse().unwrap();
+
+        let plugin = manifest.for_target(Some("plugin")).unwrap();
+        assert_eq!(plugin.build_dependencies.get("Darklua"), Some(&darklua));
+        assert_eq!(plugin.build_dependencies.get("Bundler"), Some(&bundler));
+
+        let default = manifest.for_target(None).unwrap();
+        assert_eq!(default.build_dependencies.get("Bundler"), None);
+    }
+
+    #[test]
+    fn default_realm() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "server"
+            default-realm = "server"
+
+            [dependencies]
+            Promise = "evaera/promise@2.0.1"
+            Roact = { name = "roblox/roact", version = "1.4.2", realm = "shared" }
+
+            [server-dependencies]
+            Cmdr = "evaera/cmdr@1.9.0"
+            "#,
+        )
+        .unwrap();
+
+        let aliases = |dependencies: &BTreeMap<String, PackageReq>| {
+            dependencies.keys().cloned().collect::<Vec<_>>()
+        };
+
+        assert_eq!(aliases(&manifest.dependencies), vec!["Roact"]);
+        assert_eq!(
+            aliases(&manifest.server_dependencies),
+            vec!["Cmdr", "Promise"]
+        );
+        assert_eq!(manifest.package.default_realm, Some(Realm::Server));
+    }
+
+    #[test]
+    fn default_scope() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+            default-scope = "biff"
+
+            [dependencies]
+            Utils = "utils@1.0.0"
+            Signal = { name = "signal", version = "2.0.0" }
+            Promise = "evaera/promise@2.0.1"
+            "#,
+        )
+        .unwrap();
+
+        let name = |alias: &str| manifest.dependencies[alias].name().to_string();
+
+        assert_eq!(name("Utils"), "biff/utils");
+        assert_eq!(name("Signal"), "biff/signal");
+        assert_eq!(name("Promise"), "evaera/promise");
+        assert_eq!(manifest.package.default_scope.as_deref(), Some("biff"));
+    }
+
+    #[test]
+    fn ambiguous_default_scope() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            [package]
+            default-scope = "biff"
+
+            [dependencies]
+            Utils = "utils@1.0.0"
+            Promise = "promise@1.0.0"
+
+            [server-dependencies]
+            OtherUtils = "evaera/utils@1.0.0"
+            "#,
+        )
+        .unwrap();
+
+        let ambiguities = apply_default_scope(&mut value).unwrap();
+
+        assert_eq!(
+            ambiguities,
+            vec![
+                "dependency Utils is taken to be biff/utils from package.default-scope, but \
+                 evaera/utils is also a dependency. Write out its scope to make it clear which \
+                 one is meant."
+            ]
+        );
+        assert_eq!(
+            value["server-dependencies"]["OtherUtils"].as_str(),
+            Some("evaera/utils@1.0.0")
+        );
+    }
+
+    #[test]
+    fn dependency_realm_overrides_shared_default() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "server"
+
+            [dependencies]
+            Promise = "evaera/promise@2.0.1"
+            Cmdr = { name = "evaera/cmdr", version = "1.9.0", realm = "server" }
+            "#,
+        )
+        .unwrap();
+
+        let cmdr: PackageReq = "evaera/cmdr@1.9.0".parse().unwrap();
+        assert!(manifest.dependencies.contains_key("Promise"));
+        assert!(!manifest.dependencies.contains_key("Cmdr"));
+        assert_eq!(manifest.server_dependencies.get("Cmdr"), Some(&cmdr));
+    }
+
+    #[test]
+    fn unknown_default_realm() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+            default-realm = "client"
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains("invalid package.default-realm"),
+            "Expected a realm error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn dependency_destination_outside_project() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "2.0.1", dest = "../Promise" }
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains("must be a relative path within the project"),
+            "Expected a destination error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn overlapping_dependency_destinations() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "2.0.1", dest = "Plugins" }
+            Roact = { name = "roblox/roact", version = "1.4.2", dest = "Plugins/Roact" }
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains("overlapping destinations"),
+            "Expected an overlap error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn path_dependency_subdir_without_manifest() {
+        let dir = tempdir().unwrap();
+        fs_err::create_dir_all(dir.path().join("monorepo/packages/empty")).unwrap();
+
+        fs_err::write(
+            dir.path().join(MANIFEST_FILE_NAME),
+            r#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Empty = { path = "monorepo", subdir = "packages/empty" }
+            "#,
+        )
+        .unwrap();
+
+        let err = Manifest::load(dir.path()).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("does not contain a wally.toml"),
+            "Expected a missing manifest error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn git_dependency_is_rejected() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            First = { git = "https://github.com/biff/monorepo.git", subdir = "packages/first" }
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err)
+                .contains("dependency First is a git dependency, which isn't supported yet"),
+            "Expected a git dependency error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn aliases_differing_by_case() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = "evaera/promise@2.0.1"
+            promise = "evaera/promise@2.0.1"
+            "#,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "dependency aliases Promise and promise in dependencies only differ by case, so \
+             their links would overwrite each other on case-insensitive file systems"
+        );
+    }
+
+    #[test]
+    fn alias_matching_package_folder() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [server-dependencies]
+            serverpackages = "evaera/promise@2.0.1"
+            "#,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "dependency alias serverpackages in server-dependencies conflicts with the \
+             ServerPackages folder Wally installs into"
+        );
+    }
+
+    #[test]
+    fn dependency_checksum() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "=2.0.1", checksum = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef" }
+            "#,
+        )
+        .unwrap();
+
+        let package_id: PackageId = "evaera/promise@2.0.1".parse().unwrap();
+        assert_eq!(
+            manifest.dependency_checksums[&package_id].to_string(),
+            "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+        );
+
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "=2.0.1", checksum = "md5:abc" }
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains("dependency Promise has an invalid checksum"),
+            "Expected a checksum error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn dependency_checksum_needs_an_exact_version() {
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "2.0.1", checksum = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef" }
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err).contains(
+                "dependency Promise has a checksum, so it must require an exact version like \
+                 `=2.0.1`"
+            ),
+            "Expected an exact version error. Instead we got: {:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn optional_dependency() {
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "2.0.1", optional = true, default = true }
+            Roact = { name = "roblox/roact", version = "1.4.2", optional = true }
+            "#,
+        )
+        .unwrap();
+
+        assert!(manifest.optional_dependencies["Promise"]);
+        assert!(!manifest.optional_dependencies["Roact"]);
+
+        let err = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/project"
+            version = "0.1.0"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "shared"
+
+            [dependencies]
+            Promise = { name = "evaera/promise", version = "2.0.1", default = true }
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(
+            format!("{:#}", err)
+                .contains("dependency Promise has a `default`, which only applies to optional"),
+            "Expected a default error. Instead we got: {:#}",
+            err
+        );
     }
 }
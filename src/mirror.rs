@@ -0,0 +1,120 @@
+//! Copying packages from one registry to another, for moving between
+//! registries without publishing every package again by hand.
+
+use crate::checksum::Checksum;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_source::{PackageSource, PackageSourceProvider};
+
+/// What `mirror` did with each version it looked at.
+#[derive(Debug, Default)]
+pub struct MirrorReport {
+    /// Versions published to the destination, with the checksum of the
+    /// contents that were copied.
+    pub copied: Vec<(PackageId, Checksum)>,
+
+    /// Versions the destination already had, which were left alone.
+    pub skipped: Vec<PackageId>,
+}
+
+/// Publishes every version of the named packages from `source` to
+/// `destination`, skipping versions the destination already has. Manifests
+/// and contents are copied as they are, so checksums don't change.
+pub fn mirror(
+    source: &PackageSource,
+    destination: &PackageSource,
+    names: &[PackageName],
+) -> anyhow::Result<MirrorReport> {
+    let mut report = MirrorReport::default();
+
+    for name in names {
+        for manifest in source.all_versions(name)? {
+            let package_id = manifest.package_id();
+
+            if destination.get(&package_id)?.is_some() {
+                log::info!("Skipping {}, which is already mirrored", package_id);
+                report.skipped.push(package_id);
+                continue;
+            }
+
+            let contents = source.download_package(&package_id)?;
+            destination.publish(&manifest, &contents)?;
+
+            log::info!("Mirrored {}", package_id);
+            report
+                .copied
+                .push((package_id, Checksum::compute(contents.data())));
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::TestRegistry;
+
+    const PRIMARY_REGISTRY: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-registries/primary-registry"
+    );
+
+    #[test]
+    fn mirror_between_test_registries() {
+        let source = PackageSource::TestRegistry(TestRegistry::new(PRIMARY_REGISTRY));
+
+        let destination_dir = tempfile::tempdir().unwrap();
+        let destination = PackageSource::TestRegistry(TestRegistry::new(destination_dir.path()));
+
+        // The destination already has one of the versions.
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let minimal_manifest = source.get(&minimal).unwrap().unwrap();
+        let minimal_contents = source.download_package(&minimal).unwrap();
+        destination
+            .publish(&minimal_manifest, &minimal_contents)
+            .unwrap();
+
+        let names: Vec<PackageName> = vec![
+            "biff/minimal".parse().unwrap(),
+            "diamond-graph/indirect-dependency-a".parse().unwrap(),
+        ];
+        let report = mirror(&source, &destination, &names).unwrap();
+
+        assert_eq!(report.skipped, vec![minimal]);
+
+        let copied: Vec<String> = report
+            .copied
+            .iter()
+            .map(|(package_id, _)| package_id.to_string())
+            .collect();
+        assert_eq!(
+            copied,
+            vec![
+                "diamond-graph/indirect-dependency-a@0.1.0",
+                "diamond-graph/indirect-dependency-a@0.1.1",
+                "diamond-graph/indirect-dependency-a@0.2.0",
+                "diamond-graph/indirect-dependency-a@0.2.1",
+            ]
+        );
+
+        for (package_id, checksum) in &report.copied {
+            let mirrored = destination.download_package(package_id).unwrap();
+            assert!(checksum.matches(mirrored.data()));
+
+            let mirrored = destination.get(package_id).unwrap().unwrap();
+            let original = source.get(package_id).unwrap().unwrap();
+            assert_eq!(
+                serde_json::to_value(mirrored).unwrap(),
+                serde_json::to_value(original).unwrap()
+            );
+        }
+
+        // Mirroring again has nothing left to copy.
+        let report = mirror(&source, &destination, &names).unwrap();
+        assert!(report.copied.is_empty());
+        assert_eq!(report.skipped.len(), 5);
+        assert_eq!(destination.package_names().unwrap(), names);
+    }
+}
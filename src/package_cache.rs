@@ -0,0 +1,539 @@
+//! Keeps the contents of downloaded packages between installs, shared by every
+//! project, so that each package only has to be downloaded once. Packages are
+//! cached under the checksum of their contents, so contents are only ever
+//! reused for a package pinned to exactly that checksum.
+//!
+//! Packages are kept as the zips they were downloaded as. With deduplication
+//! on, they're split into files instead, each stored once under its own
+//! checksum, so that versions of a package that share most of their files
+//! share the space they take up too.
+//!
+//! Cached packages are checked against their checksum every time they're
+//! read, unless the cache is told to only check them again once a while has
+//! passed since they were last checked.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, format_err, Context};
+use serde::{Deserialize, Serialize};
+use zip::{write::FileOptions, DateTime, ZipArchive, ZipWriter};
+
+use crate::checksum::Checksum;
+use crate::package_contents::PackageContents;
+
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    dir: PathBuf,
+    dedupe: bool,
+    revalidate_after: Option<Duration>,
+}
+
+/// What's recorded about each cached package.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// The checksum of the package's zip, which it's cached under.
+    checksum: String,
+
+    /// When the package's contents were last checked against its checksum,
+    /// in seconds since the Unix epoch.
+    #[serde(default)]
+    verified_at: u64,
+
+    /// The files the package is made of, when they're stored on their own
+    /// instead of as a zip.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tree: Option<Tree>,
+}
+
+/// The files and folders of a package, in the order they were in its zip.
+#[derive(Debug, Serialize, Deserialize)]
+struct Tree {
+    /// The checksum of the whole tree, which the files are checked against
+    /// every time the package is read back.
+    checksum: String,
+
+    entries: Vec<TreeEntry>,
+}
+
+/// What `PackageCache::prune` removed, or would have removed.
+#[derive(Debug, Default)]
+pub struct Pruned {
+    /// The checksums of the packages that were removed.
+    pub packages: Vec<String>,
+
+    /// How many files stored for deduplicated packages were removed.
+    pub blobs: usize,
+
+    /// How many bytes the removed files took up.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TreeEntry {
+    path: String,
+
+    /// The checksum of the file's contents, which it's stored under. Folders
+    /// have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blob: Option<String>,
+}
+
+impl PackageCache {
+    /// A cache kept in `dir`, which is created once something is cached.
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            dedupe: false,
+            revalidate_after: None,
+        }
+    }
+
+    /// Store newly cached packages as files shared between the packages that
+    /// have them, instead of as whole zips. Packages that are already cached
+    /// are read back however they were stored.
+    pub fn with_dedupe(self, dedupe: bool) -> Self {
+        Self { dedupe, ..self }
+    }
+
+    /// Only check cached packages against their checksum again once this long
+    /// has passed since they were last checked, instead of every time they're
+    /// read.
+    pub fn with_revalidate_after(self, revalidate_after: Option<Duration>) -> Self {
+        Self {
+            revalidate_after,
+            ..self
+        }
+    }
+
+    /// The cached contents of the package with `checksum`, if it's cached.
+    /// Cached packages that can't be read or don't match their checksum are
+    /// treated as missing.
+    pub fn get(&self, checksum: &Checksum) -> Option<PackageContents> {
+        match self.read(checksum) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log::debug!("Ignoring unreadable cached package {}: {:#}", checksum, err);
+                None
+            }
+        }
+    }
+
+    /// Caches `contents` under their checksum, which is returned.
+    pub fn insert(&self, contents: &PackageContents) -> anyhow::Result<Checksum> {
+        let checksum = Checksum::compute(contents.data());
+
+        let tree = if self.dedupe {
+            Some(self.write_tree(contents)?)
+        } else {
+            write_atomic(&self.zip_path(&checksum), contents.data())?;
+            None
+        };
+
+        // The entry is written last, so that a package is only ever found
+        // once everything it needs is there.
+        let entry = CacheEntry {
+            checksum: checksum.to_string(),
+            verified_at: unix_time(SystemTime::now()),
+            tree,
+        };
+        self.write_entry(&checksum, &entry)?;
+
+        Ok(checksum)
+    }
+
+    fn read(&self, checksum: &Checksum) -> anyhow::Result<Option<PackageContents>> {
+        let entry_path = self.entry_path(checksum);
+        if !entry_path.is_file() {
+            return Ok(None);
+        }
+
+        let mut entry: CacheEntry = serde_json::from_slice(&fs_err::read(&entry_path)?)?;
+        if entry.checksum != checksum.to_string() {
+            bail!("its entry is for {}", entry.checksum);
+        }
+
+        let now = SystemTime::now();
+        let verify = match self.revalidate_after {
+            Some(revalidate_after) => {
+                let verified_at = UNIX_EPOCH + Duration::from_secs(entry.verified_at);
+                now.duration_since(verified_at).unwrap_or_default() >= revalidate_after
+            }
+            None => true,
+        };
+
+        let contents = match &entry.tree {
+            Some(tree) => self.read_tree(tree, verify)?,
+            None => {
+                let data = fs_err::read(self.zip_path(checksum))?;
+                if verify && !checksum.matches(&data) {
+                    bail!("its contents don't match its checksum");
+                }
+
+                PackageContents::from_buffer(data)
+            }
+        };
+
+        // Entries are only checked every time when there's no interval to
+        // remember it for.
+        if verify && self.revalidate_after.is_some() {
+            entry.verified_at = unix_time(now);
+            if let Err(err) = self.write_entry(checksum, &entry) {
+                log::debug!("Could not record that {} was checked: {:#}", checksum, err);
+            }
+        }
+
+        Ok(Some(contents))
+    }
+
+    /// Removes every cached package except the ones with a checksum in
+    /// `keep`, along with the files that only removed packages were using.
+    /// With `dry_run`, nothing is removed, but everything that would have
+    /// been is still reported.
+    pub fn prune(&self, keep: &HashSet<Checksum>, dry_run: bool) -> anyhow::Result<Pruned> {
+        let mut pruned = Pruned::default();
+        let mut kept_names = BTreeSet::new();
+        let mut kept_blobs = BTreeSet::new();
+
+        let remove = |path: &Path, pruned: &mut Pruned| -> anyhow::Result<()> {
+            pruned.bytes += fs_err::metadata(path)?.len();
+            if !dry_run {
+                fs_err::remove_file(path)?;
+            }
+
+            Ok(())
+        };
+
+        for path in list_dir(&self.dir.join("entries"))? {
+            // Entries that can't be read can't be used either.
+            let entry = fs_err::read(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_slice::<CacheEntry>(&contents).ok());
+            let checksum = entry
+                .as_ref()
+                .and_then(|entry| entry.checksum.parse::<Checksum>().ok());
+
+            if let (Some(entry), Some(checksum)) = (&entry, &checksum) {
+                if keep.contains(checksum) {
+                    kept_names.insert(stored_name(checksum));
+                    if let Some(tree) = &entry.tree {
+                        kept_blobs.extend(tree.entries.iter().filter_map(|file| file.blob.clone()));
+                    }
+
+                    continue;
+                }
+            }
+
+            remove(&path, &mut pruned)?;
+            pruned.packages.push(match entry {
+                Some(entry) => entry.checksum,
+                None => path.display().to_string(),
+            });
+        }
+
+        // Zips are removed along with their entries, as well as any that
+        // were left behind without one.
+        for path in list_dir(&self.dir.join("zips"))? {
+            let name = path.file_stem().and_then(|name| name.to_str());
+            if !name.map_or(false, |name| kept_names.contains(name)) {
+                remove(&path, &mut pruned)?;
+            }
+        }
+
+        for path in list_dir(&self.dir.join("blobs"))? {
+            let name = path.file_name().and_then(|name| name.to_str());
+            if !name.map_or(false, |name| kept_blobs.contains(name)) {
+                remove(&path, &mut pruned)?;
+                pruned.blobs += 1;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    fn write_entry(&self, checksum: &Checksum, entry: &CacheEntry) -> anyhow::Result<()> {
+        write_atomic(&self.entry_path(checksum), &serde_json::to_vec(entry)?)
+    }
+
+    /// Stores every file in `contents` as a blob, returning the tree to put
+    /// them back together with.
+    fn write_tree(&self, contents: &PackageContents) -> anyhow::Result<Tree> {
+        let mut archive = ZipArchive::new(Cursor::new(contents.data()))?;
+        let mut entries = Vec::new();
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index)?;
+            let path = file
+                .enclosed_name()
+                .ok_or_else(|| format_err!("{} has an unsafe path", file.name()))?
+                .to_str()
+                .with_context(|| format!("{} isn't valid Unicode", file.name()))?
+                .replace('\\', "/");
+
+            if file.is_dir() {
+                entries.push(TreeEntry { path, blob: None });
+                continue;
+            }
+
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+
+            let blob = stored_name(&Checksum::compute(&data));
+            let blob_path = self.dir.join("blobs").join(&blob);
+            if !blob_path.is_file() {
+                write_atomic(&blob_path, &data)?;
+            }
+
+            entries.push(TreeEntry {
+                path,
+                blob: Some(blob),
+            });
+        }
+
+        Ok(Tree {
+            checksum: tree_checksum(&entries).to_string(),
+            entries,
+        })
+    }
+
+    /// Puts a package back together from its blobs. With `verify`, every one
+    /// of them is checked against the checksum it's stored under.
+    fn read_tree(&self, tree: &Tree, verify: bool) -> anyhow::Result<PackageContents> {
+        if tree_checksum(&tree.entries).to_string() != tree.checksum {
+            bail!("its list of files doesn't match its checksum");
+        }
+
+        // Every entry gets the same timestamp, so that a package reads back
+        // the same every time.
+        let options = FileOptions::default().last_modified_time(DateTime::default());
+        let mut data = Vec::new();
+        let mut archive = ZipWriter::new(Cursor::new(&mut data));
+
+        for entry in &tree.entries {
+            match &entry.blob {
+                None => archive.add_directory(entry.path.as_str(), options)?,
+                Some(blob) => {
+                    let contents = fs_err::read(self.dir.join("blobs").join(blob))?;
+                    if verify && stored_name(&Checksum::compute(&contents)) != *blob {
+                        bail!("{} doesn't match its checksum", entry.path);
+                    }
+
+                    archive.start_file(entry.path.as_str(), options)?;
+                    archive.write_all(&contents)?;
+                }
+            }
+        }
+
+        archive.finish()?;
+        drop(archive);
+
+        Ok(PackageContents::from_buffer(data))
+    }
+
+    fn entry_path(&self, checksum: &Checksum) -> PathBuf {
+        self.dir
+            .join("entries")
+            .join(format!("{}.json", stored_name(checksum)))
+    }
+
+    fn zip_path(&self, checksum: &Checksum) -> PathBuf {
+        self.dir
+            .join("zips")
+            .join(format!("{}.zip", stored_name(checksum)))
+    }
+}
+
+/// The name a checksum is stored under, which can't contain a colon on
+/// Windows.
+fn stored_name(checksum: &Checksum) -> String {
+    checksum.to_string().replacen(':', "-", 1)
+}
+
+/// The files in a folder of the cache, sorted, or nothing if it doesn't exist
+/// yet.
+fn list_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths = Vec::new();
+    for entry in fs_err::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn tree_checksum(entries: &[TreeEntry]) -> Checksum {
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|entry| format!("{} {}\n", entry.path, entry.blob.as_deref().unwrap_or("-")))
+        .collect();
+
+    Checksum::compute(lines.concat().as_bytes())
+}
+
+/// Writes a file all at once, so that installs running at the same time never
+/// see only part of it.
+fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let parent = path.parent().unwrap();
+    fs_err::create_dir_all(parent)?;
+
+    let mut file = tempfile::NamedTempFile::new_in(parent)?;
+    file.write_all(contents)?;
+    file.persist(path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn zip_of(files: &[(&str, &str)]) -> PackageContents {
+        let mut data = Vec::new();
+        let mut archive = ZipWriter::new(Cursor::new(&mut data));
+
+        for (path, contents) in files {
+            archive.start_file(*path, FileOptions::default()).unwrap();
+            archive.write_all(contents.as_bytes()).unwrap();
+        }
+
+        archive.finish().unwrap();
+        drop(archive);
+
+        PackageContents::from_buffer(data)
+    }
+
+    fn read_file(contents: &PackageContents, path: &str) -> String {
+        let mut archive = ZipArchive::new(Cursor::new(contents.data())).unwrap();
+        let mut file = archive.by_name(path).unwrap();
+        let mut data = String::new();
+        file.read_to_string(&mut data).unwrap();
+
+        data
+    }
+
+    #[test]
+    fn cached_zips_are_read_back() {
+        let dir = tempdir().unwrap();
+        let cache = PackageCache::new(dir.path().to_owned());
+        let contents = zip_of(&[("init.lua", "return 1")]);
+
+        let checksum = cache.insert(&contents).unwrap();
+        assert_eq!(checksum, Checksum::compute(contents.data()));
+        assert_eq!(cache.get(&checksum).unwrap().data(), contents.data());
+
+        // Corrupted contents are a miss, not an error.
+        let zip = fs_err::read_dir(dir.path().join("zips"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        fs_err::write(zip, "corrupted").unwrap();
+        assert!(cache.get(&checksum).is_none());
+    }
+
+    #[test]
+    fn entries_are_only_revalidated_after_the_interval() {
+        let dir = tempdir().unwrap();
+        let cache = PackageCache::new(dir.path().to_owned())
+            .with_revalidate_after(Some(Duration::from_secs(60 * 60)));
+        let checksum = cache.insert(&zip_of(&[("init.lua", "return 1")])).unwrap();
+
+        let zip = cache.zip_path(&checksum);
+        fs_err::write(&zip, "corrupted").unwrap();
+
+        // The entry was checked when it was cached, so it isn't checked again
+        // yet.
+        assert_eq!(cache.get(&checksum).unwrap().data(), b"corrupted");
+
+        // Once the interval has passed, the corruption is caught.
+        let stale = cache.with_revalidate_after(Some(Duration::ZERO));
+        assert!(stale.get(&checksum).is_none());
+    }
+
+    #[test]
+    fn prune_keeps_blobs_of_kept_packages() {
+        let dir = tempdir().unwrap();
+        let cache = PackageCache::new(dir.path().to_owned()).with_dedupe(true);
+
+        let first = cache
+            .insert(&zip_of(&[
+                ("init.lua", "return 1"),
+                ("util.lua", "return {}"),
+            ]))
+            .unwrap();
+        let second = cache
+            .insert(&zip_of(&[
+                ("init.lua", "return 2"),
+                ("util.lua", "return {}"),
+            ]))
+            .unwrap();
+
+        let keep: HashSet<Checksum> = std::iter::once(second.clone()).collect();
+        let dry_run = cache.prune(&keep, true).unwrap();
+        assert_eq!(dry_run.packages, vec![first.to_string()]);
+        assert_eq!(dry_run.blobs, 1);
+        assert!(cache.get(&first).is_some());
+
+        let pruned = cache.prune(&keep, false).unwrap();
+        assert_eq!(pruned.packages, dry_run.packages);
+        assert_eq!(pruned.bytes, dry_run.bytes);
+        assert!(cache.get(&first).is_none());
+
+        // The file both packages shared is still there for the kept one.
+        let kept = cache.get(&second).unwrap();
+        assert_eq!(read_file(&kept, "util.lua"), "return {}");
+        assert_eq!(
+            fs_err::read_dir(dir.path().join("blobs")).unwrap().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn deduplicated_versions_share_blobs() {
+        let dir = tempdir().unwrap();
+        let cache = PackageCache::new(dir.path().to_owned()).with_dedupe(true);
+
+        let first = zip_of(&[("init.lua", "return 1"), ("util.lua", "return {}")]);
+        let second = zip_of(&[("init.lua", "return 2"), ("util.lua", "return {}")]);
+        let first_checksum = cache.insert(&first).unwrap();
+        let second_checksum = cache.insert(&second).unwrap();
+
+        // Both versions of init.lua, and util.lua once.
+        assert_eq!(
+            fs_err::read_dir(dir.path().join("blobs")).unwrap().count(),
+            3
+        );
+        assert!(!dir.path().join("zips").exists());
+
+        for (checksum, original) in &[(first_checksum, first), (second_checksum, second)] {
+            let cached = cache.get(checksum).unwrap();
+
+            for file in &["init.lua", "util.lua"] {
+                assert_eq!(read_file(&cached, file), read_file(original, file));
+            }
+        }
+
+        // A blob that changed fails the tree's check.
+        let util = stored_name(&Checksum::compute(b"return {}"));
+        fs_err::write(dir.path().join("blobs").join(util), "return nil").unwrap();
+        assert!(cache.get(&first_checksum).is_none());
+    }
+}
@@ -1,14 +1,18 @@
-use std::io::{self, BufRead, BufReader, Cursor};
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::format_err;
+use anyhow::{bail, format_err, Context};
 use fs_err::File;
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde_json::json;
+use ubyte::ToByteUnit;
 use walkdir::WalkDir;
-use zip::{write::FileOptions, ZipArchive, ZipWriter};
+use zip::{write::FileOptions, DateTime, ZipArchive, ZipWriter};
 
-use crate::manifest::Manifest;
+use crate::checksum::Checksum;
+use crate::manifest::{Manifest, MANIFEST_FILE_NAME};
+use crate::package_req::PackageReq;
 
 static EXCLUDED_GLOBS: &[&str] = &[
     ".*",
@@ -16,13 +20,31 @@ static EXCLUDED_GLOBS: &[&str] = &[
     "Packages",
     "ServerPackages",
     "DevPackages",
+    "BuildPackages",
 ];
 
+/// A file or directory inside a package, as listed by
+/// `PackageContents::entries`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentEntry {
+    /// The entry's path relative to the root of the package.
+    pub path: PathBuf,
+
+    /// The uncompressed size of the file in bytes, or 0 for directories.
+    pub size: u64,
+
+    pub is_dir: bool,
+}
+
 /// Container for the contents of a package that have been downloaded.
 #[derive(Clone)]
 pub struct PackageContents {
     /// Contains a zip with the contents of the package.
     data: Vec<u8>,
+
+    /// Where the contents were actually downloaded from, if the registry
+    /// redirected us away from where it said they were.
+    origin: Option<String>,
 }
 
 impl PackageContents {
@@ -30,6 +52,18 @@ impl PackageContents {
         let manifest = Manifest::load(input)?;
         let package_name = manifest.package.name.name();
 
+        // A manifest that extends another would be missing everything it
+        // inherits once it's packaged, so we package the flattened manifest.
+        let flattened_manifest = if Manifest::is_extended(input)? {
+            Some(toml::to_string_pretty(&manifest)?)
+        } else {
+            None
+        };
+
+        // Every entry gets the same timestamp, so packing the same files
+        // always produces the same zip and the same checksum.
+        let options = FileOptions::default().last_modified_time(DateTime::default());
+
         let mut data = Vec::new();
         let mut archive = ZipWriter::new(Cursor::new(&mut data));
 
@@ -48,11 +82,17 @@ impl PackageContents {
             let archive_name = str::replace(archive_name, "\\", "/");
 
             if path.is_dir() {
-                archive.add_directory(archive_name, FileOptions::default())?;
+                archive.add_directory(archive_name, options)?;
             } else {
-                archive.start_file(archive_name, FileOptions::default())?;
+                let flattened = flattened_manifest
+                    .as_ref()
+                    .filter(|_| archive_name == MANIFEST_FILE_NAME);
+
+                archive.start_file(archive_name, options)?;
 
-                if path.ends_with("default.project.json") {
+                if let Some(flattened) = flattened {
+                    archive.write_all(flattened.as_bytes())?;
+                } else if path.ends_with("default.project.json") {
                     let project_file = File::open(path)?;
                     let mut project_json: serde_json::Value =
                         serde_json::from_reader(project_file)?;
@@ -83,16 +123,217 @@ impl PackageContents {
         archive.finish()?;
         drop(archive);
 
-        Ok(PackageContents { data })
+        Ok(PackageContents { data, origin: None })
     }
 
-    /// Unpack the package into the given path on the filesystem.
-    pub fn unpack_into_path(&self, output: &Path) -> anyhow::Result<()> {
+    /// Packs the project at `input` like `pack_from_path`, failing if the
+    /// packed contents are larger than `max_size` bytes.
+    pub fn pack_from_path_with_limit(input: &Path, max_size: u64) -> anyhow::Result<Self> {
+        let contents = Self::pack_from_path(input)?;
+        let size = contents.data.len() as u64;
+
+        if size > max_size {
+            bail!(
+                "Package size ({}) exceeds the limit of {}. Reduce package size and try again.",
+                size.bytes(),
+                max_size.bytes()
+            );
+        }
+
+        Ok(contents)
+    }
+
+    /// Extract the package into the given directory, returning the path of
+    /// every file and directory that was written in the order they appear in
+    /// the archive.
+    ///
+    /// Entries that would be written outside of `dest`, like `../init.lua`,
+    /// are rejected before anything else is written.
+    pub fn extract_to(&self, dest: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let entries = self.entries()?;
         let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))?;
-        archive.extract(output)?;
+        let mut written = Vec::new();
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            let mut file = archive.by_index(index)?;
+            let path = dest.join(entry.path);
+
+            if file.is_dir() {
+                fs_err::create_dir_all(&path)?;
+            } else {
+                if let Some(parent) = path.parent() {
+                    fs_err::create_dir_all(parent)?;
+                }
+
+                let mut output = File::create(&path)?;
+                io::copy(&mut file, &mut output)?;
+            }
+
+            written.push(path);
+        }
+
+        Ok(written)
+    }
+
+    /// Lists every file and directory in the package in the order they
+    /// appear in the archive, without writing anything to disk.
+    ///
+    /// Like `extract_to`, this fails if any entry has an unsafe path.
+    pub fn entries(&self) -> anyhow::Result<Vec<ContentEntry>> {
+        let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))?;
+        let mut entries = Vec::new();
+
+        for index in 0..archive.len() {
+            let file = archive.by_index(index)?;
+            let path = file.enclosed_name().map(Path::to_path_buf).ok_or_else(|| {
+                format_err!(
+                    "Package contains a file with an unsafe path: {}",
+                    file.name()
+                )
+            })?;
+
+            entries.push(ContentEntry {
+                path,
+                size: if file.is_dir() { 0 } else { file.size() },
+                is_dir: file.is_dir(),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Checks that the package extracts the same way every time. Besides
+    /// every path being safe, no two entries can be extracted to the same
+    /// path, since which of them ends up on disk would depend on the order
+    /// they're written in. Directories listed more than once are allowed.
+    pub fn check_entries(&self) -> anyhow::Result<()> {
+        let mut seen: BTreeMap<PathBuf, bool> = BTreeMap::new();
+
+        for entry in self.entries()? {
+            let path = normalize_entry_path(&entry.path);
+
+            if let Some(seen_dir) = seen.insert(path.clone(), entry.is_dir) {
+                if !(seen_dir && entry.is_dir) {
+                    bail!(
+                        "Package contains more than one entry for the path {}",
+                        path.display()
+                    );
+                }
+            }
+        }
+
         Ok(())
     }
 
+    /// How many bytes the package's files take up once extracted.
+    pub fn extracted_size(&self) -> anyhow::Result<u64> {
+        Ok(self.entries()?.iter().map(|entry| entry.size).sum())
+    }
+
+    /// Reads a single file out of the package without extracting anything.
+    /// `path` is relative to the root of the package, like `src/init.lua`.
+    pub fn read_file(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let entries = self.entries()?;
+        let index = entries
+            .iter()
+            .position(|entry| !entry.is_dir && entry.path == path)
+            .ok_or_else(|| format_err!("Package has no file at {}", path.display()))?;
+
+        let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))?;
+        let mut file = archive.by_index(index)?;
+
+        let mut contents = Vec::new();
+        io::copy(&mut file, &mut contents)?;
+
+        Ok(contents)
+    }
+
+    /// The manifest packed into the package. Packages are always packed with
+    /// their manifest, but ones put together some other way may not have
+    /// one.
+    pub fn embedded_manifest(&self) -> anyhow::Result<Option<Manifest>> {
+        let path = Path::new(MANIFEST_FILE_NAME);
+        let has_manifest = self
+            .entries()?
+            .iter()
+            .any(|entry| !entry.is_dir && entry.path == path);
+
+        if !has_manifest {
+            return Ok(None);
+        }
+
+        let contents = self.read_file(path)?;
+        let manifest = Manifest::from_slice(&contents)
+            .with_context(|| format!("Package has an invalid {}", MANIFEST_FILE_NAME))?;
+
+        Ok(Some(manifest))
+    }
+
+    /// Checks `manifest`, the entry a registry's index has for this package,
+    /// against the manifest packed into the package, failing if they disagree
+    /// about which package this is or what it depends on. Returns false if
+    /// the package has no manifest to check against.
+    pub fn verify_manifest(&self, manifest: &Manifest) -> anyhow::Result<bool> {
+        let embedded = match self.embedded_manifest()? {
+            Some(embedded) => embedded,
+            None => return Ok(false),
+        };
+
+        let mut differences = Vec::new();
+
+        if embedded.package_id() != manifest.package_id() {
+            differences.push(format!(
+                "the index is for {} but the package is {}",
+                manifest.package_id(),
+                embedded.package_id()
+            ));
+        }
+
+        if embedded.package.realm != manifest.package.realm {
+            differences.push(format!(
+                "the realm is {} in the index but {} in the package",
+                manifest.package.realm, embedded.package.realm
+            ));
+        }
+
+        let tables = [
+            ("dependency", &manifest.dependencies, &embedded.dependencies),
+            (
+                "server dependency",
+                &manifest.server_dependencies,
+                &embedded.server_dependencies,
+            ),
+            (
+                "dev dependency",
+                &manifest.dev_dependencies,
+                &embedded.dev_dependencies,
+            ),
+            (
+                "peer dependency",
+                &manifest.peer_dependencies,
+                &embedded.peer_dependencies,
+            ),
+        ];
+
+        for (kind, listed, packed) in &tables {
+            dependency_differences(kind, listed, packed, &mut differences);
+        }
+
+        if embedded.optional_dependencies != manifest.optional_dependencies {
+            differences.push("the optional dependencies are different".to_owned());
+        }
+
+        if !differences.is_empty() {
+            bail!(
+                "The index entry for {} doesn't match the manifest in its contents: {}",
+                manifest.package_id(),
+                differences.join(", ")
+            );
+        }
+
+        Ok(true)
+    }
+
     pub fn filtered_contents(input: &Path) -> anyhow::Result<Vec<PathBuf>> {
         let manifest = Manifest::load(input)?;
         let includes = manifest.package.include;
@@ -119,10 +360,17 @@ impl PackageContents {
 
         Ok(WalkDir::new(input)
             .min_depth(1)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
             .into_iter()
             .filter_entry(|entry| {
                 let relative = entry.path().strip_prefix(input).unwrap();
 
+                // The manifest is always packed, whatever the filters say, so
+                // consumers can check a registry's index against it.
+                if relative == Path::new(MANIFEST_FILE_NAME) {
+                    return true;
+                }
+
                 if !includes.is_empty() && !include.matches(relative).is_empty() {
                     return true;
                 };
@@ -138,10 +386,75 @@ impl PackageContents {
         &self.data
     }
 
+    /// The checksum of the package's zip, like `sha256:<hex>`, as recorded in
+    /// lockfiles.
+    pub fn checksum(&self) -> String {
+        Checksum::compute(&self.data).to_string()
+    }
+
     /// Create a new PackageContents object from a buffer.
     pub fn from_buffer(data: Vec<u8>) -> PackageContents {
-        PackageContents { data }
+        PackageContents { data, origin: None }
+    }
+
+    /// Record that the contents were redirected to `origin`.
+    pub fn with_origin(self, origin: String) -> PackageContents {
+        PackageContents {
+            origin: Some(origin),
+            ..self
+        }
     }
+
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+}
+
+/// Describes every alias that `index` and `package` list differently.
+fn dependency_differences(
+    kind: &str,
+    index: &BTreeMap<String, PackageReq>,
+    package: &BTreeMap<String, PackageReq>,
+    differences: &mut Vec<String>,
+) {
+    let aliases: BTreeSet<&String> = index.keys().chain(package.keys()).collect();
+
+    for alias in aliases {
+        match (index.get(alias), package.get(alias)) {
+            (Some(listed), Some(packed)) if listed == packed => {}
+            (Some(listed), Some(packed)) => differences.push(format!(
+                "{} {} is {} in the index but {} in the package",
+                kind, alias, listed, packed
+            )),
+            (Some(listed), None) => differences.push(format!(
+                "the index has a {} {} on {} that the package doesn't",
+                kind, alias, listed
+            )),
+            (None, Some(packed)) => differences.push(format!(
+                "the package has a {} {} on {} that the index doesn't",
+                kind, alias, packed
+            )),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// The path an entry is extracted to, with `.` and `..` resolved, so that
+/// two spellings of the same path compare equal.
+fn normalize_entry_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => {}
+        }
+    }
+
+    normalized
 }
 
 fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
@@ -153,3 +466,254 @@ fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
 
     Ok(builder.build()?)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn zip_of(entries: &[(&str, Option<&str>)]) -> PackageContents {
+        let mut data = Vec::new();
+        let mut archive = ZipWriter::new(Cursor::new(&mut data));
+
+        for (name, contents) in entries {
+            match contents {
+                Some(contents) => {
+                    archive.start_file(*name, FileOptions::default()).unwrap();
+                    archive.write_all(contents.as_bytes()).unwrap();
+                }
+                None => archive
+                    .add_directory(*name, FileOptions::default())
+                    .unwrap(),
+            }
+        }
+
+        archive.finish().unwrap();
+        drop(archive);
+
+        PackageContents::from_buffer(data)
+    }
+
+    #[test]
+    fn extract_to_returns_written_paths() {
+        let contents = zip_of(&[
+            ("src/", None),
+            ("src/init.lua", Some("return \"hey\"")),
+            ("wally.toml", Some("[package]")),
+        ]);
+
+        let dir = tempdir().unwrap();
+        let written = contents.extract_to(dir.path()).unwrap();
+
+        assert_eq!(
+            written,
+            vec![
+                dir.path().join("src"),
+                dir.path().join("src/init.lua"),
+                dir.path().join("wally.toml"),
+            ]
+        );
+
+        assert!(dir.path().join("src").is_dir());
+        assert_eq!(
+            fs_err::read_to_string(dir.path().join("src/init.lua")).unwrap(),
+            "return \"hey\""
+        );
+        assert_eq!(
+            fs_err::read_to_string(dir.path().join("wally.toml")).unwrap(),
+            "[package]"
+        );
+    }
+
+    #[test]
+    fn entries_and_read_file() {
+        let contents = zip_of(&[
+            ("src/", None),
+            ("src/init.lua", Some("return \"hey\"")),
+            ("wally.toml", Some("[package]")),
+        ]);
+
+        assert_eq!(
+            contents.entries().unwrap(),
+            vec![
+                ContentEntry {
+                    path: PathBuf::from("src"),
+                    size: 0,
+                    is_dir: true,
+                },
+                ContentEntry {
+                    path: PathBuf::from("src/init.lua"),
+                    size: 12,
+                    is_dir: false,
+                },
+                ContentEntry {
+                    path: PathBuf::from("wally.toml"),
+                    size: 9,
+                    is_dir: false,
+                },
+            ]
+        );
+
+        assert_eq!(
+            contents.read_file(Path::new("src/init.lua")).unwrap(),
+            b"return \"hey\""
+        );
+        assert!(contents.read_file(Path::new("src")).is_err());
+        assert!(contents.read_file(Path::new("missing.lua")).is_err());
+    }
+
+    #[test]
+    fn tampered_index_entry() {
+        let manifest = r#"
+            [package]
+            name = "biff/logger"
+            version = "1.0.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Colors = "biff/colors@1.0.0"
+            "#;
+        let contents = zip_of(&[
+            ("init.lua", Some("return nil")),
+            ("wally.toml", Some(manifest)),
+        ]);
+
+        let index_entry = Manifest::from_slice(manifest.as_bytes()).unwrap();
+        assert!(contents.verify_manifest(&index_entry).unwrap());
+
+        // The index claims different dependencies than the package has.
+        let mut tampered = index_entry.clone();
+        tampered
+            .dependencies
+            .insert("Colors".to_owned(), "biff/colors@2.0.0".parse().unwrap());
+        tampered
+            .server_dependencies
+            .insert("Miner".to_owned(), "biff/miner@1.0.0".parse().unwrap());
+
+        let err = contents.verify_manifest(&tampered).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The index entry for biff/logger@1.0.0 doesn't match the manifest in its contents: \
+             dependency Colors is biff/colors@^2.0.0 in the index but biff/colors@^1.0.0 in \
+             the package, the index has a server dependency Miner on biff/miner@^1.0.0 that the \
+             package doesn't"
+        );
+
+        // Packages with no manifest have nothing to check against.
+        let unpacked = zip_of(&[("init.lua", Some("return nil"))]);
+        assert!(!unpacked.verify_manifest(&tampered).unwrap());
+    }
+
+    #[test]
+    fn entries_of_published_package() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry/contents/biff/minimal/0.1.0.zip"
+        );
+        let contents = PackageContents::from_buffer(fs_err::read(path).unwrap());
+
+        assert_eq!(
+            contents.entries().unwrap(),
+            vec![ContentEntry {
+                path: PathBuf::from("init.lua"),
+                size: 12,
+                is_dir: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn packing_is_deterministic() {
+        let project = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/minimal"
+        ));
+        let first = PackageContents::pack_from_path(project).unwrap();
+
+        // A copy has newer timestamps, and its files were created in a
+        // different order.
+        let copy = tempdir().unwrap();
+        let mut files: Vec<_> = WalkDir::new(project)
+            .min_depth(1)
+            .into_iter()
+            .map(|entry| entry.unwrap().path().to_path_buf())
+            .collect();
+        files.sort();
+        for path in files.iter().filter(|path| path.is_dir()) {
+            fs_err::create_dir_all(copy.path().join(path.strip_prefix(project).unwrap())).unwrap();
+        }
+        for path in files.iter().rev().filter(|path| path.is_file()) {
+            fs_err::copy(path, copy.path().join(path.strip_prefix(project).unwrap())).unwrap();
+        }
+        let second = PackageContents::pack_from_path(copy.path()).unwrap();
+
+        assert_eq!(first.checksum(), second.checksum());
+        assert_eq!(
+            first.checksum(),
+            PackageContents::pack_from_path(project).unwrap().checksum()
+        );
+
+        let paths: Vec<_> = first
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+
+        let mut archive = ZipArchive::new(Cursor::new(first.data())).unwrap();
+        for i in 0..archive.len() {
+            let modified = archive.by_index(i).unwrap().last_modified();
+            assert_eq!(modified.datepart(), DateTime::default().datepart());
+            assert_eq!(modified.timepart(), DateTime::default().timepart());
+        }
+    }
+
+    #[test]
+    fn extract_to_rejects_paths_outside_dest() {
+        let contents = zip_of(&[
+            ("init.lua", Some("return nil")),
+            ("../escaped.lua", Some("return nil")),
+        ]);
+
+        let dir = tempdir().unwrap();
+        let dest = dir.path().join("package");
+
+        let err = contents.extract_to(&dest).unwrap_err();
+
+        assert!(err.to_string().contains("../escaped.lua"));
+        assert!(!dest.join("init.lua").exists());
+        assert!(!dir.path().join("escaped.lua").exists());
+    }
+
+    #[test]
+    fn duplicate_entries() {
+        let contents = zip_of(&[
+            ("src/", None),
+            ("src/", None),
+            ("src/init.lua", Some("return nil")),
+        ]);
+        contents.check_entries().unwrap();
+
+        let contents = zip_of(&[
+            ("src/init.lua", Some("return 1")),
+            ("src/util/../init.lua", Some("return 2")),
+        ]);
+        let err = contents.check_entries().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Package contains more than one entry for the path {}",
+                Path::new("src/init.lua").display()
+            )
+        );
+
+        // A file and a directory can't share a path either.
+        let contents = zip_of(&[("src", Some("return nil")), ("src/", None)]);
+        assert!(contents.check_entries().is_err());
+    }
+}
@@ -1,9 +1,9 @@
 use std::collections::HashMap;
-use std::io::{BufReader, ErrorKind, Write};
-use std::path::PathBuf;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use fs_err::{create_dir_all, File, OpenOptions};
 use git2::Repository;
 use serde::{Deserialize, Serialize};
@@ -14,6 +14,13 @@ use crate::git_util;
 use crate::manifest::Manifest;
 use crate::package_name::PackageName;
 
+/// The most entries a registry is trusted to have in one package's index
+/// file unless configured otherwise.
+pub const DEFAULT_MAX_INDEX_ENTRIES: usize = 10_000;
+
+/// [`DEFAULT_MAX_INDEX_ENTRIES`] as the command line spells it.
+pub const DEFAULT_MAX_INDEX_ENTRIES_ARG: &str = "10000";
+
 /// Configuration contained in the index's `config.json` file.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageIndexConfig {
@@ -22,6 +29,26 @@ pub struct PackageIndexConfig {
 
     #[serde(default)]
     pub fallback_registries: Vec<String>,
+
+    /// Base URL to download package contents from, when they're served from
+    /// somewhere other than the registry API, like a CDN. It must serve the
+    /// same `/v1/package-contents` paths as the registry API.
+    #[serde(default)]
+    pub content_url: Option<Url>,
+
+    /// Whether a sparse index serves the files of many packages with one
+    /// request, at `index/-/batch?packages=<scope/name>,<scope/name>`. The
+    /// response is a JSON object from each package's name to its index file,
+    /// leaving out packages the index doesn't have.
+    #[serde(default)]
+    pub batch_queries: bool,
+}
+
+impl PackageIndexConfig {
+    /// The base URL that package contents should be downloaded from.
+    pub fn contents_base_url(&self) -> &Url {
+        self.content_url.as_ref().unwrap_or(&self.api)
+    }
 }
 
 pub struct PackageIndex {
@@ -43,6 +70,9 @@ pub struct PackageIndex {
     /// configuration.
     access_token: Option<String>,
 
+    /// The most entries to read from one package's file.
+    max_entries: usize,
+
     /// If this index is contained in a temporary location, like when running
     /// tests or a registry server, hold onto it here so that it'll be dropped
     /// at the right time.
@@ -61,6 +91,7 @@ impl PackageIndex {
             repository: Mutex::new(repository),
             package_cache: Mutex::new(HashMap::new()),
             access_token,
+            max_entries: DEFAULT_MAX_INDEX_ENTRIES,
             temp_dir: None,
         };
 
@@ -68,6 +99,15 @@ impl PackageIndex {
         Ok(index)
     }
 
+    /// Fail instead of reading more than `max_entries` entries from one
+    /// package's file.
+    pub fn with_max_entries(self, max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            ..self
+        }
+    }
+
     pub fn url(&self) -> &Url {
         &self.url
     }
@@ -87,6 +127,7 @@ impl PackageIndex {
             repository: Mutex::new(repository),
             package_cache: Mutex::new(HashMap::new()),
             access_token,
+            max_entries: DEFAULT_MAX_INDEX_ENTRIES,
             temp_dir: Some(temp_dir),
         };
 
@@ -107,6 +148,50 @@ impl PackageIndex {
         Ok(())
     }
 
+    /// The commit the index is checked out at.
+    pub fn head_rev(&self) -> anyhow::Result<String> {
+        let repository = self.repository.lock().unwrap();
+        let commit = repository.head()?.peel_to_commit()?;
+
+        Ok(commit.id().to_string())
+    }
+
+    /// Checks out the index as it was at the commit `rev`, fetching the latest
+    /// commits first if it isn't known yet. Updating the index moves it back
+    /// to the latest commit.
+    pub fn checkout(&self, rev: &str) -> anyhow::Result<()> {
+        let known = {
+            let repository = self.repository.lock().unwrap();
+            repository.revparse_single(rev).is_ok()
+        };
+        if !known {
+            self.update()?;
+        }
+
+        let repository = self.repository.lock().unwrap();
+        let commit = repository
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("The package index {} has no commit {}", self.url, rev))?;
+
+        log::info!("Checking out package index {} at {}", self.url, commit.id());
+
+        let mut options = git2::build::CheckoutBuilder::new();
+        options.force();
+        repository
+            .reset(
+                commit.as_object(),
+                git2::ResetType::Hard,
+                Some(&mut options),
+            )
+            .with_context(|| format!("could not check out package index at {}", rev))?;
+
+        // Everything cached so far was read at a different commit.
+        self.package_cache.lock().unwrap().clear();
+
+        Ok(())
+    }
+
     pub fn config(&self) -> anyhow::Result<PackageIndexConfig> {
         let config_path = self.path.join("config.json");
         let contents = fs_err::read_to_string(config_path)?;
@@ -160,8 +245,10 @@ impl PackageIndex {
         let mut package_cache = self.package_cache.lock().unwrap();
 
         if package_cache.contains_key(name) {
+            log::trace!("Index cache hit for {}", name);
             Ok(Arc::clone(&package_cache[name]))
         } else {
+            log::trace!("Index cache miss for {}, reading from index", name);
             let package_path = self.package_path(name);
 
             // Construct a buffered file reader, with a nice error message in the
@@ -172,17 +259,7 @@ impl PackageIndex {
                 .with_context(|| format!("could not open package {} from index", name))?;
             let file = BufReader::new(file);
 
-            // Read all of the manifests from the package file.
-            //
-            // Entries into the index are stored as JSON Lines. This block will
-            // either parse all of the entries, or fail with a single error.
-            let manifest_stream: Result<Vec<Manifest>, serde_json::Error> =
-                serde_json::Deserializer::from_reader(file)
-                    .into_iter::<Manifest>()
-                    .collect();
-
-            let mut versions = manifest_stream
-                .with_context(|| format!("could not parse package index entry for {}", name))?;
+            let mut versions = read_package_entries(file, name, self.max_entries)?;
 
             versions.sort_by(|a, b| b.package.version.cmp(&a.package.version));
 
@@ -248,6 +325,16 @@ impl PackageIndex {
         Ok(())
     }
 
+    /// Whether the index has an entry for any version of this package.
+    pub fn has_package(&self, name: &PackageName) -> bool {
+        self.package_path(name).is_file()
+    }
+
+    /// The name of every package in the index.
+    pub fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        package_names_in(&self.path)
+    }
+
     fn package_path(&self, name: &PackageName) -> PathBuf {
         // Each package has all of its versions stored in a folder based on its
         // scope and name.
@@ -263,6 +350,62 @@ pub struct PackageMetadata {
     pub versions: Vec<Manifest>,
 }
 
+/// Reads every entry from a package's index file, which are stored as JSON
+/// Lines. Files with more than `max_entries` entries are rejected as soon as
+/// the limit is passed, so that a registry can't make us read forever.
+pub fn read_package_entries(
+    reader: impl Read,
+    name: &PackageName,
+    max_entries: usize,
+) -> anyhow::Result<Vec<Manifest>> {
+    let mut versions = Vec::new();
+
+    for manifest in serde_json::Deserializer::from_reader(reader).into_iter::<Manifest>() {
+        if versions.len() == max_entries {
+            bail!(
+                "The index file for {} has more than {} entries. If the registry is trusted, \
+                 raise the limit with --max-index-entries",
+                name,
+                max_entries
+            );
+        }
+
+        let manifest = manifest
+            .with_context(|| format!("could not parse package index entry for {}", name))?;
+        versions.push(manifest);
+    }
+
+    Ok(versions)
+}
+
+/// The name of every package in the index checked out at `path`, where each
+/// scope is a folder holding one file per package.
+pub fn package_names_in(path: &Path) -> anyhow::Result<Vec<PackageName>> {
+    let mut names = Vec::new();
+
+    for scope in fs_err::read_dir(path)? {
+        let scope = scope?;
+        let scope_name = scope.file_name().to_string_lossy().into_owned();
+
+        if scope_name.starts_with('.') || !scope.file_type()?.is_dir() {
+            continue;
+        }
+
+        for package in fs_err::read_dir(scope.path())? {
+            let package_name = package?.file_name().to_string_lossy().into_owned();
+
+            if package_name == "owners.json" {
+                continue;
+            }
+
+            names.push(PackageName::new(scope_name.as_str(), package_name)?);
+        }
+    }
+
+    names.sort();
+    Ok(names)
+}
+
 fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
     let registry_name = match (index_url.domain(), index_url.scheme()) {
         (Some(domain), _) => domain,
@@ -0,0 +1,313 @@
+//! What a registry knows about a package, shown by `wally info` before
+//! depending on it.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use anyhow::bail;
+use semver::Version;
+use serde::Serialize;
+
+use crate::manifest::{Manifest, Realm};
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::{PackageSourceMap, PackageSourceProvider};
+
+/// The package to look up: either exactly one version of it, or the newest
+/// version matching a requirement.
+#[derive(Debug, Clone)]
+pub enum PackageQuery {
+    Exact(PackageId),
+    Newest(PackageReq),
+}
+
+impl PackageQuery {
+    fn name(&self) -> &PackageName {
+        match self {
+            PackageQuery::Exact(package_id) => package_id.name(),
+            PackageQuery::Newest(package_req) => package_req.name(),
+        }
+    }
+
+    fn matches(&self, manifest: &Manifest) -> bool {
+        match self {
+            PackageQuery::Exact(package_id) => manifest.package.version == *package_id.version(),
+            PackageQuery::Newest(package_req) => {
+                package_req.matches(&manifest.package.name, &manifest.package.version)
+            }
+        }
+    }
+}
+
+impl FromStr for PackageQuery {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        // A bare version names exactly that version, not every version
+        // compatible with it like it would in a dependency.
+        if let Ok(package_id) = value.parse() {
+            Ok(PackageQuery::Exact(package_id))
+        } else {
+            Ok(PackageQuery::Newest(value.parse()?))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageInfo {
+    pub name: PackageName,
+    pub version: Version,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub authors: Vec<String>,
+    pub repository: Option<String>,
+    pub realm: Realm,
+    pub dependencies: Vec<DependencyInfo>,
+
+    /// Every version of the package the registry has, oldest first.
+    pub versions: Vec<Version>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyInfo {
+    /// The manifest table the dependency is listed in, like
+    /// `server-dependencies`.
+    pub table: &'static str,
+    pub alias: String,
+    pub requirement: PackageReq,
+}
+
+impl PackageInfo {
+    /// Looks the package up in the first source that has any version of it.
+    pub fn lookup(
+        package_sources: &PackageSourceMap,
+        query: &PackageQuery,
+    ) -> anyhow::Result<Self> {
+        let name = query.name();
+
+        for source_id in package_sources.source_order() {
+            let source = package_sources.get(source_id).unwrap();
+
+            let versions = match source.all_versions(name) {
+                Ok(versions) if !versions.is_empty() => versions,
+                Ok(_) => continue,
+                Err(err) => {
+                    log::debug!(
+                        "Source {:?} could not provide {}: {:#}",
+                        source_id,
+                        name,
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            let manifest = match versions
+                .iter()
+                .rev()
+                .find(|manifest| query.matches(manifest))
+            {
+                Some(manifest) => manifest,
+                None => {
+                    let available: Vec<String> = versions
+                        .iter()
+                        .map(|manifest| manifest.package.version.to_string())
+                        .collect();
+
+                    bail!(
+                        "No version of {} matches. Available versions: {}",
+                        name,
+                        available.join(", ")
+                    );
+                }
+            };
+
+            return Ok(Self::from_manifest(manifest, &versions));
+        }
+
+        bail!("No package named {} could be found", name)
+    }
+
+    fn from_manifest(manifest: &Manifest, versions: &[Manifest]) -> Self {
+        let tables = [
+            ("dependencies", &manifest.dependencies),
+            ("server-dependencies", &manifest.server_dependencies),
+            ("dev-dependencies", &manifest.dev_dependencies),
+            ("build-dependencies", &manifest.build_dependencies),
+            ("peer-dependencies", &manifest.peer_dependencies),
+        ];
+
+        let dependencies = tables
+            .iter()
+            .flat_map(|(table, dependencies)| {
+                dependencies
+                    .iter()
+                    .map(move |(alias, requirement)| DependencyInfo {
+                        table: *table,
+                        alias: alias.clone(),
+                        requirement: requirement.clone(),
+                    })
+            })
+            .collect();
+
+        Self {
+            name: manifest.package.name.clone(),
+            version: manifest.package.version.clone(),
+            description: manifest.package.description.clone(),
+            license: manifest.package.license.clone(),
+            authors: manifest.package.authors.clone(),
+            repository: manifest.package.repository.clone(),
+            realm: manifest.package.realm,
+            dependencies,
+            versions: versions
+                .iter()
+                .map(|manifest| manifest.package.version.clone())
+                .collect(),
+        }
+    }
+
+    /// Formats the metadata for people to read.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        writeln!(text, "{}@{}", self.name, self.version).unwrap();
+
+        if let Some(description) = &self.description {
+            writeln!(text, "{}", description).unwrap();
+        }
+
+        writeln!(text).unwrap();
+
+        let versions: Vec<String> = self.versions.iter().map(ToString::to_string).collect();
+        let fields = [
+            ("license", self.license.clone()),
+            (
+                "authors",
+                Some(self.authors.join(", ")).filter(|authors| !authors.is_empty()),
+            ),
+            ("repository", self.repository.clone()),
+            ("realm", Some(self.realm.to_string())),
+            ("versions", Some(versions.join(", "))),
+        ];
+
+        for (field, value) in &fields {
+            if let Some(value) = value {
+                writeln!(text, "{}: {}", field, value).unwrap();
+            }
+        }
+
+        if !self.dependencies.is_empty() {
+            writeln!(text, "\ndependencies:").unwrap();
+
+            for dependency in &self.dependencies {
+                writeln!(
+                    text,
+                    "    {} = \"{}\" ({})",
+                    dependency.alias, dependency.requirement, dependency.table
+                )
+                .unwrap();
+            }
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::{PackageSource, TestRegistry};
+    use crate::test_package::PackageBuilder;
+
+    fn seeded_registry(registry_dir: &std::path::Path) -> PackageSourceMap {
+        let registry = PackageSource::TestRegistry(TestRegistry::new(registry_dir));
+
+        for version in &["1.0.0", "1.1.0", "2.0.0-beta.1"] {
+            let builder = PackageBuilder::new(format!("biff/signal@{}", version))
+                .with_license("MIT")
+                .with_dep("Minimal", "biff/minimal@0.1.0")
+                .with_server_dep("Logger", "biff/logger@1.2.0");
+            let contents = builder.contents();
+
+            let mut manifest = builder.into_manifest();
+            manifest.package.description = Some("Signals for everyone.".to_owned());
+            manifest.package.authors = vec!["Biff Lumfer <biff@playadopt.me>".to_owned()];
+            manifest.package.repository = Some("https://github.com/biff/signal".to_owned());
+
+            registry.publish(&manifest, &contents).unwrap();
+        }
+
+        PackageSourceMap::new(Box::new(registry))
+    }
+
+    #[test]
+    fn newest_version() {
+        let registry_dir = tempfile::tempdir().unwrap();
+        let package_sources = seeded_registry(registry_dir.path());
+
+        let query = "biff/signal".parse().unwrap();
+        let info = PackageInfo::lookup(&package_sources, &query).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&info).unwrap(),
+            serde_json::json!({
+                "name": "biff/signal",
+                "version": "1.1.0",
+                "description": "Signals for everyone.",
+                "license": "MIT",
+                "authors": ["Biff Lumfer <biff@playadopt.me>"],
+                "repository": "https://github.com/biff/signal",
+                "realm": "shared",
+                "dependencies": [
+                    {
+                        "table": "dependencies",
+                        "alias": "Minimal",
+                        "requirement": "biff/minimal@>=0.1.0, <0.2.0",
+                    },
+                    {
+                        "table": "server-dependencies",
+                        "alias": "Logger",
+                        "requirement": "biff/logger@>=1.2.0, <2.0.0",
+                    },
+                ],
+                "versions": ["1.0.0", "1.1.0", "2.0.0-beta.1"],
+            })
+        );
+
+        assert_eq!(
+            info.to_text(),
+            concat!(
+                "biff/signal@1.1.0\n",
+                "Signals for everyone.\n",
+                "\n",
+                "license: MIT\n",
+                "authors: Biff Lumfer <biff@playadopt.me>\n",
+                "repository: https://github.com/biff/signal\n",
+                "realm: shared\n",
+                "versions: 1.0.0, 1.1.0, 2.0.0-beta.1\n",
+                "\n",
+                "dependencies:\n",
+                "    Minimal = \"biff/minimal@>=0.1.0, <0.2.0\" (dependencies)\n",
+                "    Logger = \"biff/logger@>=1.2.0, <2.0.0\" (server-dependencies)\n",
+            )
+        );
+    }
+
+    #[test]
+    fn exact_version() {
+        let registry_dir = tempfile::tempdir().unwrap();
+        let package_sources = seeded_registry(registry_dir.path());
+
+        let query = "biff/signal@1.0.0".parse().unwrap();
+        let info = PackageInfo::lookup(&package_sources, &query).unwrap();
+        assert_eq!(info.version.to_string(), "1.0.0");
+
+        let query = "biff/signal@3.0.0".parse().unwrap();
+        assert!(PackageInfo::lookup(&package_sources, &query).is_err());
+
+        let query = "biff/missing".parse().unwrap();
+        assert!(PackageInfo::lookup(&package_sources, &query).is_err());
+    }
+}
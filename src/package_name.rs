@@ -9,7 +9,9 @@ use serde::ser::{Serialize, Serializer};
 /// scope and name.
 ///
 /// Both the scope and name portions of a package name must consist only of
-/// lowercase letters, digits, and dashes (`-`).
+/// lowercase letters, digits, and dashes (`-`). Uppercase ASCII letters are
+/// lowercased when a name is created, so every `PackageName` that exists is
+/// valid and in its normal form, safe to use as a path component.
 ///
 /// Examples of package names:
 /// * `hello/world`
@@ -23,13 +25,18 @@ pub struct PackageName {
 }
 
 impl PackageName {
+    /// Creates a package name, lowercasing the scope and name and then
+    /// checking that they're valid.
     pub fn new<S, N>(scope: S, name: N) -> anyhow::Result<Self>
     where
         S: Into<String>,
         N: Into<String>,
     {
-        let scope = scope.into();
-        let name = name.into();
+        let mut scope = scope.into();
+        let mut name = name.into();
+
+        scope.make_ascii_lowercase();
+        name.make_ascii_lowercase();
 
         validate_scope(&scope)?;
         validate_name(&name)?;
@@ -144,10 +151,25 @@ mod test {
         assert_eq!(package.name(), "sisyphus-simulator-2");
     }
 
+    #[test]
+    fn new_lowercases() {
+        let package = PackageName::new("Upper-Skewer-Case", "Foo").unwrap();
+        assert_eq!(package.scope(), "upper-skewer-case");
+        assert_eq!(package.name(), "foo");
+        assert_eq!(
+            package,
+            PackageName::new("upper-skewer-case", "foo").unwrap()
+        );
+
+        let parsed: PackageName = "Evaera/Promise".parse().unwrap();
+        assert_eq!(parsed.to_string(), "evaera/promise");
+    }
+
     #[test]
     fn new_invalid() {
-        // Uppercase letters are not allowed.
-        assert!(PackageName::new("Upper-Skewer-Case", "Foo").is_err());
+        // Only ASCII letters can be lowercased, so other uppercase letters
+        // are still rejected.
+        assert!(PackageName::new("ÜBER", "foo").is_err());
 
         // Underscores are not allowed to prevent confusion with dashes.
         assert!(PackageName::new("snake_case", "foo").is_err());
@@ -155,6 +177,10 @@ mod test {
         // Slashes are not allowed to avoid ambiguity.
         assert!(PackageName::new("hello/world", "from/me").is_err());
 
+        // Spaces and other punctuation are not allowed.
+        assert!(PackageName::new("biff", "bad name").is_err());
+        assert!(PackageName::new("biff", "..").is_err());
+
         // Scopes and names must have one or more characters.
         assert!(PackageName::new("", "").is_err());
     }
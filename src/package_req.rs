@@ -3,9 +3,11 @@ use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Context};
 use semver::{Version, VersionReq};
+use semver_parser::{Compat, Op, RangeSet};
 use serde::de::{Deserialize, Deserializer, Error, Visitor};
 use serde::ser::{Serialize, Serializer};
 
+use crate::manifest::ZeroVersionCompat;
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 
@@ -16,15 +18,42 @@ use crate::package_name::PackageName;
 /// * `roblox/roact@1.4.2`
 /// * `lpghatguy/asink@0.2.0-alpha.3`
 /// * `foo/bar@1`
+/// * `foo/bar`, which means the newest version that isn't a pre-release
+/// * `foo/bar@^1, !=1.3.2`, which is any `1.x` version except `1.3.2`
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PackageReq {
     name: PackageName,
     version_req: VersionReq,
+
+    /// Exact versions within the range that never match, like a release
+    /// that's known to be broken. Kept sorted and without duplicates.
+    excluded: Vec<Version>,
 }
 
 impl PackageReq {
     pub fn new(name: PackageName, version_req: VersionReq) -> Self {
-        PackageReq { name, version_req }
+        PackageReq {
+            name,
+            version_req,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// The same requirement, except that none of the given versions match.
+    pub fn with_exclusions(mut self, excluded: impl IntoIterator<Item = Version>) -> Self {
+        self.excluded.extend(excluded);
+        self.excluded.sort();
+        self.excluded.dedup();
+        self
+    }
+
+    /// A requirement for any version of the package that isn't a pre-release,
+    /// which is what a package requirement without a version means.
+    pub fn latest(name: PackageName) -> Self {
+        // Comparisons only match pre-releases of the exact version they name,
+        // so this range leaves all of them out.
+        let version_req = VersionReq::parse(">=0.0.0").unwrap();
+        PackageReq::new(name, version_req)
     }
 
     pub fn name(&self) -> &PackageName {
@@ -35,18 +64,204 @@ impl PackageReq {
         &self.version_req
     }
 
+    /// The versions within the range that never match.
+    pub fn excluded(&self) -> &[Version] {
+        &self.excluded
+    }
+
     pub fn matches_id(&self, package_id: &PackageId) -> bool {
         self.matches(package_id.name(), package_id.version())
     }
 
     pub fn matches(&self, name: &PackageName, version: &Version) -> bool {
-        self.name() == name && self.version_req.matches(version)
+        self.name() == name && self.version_req.matches(version) && !self.excluded.contains(version)
+    }
+
+    /// The requirement as matched under the given compatibility mode for
+    /// `0.x` versions. Relaxed mode widens every range that stops before the
+    /// next `0.x` minor version, like `^0.2` (`>=0.2.0, <0.3.0`), to stop
+    /// before `1.0.0` instead. Ranges within a single `0.0.x` version are
+    /// left alone, as are exact versions.
+    pub fn with_zero_version_compat(&self, compat: ZeroVersionCompat) -> PackageReq {
+        if compat == ZeroVersionCompat::Strict {
+            return self.clone();
+        }
+
+        // semver keeps a requirement's comparators to itself, so they're read
+        // back with the parser it uses, changed in place, and handed back to
+        // semver as they are.
+        let mut range_set = match RangeSet::parse(&self.version_req.to_string(), Compat::Cargo) {
+            Ok(range_set) => range_set,
+            Err(_) => return self.clone(),
+        };
+
+        for range in &mut range_set.ranges {
+            for comparator in &mut range.comparator_set {
+                let stops_before_next_minor = matches!(comparator.op, Op::Lt)
+                    && comparator.major == 0
+                    && comparator.minor > 0
+                    && comparator.patch == 0
+                    && comparator.pre.is_empty();
+
+                if stops_before_next_minor {
+                    comparator.major = 1;
+                    comparator.minor = 0;
+                }
+            }
+        }
+
+        PackageReq::new(self.name.clone(), VersionReq::from(range_set))
+            .with_exclusions(self.excluded.iter().cloned())
+    }
+
+    /// Combines two requirements on the same package into one that only allows
+    /// the versions both of them allow, like `^1.2.0` and `<1.5.0` into
+    /// `>=1.2.0, <1.5.0`. Returns `None` if the packages differ or no version
+    /// could satisfy both.
+    ///
+    /// Pre-releases follow the usual rules for the combined range, so one that
+    /// only one of the requirements opted into may be allowed by the result.
+    /// Versions excluded by either requirement are excluded from the result.
+    pub fn intersect(&self, other: &PackageReq) -> Option<PackageReq> {
+        if self.name != other.name {
+            return None;
+        }
+
+        // Requirements are always displayed in their most explicit form, like
+        // `>=1.2.0, <2.0.0 || =3.0.0`, which is what we combine.
+        let ours = self.version_req.to_string();
+        let theirs = other.version_req.to_string();
+
+        let mut alternatives = Vec::new();
+
+        for our_range in ours.split(" || ") {
+            for their_range in theirs.split(" || ") {
+                let mut bounds = Bounds::default();
+                bounds.add_range(our_range)?;
+                bounds.add_range(their_range)?;
+
+                if let Some(range) = bounds.to_range() {
+                    if !alternatives.contains(&range) {
+                        alternatives.push(range);
+                    }
+                }
+            }
+        }
+
+        if alternatives.is_empty() {
+            return None;
+        }
+
+        let version_req = VersionReq::parse(&alternatives.join(" || ")).ok()?;
+        let excluded = self.excluded.iter().chain(&other.excluded).cloned();
+        Some(PackageReq::new(self.name.clone(), version_req).with_exclusions(excluded))
+    }
+}
+
+/// The tightest lower and upper bounds of a range of versions, each with
+/// whether the bound itself is included.
+#[derive(Default)]
+struct Bounds {
+    lower: Option<(Version, bool)>,
+    upper: Option<(Version, bool)>,
+}
+
+impl Bounds {
+    /// Narrows the bounds by every comparison in a range like
+    /// `>=1.2.0, <2.0.0`. Returns `None` if the range can't be understood.
+    fn add_range(&mut self, range: &str) -> Option<()> {
+        for comparison in range.split(',').map(str::trim) {
+            if comparison == "*" || comparison.is_empty() {
+                continue;
+            }
+
+            let split = comparison
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(comparison.len());
+            let (op, version) = comparison.split_at(split);
+            let version = Version::parse(version).ok()?;
+
+            match op.trim() {
+                ">=" => self.raise_lower(version, true),
+                ">" => self.raise_lower(version, false),
+                "<=" => self.lower_upper(version, true),
+                "<" => self.lower_upper(version, false),
+                "=" | "" => {
+                    self.raise_lower(version.clone(), true);
+                    self.lower_upper(version, true);
+                }
+                _ => return None,
+            }
+        }
+
+        Some(())
+    }
+
+    fn raise_lower(&mut self, version: Version, inclusive: bool) {
+        let tighter = match &self.lower {
+            Some((lower, lower_inclusive)) => {
+                version > *lower || (version == *lower && *lower_inclusive && !inclusive)
+            }
+            None => true,
+        };
+
+        if tighter {
+            self.lower = Some((version, inclusive));
+        }
+    }
+
+    fn lower_upper(&mut self, version: Version, inclusive: bool) {
+        let tighter = match &self.upper {
+            Some((upper, upper_inclusive)) => {
+                version < *upper || (version == *upper && *upper_inclusive && !inclusive)
+            }
+            None => true,
+        };
+
+        if tighter {
+            self.upper = Some((version, inclusive));
+        }
+    }
+
+    /// Writes the bounds back out as a range, or returns `None` if no version
+    /// is within them.
+    fn to_range(&self) -> Option<String> {
+        match (&self.lower, &self.upper) {
+            (Some((lower, lower_inclusive)), Some((upper, upper_inclusive))) => {
+                if lower > upper || (lower == upper && !(*lower_inclusive && *upper_inclusive)) {
+                    None
+                } else if lower == upper {
+                    Some(format!("={}", lower))
+                } else {
+                    Some(format!(
+                        "{}{}, {}{}",
+                        if *lower_inclusive { ">=" } else { ">" },
+                        lower,
+                        if *upper_inclusive { "<=" } else { "<" },
+                        upper
+                    ))
+                }
+            }
+            (Some((lower, inclusive)), None) => {
+                Some(format!("{}{}", if *inclusive { ">=" } else { ">" }, lower))
+            }
+            (None, Some((upper, inclusive))) => {
+                Some(format!("{}{}", if *inclusive { "<=" } else { "<" }, upper))
+            }
+            (None, None) => Some("*".to_owned()),
+        }
     }
 }
 
 impl fmt::Display for PackageReq {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        write!(formatter, "{}@{}", self.name, self.version_req)
+        write!(formatter, "{}@{}", self.name, self.version_req)?;
+
+        for version in &self.excluded {
+            write!(formatter, ", !={}", version)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -54,7 +269,8 @@ impl FromStr for PackageReq {
     type Err = anyhow::Error;
 
     fn from_str(value: &str) -> anyhow::Result<Self> {
-        const BAD_FORMAT_MSG: &str = "a package requirement is of the form SCOPE/NAME@VERSION_REQ";
+        const BAD_FORMAT_MSG: &str =
+            "a package requirement is of the form SCOPE/NAME@VERSION_REQ or SCOPE/NAME";
 
         let mut first_half = value.splitn(2, '/');
         let scope = first_half.next().ok_or_else(|| anyhow!(BAD_FORMAT_MSG))?;
@@ -63,7 +279,13 @@ impl FromStr for PackageReq {
         let mut second_half = name_and_version.splitn(2, '@');
         let name = second_half.next().ok_or_else(|| anyhow!(BAD_FORMAT_MSG))?;
 
-        let version_req_source = second_half.next().ok_or_else(|| anyhow!(BAD_FORMAT_MSG))?;
+        let version_req_source = match second_half.next() {
+            Some(version_req_source) => version_req_source,
+            None => {
+                let package_name = PackageName::new(scope, name).context(BAD_FORMAT_MSG)?;
+                return Ok(PackageReq::latest(package_name));
+            }
+        };
 
         // The VersionReq type will successfully parse from an empty or
         // all-spaces string, yielding a wildcard. This is not behavior we want,
@@ -74,24 +296,39 @@ impl FromStr for PackageReq {
             bail!(BAD_FORMAT_MSG);
         }
 
-        let version_req = version_req_source
+        // Exclusions like `!=1.3.2` aren't something semver understands, so
+        // they're taken out of the range before it's parsed.
+        let mut comparisons = Vec::new();
+        let mut excluded = Vec::new();
+        for comparison in version_req_source.split(',') {
+            match comparison.trim().strip_prefix("!=") {
+                Some(version) => excluded
+                    .push(Version::parse(version.trim()).with_context(|| {
+                        format!("could not parse excluded version {}", version)
+                    })?),
+                None => comparisons.push(comparison),
+            }
+        }
+
+        let package_name = PackageName::new(scope, name).context(BAD_FORMAT_MSG)?;
+
+        // A requirement made only of exclusions allows everything else.
+        if comparisons.is_empty() {
+            return Ok(PackageReq::latest(package_name).with_exclusions(excluded));
+        }
+
+        let version_req = comparisons
+            .join(",")
             .parse()
             .context("could not parse version requirement")?;
 
-        let package_name = PackageName::new(scope, name).context(BAD_FORMAT_MSG)?;
-        Ok(PackageReq::new(package_name, version_req))
+        Ok(PackageReq::new(package_name, version_req).with_exclusions(excluded))
     }
 }
 
 impl Serialize for PackageReq {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let combined_name = format!(
-            "{}/{}@{}",
-            self.name().scope(),
-            self.name().name(),
-            self.version_req()
-        );
-        serializer.serialize_str(&combined_name)
+        serializer.serialize_str(&self.to_string())
     }
 }
 
@@ -109,7 +346,7 @@ impl<'de> Visitor<'de> for PackageReqVisitor {
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         write!(
             formatter,
-            "a package requirement of the form SCOPE/NAME@VERSION_REQ"
+            "a package requirement of the form SCOPE/NAME@VERSION_REQ or SCOPE/NAME"
         )
     }
 
@@ -169,13 +406,128 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_latest() {
+        // Leaving out the version means the newest stable version.
+        let latest: PackageReq = "hello/world".parse().unwrap();
+        assert_eq!(latest.name().scope(), "hello");
+        assert_eq!(latest.name().name(), "world");
+
+        let name = latest.name().clone();
+        assert!(latest.matches(&name, &Version::parse("0.1.0").unwrap()));
+        assert!(latest.matches(&name, &Version::parse("3.2.1").unwrap()));
+        assert!(!latest.matches(&name, &Version::parse("4.0.0-beta.1").unwrap()));
+    }
+
+    #[test]
+    fn exclusions() {
+        let req: PackageReq = "hello/world@^1, !=1.3.2".parse().unwrap();
+        assert_eq!(req.version_req(), &VersionReq::parse("^1").unwrap());
+        assert_eq!(req.excluded(), &[Version::parse("1.3.2").unwrap()]);
+
+        let name = req.name().clone();
+        assert!(req.matches(&name, &Version::parse("1.3.1").unwrap()));
+        assert!(!req.matches(&name, &Version::parse("1.3.2").unwrap()));
+        assert!(req.matches(&name, &Version::parse("1.3.3").unwrap()));
+        assert!(!req.matches(&name, &Version::parse("2.0.0").unwrap()));
+
+        // Exclusions survive being written out and read back in.
+        assert_eq!(req.to_string(), "hello/world@>=1.0.0, <2.0.0, !=1.3.2");
+        assert_eq!(req.to_string().parse::<PackageReq>().unwrap(), req);
+
+        // Combining requirements keeps what either of them excluded.
+        let other: PackageReq = "hello/world@>=1.2.0, !=1.4.0".parse().unwrap();
+        let combined = req.intersect(&other).unwrap();
+        assert_eq!(
+            combined.to_string(),
+            "hello/world@>=1.2.0, <2.0.0, !=1.3.2, !=1.4.0"
+        );
+
+        let bad: Result<PackageReq, _> = "hello/world@^1, !=1.3".parse();
+        bad.unwrap_err();
+    }
+
+    #[test]
+    fn intersect_overlapping() {
+        let a: PackageReq = "hello/world@1.2.0".parse().unwrap();
+        let b: PackageReq = "hello/world@>=1.0.0, <1.5.0".parse().unwrap();
+
+        let combined = a.intersect(&b).unwrap();
+        assert_eq!(combined.version_req().to_string(), ">=1.2.0, <1.5.0");
+        assert_eq!(b.intersect(&a), Some(combined));
+    }
+
+    #[test]
+    fn intersect_disjoint() {
+        let a: PackageReq = "hello/world@1.2.0".parse().unwrap();
+        let b: PackageReq = "hello/world@2.0.0".parse().unwrap();
+        assert_eq!(a.intersect(&b), None);
+
+        // Bounds that meet at a version only one of them includes.
+        let below: PackageReq = "hello/world@<1.0.0".parse().unwrap();
+        let above: PackageReq = "hello/world@>=1.0.0".parse().unwrap();
+        assert_eq!(below.intersect(&above), None);
+
+        // Requirements on different packages never intersect.
+        let other: PackageReq = "hello/other@1.2.0".parse().unwrap();
+        assert_eq!(a.intersect(&other), None);
+    }
+
+    #[test]
+    fn intersect_subset() {
+        let outer: PackageReq = "hello/world@1.0.0".parse().unwrap();
+        let inner: PackageReq = "hello/world@1.4.2".parse().unwrap();
+
+        assert_eq!(
+            outer.intersect(&inner).unwrap().version_req().to_string(),
+            ">=1.4.2, <2.0.0"
+        );
+
+        let exact: PackageReq = "hello/world@=1.4.3".parse().unwrap();
+        assert_eq!(
+            outer.intersect(&exact).unwrap().version_req().to_string(),
+            "=1.4.3"
+        );
+    }
+
+    #[test]
+    fn zero_version_compat() {
+        let req: PackageReq = "hello/world@^0.2".parse().unwrap();
+        let name = req.name().clone();
+        let v0_3 = Version::parse("0.3.0").unwrap();
+        let v1 = Version::parse("1.0.0").unwrap();
+
+        let strict = req.with_zero_version_compat(ZeroVersionCompat::Strict);
+        assert!(!strict.matches(&name, &v0_3));
+
+        let relaxed = req.with_zero_version_compat(ZeroVersionCompat::Relaxed);
+        assert!(relaxed.matches(&name, &v0_3));
+        assert!(!relaxed.matches(&name, &v1));
+        assert_eq!(relaxed.version_req().to_string(), ">=0.2.0, <1.0.0");
+
+        // Every alternative is widened, and exclusions are kept.
+        let req: PackageReq = "hello/world@^0.2 || ^0.5, !=0.4.0".parse().unwrap();
+        let relaxed = req.with_zero_version_compat(ZeroVersionCompat::Relaxed);
+        assert!(relaxed.matches(&name, &Version::parse("0.3.0").unwrap()));
+        assert!(!relaxed.matches(&name, &Version::parse("0.4.0").unwrap()));
+        assert!(relaxed.matches(&name, &Version::parse("0.6.1").unwrap()));
+        assert!(!relaxed.matches(&name, &v1));
+
+        // Exact versions and ranges within a 0.0.x version aren't widened.
+        for unchanged in &["hello/world@=0.2.0", "hello/world@^0.0.3"] {
+            let req: PackageReq = unchanged.parse().unwrap();
+            let relaxed = req.with_zero_version_compat(ZeroVersionCompat::Relaxed);
+            assert_eq!(relaxed.to_string(), req.to_string());
+        }
+    }
+
     #[test]
     fn parse_invalid() {
-        // Package requirements require a version requirement.
-        let no_version: Result<PackageReq, _> = "hello/world".parse();
-        no_version.unwrap_err();
+        // Giving an @ means there must be a version requirement after it.
         let no_version_at: Result<PackageReq, _> = "hello/world@".parse();
         no_version_at.unwrap_err();
+        let no_name: Result<PackageReq, _> = "hello".parse();
+        no_name.unwrap_err();
     }
 
     #[test]
@@ -1,33 +1,67 @@
+mod assert_offline;
+mod http_registry;
 mod in_memory;
+mod local;
 mod registry;
 mod test_registry;
 
+pub use self::assert_offline::AssertOffline;
+pub use self::http_registry::{HttpRegistry, SPARSE_PREFIX};
 pub use self::in_memory::InMemoryRegistry;
 use self::in_memory::InMemoryRegistrySource;
-pub use self::registry::Registry;
+pub use self::local::LocalPackage;
+pub use self::registry::{Registry, DEFAULT_MAX_DOWNLOAD_SIZE, DEFAULT_MAX_DOWNLOAD_SIZE_ARG};
 pub use self::test_registry::TestRegistry;
 
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use serde::Serialize;
+use anyhow::bail;
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
 
+use crate::http_config::HttpConfig;
 use crate::manifest::Manifest;
 use crate::package_contents::PackageContents;
 use crate::package_id::PackageId;
+use crate::package_index::DEFAULT_MAX_INDEX_ENTRIES;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PackageSourceId {
     DefaultRegistry,
     Git(String),
+
+    /// A registry read over HTTP, named by its `sparse+` registry spec.
+    Http(String),
+
     Path(PathBuf),
+
+    /// A single package in a folder on disk, from a path dependency.
+    Local(PathBuf),
+}
+
+impl PackageSourceId {
+    /// The ID of the registry at `spec`, which is an HTTP registry if it has
+    /// the `sparse+` prefix and a Git registry otherwise, the same way
+    /// `PackageSource::from_registry_spec` decides.
+    pub fn for_registry(spec: &str) -> Self {
+        if spec.starts_with(SPARSE_PREFIX) {
+            PackageSourceId::Http(spec.to_owned())
+        } else {
+            PackageSourceId::Git(spec.to_owned())
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct PackageSourceMap {
     sources: HashMap<PackageSourceId, Box<PackageSource>>,
     source_order: Vec<PackageSourceId>,
+
+    /// Whether sources added to the map are wrapped in `AssertOffline`.
+    assert_offline: bool,
 }
 
 impl PackageSourceMap {
@@ -38,6 +72,7 @@ impl PackageSourceMap {
         Self {
             sources,
             source_order: vec![PackageSourceId::DefaultRegistry],
+            assert_offline: false,
         }
     }
 
@@ -49,6 +84,40 @@ impl PackageSourceMap {
         &self.source_order
     }
 
+    /// The commit the default registry's index was read at, if it's a Git
+    /// index that's been read.
+    pub fn index_rev(&self) -> anyhow::Result<Option<String>> {
+        self.sources[&PackageSourceId::DefaultRegistry].index_rev()
+    }
+
+    /// Adds a source that's only used for the packages explicitly assigned to
+    /// it, so it isn't searched for any other package.
+    pub fn add_source(&mut self, id: PackageSourceId, source: Box<PackageSource>) {
+        let source = self.guard(source);
+        self.sources.insert(id, source);
+    }
+
+    /// Makes every source in the map, including any added later, fail instead
+    /// of accessing the network.
+    pub fn assert_offline(&mut self) {
+        self.assert_offline = true;
+
+        self.sources = std::mem::take(&mut self.sources)
+            .into_iter()
+            .map(|(id, source)| (id, self.guard(source)))
+            .collect();
+    }
+
+    fn guard(&self, source: Box<PackageSource>) -> Box<PackageSource> {
+        let is_guarded = matches!(source.as_ref(), PackageSource::AssertOffline(_));
+
+        if self.assert_offline && !is_guarded {
+            Box::new(PackageSource::AssertOffline(AssertOffline::new(source)))
+        } else {
+            source
+        }
+    }
+
     /// Searches the current list of sources for fallbacks and adds any not yet in the list, producing
     /// a complete tree of reachable sources for packages.
     /// Sources are searched breadth-first to ensure correct fallback priority.
@@ -58,12 +127,28 @@ impl PackageSourceMap {
         while source_index < self.source_order.len() {
             let registry = self.sources.get(&self.source_order[source_index]).unwrap();
 
+            // Fallback registries are trusted with as much as the registry
+            // that pointed us at them.
+            let (max_download_size, max_index_entries) = match registry.as_ref() {
+                PackageSource::Registry(registry) => {
+                    (registry.max_download_size(), registry.max_index_entries())
+                }
+                PackageSource::HttpRegistry(registry) => {
+                    (registry.max_download_size(), registry.max_index_entries())
+                }
+                _ => (DEFAULT_MAX_DOWNLOAD_SIZE, DEFAULT_MAX_INDEX_ENTRIES),
+            };
+
             for fallback in registry.fallback_sources()? {
                 // Prevent circular references by only adding new sources
                 if !self.source_order.contains(&fallback) {
                     let source: Box<PackageSource> = match &fallback {
-                        PackageSourceId::Git(url) => {
-                            Box::new(PackageSource::Registry(Registry::from_registry_spec(url)?))
+                        PackageSourceId::Git(url) | PackageSourceId::Http(url) => {
+                            Box::new(PackageSource::from_registry_spec(
+                                url,
+                                max_download_size,
+                                max_index_entries,
+                            )?)
                         }
                         PackageSourceId::Path(path) => {
                             Box::new(PackageSource::TestRegistry(TestRegistry::new(path.clone())))
@@ -71,8 +156,12 @@ impl PackageSourceMap {
                         PackageSourceId::DefaultRegistry => {
                             panic!("Default registry should never be added as a fallback source!")
                         }
+                        PackageSourceId::Local(_) => {
+                            panic!("Local packages should never be added as a fallback source!")
+                        }
                     };
 
+                    let source = self.guard(source);
                     self.sources.insert(fallback.clone(), source);
                     self.source_order.push(fallback);
                 }
@@ -85,13 +174,117 @@ impl PackageSourceMap {
     }
 }
 
+/// What a package source can do besides finding and downloading packages, so
+/// that commands can pick a way of doing things that works with it, or fail
+/// early and say why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Packages can be added to it with `publish`.
+    pub can_publish: bool,
+
+    /// It can list every package it has with `package_names`.
+    pub can_enumerate: bool,
+
+    /// It overrides `get` to look up a single version directly, instead of
+    /// going through every version that `query` finds for the package.
+    pub supports_get: bool,
+
+    /// It has a search API that `wally search` can send queries to.
+    pub supports_search: bool,
+}
+
+/// A lazily evaluated stream of manifests returned by
+/// `PackageSourceProvider::query_iter`.
+pub type ManifestIter<'a> = Box<dyn Iterator<Item = anyhow::Result<Manifest>> + 'a>;
+
 pub trait PackageSourceProvider: Sync + Send + Clone {
     /// Update this package source, if it has state that needs to be updated.
     fn update(&self) -> anyhow::Result<()>;
 
+    /// Query this package source for the packages that match this
+    /// `PackageReq`, yielding them one at a time. Nothing past the last
+    /// manifest taken from the iterator needs to be read or parsed, so callers
+    /// that only want the first match can stop early.
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>>;
+
     /// Query this package source for all of the packages that match this
     /// `PackageReq`.
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>>;
+    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        self.query_iter(package_req)?.collect()
+    }
+
+    /// Query this package source for many `PackageReq`s at once, returning
+    /// what `query` would for each of them in the same order.
+    ///
+    /// Sources that can look up many packages with a single request should
+    /// override this. The rest query one package at a time.
+    fn query_many(&self, package_reqs: &[PackageReq]) -> Vec<anyhow::Result<Vec<Manifest>>> {
+        package_reqs
+            .iter()
+            .map(|package_req| self.query(package_req))
+            .collect()
+    }
+
+    /// The manifest of exactly this version of a package, or `None` if this
+    /// source doesn't have that version.
+    ///
+    /// Sources that can find a single version without looking through the
+    /// others should override this.
+    fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<Manifest>> {
+        let package_req = PackageReq::new(
+            package_id.name().clone(),
+            VersionReq::exact(package_id.version()),
+        );
+
+        for manifest in self.query_iter(&package_req)? {
+            let manifest = manifest?;
+
+            if manifest.package.version == *package_id.version() {
+                return Ok(Some(manifest));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Every version of the package with this name that this source has,
+    /// sorted from oldest to newest.
+    fn all_versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        let mut versions = self.query(&PackageReq::new(name.clone(), VersionReq::any()))?;
+        versions.sort_by(|a, b| a.package.version.cmp(&b.package.version));
+
+        Ok(versions)
+    }
+
+    /// Query this package source for the newest `limit` packages that match
+    /// this `PackageReq`, sorted from newest to oldest.
+    ///
+    /// Sources whose `query_iter` already yields the newest packages first
+    /// should override this to stop after `limit` of them.
+    fn query_newest(
+        &self,
+        package_req: &PackageReq,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Manifest>> {
+        // Only the newest packages seen so far are kept, so no more than
+        // `limit` manifests are ever held at once.
+        let mut newest: Vec<Manifest> = Vec::new();
+
+        for manifest in self.query_iter(package_req)? {
+            let manifest = manifest?;
+            let position = newest
+                .iter()
+                .position(|kept| kept.package.version < manifest.package.version)
+                .unwrap_or(newest.len());
+
+            if position < limit {
+                newest.insert(position, manifest);
+                newest.truncate(limit);
+            }
+        }
+
+        Ok(newest)
+    }
 
     /// Downloads the contents of a package given its fully-qualified
     /// `PackageId`.
@@ -99,13 +292,107 @@ pub trait PackageSourceProvider: Sync + Send + Clone {
 
     /// Provide a list of fallback sources to search if this source can't provide a package
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>>;
+
+    /// The name of every package this source has, sorted.
+    fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        bail!("This package source can't list the packages it has")
+    }
+
+    /// Adds a package to this source, with `contents` stored exactly as given.
+    fn publish(&self, manifest: &Manifest, _contents: &PackageContents) -> anyhow::Result<()> {
+        bail!(
+            "{} can't be published to this package source",
+            manifest.package_id()
+        )
+    }
+
+    /// What this source can do. Sources that override `publish`,
+    /// `package_names` or `get` should say so here.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_publish: false,
+            can_enumerate: false,
+            supports_get: false,
+            supports_search: false,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum PackageSource {
     InMemory(InMemoryRegistrySource),
     Registry(Registry),
+    HttpRegistry(HttpRegistry),
     TestRegistry(TestRegistry),
+    Local(LocalPackage),
+    AssertOffline(AssertOffline),
+}
+
+impl PackageSource {
+    /// Create the source for a registry spec, which usually comes from the
+    /// `registry` field of a package manifest. Specs starting with `sparse+`
+    /// are read over HTTP, using the user's `HttpConfig` for that registry,
+    /// and everything else is a Git index.
+    pub fn from_registry_spec(
+        spec: &str,
+        max_download_size: u64,
+        max_index_entries: usize,
+    ) -> anyhow::Result<Self> {
+        if spec.starts_with(SPARSE_PREFIX) {
+            let http_config = HttpConfig::load()?;
+
+            Ok(PackageSource::HttpRegistry(
+                HttpRegistry::from_registry_spec(spec)?
+                    .with_max_download_size(max_download_size)
+                    .with_max_index_entries(max_index_entries)
+                    .with_http_config(&http_config.for_registry(spec))?
+                    .with_retry_config(&http_config.retry),
+            ))
+        } else {
+            Ok(PackageSource::Registry(
+                Registry::from_registry_spec(spec)?
+                    .with_max_download_size(max_download_size)
+                    .with_max_index_entries(max_index_entries),
+            ))
+        }
+    }
+
+    /// Reads a registry's Git index as it was at the commit `index_rev`
+    /// instead of at the latest commit. Other sources have no commits to pin.
+    pub fn with_index_rev(self, index_rev: Option<String>) -> anyhow::Result<Self> {
+        match (self, index_rev) {
+            (PackageSource::Registry(source), index_rev) => {
+                Ok(PackageSource::Registry(source.with_index_rev(index_rev)))
+            }
+            (source, None) => Ok(source),
+            (_, Some(index_rev)) => bail!(
+                "The index can only be pinned to commit {} for registries with a Git index",
+                index_rev
+            ),
+        }
+    }
+
+    /// The commit a registry's Git index was read at, if it's been read.
+    pub fn index_rev(&self) -> anyhow::Result<Option<String>> {
+        match self {
+            PackageSource::Registry(source) => source.index_rev(),
+            PackageSource::AssertOffline(source) => source.index_rev(),
+            _ => Ok(None),
+        }
+    }
+
+    /// Where this source reaches out to over the network, or `None` if it
+    /// only reads from disk or memory.
+    pub fn network_location(&self) -> Option<String> {
+        match self {
+            PackageSource::Registry(source) => Some(source.index_url().to_string()),
+            PackageSource::HttpRegistry(source) => Some(source.base_url().to_string()),
+            PackageSource::AssertOffline(source) => source.network_location(),
+            PackageSource::InMemory(_)
+            | PackageSource::TestRegistry(_)
+            | PackageSource::Local(_) => None,
+        }
+    }
 }
 
 impl PackageSourceProvider for PackageSource {
@@ -113,15 +400,69 @@ impl PackageSourceProvider for PackageSource {
         match self {
             PackageSource::InMemory(source) => source.update(),
             PackageSource::Registry(source) => source.update(),
+            PackageSource::HttpRegistry(source) => source.update(),
             PackageSource::TestRegistry(source) => source.update(),
+            PackageSource::Local(source) => source.update(),
+            PackageSource::AssertOffline(source) => source.update(),
         }
     }
 
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
+        match self {
+            PackageSource::InMemory(source) => source.query_iter(package_req),
+            PackageSource::Registry(source) => source.query_iter(package_req),
+            PackageSource::HttpRegistry(source) => source.query_iter(package_req),
+            PackageSource::TestRegistry(source) => source.query_iter(package_req),
+            PackageSource::Local(source) => source.query_iter(package_req),
+            PackageSource::AssertOffline(source) => source.query_iter(package_req),
+        }
+    }
+
+    fn query_many(&self, package_reqs: &[PackageReq]) -> Vec<anyhow::Result<Vec<Manifest>>> {
+        match self {
+            PackageSource::InMemory(source) => source.query_many(package_reqs),
+            PackageSource::Registry(source) => source.query_many(package_reqs),
+            PackageSource::HttpRegistry(source) => source.query_many(package_reqs),
+            PackageSource::TestRegistry(source) => source.query_many(package_reqs),
+            PackageSource::Local(source) => source.query_many(package_reqs),
+            PackageSource::AssertOffline(source) => source.query_many(package_reqs),
+        }
+    }
+
+    fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<Manifest>> {
+        match self {
+            PackageSource::InMemory(source) => source.get(package_id),
+            PackageSource::Registry(source) => source.get(package_id),
+            PackageSource::HttpRegistry(source) => source.get(package_id),
+            PackageSource::TestRegistry(source) => source.get(package_id),
+            PackageSource::Local(source) => source.get(package_id),
+            PackageSource::AssertOffline(source) => source.get(package_id),
+        }
+    }
+
+    fn all_versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        match self {
+            PackageSource::InMemory(source) => source.all_versions(name),
+            PackageSource::Registry(source) => source.all_versions(name),
+            PackageSource::HttpRegistry(source) => source.all_versions(name),
+            PackageSource::TestRegistry(source) => source.all_versions(name),
+            PackageSource::Local(source) => source.all_versions(name),
+            PackageSource::AssertOffline(source) => source.all_versions(name),
+        }
+    }
+
+    fn query_newest(
+        &self,
+        package_req: &PackageReq,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Manifest>> {
         match self {
-            PackageSource::InMemory(source) => source.query(package_req),
-            PackageSource::Registry(source) => source.query(package_req),
-            PackageSource::TestRegistry(source) => source.query(package_req),
+            PackageSource::InMemory(source) => source.query_newest(package_req, limit),
+            PackageSource::Registry(source) => source.query_newest(package_req, limit),
+            PackageSource::HttpRegistry(source) => source.query_newest(package_req, limit),
+            PackageSource::TestRegistry(source) => source.query_newest(package_req, limit),
+            PackageSource::Local(source) => source.query_newest(package_req, limit),
+            PackageSource::AssertOffline(source) => source.query_newest(package_req, limit),
         }
     }
 
@@ -129,7 +470,10 @@ impl PackageSourceProvider for PackageSource {
         match self {
             PackageSource::InMemory(source) => source.download_package(package_id),
             PackageSource::Registry(source) => source.download_package(package_id),
+            PackageSource::HttpRegistry(source) => source.download_package(package_id),
             PackageSource::TestRegistry(source) => source.download_package(package_id),
+            PackageSource::Local(source) => source.download_package(package_id),
+            PackageSource::AssertOffline(source) => source.download_package(package_id),
         }
     }
 
@@ -137,7 +481,43 @@ impl PackageSourceProvider for PackageSource {
         match self {
             PackageSource::InMemory(source) => source.fallback_sources(),
             PackageSource::Registry(source) => source.fallback_sources(),
+            PackageSource::HttpRegistry(source) => source.fallback_sources(),
             PackageSource::TestRegistry(source) => source.fallback_sources(),
+            PackageSource::Local(source) => source.fallback_sources(),
+            PackageSource::AssertOffline(source) => source.fallback_sources(),
+        }
+    }
+
+    fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        match self {
+            PackageSource::InMemory(source) => source.package_names(),
+            PackageSource::Registry(source) => source.package_names(),
+            PackageSource::HttpRegistry(source) => source.package_names(),
+            PackageSource::TestRegistry(source) => source.package_names(),
+            PackageSource::Local(source) => source.package_names(),
+            PackageSource::AssertOffline(source) => source.package_names(),
+        }
+    }
+
+    fn publish(&self, manifest: &Manifest, contents: &PackageContents) -> anyhow::Result<()> {
+        match self {
+            PackageSource::InMemory(source) => source.publish(manifest, contents),
+            PackageSource::Registry(source) => source.publish(manifest, contents),
+            PackageSource::HttpRegistry(source) => source.publish(manifest, contents),
+            PackageSource::TestRegistry(source) => source.publish(manifest, contents),
+            PackageSource::Local(source) => source.publish(manifest, contents),
+            PackageSource::AssertOffline(source) => source.publish(manifest, contents),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        match self {
+            PackageSource::InMemory(source) => source.capabilities(),
+            PackageSource::Registry(source) => source.capabilities(),
+            PackageSource::HttpRegistry(source) => source.capabilities(),
+            PackageSource::TestRegistry(source) => source.capabilities(),
+            PackageSource::Local(source) => source.capabilities(),
+            PackageSource::AssertOffline(source) => source.capabilities(),
         }
     }
 }
@@ -0,0 +1,158 @@
+use anyhow::bail;
+
+use crate::manifest::Manifest;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+
+use super::{Capabilities, ManifestIter, PackageSource, PackageSourceId, PackageSourceProvider};
+
+/// Wraps a package source for `--assert-offline`, turning anything that would
+/// make a network-backed source reach out into an error that names what was
+/// being done. Sources that only read from disk or memory work as usual.
+#[derive(Clone)]
+pub struct AssertOffline {
+    source: Box<PackageSource>,
+}
+
+impl AssertOffline {
+    pub fn new(source: Box<PackageSource>) -> Self {
+        Self { source }
+    }
+
+    /// Where the wrapped source would reach out to over the network, if
+    /// anywhere.
+    pub fn network_location(&self) -> Option<String> {
+        self.source.network_location()
+    }
+
+    /// The commit the wrapped source's Git index was read at, if any. This
+    /// only looks at what's already on disk.
+    pub fn index_rev(&self) -> anyhow::Result<Option<String>> {
+        self.source.index_rev()
+    }
+
+    fn check<F>(&self, operation: F) -> anyhow::Result<()>
+    where
+        F: FnOnce() -> String,
+    {
+        if let Some(location) = self.network_location() {
+            bail!(
+                "{} would access the network at {}, but --assert-offline was given",
+                operation(),
+                location
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl PackageSourceProvider for AssertOffline {
+    fn update(&self) -> anyhow::Result<()> {
+        self.check(|| "Updating the package index".to_owned())?;
+        self.source.update()
+    }
+
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
+        self.check(|| format!("Querying for {}", package_req))?;
+        self.source.query_iter(package_req)
+    }
+
+    fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<Manifest>> {
+        self.check(|| format!("Looking up {}", package_id))?;
+        self.source.get(package_id)
+    }
+
+    fn all_versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        self.check(|| format!("Listing the versions of {}", name))?;
+        self.source.all_versions(name)
+    }
+
+    fn query_newest(
+        &self,
+        package_req: &PackageReq,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Manifest>> {
+        self.check(|| format!("Querying for {}", package_req))?;
+        self.source.query_newest(package_req, limit)
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        self.check(|| format!("Downloading {}", package_id))?;
+        self.source.download_package(package_id)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        self.check(|| "Looking up fallback registries".to_owned())?;
+        self.source.fallback_sources()
+    }
+
+    fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        self.check(|| "Listing the packages".to_owned())?;
+        self.source.package_names()
+    }
+
+    fn publish(&self, manifest: &Manifest, contents: &PackageContents) -> anyhow::Result<()> {
+        self.check(|| format!("Publishing {}", manifest.package_id()))?;
+        self.source.publish(manifest, contents)
+    }
+
+    // The wrapped source can still do everything it could, it just fails if
+    // doing it needs the network.
+    fn capabilities(&self) -> Capabilities {
+        self.source.capabilities()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::installation::InstallationContext;
+    use crate::integrity::LockfileStore;
+    use crate::package_source::{InMemoryRegistry, PackageSourceMap, Registry};
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn install_uncached_package() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+
+        let manifest = PackageBuilder::new("biff/one-dependency@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+
+        // Local sources are still usable, so the graph can be resolved.
+        let mut package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        package_sources.assert_offline();
+        let resolved = resolve(&manifest, &Default::default(), &package_sources).unwrap();
+
+        // Nothing has been installed yet, so the package has to be downloaded
+        // from the registry, which is over the network. Creating a registry
+        // doesn't reach out by itself.
+        let remote = Registry::from_registry_spec("https://example.com/index").unwrap();
+        let mut package_sources = PackageSourceMap::new(Box::new(PackageSource::Registry(remote)));
+        package_sources.assert_offline();
+
+        let project = tempfile::tempdir().unwrap();
+        let err = InstallationContext::new(project.path(), None, None)
+            .install(
+                package_sources,
+                manifest.package_id(),
+                resolved,
+                Arc::new(LockfileStore::default()),
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Downloading biff/minimal@0.1.0 would access the network at \
+             https://example.com/index, but --assert-offline was given"
+        );
+    }
+}
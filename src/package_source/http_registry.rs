@@ -0,0 +1,1466 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{format_err, Context};
+use once_cell::sync::OnceCell;
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::redirect::Policy;
+use reqwest::{Proxy, StatusCode};
+use url::Url;
+
+use crate::auth::AuthStore;
+use crate::http_config::{ProxyUrl, RegistryHttpConfig, RetryConfig, Timeouts};
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_index::{read_package_entries, PackageIndexConfig, DEFAULT_MAX_INDEX_ENTRIES};
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::package_source::PackageContents;
+use crate::retry::{Failure, RetryPolicy, DEFAULT_RETRY_POLICY};
+
+use super::registry::{
+    error_body, package_contents_url, publish_to_api, read_limited, DEFAULT_MAX_DOWNLOAD_SIZE,
+};
+use super::{Capabilities, ManifestIter, PackageSourceId, PackageSourceProvider};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Registry specs starting with this prefix point at a sparse index, which is
+/// read over HTTP one package at a time instead of being cloned with Git.
+pub const SPARSE_PREFIX: &str = "sparse+";
+
+/// Index files are small, so a registry that takes longer than this to serve
+/// one is most likely stuck.
+pub const DEFAULT_INDEX_TIMEOUTS: Timeouts = Timeouts {
+    connect: Duration::from_secs(10),
+    read: Duration::from_secs(30),
+};
+
+pub const DEFAULT_DOWNLOAD_TIMEOUTS: Timeouts = Timeouts {
+    connect: Duration::from_secs(10),
+    read: Duration::from_secs(300),
+};
+
+/// How many redirects a request follows before giving up. A registry that
+/// moved only needs one or two, so any more is most likely a loop.
+pub const MAX_REDIRECTS: usize = 10;
+
+/// The largest file Wally reads from a sparse index by default. Even a package
+/// with as many versions as `--max-index-entries` allows fits well within
+/// this, so a bigger file is rejected as it comes in instead of being read to
+/// the end.
+pub const DEFAULT_MAX_INDEX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A registry whose index is served over HTTP. The index uses the same layout
+/// as a Git index, with each package's versions stored as JSON Lines at
+/// `index/<scope>/<name>`, but only the files for packages that are actually
+/// queried are ever downloaded.
+#[derive(Clone)]
+pub struct HttpRegistry {
+    base_url: Url,
+
+    /// The clients used to fetch index files and download package contents,
+    /// which are kept apart so that each can have its own timeouts.
+    index_client: Client,
+    download_client: Client,
+
+    index_timeouts: Timeouts,
+    download_timeouts: Timeouts,
+
+    /// The extra headers sent with every request. Sensitive ones are marked
+    /// as such, so they're redacted when this is logged.
+    headers: HeaderMap,
+
+    config: OnceCell<Arc<PackageIndexConfig>>,
+    auth_token: OnceCell<Option<Arc<str>>>,
+
+    /// Every package we've fetched from the index so far, sorted from newest
+    /// to oldest like `PackageIndex` does. This cache is never emptied.
+    package_cache: Arc<Mutex<HashMap<PackageName, Arc<Vec<Manifest>>>>>,
+
+    max_download_size: u64,
+    max_index_entries: usize,
+    max_index_file_size: u64,
+
+    /// How fetching from the index and downloading contents are retried.
+    retry_policy: RetryPolicy,
+
+    /// How publishing is retried, which is never unless the user asks.
+    publish_retry_policy: RetryPolicy,
+
+    /// Waits between retries. Tests swap this out to see the delays without
+    /// waiting for them.
+    sleep: Arc<dyn Fn(Duration) + Send + Sync>,
+}
+
+impl HttpRegistry {
+    /// Create an `HttpRegistry` from a registry spec of the form
+    /// `sparse+https://example.com/registry`.
+    pub fn from_registry_spec(spec: &str) -> anyhow::Result<Self> {
+        let url = spec.strip_prefix(SPARSE_PREFIX).unwrap_or(spec);
+        Self::new(Url::parse(url)?)
+    }
+
+    pub fn new(mut base_url: Url) -> anyhow::Result<Self> {
+        // Make sure that joining paths onto the base URL keeps its last
+        // segment.
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+
+        let headers = HeaderMap::new();
+        let user_agent = default_user_agent();
+
+        Ok(Self {
+            base_url,
+            index_client: build_client(&user_agent, &headers, DEFAULT_INDEX_TIMEOUTS, None)?,
+            download_client: build_client(&user_agent, &headers, DEFAULT_DOWNLOAD_TIMEOUTS, None)?,
+            index_timeouts: DEFAULT_INDEX_TIMEOUTS,
+            download_timeouts: DEFAULT_DOWNLOAD_TIMEOUTS,
+            headers,
+            config: OnceCell::new(),
+            auth_token: OnceCell::new(),
+            package_cache: Arc::new(Mutex::new(HashMap::new())),
+            max_download_size: DEFAULT_MAX_DOWNLOAD_SIZE,
+            max_index_entries: DEFAULT_MAX_INDEX_ENTRIES,
+            max_index_file_size: DEFAULT_MAX_INDEX_FILE_SIZE,
+            retry_policy: DEFAULT_RETRY_POLICY,
+            publish_retry_policy: RetryPolicy::NEVER,
+            sleep: Arc::new(thread::sleep),
+        })
+    }
+
+    /// Abort downloading any package whose contents are larger than
+    /// `max_download_size` bytes.
+    pub fn with_max_download_size(self, max_download_size: u64) -> Self {
+        Self {
+            max_download_size,
+            ..self
+        }
+    }
+
+    pub fn max_download_size(&self) -> u64 {
+        self.max_download_size
+    }
+
+    /// Fail instead of reading more than `max_index_entries` entries from
+    /// one package's index file.
+    pub fn with_max_index_entries(self, max_index_entries: usize) -> Self {
+        Self {
+            max_index_entries,
+            ..self
+        }
+    }
+
+    pub fn max_index_entries(&self) -> usize {
+        self.max_index_entries
+    }
+
+    /// Fail instead of reading an index file larger than
+    /// `max_index_file_size` bytes.
+    pub fn with_max_index_file_size(self, max_index_file_size: u64) -> Self {
+        Self {
+            max_index_file_size,
+            ..self
+        }
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// Send the user agent and extra headers from the user's configuration
+    /// for this registry with every request, and use its timeouts and proxy.
+    pub fn with_http_config(self, config: &RegistryHttpConfig) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in &config.headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name '{}'", name))?;
+            let mut header_value = HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value for header '{}'", name))?;
+
+            let is_sensitive = header_name == AUTHORIZATION
+                || config
+                    .sensitive_headers
+                    .iter()
+                    .any(|sensitive| sensitive.eq_ignore_ascii_case(name));
+            header_value.set_sensitive(is_sensitive);
+
+            headers.insert(header_name, header_value);
+        }
+
+        let user_agent = config.user_agent.clone().unwrap_or_else(default_user_agent);
+        let index_timeouts = config.index_timeouts.or(DEFAULT_INDEX_TIMEOUTS);
+        let download_timeouts = config.download_timeouts.or(DEFAULT_DOWNLOAD_TIMEOUTS);
+
+        Ok(Self {
+            index_client: build_client(
+                &user_agent,
+                &headers,
+                index_timeouts,
+                config.proxy.as_ref(),
+            )?,
+            download_client: build_client(
+                &user_agent,
+                &headers,
+                download_timeouts,
+                config.proxy.as_ref(),
+            )?,
+            index_timeouts,
+            download_timeouts,
+            headers,
+            ..self
+        })
+    }
+
+    /// Retry requests using the user's retry configuration, which applies to
+    /// every registry.
+    pub fn with_retry_config(self, config: &RetryConfig) -> Self {
+        Self {
+            retry_policy: config.policy(),
+            publish_retry_policy: config.publish_policy(),
+            ..self
+        }
+    }
+
+    #[cfg(test)]
+    fn with_sleep(self, sleep: impl Fn(Duration) + Send + Sync + 'static) -> Self {
+        Self {
+            sleep: Arc::new(sleep),
+            ..self
+        }
+    }
+
+    /// Fetches a file from the index with a GET request, retrying failures
+    /// that might pass. Returns `None` if the index doesn't have the file.
+    fn fetch(&self, path: &str) -> anyhow::Result<Option<String>> {
+        let url = self.base_url.join(path)?;
+        log::trace!(
+            "Fetching {} from sparse index with headers {:?}",
+            url,
+            self.headers
+        );
+
+        let action = || format!("fetching {}", url);
+
+        self.retry_policy.run(&*self.sleep, || {
+            let mut response = self
+                .index_client
+                .get(url.clone())
+                .header("Wally-Version", VERSION)
+                .send()
+                .map_err(|err| send_failure(err, action(), self.index_timeouts))?;
+
+            if *response.url() != url {
+                log::debug!("{} was redirected to {}", url, response.url());
+            }
+
+            let status = response.status();
+            if status == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+
+            if !status.is_success() {
+                let err = format_err!("Failed to fetch {}: {}", url, status);
+                return Err(status_failure(status, err));
+            }
+
+            // Like downloads, a body cut off partway through is worth
+            // retrying, but one that's too big will stay too big.
+            let advertised_size = response.content_length();
+            let data = read_limited(
+                &mut response,
+                "index file",
+                advertised_size,
+                self.max_index_file_size,
+            )
+            .map_err(|err| {
+                let is_io = err.is::<io::Error>();
+                let err = request_error(err, action(), self.index_timeouts);
+
+                if is_io {
+                    Failure::Transient(err)
+                } else {
+                    Failure::Permanent(err)
+                }
+            })?;
+
+            let contents = String::from_utf8(data).map_err(|err| {
+                Failure::Permanent(anyhow::Error::new(err).context(format!("{} is not UTF-8", url)))
+            })?;
+
+            Ok(Some(contents))
+        })
+    }
+
+    fn config(&self) -> anyhow::Result<&Arc<PackageIndexConfig>> {
+        self.config.get_or_try_init(|| {
+            let contents = self
+                .fetch("index/config.json")?
+                .with_context(|| format!("sparse index at {} has no config.json", self.base_url))?;
+
+            Ok(Arc::new(serde_json::from_str(&contents)?))
+        })
+    }
+
+    /// Every version of the package with this name, fetching the package's
+    /// file from the index the first time it's asked for.
+    fn package_versions(&self, name: &PackageName) -> anyhow::Result<Arc<Vec<Manifest>>> {
+        self.find_package_versions(name)?
+            .with_context(|| format!("could not find package {} in index", name))
+    }
+
+    /// Like `package_versions`, but `None` if the index has no such package.
+    fn find_package_versions(
+        &self,
+        name: &PackageName,
+    ) -> anyhow::Result<Option<Arc<Vec<Manifest>>>> {
+        let mut package_cache = self.package_cache.lock().unwrap();
+
+        if let Some(versions) = package_cache.get(name) {
+            log::trace!("Sparse index cache hit for {}", name);
+            return Ok(Some(Arc::clone(versions)));
+        }
+
+        let path = format!("index/{}/{}", name.scope(), name.name());
+        let contents = match self.fetch(&path)? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+
+        let mut versions = read_package_entries(contents.as_bytes(), name, self.max_index_entries)?;
+
+        versions.sort_by(|a, b| b.package.version.cmp(&a.package.version));
+
+        let versions = Arc::new(versions);
+        package_cache.insert(name.clone(), Arc::clone(&versions));
+
+        Ok(Some(versions))
+    }
+
+    /// Fetches the files of the packages in `names` that aren't cached yet
+    /// with a single request, if the index supports batch queries. Packages
+    /// it leaves out are fetched one at a time when they're queried.
+    fn prefetch_package_versions(&self, names: &[&PackageName]) -> anyhow::Result<()> {
+        let uncached: BTreeSet<&PackageName> = {
+            let package_cache = self.package_cache.lock().unwrap();
+            names
+                .iter()
+                .copied()
+                .filter(|name| !package_cache.contains_key(*name))
+                .collect()
+        };
+
+        // A batch of one is no faster than fetching the package's own file.
+        if uncached.len() < 2 || !self.config()?.batch_queries {
+            return Ok(());
+        }
+
+        let packages: Vec<String> = uncached.iter().map(|name| name.to_string()).collect();
+        let path = format!("index/-/batch?packages={}", packages.join(","));
+        let contents = match self.fetch(&path)? {
+            Some(contents) => contents,
+            None => return Ok(()),
+        };
+
+        let files: HashMap<String, String> = serde_json::from_str(&contents)
+            .with_context(|| format!("invalid batch query response from {}", self.base_url))?;
+
+        let mut package_cache = self.package_cache.lock().unwrap();
+        for name in uncached {
+            if let Some(file) = files.get(&name.to_string()) {
+                let mut versions =
+                    read_package_entries(file.as_bytes(), name, self.max_index_entries)?;
+                versions.sort_by(|a, b| b.package.version.cmp(&a.package.version));
+
+                package_cache.insert(name.clone(), Arc::new(versions));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The token used to download package contents, looked up by the host
+    /// that contents are downloaded from.
+    fn auth_token(&self) -> anyhow::Result<Option<Arc<str>>> {
+        self.auth_token
+            .get_or_try_init(|| {
+                let contents_url = self.config()?.contents_base_url().clone();
+
+                match AuthStore::get_token(contents_url.as_str())? {
+                    Some(token) => Ok(Some(Arc::from(token.as_str()))),
+                    None => Ok(None),
+                }
+            })
+            .map(|token| token.clone())
+    }
+}
+
+fn default_user_agent() -> String {
+    format!("wally/{}", VERSION)
+}
+
+/// Builds a client that goes through `proxy` if one is configured. Otherwise,
+/// reqwest picks up the proxy from `HTTPS_PROXY` and `NO_PROXY` itself.
+fn build_client(
+    user_agent: &str,
+    headers: &HeaderMap,
+    timeouts: Timeouts,
+    proxy: Option<&ProxyUrl>,
+) -> anyhow::Result<Client> {
+    let mut builder = Client::builder()
+        .user_agent(user_agent)
+        .default_headers(headers.clone())
+        .connect_timeout(timeouts.connect)
+        .timeout(timeouts.read)
+        .redirect(redirect_policy());
+
+    // Adding a proxy stops reqwest from reading the environment's, and the
+    // credentials in its URL are sent to the proxy as basic auth.
+    if let Some(proxy) = proxy {
+        let proxy =
+            Proxy::all(proxy.url().clone()).with_context(|| format!("invalid proxy {}", proxy))?;
+        builder = builder.proxy(proxy);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Follows up to `MAX_REDIRECTS` redirects, then fails with every URL the
+/// request was sent to so that a loop can be spotted.
+fn redirect_policy() -> Policy {
+    Policy::custom(|attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            let chain: Vec<&str> = attempt
+                .previous()
+                .iter()
+                .chain(std::iter::once(attempt.url()))
+                .map(Url::as_str)
+                .collect();
+
+            let message = format!(
+                "Redirected more than {} times: {}",
+                MAX_REDIRECTS,
+                chain.join(" -> ")
+            );
+            attempt.error(message)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// Failing to send a request might pass, unless we were redirected too many
+/// times, which will keep happening.
+fn send_failure(err: reqwest::Error, action: String, timeouts: Timeouts) -> Failure {
+    let is_redirect = err.is_redirect();
+    let err = request_error(err.into(), action, timeouts);
+
+    if is_redirect {
+        Failure::Permanent(err)
+    } else {
+        Failure::Transient(err)
+    }
+}
+
+/// Server errors and rate limiting might pass, so requests that got them are
+/// worth retrying. Anything else will keep failing the same way.
+fn status_failure(status: StatusCode, err: anyhow::Error) -> Failure {
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        Failure::Transient(err)
+    } else {
+        Failure::Permanent(err)
+    }
+}
+
+/// Adds what we were doing to the error from a failed request. Timeouts are
+/// called out along with the limit that was hit, since otherwise they only
+/// say that the operation timed out.
+fn request_error(err: anyhow::Error, action: String, timeouts: Timeouts) -> anyhow::Error {
+    let message = match err.chain().find_map(timeout_kind) {
+        Some(TimeoutKind::Connect) => format!(
+            "Timed out after {:?} connecting to the registry while {}",
+            timeouts.connect, action
+        ),
+        Some(TimeoutKind::Read) => format!("Timed out after {:?} while {}", timeouts.read, action),
+        None => format!("Failed while {}", action),
+    };
+
+    err.context(message)
+}
+
+enum TimeoutKind {
+    Connect,
+    Read,
+}
+
+/// Works out whether an error is a timeout. Timeouts while reading a response
+/// body arrive as I/O errors, which may or may not wrap the original error
+/// from reqwest.
+fn timeout_kind(cause: &(dyn std::error::Error + 'static)) -> Option<TimeoutKind> {
+    let reqwest_error = match cause.downcast_ref::<io::Error>() {
+        Some(io_error) if io_error.kind() == io::ErrorKind::TimedOut => {
+            return Some(TimeoutKind::Read)
+        }
+        Some(io_error) => io_error.get_ref()?.downcast_ref::<reqwest::Error>()?,
+        None => cause.downcast_ref::<reqwest::Error>()?,
+    };
+
+    if !reqwest_error.is_timeout() {
+        None
+    } else if reqwest_error.is_connect() {
+        Some(TimeoutKind::Connect)
+    } else {
+        Some(TimeoutKind::Read)
+    }
+}
+
+impl PackageSourceProvider for HttpRegistry {
+    fn update(&self) -> anyhow::Result<()> {
+        // Packages are fetched as they're queried, so there's no index to
+        // bring up to date ahead of time.
+        Ok(())
+    }
+
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
+        let versions = self.package_versions(package_req.name())?;
+
+        let matches = (0..versions.len()).filter_map(move |i| {
+            let manifest = &versions[i];
+
+            if package_req.matches(&manifest.package.name, &manifest.package.version) {
+                Some(Ok(manifest.clone()))
+            } else {
+                None
+            }
+        });
+
+        Ok(Box::new(matches))
+    }
+
+    fn query_many(&self, package_reqs: &[PackageReq]) -> Vec<anyhow::Result<Vec<Manifest>>> {
+        let names: Vec<&PackageName> = package_reqs
+            .iter()
+            .map(|package_req| package_req.name())
+            .collect();
+
+        // Anything the batch didn't cover is still fetched on its own below.
+        if let Err(err) = self.prefetch_package_versions(&names) {
+            log::debug!(
+                "Batch query to {} failed, fetching packages one at a time: {:#}",
+                self.base_url,
+                err
+            );
+        }
+
+        package_reqs
+            .iter()
+            .map(|package_req| self.query(package_req))
+            .collect()
+    }
+
+    // The index has no file per version, so the package's file is fetched
+    // (or taken from the cache) just like for a query.
+    fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<Manifest>> {
+        let versions = match self.find_package_versions(package_id.name())? {
+            Some(versions) => versions,
+            None => return Ok(None),
+        };
+
+        Ok(versions
+            .iter()
+            .find(|manifest| manifest.package.version == *package_id.version())
+            .cloned())
+    }
+
+    fn all_versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        let versions = self.package_versions(name)?;
+        Ok(versions.iter().rev().cloned().collect())
+    }
+
+    fn query_newest(
+        &self,
+        package_req: &PackageReq,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Manifest>> {
+        // Matches come out newest first, so none past the limit are cloned.
+        self.query_iter(package_req)?.take(limit).collect()
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let contents_url = self.config()?.contents_base_url().clone();
+        let url = package_contents_url(&contents_url, package_id)?;
+
+        let token = self.auth_token()?;
+
+        let action = || format!("downloading package {} from {}", package_id, url);
+
+        let (data, final_url) = self.retry_policy.run(&*self.sleep, || {
+            let mut request = self
+                .download_client
+                .get(url.clone())
+                .header("Wally-Version", VERSION);
+
+            if let Some(token) = &token {
+                request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let mut response = request
+                .send()
+                .map_err(|err| send_failure(err, action(), self.download_timeouts))?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let err = format_err!(
+                    "Failed to download package {} from registry: {}\n{} {}",
+                    package_id,
+                    contents_url,
+                    status,
+                    error_body(&mut response)
+                );
+                return Err(status_failure(status, err));
+            }
+
+            // Packages that are too big will stay too big, but the body being
+            // cut off partway through might not happen again.
+            let advertised_size = response.content_length();
+            let data = read_limited(
+                &mut response,
+                "package",
+                advertised_size,
+                self.max_download_size,
+            )
+            .map_err(|err| {
+                let is_io = err.is::<io::Error>();
+                let err = request_error(err, action(), self.download_timeouts);
+
+                if is_io {
+                    Failure::Transient(err)
+                } else {
+                    Failure::Permanent(err)
+                }
+            })?;
+
+            Ok((data, response.url().clone()))
+        })?;
+
+        let contents = PackageContents::from_buffer(data);
+
+        // Record where the contents really came from, so the lockfile points
+        // at the registry's new home instead of the one it moved away from.
+        if final_url != url {
+            log::debug!("{} was redirected to {}", url, final_url);
+            return Ok(contents.with_origin(final_url.to_string()));
+        }
+
+        Ok(contents)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        let sources = self
+            .config()?
+            .fallback_registries
+            .iter()
+            .map(|registry| PackageSourceId::for_registry(registry))
+            .collect();
+
+        Ok(sources)
+    }
+
+    fn publish(&self, manifest: &Manifest, contents: &PackageContents) -> anyhow::Result<()> {
+        let api = &self.config()?.api;
+
+        // Only failures to send the request are worth retrying. The registry
+        // rejecting the package will keep happening.
+        self.publish_retry_policy.run(&*self.sleep, || {
+            publish_to_api(&self.download_client, api, manifest, contents).map_err(|err| {
+                if err.is::<reqwest::Error>() {
+                    Failure::Transient(err)
+                } else {
+                    Failure::Permanent(err)
+                }
+            })
+        })
+    }
+
+    // Index files are only fetched one package at a time, so there's no way
+    // to list every package short of guessing their names.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_publish: true,
+            can_enumerate: false,
+            supports_get: true,
+            supports_search: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Instant;
+
+    use semver::VersionReq;
+
+    use crate::http_config::TimeoutConfig;
+    use crate::package_source::{PackageSource, PackageSourceMap};
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    /// A request received by the server from `serve` or `proxy`.
+    struct Request {
+        path: String,
+
+        /// Headers with lowercased names.
+        headers: HashMap<String, String>,
+    }
+
+    /// Serves `files` over HTTP on a local port, recording every request
+    /// that's made. Anything not in `files` gets a 404.
+    fn serve(files: HashMap<&'static str, String>) -> (Url, Arc<Mutex<Vec<Request>>>) {
+        serve_flapping(files, 0)
+    }
+
+    /// Like `serve`, but the first `failures` requests get a 503.
+    fn serve_flapping(
+        files: HashMap<&'static str, String>,
+        failures: usize,
+    ) -> (Url, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/registry",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&stream);
+
+                let body = request
+                    .path
+                    .strip_prefix("/registry/")
+                    .and_then(|path| files.get(path));
+                let count = {
+                    let mut recorded = recorded.lock().unwrap();
+                    recorded.push(request);
+                    recorded.len()
+                };
+
+                let response = match body {
+                    _ if count <= failures => "HTTP/1.1 503 Service Unavailable\r\n\
+                                               Content-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_owned(),
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_owned()
+                    }
+                };
+
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    /// Reads a request's line and headers from `stream`.
+    fn read_request(stream: &TcpStream) -> Request {
+        let mut reader = BufReader::new(stream);
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+
+        // Headers end with an empty line.
+        let mut headers = HashMap::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+
+            match header.trim().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.to_lowercase(), value.trim().to_owned());
+                }
+                None => break,
+            }
+        }
+
+        Request {
+            path: request_line.split(' ').nth(1).unwrap_or("").to_owned(),
+            headers,
+        }
+    }
+
+    /// An HTTP proxy on a local port, which answers requests for the full
+    /// URLs in `files` itself instead of forwarding them. Requests sent
+    /// through a proxy name the whole URL, so that's what's recorded as each
+    /// request's path.
+    fn proxy(files: HashMap<String, String>) -> (SocketAddr, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&stream);
+
+                let response = match files.get(&request.path) {
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_owned()
+                    }
+                };
+
+                recorded.lock().unwrap().push(request);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (addr, requests)
+    }
+
+    /// Accepts connections on a local port but never responds to them.
+    fn stall() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        thread::spawn(move || {
+            let mut streams = Vec::new();
+
+            for stream in listener.incoming() {
+                streams.push(stream.unwrap());
+            }
+        });
+
+        url
+    }
+
+    /// Answers every request on a local port with a 301 to `location`, which
+    /// is given the requested path.
+    fn redirect(location: impl Fn(&str) -> String + Send + 'static) -> (Url, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let redirected = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&redirected);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                *count.lock().unwrap() += 1;
+
+                let path = request_line.split(' ').nth(1).unwrap_or("");
+                let response = format!(
+                    "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\n\
+                     Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    location(path)
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (url, redirected)
+    }
+
+    fn requested_paths(requests: &Mutex<Vec<Request>>) -> Vec<String> {
+        let requests = requests.lock().unwrap();
+        requests
+            .iter()
+            .map(|request| request.path.clone())
+            .collect()
+    }
+
+    fn entry(name: &str, version: &str) -> String {
+        format!(
+            concat!(
+                r#"{{"package":{{"name":"{}","version":"{}","registry":"test","#,
+                r#""realm":"server","description":null,"license":null,"authors":[]}}}}"#
+            ),
+            name, version
+        )
+    }
+
+    #[test]
+    fn query_fetches_only_the_package_file() {
+        let mut files = HashMap::new();
+        files.insert(
+            "index/biff/minimal",
+            format!(
+                "{}\n{}\n",
+                entry("biff/minimal", "0.1.0"),
+                entry("biff/minimal", "0.2.0")
+            ),
+        );
+        files.insert("index/biff/other", entry("biff/other", "1.0.0"));
+
+        let (url, requests) = serve(files);
+        let registry = HttpRegistry::new(url).unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::parse("0.1.0").unwrap(),
+        );
+
+        let versions: Vec<_> = registry
+            .query(&req)
+            .unwrap()
+            .iter()
+            .map(|manifest| manifest.package.version.to_string())
+            .collect();
+        assert_eq!(versions, vec!["0.1.0"]);
+
+        // Asking again is answered from the cache.
+        registry.query(&req).unwrap();
+
+        assert_eq!(
+            requested_paths(&requests),
+            vec!["/registry/index/biff/minimal"]
+        );
+    }
+
+    #[test]
+    fn query_newest_takes_the_newest_matches() {
+        let mut files = HashMap::new();
+        files.insert(
+            "index/biff/minimal",
+            ["0.1.0", "0.3.0", "0.2.0", "0.4.0", "1.0.0"]
+                .iter()
+                .map(|version| entry("biff/minimal", version))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+
+        let (url, _) = serve(files);
+        let registry = HttpRegistry::new(url).unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::parse("<1.0.0").unwrap(),
+        );
+
+        let versions: Vec<_> = registry
+            .query_newest(&req, 3)
+            .unwrap()
+            .iter()
+            .map(|manifest| manifest.package.version.to_string())
+            .collect();
+        assert_eq!(versions, vec!["0.4.0", "0.3.0", "0.2.0"]);
+    }
+
+    #[test]
+    fn oversized_index_file_is_rejected() {
+        let mut files = HashMap::new();
+        files.insert(
+            "index/biff/minimal",
+            (0..101)
+                .map(|patch| entry("biff/minimal", &format!("0.1.{}", patch)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+
+        let (url, _) = serve(files);
+        let registry = HttpRegistry::new(url).unwrap().with_max_index_entries(100);
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+
+        let err = registry.query(&req).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The index file for biff/minimal has more than 100 entries. If the registry is \
+             trusted, raise the limit with --max-index-entries"
+        );
+    }
+
+    #[test]
+    fn index_file_over_the_size_limit_is_rejected() {
+        let mut files = HashMap::new();
+        files.insert(
+            "index/biff/minimal",
+            (0..20)
+                .map(|patch| entry("biff/minimal", &format!("0.1.{}", patch)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+
+        let (url, requests) = serve(files);
+        let registry = HttpRegistry::new(url)
+            .unwrap()
+            .with_max_index_file_size(1024);
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+
+        let err = registry.query(&req).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("The index file is")
+                && format!("{:#}", err).contains("larger than the limit"),
+            "Unexpected error: {:#}",
+            err
+        );
+
+        // A file that's too big will stay too big, so it isn't fetched again.
+        assert_eq!(requested_paths(&requests).len(), 1);
+    }
+
+    #[test]
+    fn query_missing_package() {
+        let (url, requests) = serve(HashMap::new());
+        let registry = HttpRegistry::new(url).unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "missing").unwrap(),
+            VersionReq::any(),
+        );
+
+        let err = registry.query(&req).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("could not find package biff/missing"),
+            "Unexpected error: {}",
+            err
+        );
+        assert_eq!(
+            requested_paths(&requests),
+            vec!["/registry/index/biff/missing"]
+        );
+    }
+
+    /// A root package with two direct dependencies on `biff/minimal` and
+    /// `biff/other`.
+    fn two_dependencies() -> Manifest {
+        PackageBuilder::new("biff/root@1.0.0")
+            .with_server_dep("Minimal", "biff/minimal@0.1.0")
+            .with_server_dep("Other", "biff/other@1.0.0")
+            .into_manifest()
+    }
+
+    #[test]
+    fn resolve_batches_direct_dependencies() {
+        let mut files = HashMap::new();
+        files.insert(
+            "index/config.json",
+            r#"{"api":"http://localhost","batch_queries":true}"#.to_owned(),
+        );
+        files.insert(
+            "index/-/batch?packages=biff/minimal,biff/other",
+            serde_json::json!({
+                "biff/minimal": entry("biff/minimal", "0.1.0"),
+                "biff/other": entry("biff/other", "1.0.0"),
+            })
+            .to_string(),
+        );
+
+        let (url, requests) = serve(files);
+        let registry = HttpRegistry::new(url).unwrap();
+        let package_sources =
+            PackageSourceMap::new(Box::new(PackageSource::HttpRegistry(registry)));
+
+        let resolved = resolve(&two_dependencies(), &Default::default(), &package_sources).unwrap();
+        assert!(resolved
+            .activated
+            .contains(&"biff/other@1.0.0".parse().unwrap()));
+
+        assert_eq!(
+            requested_paths(&requests),
+            vec![
+                "/registry/index/config.json",
+                "/registry/index/-/batch?packages=biff/minimal,biff/other"
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_without_batch_queries() {
+        let mut files = HashMap::new();
+        files.insert(
+            "index/config.json",
+            r#"{"api":"http://localhost"}"#.to_owned(),
+        );
+        files.insert("index/biff/minimal", entry("biff/minimal", "0.1.0"));
+        files.insert("index/biff/other", entry("biff/other", "1.0.0"));
+
+        let (url, requests) = serve(files);
+        let registry = HttpRegistry::new(url).unwrap();
+        let package_sources =
+            PackageSourceMap::new(Box::new(PackageSource::HttpRegistry(registry)));
+
+        resolve(&two_dependencies(), &Default::default(), &package_sources).unwrap();
+
+        assert_eq!(
+            requested_paths(&requests),
+            vec![
+                "/registry/index/config.json",
+                "/registry/index/biff/minimal",
+                "/registry/index/biff/other"
+            ]
+        );
+    }
+
+    fn flapping_registry(
+        failures: usize,
+    ) -> (
+        HttpRegistry,
+        Arc<Mutex<Vec<Request>>>,
+        Arc<Mutex<Vec<Duration>>>,
+    ) {
+        let mut files = HashMap::new();
+        files.insert("index/biff/minimal", entry("biff/minimal", "0.1.0"));
+        let (url, requests) = serve_flapping(files, failures);
+
+        let config = RetryConfig {
+            max_attempts: Some(3),
+            base_delay: Some(Duration::from_millis(100)),
+            jitter: Some(false),
+            ..Default::default()
+        };
+
+        let slept = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&slept);
+        let registry = HttpRegistry::new(url)
+            .unwrap()
+            .with_retry_config(&config)
+            .with_sleep(move |delay| recorded.lock().unwrap().push(delay));
+
+        (registry, requests, slept)
+    }
+
+    #[test]
+    fn flapping_index_is_retried() {
+        let (registry, requests, slept) = flapping_registry(2);
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+        registry.query(&req).unwrap();
+
+        assert_eq!(
+            requested_paths(&requests),
+            vec!["/registry/index/biff/minimal"; 3]
+        );
+        assert_eq!(
+            *slept.lock().unwrap(),
+            vec![Duration::from_millis(100), Duration::from_millis(200)]
+        );
+    }
+
+    #[test]
+    fn retries_give_up_after_max_attempts() {
+        let (registry, requests, slept) = flapping_registry(5);
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+        let err = registry.query(&req).unwrap_err();
+
+        assert!(
+            err.to_string().contains("503 Service Unavailable"),
+            "Unexpected error: {}",
+            err
+        );
+        assert_eq!(requested_paths(&requests).len(), 3);
+        assert_eq!(slept.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn configured_headers_are_sent() {
+        let mut files = HashMap::new();
+        files.insert("index/biff/minimal", entry("biff/minimal", "0.1.0"));
+        let (url, requests) = serve(files);
+
+        let mut config = RegistryHttpConfig::default();
+        config.headers.insert("X-Org".to_owned(), "biff".to_owned());
+        config
+            .headers
+            .insert("X-Org-Token".to_owned(), "hunter2".to_owned());
+        config.sensitive_headers.push("x-org-token".to_owned());
+
+        let registry = HttpRegistry::new(url)
+            .unwrap()
+            .with_http_config(&config)
+            .unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+        registry.query(&req).unwrap();
+
+        let requests = requests.lock().unwrap();
+        let headers = &requests[0].headers;
+        assert_eq!(headers["user-agent"], format!("wally/{}", VERSION));
+        assert_eq!(headers["x-org"], "biff");
+        assert_eq!(headers["x-org-token"], "hunter2");
+
+        // Sensitive headers are left out of logs.
+        let logged = format!("{:?}", registry.headers);
+        assert!(logged.contains("biff"), "Unexpected headers: {}", logged);
+        assert!(
+            !logged.contains("hunter2"),
+            "Unexpected headers: {}",
+            logged
+        );
+    }
+
+    #[test]
+    fn configured_user_agent() {
+        let mut files = HashMap::new();
+        files.insert("index/biff/minimal", entry("biff/minimal", "0.1.0"));
+        let (url, requests) = serve(files);
+
+        let config = RegistryHttpConfig {
+            user_agent: Some("biff-ci".to_owned()),
+            ..Default::default()
+        };
+        let registry = HttpRegistry::new(url)
+            .unwrap()
+            .with_http_config(&config)
+            .unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+        registry.query(&req).unwrap();
+
+        assert_eq!(requests.lock().unwrap()[0].headers["user-agent"], "biff-ci");
+    }
+
+    #[test]
+    fn stalled_index_times_out() {
+        let url = stall();
+
+        let config = RegistryHttpConfig {
+            index_timeouts: TimeoutConfig {
+                connect: None,
+                read: Some(Duration::from_millis(200)),
+            },
+            ..Default::default()
+        };
+        let registry = HttpRegistry::new(url.clone())
+            .unwrap()
+            .with_http_config(&config)
+            .unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+
+        let start = Instant::now();
+        let err = registry.query(&req).unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Timed out after 200ms while fetching {}index/biff/minimal",
+                url
+            )
+        );
+    }
+
+    #[test]
+    fn stalled_download_times_out() {
+        let api = stall();
+
+        let mut files = HashMap::new();
+        files.insert(
+            "index/config.json",
+            format!(r#"{{"api":"{}","github_oauth_id":null}}"#, api),
+        );
+        let (url, _) = serve(files);
+
+        let config = RegistryHttpConfig {
+            download_timeouts: TimeoutConfig {
+                connect: None,
+                read: Some(Duration::from_millis(200)),
+            },
+            ..Default::default()
+        };
+        let registry = HttpRegistry::new(url)
+            .unwrap()
+            .with_http_config(&config)
+            .unwrap();
+
+        let package_id = "biff/minimal@0.1.0".parse().unwrap();
+
+        let start = Instant::now();
+        let err = registry.download_package(&package_id).unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Timed out after 200ms while downloading package biff/minimal@0.1.0 from \
+                 {}v1/package-contents/biff/minimal/0.1.0",
+                api
+            )
+        );
+    }
+
+    #[test]
+    fn download_follows_redirect_to_new_host() {
+        let mut content_files = HashMap::new();
+        content_files.insert(
+            "v1/package-contents/biff/minimal/0.1.0",
+            "contents".to_owned(),
+        );
+        let (content_url, content_requests) = serve(content_files);
+
+        // The registry's API moved, and its old host sends everything on.
+        let new_host = content_url.to_string();
+        let (api, _) = redirect(move |path| format!("{}{}", new_host, path));
+
+        let mut files = HashMap::new();
+        files.insert(
+            "index/config.json",
+            format!(r#"{{"api":"{}","github_oauth_id":null}}"#, api),
+        );
+        let (url, _) = serve(files);
+        let registry = HttpRegistry::new(url).unwrap();
+
+        let package_id = "biff/minimal@0.1.0".parse().unwrap();
+        let contents = registry.download_package(&package_id).unwrap();
+
+        assert_eq!(contents.data(), b"contents");
+        assert_eq!(
+            contents.origin(),
+            Some(format!("{}/v1/package-contents/biff/minimal/0.1.0", content_url).as_str())
+        );
+        assert_eq!(
+            requested_paths(&content_requests),
+            vec!["/registry/v1/package-contents/biff/minimal/0.1.0"]
+        );
+    }
+
+    #[test]
+    fn redirect_chain_is_bounded() {
+        // Every hop is sent one step further, forever.
+        let (url, redirected) = redirect(|path| format!("{}/again", path));
+        let registry = HttpRegistry::new(url.clone()).unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::parse("0.1.0").unwrap(),
+        );
+        let err = registry.query(&req).unwrap_err();
+
+        let chain = err.root_cause().to_string();
+        assert!(
+            chain.starts_with(&format!(
+                "Redirected more than {} times: {}index/biff/minimal -> ",
+                MAX_REDIRECTS, url
+            )),
+            "Unexpected error: {:#}",
+            err
+        );
+        assert_eq!(chain.matches(" -> ").count(), MAX_REDIRECTS + 1);
+
+        // Redirect loops aren't retried.
+        assert_eq!(*redirected.lock().unwrap(), MAX_REDIRECTS + 1);
+    }
+
+    #[test]
+    fn requests_go_through_configured_proxy() {
+        // Neither host exists, so the only way to reach them is through the
+        // proxy.
+        let mut files = HashMap::new();
+        files.insert(
+            "http://registry.invalid/registry/index/config.json".to_owned(),
+            r#"{"api":"http://contents.invalid/","github_oauth_id":null}"#.to_owned(),
+        );
+        files.insert(
+            "http://registry.invalid/registry/index/biff/minimal".to_owned(),
+            entry("biff/minimal", "0.1.0"),
+        );
+        files.insert(
+            "http://contents.invalid/v1/package-contents/biff/minimal/0.1.0".to_owned(),
+            "contents".to_owned(),
+        );
+        let (addr, requests) = proxy(files);
+
+        let config = RegistryHttpConfig {
+            proxy: Some(ProxyUrl::parse(&format!("http://biff:hunter2@{}", addr)).unwrap()),
+            ..Default::default()
+        };
+        let registry = HttpRegistry::new(Url::parse("http://registry.invalid/registry").unwrap())
+            .unwrap()
+            .with_http_config(&config)
+            .unwrap();
+
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+        registry.query(&req).unwrap();
+
+        let package_id = "biff/minimal@0.1.0".parse().unwrap();
+        let contents = registry.download_package(&package_id).unwrap();
+        assert_eq!(contents.data(), b"contents");
+
+        let mut paths = requested_paths(&requests);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                "http://contents.invalid/v1/package-contents/biff/minimal/0.1.0",
+                "http://registry.invalid/registry/index/biff/minimal",
+                "http://registry.invalid/registry/index/config.json",
+            ]
+        );
+
+        // The proxy is authenticated to with the credentials from its URL.
+        for request in requests.lock().unwrap().iter() {
+            assert_eq!(
+                request.headers["proxy-authorization"],
+                "Basic YmlmZjpodW50ZXIy"
+            );
+        }
+    }
+}
@@ -3,6 +3,7 @@
 //! resolution, installation, upgrading, etc.
 
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::RwLock;
 
@@ -13,7 +14,7 @@ use crate::{
     package_source::PackageSource, test_package::PackageBuilder,
 };
 
-use super::{PackageContents, PackageSourceId, PackageSourceProvider};
+use super::{ManifestIter, PackageContents, PackageSourceId, PackageSourceProvider};
 
 /// An in-memory registry that can have packages published to it.
 ///
@@ -46,6 +47,17 @@ impl InMemoryRegistry {
         entries.push(PackageEntry { manifest, contents });
     }
 
+    /// How many times sources created from this registry have been queried.
+    pub fn query_count(&self) -> usize {
+        self.storage.queries.load(Ordering::SeqCst)
+    }
+
+    /// How many packages have been downloaded from sources created from this
+    /// registry.
+    pub fn download_count(&self) -> usize {
+        self.storage.downloads.load(Ordering::SeqCst)
+    }
+
     /// Returns a handle to an object that can be used as a `PackageSource`.
     pub fn source(&self) -> PackageSource {
         PackageSource::InMemory(InMemoryRegistrySource {
@@ -66,7 +78,15 @@ impl PackageSourceProvider for InMemoryRegistrySource {
         Ok(())
     }
 
+    // Everything already lives in memory, so there's nothing to gain from
+    // yielding entries lazily while holding the storage lock.
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
+        Ok(Box::new(self.query(package_req)?.into_iter().map(Ok)))
+    }
+
     fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        self.storage.queries.fetch_add(1, Ordering::SeqCst);
+
         let storage = self.storage.contents.read().unwrap();
         let scope = match storage.get(package_req.name().scope()) {
             Some(scope) => scope,
@@ -81,9 +101,10 @@ impl PackageSourceProvider for InMemoryRegistrySource {
         let result = entries
             .iter()
             .filter(|entry| {
-                package_req
-                    .version_req()
-                    .matches(&entry.manifest.package.version)
+                package_req.matches(
+                    &entry.manifest.package.name,
+                    &entry.manifest.package.version,
+                )
             })
             .map(|entry| &entry.manifest)
             .cloned()
@@ -93,6 +114,8 @@ impl PackageSourceProvider for InMemoryRegistrySource {
     }
 
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        self.storage.downloads.fetch_add(1, Ordering::SeqCst);
+
         let storage = self.storage.contents.read().unwrap();
         let scope = storage
             .get(package_id.name().scope())
@@ -123,4 +146,6 @@ struct PackageEntry {
 #[derive(Clone, Default)]
 struct Storage {
     contents: Arc<RwLock<HashMap<String, HashMap<String, Vec<PackageEntry>>>>>,
+    queries: Arc<AtomicUsize>,
+    downloads: Arc<AtomicUsize>,
 }
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_req::PackageReq;
+use crate::package_source::PackageContents;
+
+use super::{ManifestIter, PackageSourceId, PackageSourceProvider};
+
+/// A single package that lives in a folder on disk, used by path
+/// dependencies. Its contents are packed from the folder whenever it's
+/// downloaded, so changes to it are picked up on the next install.
+#[derive(Clone)]
+pub struct LocalPackage {
+    path: PathBuf,
+}
+
+impl LocalPackage {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl PackageSourceProvider for LocalPackage {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
+        let manifest = Manifest::load(&self.path)?;
+
+        let matches = package_req.matches(&manifest.package.name, &manifest.package.version);
+        let manifests = Some(manifest).filter(|_| matches).map(Ok);
+
+        Ok(Box::new(manifests.into_iter()))
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let manifest = Manifest::load(&self.path)?;
+
+        if manifest.package_id() != *package_id {
+            anyhow::bail!(
+                "{} contains {}, not {}",
+                self.path.display(),
+                manifest.package_id(),
+                package_id
+            );
+        }
+
+        PackageContents::pack_from_path(&self.path)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+}
@@ -1,28 +1,48 @@
 use std::io::Read;
 use std::sync::Arc;
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use once_cell::sync::OnceCell;
 use reqwest::{blocking::Client, header::AUTHORIZATION};
+use ubyte::ToByteUnit;
 use url::Url;
 
 use crate::auth::AuthStore;
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
-use crate::package_index::PackageIndex;
+use crate::package_index::{PackageIndex, DEFAULT_MAX_INDEX_ENTRIES};
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::PackageContents;
 
-use super::{PackageSourceId, PackageSourceProvider};
+use super::{Capabilities, ManifestIter, PackageSourceId, PackageSourceProvider};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The largest package, in bytes, that a registry is trusted to send unless
+/// configured otherwise.
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 64 * 1024 * 1024;
+
+/// [`DEFAULT_MAX_DOWNLOAD_SIZE`] as the command line spells it.
+pub const DEFAULT_MAX_DOWNLOAD_SIZE_ARG: &str = "67108864";
+
+/// How much of an error response's body ends up in the error. Registries only
+/// send a short explanation, so anything longer is cut off instead of being
+/// read to the end.
+const MAX_ERROR_BODY_SIZE: u64 = 64 * 1024;
+
 #[derive(Clone)]
 pub struct Registry {
     index_url: Url,
     auth_token: OnceCell<Option<Arc<str>>>,
     index: OnceCell<Arc<PackageIndex>>,
     client: Client,
+    max_download_size: u64,
+    max_index_entries: usize,
+
+    /// The commit to check the index out at instead of the latest one, so
+    /// that packages are resolved the same way as the registry grows.
+    index_rev: Option<String>,
 }
 
 impl Registry {
@@ -36,57 +56,262 @@ impl Registry {
             auth_token: OnceCell::new(),
             index: OnceCell::new(),
             client: Client::new(),
+            max_download_size: DEFAULT_MAX_DOWNLOAD_SIZE,
+            max_index_entries: DEFAULT_MAX_INDEX_ENTRIES,
+            index_rev: None,
         })
     }
 
+    /// Read the index as it was at the commit `index_rev`, or at the latest
+    /// commit if it's `None`.
+    pub fn with_index_rev(self, index_rev: Option<String>) -> Self {
+        Self { index_rev, ..self }
+    }
+
+    /// Abort downloading any package whose contents are larger than
+    /// `max_download_size` bytes.
+    pub fn with_max_download_size(self, max_download_size: u64) -> Self {
+        Self {
+            max_download_size,
+            ..self
+        }
+    }
+
+    pub fn max_download_size(&self) -> u64 {
+        self.max_download_size
+    }
+
+    /// Fail instead of reading more than `max_index_entries` entries from
+    /// one package's index file.
+    pub fn with_max_index_entries(self, max_index_entries: usize) -> Self {
+        Self {
+            max_index_entries,
+            ..self
+        }
+    }
+
+    pub fn max_index_entries(&self) -> usize {
+        self.max_index_entries
+    }
+
+    pub fn index_url(&self) -> &Url {
+        &self.index_url
+    }
+
+    /// The commit packages were read from the index at, or `None` if the index
+    /// hasn't been read yet.
+    pub fn index_rev(&self) -> anyhow::Result<Option<String>> {
+        self.index.get().map(|index| index.head_rev()).transpose()
+    }
+
+    /// The token used to download package contents. Contents may be served
+    /// from a different host than the API, so the token is looked up by the
+    /// host contents are downloaded from.
     fn auth_token(&self) -> anyhow::Result<Option<Arc<str>>> {
         self.auth_token
-            .get_or_try_init(|| match AuthStore::get_token(self.api_url()?.as_str())? {
-                Some(token) => Ok(Some(Arc::from(token.as_str()))),
-                None => Ok(None),
+            .get_or_try_init(|| {
+                let contents_url = self.contents_url()?;
+
+                match AuthStore::get_token(contents_url.as_str())? {
+                    Some(token) => Ok(Some(Arc::from(token.as_str()))),
+                    None => Ok(None),
+                }
             })
             .map(|token| token.clone())
     }
 
     fn index(&self) -> anyhow::Result<&Arc<PackageIndex>> {
-        self.index
-            .get_or_try_init(|| Ok(Arc::new(PackageIndex::new(&self.index_url, None)?)))
+        self.index.get_or_try_init(|| {
+            let index =
+                PackageIndex::new(&self.index_url, None)?.with_max_entries(self.max_index_entries);
+            self.pin(&index)?;
+
+            Ok(Arc::new(index))
+        })
     }
 
-    fn api_url(&self) -> anyhow::Result<Url> {
+    /// Checks out the commit the index is pinned to, if there is one.
+    fn pin(&self, index: &PackageIndex) -> anyhow::Result<()> {
+        match &self.index_rev {
+            Some(rev) => index.checkout(rev),
+            None => Ok(()),
+        }
+    }
+
+    fn contents_url(&self) -> anyhow::Result<Url> {
         let config = self.index()?.config()?;
-        Ok(config.api)
+        Ok(config.contents_base_url().clone())
     }
 }
 
+/// The URL to download the contents of a package from, given the base URL
+/// contents are served from.
+pub(super) fn package_contents_url(base_url: &Url, package_id: &PackageId) -> anyhow::Result<Url> {
+    let path = format!(
+        "/v1/package-contents/{}/{}/{}",
+        package_id.name().scope(),
+        package_id.name().name(),
+        package_id.version()
+    );
+
+    Ok(base_url.join(&path)?)
+}
+
+/// Publishes a package through a registry's API, the same way `wally publish`
+/// does, using the token the user logged in to that API with.
+pub(super) fn publish_to_api(
+    client: &Client,
+    api: &Url,
+    manifest: &Manifest,
+    contents: &PackageContents,
+) -> anyhow::Result<()> {
+    let token = AuthStore::get_token(api.as_str())?.with_context(|| {
+        format!(
+            "Authentication is required to publish to {}, use `wally login`",
+            api
+        )
+    })?;
+
+    let mut response = client
+        .post(api.join("/v1/publish")?)
+        .header("accept", "application/json")
+        .header("Wally-Version", VERSION)
+        .bearer_auth(token)
+        .body(contents.data().to_owned())
+        .send()?;
+
+    if !response.status().is_success() {
+        bail!(
+            "Failed to publish {} to {}\n{} {}",
+            manifest.package_id(),
+            api,
+            response.status(),
+            error_body(&mut response)
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads a response body of at most `max_size` bytes, without buffering any
+/// more than that. Bodies that advertise a larger size are rejected before
+/// anything is read. `what` names the body in errors, like `package`.
+pub(super) fn read_limited<R: Read>(
+    reader: R,
+    what: &str,
+    advertised_size: Option<u64>,
+    max_size: u64,
+) -> anyhow::Result<Vec<u8>> {
+    if let Some(size) = advertised_size {
+        if size > max_size {
+            bail!(
+                "The {} is {}, which is larger than the limit of {}",
+                what,
+                size.bytes(),
+                max_size.bytes()
+            );
+        }
+    }
+
+    // Read one byte past the limit so we can tell a body that's exactly at the
+    // limit apart from one that's over it.
+    let mut data = Vec::new();
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut data)?;
+
+    if data.len() as u64 > max_size {
+        bail!(
+            "The {} is larger than the limit of {}",
+            what,
+            max_size.bytes()
+        );
+    }
+
+    Ok(data)
+}
+
+/// The start of an error response's body, for showing in the error. The body
+/// is never read past `MAX_ERROR_BODY_SIZE`, and isn't shown at all if it
+/// can't be read.
+pub(super) fn error_body<R: Read>(reader: R) -> String {
+    let mut data = Vec::new();
+    if reader
+        .take(MAX_ERROR_BODY_SIZE + 1)
+        .read_to_end(&mut data)
+        .is_err()
+    {
+        return String::new();
+    }
+
+    let truncated = data.len() as u64 > MAX_ERROR_BODY_SIZE;
+    data.truncate(MAX_ERROR_BODY_SIZE as usize);
+
+    let mut body = String::from_utf8_lossy(&data).into_owned();
+    if truncated {
+        body.push_str("...");
+    }
+
+    body
+}
+
 impl PackageSourceProvider for Registry {
     fn update(&self) -> anyhow::Result<()> {
-        self.index()?.update()
+        let index = self.index()?;
+        index.update()?;
+        self.pin(index)
     }
 
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
         let metadata = self.index()?.get_package_metadata(package_req.name())?;
-        let versions: Vec<_> = metadata
+
+        // The index keeps its parsed metadata cached, so only clone the
+        // manifests that are actually taken from the iterator.
+        let versions = (0..metadata.versions.len()).filter_map(move |i| {
+            let manifest = &metadata.versions[i];
+
+            if package_req.matches(&manifest.package.name, &manifest.package.version) {
+                Some(Ok(manifest.clone()))
+            } else {
+                None
+            }
+        });
+
+        Ok(Box::new(versions))
+    }
+
+    fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<Manifest>> {
+        if !self.index()?.has_package(package_id.name()) {
+            return Ok(None);
+        }
+
+        let metadata = self.index()?.get_package_metadata(package_id.name())?;
+
+        Ok(metadata
             .versions
             .iter()
-            .filter(|manifest| {
-                package_req.matches(&manifest.package.name, &manifest.package.version)
-            })
-            .cloned()
-            .collect();
+            .find(|manifest| manifest.package.version == *package_id.version())
+            .cloned())
+    }
 
-        Ok(versions)
+    fn all_versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        let metadata = self.index()?.get_package_metadata(name)?;
+
+        // The index sorts versions from newest to oldest.
+        Ok(metadata.versions.iter().rev().cloned().collect())
     }
 
-    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
-        let path = format!(
-            "/v1/package-contents/{}/{}/{}",
-            package_id.name().scope(),
-            package_id.name().name(),
-            package_id.version()
-        );
+    fn query_newest(
+        &self,
+        package_req: &PackageReq,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Manifest>> {
+        // Matches come out newest first, so none past the limit are cloned.
+        self.query_iter(package_req)?.take(limit).collect()
+    }
 
-        let url = self.api_url()?.join(&path)?;
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let url = package_contents_url(&self.contents_url()?, package_id)?;
 
         let mut request = self.client.get(url).header("Wally-Version", VERSION);
 
@@ -99,14 +324,20 @@ impl PackageSourceProvider for Registry {
             bail!(
                 "Failed to download package {} from registry: {}\n{} {}",
                 package_id,
-                self.api_url()?,
+                self.contents_url()?,
                 response.status(),
-                response.text()?
+                error_body(&mut response)
             );
         }
 
-        let mut data = Vec::new();
-        response.read_to_end(&mut data)?;
+        let advertised_size = response.content_length();
+        let data = read_limited(
+            &mut response,
+            "package",
+            advertised_size,
+            self.max_download_size,
+        )
+        .map_err(|err| err.context(format!("Failed to download package {}", package_id)))?;
 
         Ok(PackageContents::from_buffer(data))
     }
@@ -116,9 +347,214 @@ impl PackageSourceProvider for Registry {
 
         let sources = fallback_registries
             .into_iter()
-            .map(PackageSourceId::Git)
+            .map(|registry| PackageSourceId::for_registry(&registry))
             .collect();
 
         Ok(sources)
     }
+
+    fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        self.index()?.package_names()
+    }
+
+    fn publish(&self, manifest: &Manifest, contents: &PackageContents) -> anyhow::Result<()> {
+        let api = self.index()?.config()?.api;
+        publish_to_api(&self.client, &api, manifest, contents)
+    }
+
+    // The whole index is cloned, so every package can be listed, and the
+    // registry API answers searches.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_publish: true,
+            can_enumerate: true,
+            supports_get: true,
+            supports_search: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::Path;
+
+    use git2::{Repository, RepositoryInitOptions, Signature};
+
+    use crate::package_index::PackageIndexConfig;
+    use crate::package_source::{PackageSource, PackageSourceMap};
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    /// Commits `files` to the Git repository at `path`, creating it if it
+    /// doesn't exist yet, and returns the new commit.
+    fn commit(path: &Path, files: &[(&str, String)]) -> String {
+        let repository = Repository::open(path)
+            .or_else(|_| {
+                Repository::init_opts(
+                    path,
+                    RepositoryInitOptions::new().initial_head("refs/heads/main"),
+                )
+            })
+            .unwrap();
+
+        let mut git_index = repository.index().unwrap();
+        for (name, contents) in files {
+            let file_path = path.join(name);
+            fs_err::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs_err::write(file_path, contents).unwrap();
+            git_index.add_path(Path::new(name)).unwrap();
+        }
+        git_index.write().unwrap();
+
+        let sig = Signature::now("PackageUser", "PackageUser@localhost").unwrap();
+        let tree = repository
+            .find_tree(git_index.write_tree().unwrap())
+            .unwrap();
+        let parent = repository
+            .head()
+            .ok()
+            .map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+
+        repository
+            .commit(Some("HEAD"), &sig, &sig, "Publish", &tree, &parents)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn resolve_at_index_rev() {
+        let entry = |package_id: &str| {
+            let manifest = PackageBuilder::new(package_id).manifest().clone();
+            format!("{}\n", serde_json::to_string(&manifest).unwrap())
+        };
+
+        let remote = tempfile::tempdir().unwrap();
+        let first = commit(
+            remote.path(),
+            &[
+                ("config.json", r#"{ "api": "http://localhost" }"#.to_owned()),
+                ("biff/minimal", entry("biff/minimal@1.0.0")),
+            ],
+        );
+        let second = commit(
+            remote.path(),
+            &[(
+                "biff/minimal",
+                entry("biff/minimal@1.0.0") + &entry("biff/minimal@1.1.0"),
+            )],
+        );
+        let url = Url::from_directory_path(remote.path()).unwrap();
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@1.0.0");
+        let resolve_at = |rev: &str| {
+            let registry = Registry::from_registry_spec(url.as_str())
+                .unwrap()
+                .with_index_rev(Some(rev.to_owned()));
+
+            // A temporary index keeps the test out of the user's cache.
+            let index = PackageIndex::new_temp(&url, None).unwrap();
+            registry.pin(&index).unwrap();
+            assert!(registry.index.set(Arc::new(index)).is_ok());
+
+            let sources = PackageSourceMap::new(Box::new(PackageSource::Registry(registry)));
+            let resolved = resolve(root.manifest(), &Default::default(), &sources).unwrap();
+            let activated: Vec<String> =
+                resolved.activated.iter().map(ToString::to_string).collect();
+
+            (activated, sources.index_rev().unwrap())
+        };
+
+        assert_eq!(
+            resolve_at(&first),
+            (
+                vec![
+                    "biff/minimal@1.0.0".to_owned(),
+                    "biff/root@1.0.0".to_owned()
+                ],
+                Some(first.clone())
+            )
+        );
+        assert_eq!(
+            resolve_at(&second),
+            (
+                vec![
+                    "biff/minimal@1.1.0".to_owned(),
+                    "biff/root@1.0.0".to_owned()
+                ],
+                Some(second.clone())
+            )
+        );
+    }
+
+    #[test]
+    fn contents_from_api_by_default() {
+        let config: PackageIndexConfig =
+            serde_json::from_str(r#"{ "api": "https://api.example.com" }"#).unwrap();
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let url = package_contents_url(config.contents_base_url(), &package_id).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://api.example.com/v1/package-contents/biff/minimal/0.1.0"
+        );
+    }
+
+    #[test]
+    fn contents_from_content_url() {
+        let config: PackageIndexConfig = serde_json::from_str(
+            r#"{ "api": "https://api.example.com", "content_url": "https://cdn.example.com" }"#,
+        )
+        .unwrap();
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let url = package_contents_url(config.contents_base_url(), &package_id).unwrap();
+        assert_eq!(url.host_str(), Some("cdn.example.com"));
+        assert_eq!(
+            url.as_str(),
+            "https://cdn.example.com/v1/package-contents/biff/minimal/0.1.0"
+        );
+
+        // Everything else still talks to the registry API.
+        assert_eq!(config.api.host_str(), Some("api.example.com"));
+    }
+
+    #[test]
+    fn download_within_limit() {
+        let data = read_limited(&b"contents"[..], "package", Some(8), 8).unwrap();
+        assert_eq!(data, b"contents");
+    }
+
+    #[test]
+    fn download_advertised_over_limit() {
+        let err = read_limited(&b"contents"[..], "package", Some(1024), 8).unwrap_err();
+        assert!(
+            err.to_string().starts_with("The package is")
+                && err.to_string().contains("larger than the limit"),
+            "Unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn download_streamed_over_limit() {
+        // A body that doesn't advertise its size, or lies about it, is still
+        // cut off once it passes the limit.
+        let body = vec![0; 1024];
+
+        assert!(read_limited(&body[..], "package", None, 8).is_err());
+        assert!(read_limited(&body[..], "package", Some(4), 8).is_err());
+    }
+
+    #[test]
+    fn error_bodies_are_cut_off() {
+        assert_eq!(error_body(&b"Package not found"[..]), "Package not found");
+
+        let body = vec![b'a'; MAX_ERROR_BODY_SIZE as usize * 4];
+        let shown = error_body(&body[..]);
+        assert_eq!(shown.len(), MAX_ERROR_BODY_SIZE as usize + "...".len());
+        assert!(shown.ends_with("..."));
+    }
 }
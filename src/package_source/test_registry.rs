@@ -1,4 +1,4 @@
-use std::io::BufReader;
+use std::io::{BufReader, ErrorKind};
 use std::path::PathBuf;
 
 use anyhow::Context;
@@ -6,11 +6,12 @@ use fs_err::File;
 
 use crate::manifest::Manifest;
 use crate::package_id::PackageId;
-use crate::package_index::PackageIndexConfig;
+use crate::package_index::{package_names_in, PackageIndexConfig};
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
 use crate::package_source::PackageContents;
 
-use super::{PackageSourceId, PackageSourceProvider};
+use super::{Capabilities, ManifestIter, PackageSourceId, PackageSourceProvider};
 
 #[derive(Clone)]
 pub struct TestRegistry {
@@ -23,61 +24,96 @@ impl TestRegistry {
     }
 }
 
-impl PackageSourceProvider for TestRegistry {
-    fn update(&self) -> anyhow::Result<()> {
-        Ok(())
-    }
-
-    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
-        // Each package has all of its versions stored in a folder based on its
-        // scope and name.
-        let mut package_path = self.path.clone();
-        package_path.push("index");
-        package_path.push(package_req.name().scope());
-        package_path.push(package_req.name().name());
+impl TestRegistry {
+    /// Reads every entry in the index for the package with this name, parsing
+    /// them one at a time as they're asked for.
+    fn entries<'a>(&self, name: &'a PackageName) -> anyhow::Result<ManifestIter<'a>> {
+        let package_path = self.index_path(name);
 
         // Construct a buffered file reader, with a nice error message in the
         // event of failure. We might want to return a structured error from
         // this method in the future to distinguish between general I/O errors
         // and a package not existing.
         let file = File::open(&package_path)
-            .with_context(|| format!("could not open package {} from index", package_req.name()))?;
+            .with_context(|| format!("could not open package {} from index", name))?;
         let file = BufReader::new(file);
 
-        // Read all of the manifests from the package file.
-        //
-        // Entries into the index are stored as JSON Lines. This block will
-        // either parse all of the entries, or fail with a single error.
-        let manifest_stream: Result<Vec<Manifest>, serde_json::Error> =
-            serde_json::Deserializer::from_reader(file)
-                .into_iter::<Manifest>()
-                .filter(|manifest| {
-                    if let Ok(manifest) = manifest {
-                        package_req.matches(&manifest.package.name, &manifest.package.version)
-                    } else {
-                        true
-                    }
-                })
-                .collect();
-
-        let versions = manifest_stream.with_context(|| {
-            format!(
-                "could not parse package index entry for {}",
-                package_req.name()
-            )
-        })?;
+        // Entries into the index are stored as JSON Lines, so they can be
+        // parsed lazily instead of reading the whole file up front.
+        let manifests = serde_json::Deserializer::from_reader(file)
+            .into_iter::<Manifest>()
+            .map(move |manifest| {
+                manifest
+                    .with_context(|| format!("could not parse package index entry for {}", name))
+            });
 
-        Ok(versions)
+        Ok(Box::new(manifests))
     }
 
-    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+    fn index_path(&self, name: &PackageName) -> PathBuf {
+        // Each package has all of its versions stored in a folder based on its
+        // scope and name.
+        let mut package_path = self.path.clone();
+        package_path.push("index");
+        package_path.push(name.scope());
+        package_path.push(name.name());
+        package_path
+    }
+
+    fn contents_path(&self, package_id: &PackageId) -> PathBuf {
         let mut package_path = self.path.clone();
         package_path.push("contents");
         package_path.push(package_id.name().scope());
         package_path.push(package_id.name().name());
         package_path.push(format!("{}.zip", package_id.version()));
+        package_path
+    }
+}
 
-        let data = fs_err::read(&package_path)?;
+impl PackageSourceProvider for TestRegistry {
+    fn update(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn query_iter<'a>(&'a self, package_req: &'a PackageReq) -> anyhow::Result<ManifestIter<'a>> {
+        let manifests = self
+            .entries(package_req.name())?
+            .filter(move |manifest| match manifest {
+                Ok(manifest) => {
+                    package_req.matches(&manifest.package.name, &manifest.package.version)
+                }
+                Err(_) => true,
+            });
+
+        Ok(Box::new(manifests))
+    }
+
+    // Only the entries up to the matching version are parsed.
+    fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<Manifest>> {
+        if !self.index_path(package_id.name()).is_file() {
+            return Ok(None);
+        }
+
+        for manifest in self.entries(package_id.name())? {
+            let manifest = manifest?;
+
+            if manifest.package.version == *package_id.version() {
+                return Ok(Some(manifest));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn all_versions(&self, name: &PackageName) -> anyhow::Result<Vec<Manifest>> {
+        let mut versions = self.entries(name)?.collect::<anyhow::Result<Vec<_>>>()?;
+        versions.sort_by(|a, b| a.package.version.cmp(&b.package.version));
+
+        Ok(versions)
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let data = fs_err::read(self.contents_path(package_id))?;
         Ok(PackageContents::from_buffer(data))
     }
 
@@ -95,4 +131,166 @@ impl PackageSourceProvider for TestRegistry {
 
         Ok(sources)
     }
+
+    fn package_names(&self) -> anyhow::Result<Vec<PackageName>> {
+        package_names_in(&self.path.join("index"))
+    }
+
+    fn publish(&self, manifest: &Manifest, contents: &PackageContents) -> anyhow::Result<()> {
+        let index_path = self.index_path(&manifest.package.name);
+        let contents_path = self.contents_path(&manifest.package_id());
+
+        fs_err::create_dir_all(index_path.parent().unwrap())?;
+        fs_err::create_dir_all(contents_path.parent().unwrap())?;
+        fs_err::write(&contents_path, contents.data())?;
+
+        // Entries are newline-delimited, but the last one might not end in a
+        // newline yet.
+        let mut entries = match fs_err::read_to_string(&index_path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        if !entries.is_empty() && !entries.ends_with('\n') {
+            entries.push('\n');
+        }
+
+        entries.push_str(&serde_json::to_string(manifest)?);
+        entries.push('\n');
+        fs_err::write(&index_path, entries)?;
+
+        Ok(())
+    }
+
+    // There's no registry API behind a test registry to search with.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_publish: true,
+            can_enumerate: true,
+            supports_get: true,
+            supports_search: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use semver::VersionReq;
+
+    #[test]
+    fn query_iter_stops_parsing_early() {
+        let registry_dir = tempfile::tempdir().unwrap();
+        let scope_dir = registry_dir.path().join("index").join("biff");
+        fs_err::create_dir_all(&scope_dir).unwrap();
+
+        // Only the first entry is valid, so anything that reads past it will
+        // fail to parse.
+        let entry = concat!(
+            r#"{"package":{"name":"biff/minimal","version":"0.1.0","registry":"test","#,
+            r#""realm":"server","description":null,"license":null,"authors":[]}}"#
+        );
+        fs_err::write(scope_dir.join("minimal"), format!("{}\nnot json\n", entry)).unwrap();
+
+        let registry = TestRegistry::new(registry_dir.path());
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::any(),
+        );
+
+        let first = registry.query_iter(&req).unwrap().next().unwrap().unwrap();
+        assert_eq!(first.package.version.to_string(), "0.1.0");
+
+        assert!(registry.query(&req).is_err());
+    }
+
+    #[test]
+    fn all_versions_sorted() {
+        let registry = TestRegistry::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry"
+        ));
+        let name = PackageName::new("diamond-graph", "indirect-dependency-a").unwrap();
+
+        let versions: Vec<_> = registry
+            .all_versions(&name)
+            .unwrap()
+            .iter()
+            .map(|manifest| manifest.package.version.to_string())
+            .collect();
+
+        assert_eq!(versions, vec!["0.1.0", "0.1.1", "0.2.0", "0.2.1"]);
+    }
+
+    #[test]
+    fn capabilities() {
+        let registry = TestRegistry::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry"
+        ));
+
+        assert_eq!(
+            registry.capabilities(),
+            Capabilities {
+                can_publish: true,
+                can_enumerate: true,
+                supports_get: true,
+                supports_search: false,
+            }
+        );
+    }
+
+    #[test]
+    fn get_exact_version() {
+        let registry = TestRegistry::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry"
+        ));
+
+        let package_id: PackageId = "diamond-graph/indirect-dependency-a@0.1.1".parse().unwrap();
+        let manifest = registry.get(&package_id).unwrap().unwrap();
+        assert_eq!(manifest.package_id(), package_id);
+
+        let missing: PackageId = "diamond-graph/indirect-dependency-a@0.3.0".parse().unwrap();
+        assert!(registry.get(&missing).unwrap().is_none());
+    }
+
+    #[test]
+    fn query_newest() {
+        let registry_dir = tempfile::tempdir().unwrap();
+        let scope_dir = registry_dir.path().join("index").join("biff");
+        fs_err::create_dir_all(&scope_dir).unwrap();
+
+        // Entries are deliberately out of order.
+        let entries: Vec<String> = ["1.0.0", "1.3.0", "2.0.0", "1.1.0", "1.2.0", "0.9.0"]
+            .iter()
+            .map(|version| {
+                format!(
+                    concat!(
+                        r#"{{"package":{{"name":"biff/minimal","version":"{}","registry":"test","#,
+                        r#""realm":"server","description":null,"license":null,"authors":[]}}}}"#
+                    ),
+                    version
+                )
+            })
+            .collect();
+        fs_err::write(scope_dir.join("minimal"), entries.join("\n")).unwrap();
+
+        let registry = TestRegistry::new(registry_dir.path());
+        let req = PackageReq::new(
+            PackageName::new("biff", "minimal").unwrap(),
+            VersionReq::parse("^1").unwrap(),
+        );
+
+        let versions: Vec<_> = registry
+            .query_newest(&req, 3)
+            .unwrap()
+            .iter()
+            .map(|manifest| manifest.package.version.to_string())
+            .collect();
+
+        assert_eq!(versions, vec!["1.3.0", "1.2.0", "1.1.0"]);
+    }
 }
@@ -0,0 +1,214 @@
+//! Installs packages in two stages with their own limits on how much runs at
+//! once. Downloads wait on the network and extraction on the CPU and disk, so
+//! a package is handed over to the extraction stage as soon as it's
+//! downloaded, and the next download can start while it's being extracted.
+
+use std::sync::Arc;
+
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::warnings;
+
+/// How many packages are downloaded at once by default.
+pub const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 32;
+
+/// [`DEFAULT_DOWNLOAD_CONCURRENCY`] as the command line spells it.
+pub const DEFAULT_DOWNLOAD_CONCURRENCY_ARG: &str = "32";
+
+/// How many packages are extracted at once by default.
+pub const DEFAULT_EXTRACT_CONCURRENCY: usize = 8;
+
+/// [`DEFAULT_EXTRACT_CONCURRENCY`] as the command line spells it.
+pub const DEFAULT_EXTRACT_CONCURRENCY_ARG: &str = "8";
+
+#[derive(Clone)]
+pub struct Pipeline {
+    downloads: Arc<Semaphore>,
+    extractions: Arc<Semaphore>,
+}
+
+impl Pipeline {
+    /// A pipeline running up to `download_concurrency` downloads and
+    /// `extract_concurrency` extractions at once. Either limit is at least
+    /// one, so that packages always make progress.
+    pub fn new(download_concurrency: usize, extract_concurrency: usize) -> Self {
+        Self {
+            downloads: Arc::new(Semaphore::new(download_concurrency.max(1))),
+            extractions: Arc::new(Semaphore::new(extract_concurrency.max(1))),
+        }
+    }
+
+    /// Runs `download` and then `extract` on what it downloaded, each once
+    /// there's room in its stage. Extraction is skipped if the download
+    /// failed. Warnings from either stage go to the caller's collector.
+    pub fn spawn<T, R>(
+        &self,
+        runtime: &Runtime,
+        download: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+        extract: impl FnOnce(T) -> anyhow::Result<R> + Send + 'static,
+    ) -> JoinHandle<anyhow::Result<R>>
+    where
+        T: Send + 'static,
+        R: Send + 'static,
+    {
+        let downloads = Arc::clone(&self.downloads);
+        let extractions = Arc::clone(&self.extractions);
+        let collector = warnings::Collector::current().unwrap_or_default();
+
+        runtime.spawn(async move {
+            let downloaded = {
+                let _permit = downloads
+                    .acquire_owned()
+                    .await
+                    .expect("The download stage was closed.");
+
+                let collector = collector.clone();
+                tokio::task::spawn_blocking(move || collector.scope(download))
+                    .await
+                    .expect("Package failed to be downloaded.")?
+            };
+
+            let _permit = extractions
+                .acquire_owned()
+                .await
+                .expect("The extraction stage was closed.");
+
+            tokio::task::spawn_blocking(move || collector.scope(|| extract(downloaded)))
+                .await
+                .expect("Package failed to be extracted.")
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Counts how many of something are running, remembering the most there
+    /// ever were at once.
+    #[derive(Default)]
+    struct Gauge {
+        running: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    impl Gauge {
+        fn run<T>(&self, work: impl FnOnce() -> T) -> T {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(running, Ordering::SeqCst);
+            let result = work();
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+
+        fn most(&self) -> usize {
+            self.most.load(Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn stages_have_separate_limits() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_all()
+            .build()
+            .unwrap();
+
+        let pipeline = Pipeline::new(6, 2);
+        let downloads = Arc::new(Gauge::default());
+        let extractions = Arc::new(Gauge::default());
+
+        let handles: Vec<_> = (0..24)
+            .map(|package| {
+                let downloads = Arc::clone(&downloads);
+                let extractions = Arc::clone(&extractions);
+
+                pipeline.spawn(
+                    &runtime,
+                    move || {
+                        downloads.run(|| thread::sleep(Duration::from_millis(20)));
+                        Ok(package)
+                    },
+                    move |package| {
+                        extractions.run(|| thread::sleep(Duration::from_millis(20)));
+                        Ok(package * 2)
+                    },
+                )
+            })
+            .collect();
+
+        let results: Vec<usize> = handles
+            .into_iter()
+            .map(|handle| runtime.block_on(handle).unwrap().unwrap())
+            .collect();
+
+        // Results come back in the order packages were handed in, however
+        // the stages interleaved.
+        assert_eq!(
+            results,
+            (0..24).map(|package| package * 2).collect::<Vec<_>>()
+        );
+
+        // Downloads weren't held back to the extraction limit.
+        assert!(
+            downloads.most() > 2 && downloads.most() <= 6,
+            "{} downloads ran at once",
+            downloads.most()
+        );
+        assert_eq!(extractions.most(), 2);
+    }
+
+    #[test]
+    fn failed_downloads_are_not_extracted() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let pipeline = Pipeline::new(1, 1);
+        let extractions = Arc::new(Gauge::default());
+
+        let extracted = Arc::clone(&extractions);
+        let handle = pipeline.spawn(
+            &runtime,
+            || -> anyhow::Result<()> { anyhow::bail!("connection reset") },
+            move |()| {
+                extracted.run(|| ());
+                Ok(())
+            },
+        );
+
+        let err = runtime.block_on(handle).unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "connection reset");
+        assert_eq!(extractions.most(), 0);
+    }
+
+    #[test]
+    fn warnings_go_to_the_callers_collector() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let pipeline = Pipeline::new(1, 1);
+        let collector = warnings::Collector::default();
+
+        let handle = collector.scope(|| {
+            pipeline.spawn(
+                &runtime,
+                || {
+                    warnings::record("while downloading");
+                    Ok(())
+                },
+                |()| {
+                    warnings::record("while extracting");
+                    Ok(())
+                },
+            )
+        });
+
+        runtime.block_on(handle).unwrap().unwrap();
+        assert_eq!(
+            collector.take(),
+            vec!["while downloading", "while extracting"]
+        );
+    }
+}
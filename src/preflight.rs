@@ -0,0 +1,176 @@
+//! Checks made before an install changes anything on disk, so that it fails
+//! up front instead of partway through.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::path::Path;
+
+use ubyte::ToByteUnit;
+
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider};
+use crate::resolution::Resolve;
+
+/// Where the free space of a volume comes from. Tests swap this out to
+/// pretend the disk is full.
+pub trait DiskSpace {
+    /// How many bytes are free on the volume holding `path`.
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// Asks the operating system.
+pub struct SystemDiskSpace;
+
+impl DiskSpace for SystemDiskSpace {
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::available_space(path)
+    }
+}
+
+/// How much space an install will take, from the sizes the registry recorded
+/// in its index.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SizeEstimate {
+    /// The extracted size of every package whose size is known, in bytes.
+    pub bytes: u64,
+
+    /// Packages with no size in the index, which aren't counted.
+    pub unknown: Vec<PackageId>,
+}
+
+/// Estimates the space needed to install `resolved`. The root, path
+/// dependencies, and packages in `installed` are already on disk, so they
+/// aren't counted.
+pub fn estimate_install_size(
+    root_package_id: &PackageId,
+    resolved: &Resolve,
+    installed: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<SizeEstimate> {
+    let mut estimate = SizeEstimate::default();
+
+    for package_id in &resolved.activated {
+        if package_id == root_package_id || installed.contains(package_id) {
+            continue;
+        }
+
+        let source_id = &resolved.metadata[package_id].source_registry;
+        if matches!(source_id, PackageSourceId::Local(_)) {
+            continue;
+        }
+
+        let manifest = match package_sources.get(source_id) {
+            Some(source) => source.get(package_id)?,
+            None => None,
+        };
+
+        match manifest.and_then(|manifest| manifest.extracted_size) {
+            Some(size) => estimate.bytes += size,
+            None => estimate.unknown.push(package_id.clone()),
+        }
+    }
+
+    Ok(estimate)
+}
+
+/// Checks that the volume holding `project_path` has room for `estimate`,
+/// returning a message saying how short it is if it doesn't.
+pub fn check_disk_space(
+    disk: &dyn DiskSpace,
+    project_path: &Path,
+    estimate: &SizeEstimate,
+) -> anyhow::Result<Option<String>> {
+    let available = disk.available_space(project_path)?;
+
+    if estimate.bytes <= available {
+        return Ok(None);
+    }
+
+    let mut message = format!(
+        "Installing needs about {} of disk space, but only {} is free on the volume holding {}",
+        estimate.bytes.bytes(),
+        available.bytes(),
+        project_path.display()
+    );
+
+    if !estimate.unknown.is_empty() {
+        message.push_str(&format!(
+            ", not counting {} packages whose size the registry didn't record",
+            estimate.unknown.len()
+        ));
+    }
+
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    struct FixedDiskSpace(u64);
+
+    impl DiskSpace for FixedDiskSpace {
+        fn available_space(&self, _path: &Path) -> io::Result<u64> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn low_disk_space() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/large@1.0.0").with_extracted_size(40_000));
+        registry.publish(PackageBuilder::new("biff/small@1.0.0").with_extracted_size(2_000));
+        registry.publish(PackageBuilder::new("biff/unsized@1.0.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Large", "biff/large@1.0.0")
+            .with_dep("Small", "biff/small@1.0.0")
+            .with_dep("Unsized", "biff/unsized@1.0.0")
+            .into_manifest();
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(&root, &Default::default(), &package_sources).unwrap();
+
+        let estimate = estimate_install_size(
+            &root.package_id(),
+            &resolved,
+            &BTreeSet::new(),
+            &package_sources,
+        )
+        .unwrap();
+        assert_eq!(estimate.bytes, 42_000);
+        assert_eq!(
+            estimate.unknown,
+            vec!["biff/unsized@1.0.0".parse().unwrap()]
+        );
+
+        let warning = check_disk_space(&FixedDiskSpace(1_000), Path::new("project"), &estimate)
+            .unwrap()
+            .expect("the preflight should warn about low disk space");
+        assert_eq!(
+            warning,
+            format!(
+                "Installing needs about {} of disk space, but only {} is free on the volume \
+                 holding project, not counting 1 packages whose size the registry didn't record",
+                42_000u64.bytes(),
+                1_000u64.bytes()
+            )
+        );
+
+        // Packages that are already installed don't need any more space.
+        let installed = vec!["biff/large@1.0.0".parse().unwrap()]
+            .into_iter()
+            .collect();
+        let estimate =
+            estimate_install_size(&root.package_id(), &resolved, &installed, &package_sources)
+                .unwrap();
+        assert_eq!(
+            check_disk_space(&FixedDiskSpace(10_000), Path::new("project"), &estimate).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! A structured record of what an install did, meant to be archived by CI to
+//! audit installs and debug builds that can't be reproduced.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::installation::InstallTimings;
+use crate::package_id::PackageId;
+use crate::resolution::Resolve;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// The package that dependencies were installed for.
+    pub root: PackageId,
+
+    /// The resolved package graph that was installed.
+    pub resolve: Resolve,
+
+    /// The checksum of every installed package.
+    pub checksums: BTreeMap<PackageId, String>,
+
+    pub cache_hits: CacheHits,
+    pub timings: Timings,
+}
+
+/// The work that an install was able to skip.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheHits {
+    /// Whether the package graph was reused from an earlier install instead
+    /// of being solved.
+    pub resolution: bool,
+
+    /// The packages that were already installed, so they weren't downloaded.
+    pub packages: BTreeSet<PackageId>,
+}
+
+/// How long each step of an install took, in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct Timings {
+    pub resolve_ms: u64,
+    pub install_ms: u64,
+}
+
+impl Timings {
+    pub fn new(resolve: Duration, install: Duration) -> Self {
+        Self {
+            resolve_ms: resolve.as_millis() as u64,
+            install_ms: install.as_millis() as u64,
+        }
+    }
+}
+
+impl Report {
+    /// Writes the report to `path` as JSON.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs_err::write(path, contents)
+            .with_context(|| format!("could not write install report to {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Where an install's time went, from `wally install --timings`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimingReport {
+    pub phases: PhaseTimings,
+
+    /// The packages that took longest to install, slowest first.
+    pub slowest: Vec<PackageTiming>,
+}
+
+/// How long each phase of an install took, in milliseconds. Packages are
+/// downloaded, verified, and extracted concurrently, so those phases are
+/// summed across packages and can add up to more than the install took.
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTimings {
+    pub index_update_ms: u64,
+    pub resolution_ms: u64,
+    pub download_ms: u64,
+    pub verification_ms: u64,
+    pub extraction_ms: u64,
+    pub link_generation_ms: u64,
+}
+
+/// How long one package took to install, in milliseconds.
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageTiming {
+    pub package: PackageId,
+    pub download_ms: u64,
+    pub verification_ms: u64,
+    pub extraction_ms: u64,
+}
+
+impl PackageTiming {
+    fn total_ms(&self) -> u64 {
+        self.download_ms + self.verification_ms + self.extraction_ms
+    }
+}
+
+impl TimingReport {
+    /// Puts together the timings of an install, keeping the `slowest`
+    /// packages that took longest.
+    pub fn new(
+        index_update: Duration,
+        resolution: Duration,
+        install: &InstallTimings,
+        slowest: usize,
+    ) -> Self {
+        let mut packages: Vec<PackageTiming> = install
+            .packages
+            .iter()
+            .map(|(package_id, timings)| PackageTiming {
+                package: package_id.clone(),
+                download_ms: millis(timings.download),
+                verification_ms: millis(timings.verification),
+                extraction_ms: millis(timings.extraction),
+            })
+            .collect();
+
+        let phases = PhaseTimings {
+            index_update_ms: millis(index_update),
+            resolution_ms: millis(resolution),
+            download_ms: packages.iter().map(|package| package.download_ms).sum(),
+            verification_ms: packages.iter().map(|package| package.verification_ms).sum(),
+            extraction_ms: packages.iter().map(|package| package.extraction_ms).sum(),
+            link_generation_ms: millis(install.links),
+        };
+
+        // Ties keep the packages in order by name.
+        packages.sort_by(|a, b| b.total_ms().cmp(&a.total_ms()));
+        packages.truncate(slowest);
+
+        Self {
+            phases,
+            slowest: packages,
+        }
+    }
+
+    /// Writes the timings to `path` as JSON.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs_err::write(path, contents)
+            .with_context(|| format!("could not write install timings to {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let phases = [
+            ("index update", self.phases.index_update_ms),
+            ("resolution", self.phases.resolution_ms),
+            ("download", self.phases.download_ms),
+            ("verification", self.phases.verification_ms),
+            ("extraction", self.phases.extraction_ms),
+            ("link generation", self.phases.link_generation_ms),
+        ];
+
+        writeln!(formatter, "Timings:")?;
+        for (phase, ms) in phases {
+            writeln!(formatter, "  {:<16} {}ms", phase, ms)?;
+        }
+
+        if !self.slowest.is_empty() {
+            writeln!(formatter, "Slowest packages:")?;
+        }
+
+        for package in &self.slowest {
+            writeln!(
+                formatter,
+                "  {} (download {}ms, verification {}ms, extraction {}ms)",
+                package.package,
+                package.download_ms,
+                package.verification_ms,
+                package.extraction_ms
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
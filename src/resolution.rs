@@ -1,21 +1,27 @@
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::str::FromStr;
 
 use anyhow::bail;
 use anyhow::format_err;
 use semver::Version;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::manifest::{Manifest, Realm};
+use crate::manifest::{ConflictPolicy, Manifest, Realm, ZeroVersionCompat};
 use crate::package_id::PackageId;
+use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
-use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider};
+use crate::package_source::{
+    PackageSource, PackageSourceId, PackageSourceMap, PackageSourceProvider,
+};
+use crate::resolution_conflict::{ConflictingRequirement, ResolutionConflict, ResolutionErrors};
+use crate::resolution_trace::{ResolutionEvent, ResolutionTrace};
 
 /// A completely resolved graph of packages returned by `resolve`.
 ///
 /// State here is stored in multiple maps, all keyed by PackageId, to facilitate
 /// concurrent mutable access to unrelated information about different packages.
-#[derive(Debug, Default, Serialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Resolve {
     /// Set of all packages that have been chosen to be part of the package
     /// graph.
@@ -33,6 +39,14 @@ pub struct Resolve {
 
     /// Graph of all dependencies originating from the "dev" dependency realm.
     pub dev_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageId>>,
+
+    /// Graph of all dependencies originating from the "build" dependency realm.
+    pub build_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageId>>,
+
+    /// The peer dependencies of activated packages, which aren't part of the
+    /// graph but must be provided by a package that depends on them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageReq>>,
 }
 
 impl Resolve {
@@ -43,29 +57,472 @@ impl Resolve {
             Realm::Shared => self.shared_dependencies.entry(source).or_default(),
             Realm::Server => self.server_dependencies.entry(source).or_default(),
             Realm::Dev => self.dev_dependencies.entry(source).or_default(),
+            Realm::Build => self.build_dependencies.entry(source).or_default(),
         };
         dependencies.insert(dep_name, dep);
     }
+
+    /// Checks that every peer dependency in the graph is provided by a package
+    /// somewhere above the package that needs it, returning a warning for each
+    /// one that isn't. Unmet peer dependencies are an error if `strict` is set.
+    pub fn check_peer_dependencies(&self, strict: bool) -> anyhow::Result<Vec<String>> {
+        let unmet = self.unmet_peer_dependencies();
+
+        if strict && !unmet.is_empty() {
+            bail!("{}", unmet.join("\n"));
+        }
+
+        Ok(unmet)
+    }
+
+    /// Finds packages in the graph that are on an older patch than their
+    /// requirements allow, returning a warning for each one naming the newest
+    /// patch of the same minor version that every requirement on it accepts.
+    /// Upgrading to it is free, and often fixes bugs or security issues.
+    pub fn stale_patches(
+        &self,
+        root_manifest: &Manifest,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<Vec<String>> {
+        let root = root_manifest.package_id();
+
+        // Every requirement in the graph, keyed by the package it resolved to.
+        let mut requirements: BTreeMap<&PackageId, Vec<PackageReq>> = BTreeMap::new();
+
+        for package_id in &self.activated {
+            let manifest = if *package_id == root {
+                root_manifest.clone()
+            } else {
+                match self.registry_source(package_id, package_sources) {
+                    Some(source) => match source.get(package_id)? {
+                        Some(manifest) => manifest,
+                        None => continue,
+                    },
+                    None => continue,
+                }
+            };
+
+            let tables = [
+                &manifest.dependencies,
+                &manifest.server_dependencies,
+                &manifest.dev_dependencies,
+                &manifest.build_dependencies,
+            ];
+
+            for package_req in tables.iter().flat_map(|table| table.values()) {
+                let resolved = self
+                    .activated
+                    .iter()
+                    .find(|activated| package_req.matches(activated.name(), activated.version()));
+
+                if let Some(resolved) = resolved {
+                    requirements
+                        .entry(resolved)
+                        .or_default()
+                        .push(package_req.clone());
+                }
+            }
+        }
+
+        let mut warnings = Vec::new();
+
+        for (package_id, package_reqs) in requirements {
+            let source = match self.registry_source(package_id, package_sources) {
+                Some(source) => source,
+                None => continue,
+            };
+
+            let current = package_id.version();
+            let newest_patch = source
+                .query(&package_reqs[0])?
+                .into_iter()
+                .map(|manifest| manifest.package.version)
+                .filter(|version| {
+                    version.major == current.major
+                        && version.minor == current.minor
+                        && version.patch > current.patch
+                        && !version.is_prerelease()
+                        && package_reqs
+                            .iter()
+                            .all(|package_req| package_req.matches(package_id.name(), version))
+                })
+                .max();
+
+            if let Some(newest_patch) = newest_patch {
+                warnings.push(format!(
+                    "{} is locked to {}, but {} is available and still satisfies its requirements",
+                    package_id.name(),
+                    current,
+                    newest_patch
+                ));
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// The registry a package in the graph came from, or `None` if it didn't
+    /// come from a registry, like a path dependency.
+    fn registry_source<'a>(
+        &self,
+        package_id: &PackageId,
+        package_sources: &'a PackageSourceMap,
+    ) -> Option<&'a PackageSource> {
+        let metadata = self.metadata.get(package_id)?;
+
+        match metadata.source_registry {
+            PackageSourceId::Local(_) => None,
+            _ => package_sources.get(&metadata.source_registry),
+        }
+    }
+
+    /// The part of the graph that's needed by every package named `name`:
+    /// those packages, everything they depend on, and the root's links to
+    /// them. Fails if no package has that name.
+    pub fn subtree(&self, root: &PackageId, name: &PackageName) -> anyhow::Result<Resolve> {
+        let graphs = [
+            &self.shared_dependencies,
+            &self.server_dependencies,
+            &self.dev_dependencies,
+            &self.build_dependencies,
+        ];
+
+        let mut to_visit: Vec<&PackageId> = self
+            .activated
+            .iter()
+            .filter(|package_id| *package_id != root && package_id.name() == name)
+            .collect();
+
+        if to_visit.is_empty() {
+            bail!("{} is not in the dependency graph", name);
+        }
+
+        let mut kept = BTreeSet::new();
+
+        while let Some(package_id) = to_visit.pop() {
+            if !kept.insert(package_id.clone()) {
+                continue;
+            }
+
+            for graph in &graphs {
+                if let Some(dependencies) = graph.get(package_id) {
+                    to_visit.extend(dependencies.values());
+                }
+            }
+        }
+
+        let keep_graph = |graph: &BTreeMap<PackageId, BTreeMap<String, PackageId>>| {
+            graph
+                .iter()
+                .filter(|(source, _)| *source == root || kept.contains(*source))
+                .map(|(source, dependencies)| {
+                    let dependencies = dependencies
+                        .iter()
+                        .filter(|(_, dependency)| kept.contains(*dependency))
+                        .map(|(alias, dependency)| (alias.clone(), dependency.clone()))
+                        .collect();
+
+                    (source.clone(), dependencies)
+                })
+                .collect()
+        };
+
+        let mut subtree = Resolve {
+            activated: BTreeSet::new(),
+            metadata: BTreeMap::new(),
+            shared_dependencies: keep_graph(&self.shared_dependencies),
+            server_dependencies: keep_graph(&self.server_dependencies),
+            dev_dependencies: keep_graph(&self.dev_dependencies),
+            build_dependencies: keep_graph(&self.build_dependencies),
+            peer_dependencies: BTreeMap::new(),
+        };
+
+        for package_id in kept.iter().chain(Some(root)) {
+            subtree.activated.insert(package_id.clone());
+            subtree
+                .metadata
+                .insert(package_id.clone(), self.metadata[package_id].clone());
+
+            if let Some(peers) = self.peer_dependencies.get(package_id) {
+                subtree
+                    .peer_dependencies
+                    .insert(package_id.clone(), peers.clone());
+            }
+        }
+
+        Ok(subtree)
+    }
+
+    /// The shortest chain of dependencies from `root` to `target`, starting
+    /// with `root` and ending with `target`, or just `target` if the graph
+    /// doesn't lead there.
+    fn path_to(&self, root: &PackageId, target: &PackageId) -> Vec<PackageId> {
+        let graphs = [
+            &self.shared_dependencies,
+            &self.server_dependencies,
+            &self.dev_dependencies,
+            &self.build_dependencies,
+        ];
+
+        let mut parents: BTreeMap<&PackageId, &PackageId> = BTreeMap::new();
+        let mut to_visit = VecDeque::new();
+        to_visit.push_back(root);
+
+        while let Some(package_id) = to_visit.pop_front() {
+            if package_id == target {
+                break;
+            }
+
+            for graph in &graphs {
+                for dependency in graph.get(package_id).into_iter().flat_map(BTreeMap::values) {
+                    if dependency != root && !parents.contains_key(dependency) {
+                        parents.insert(dependency, package_id);
+                        to_visit.push_back(dependency);
+                    }
+                }
+            }
+        }
+
+        let mut path = vec![target.clone()];
+        let mut current = target;
+        while let Some(parent) = parents.get(current) {
+            path.push((*parent).clone());
+            current = parent;
+        }
+
+        path.reverse();
+        path
+    }
+
+    fn unmet_peer_dependencies(&self) -> Vec<String> {
+        let graphs = [
+            &self.shared_dependencies,
+            &self.server_dependencies,
+            &self.dev_dependencies,
+            &self.build_dependencies,
+        ];
+
+        let mut dependents: BTreeMap<&PackageId, BTreeSet<&PackageId>> = BTreeMap::new();
+        for graph in &graphs {
+            for (source, dependencies) in graph.iter() {
+                for dependency in dependencies.values() {
+                    dependents.entry(dependency).or_default().insert(source);
+                }
+            }
+        }
+
+        let provides = |ancestor: &PackageId, req: &PackageReq| {
+            graphs
+                .iter()
+                .filter_map(|graph| graph.get(ancestor))
+                .flat_map(|dependencies| dependencies.values())
+                .any(|dependency| req.matches_id(dependency))
+        };
+
+        let mut unmet = Vec::new();
+
+        for (package_id, peers) in &self.peer_dependencies {
+            for req in peers.values() {
+                let mut visited = BTreeSet::new();
+                let mut to_visit: Vec<&PackageId> = Vec::new();
+                to_visit.extend(dependents.get(package_id).into_iter().flatten());
+
+                let mut provided = false;
+
+                while let Some(ancestor) = to_visit.pop() {
+                    if !visited.insert(ancestor) {
+                        continue;
+                    }
+
+                    if provides(ancestor, req) {
+                        provided = true;
+                        break;
+                    }
+
+                    to_visit.extend(dependents.get(ancestor).into_iter().flatten());
+                }
+
+                if !provided {
+                    unmet.push(format!(
+                        "{} has a peer dependency on {}, but no package that depends on it \
+                         provides a satisfying version",
+                        package_id, req
+                    ));
+                }
+            }
+        }
+
+        unmet
+    }
 }
 
 /// A single node in the package resolution graph.
 /// Origin realm is the "most restrictive" realm the package can still be dependended
 /// upon. It is where the package gets placed during install.
 /// See [ origin_realm clarification ]. In the resolve function for more info.
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ResolvePackageMetadata {
     pub realm: Realm,
     pub origin_realm: Realm,
     pub source_registry: PackageSourceId,
 }
 
+/// What resolution does when it runs into a problem with a dependency, like a
+/// package that can't be found or a version conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorMode {
+    /// Stop at the first problem.
+    FailFast,
+
+    /// Skip the dependency and keep going, failing at the end with every
+    /// problem that was found. Dependencies of a skipped package aren't
+    /// looked at, so problems among them only show up once it's fixed.
+    Collect,
+}
+
+impl Default for ErrorMode {
+    fn default() -> Self {
+        ErrorMode::FailFast
+    }
+}
+
+impl FromStr for ErrorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "fail-fast" => Ok(ErrorMode::FailFast),
+            "collect" => Ok(ErrorMode::Collect),
+            _ => bail!(
+                "Unknown error mode `{}`. Expected fail-fast or collect",
+                value
+            ),
+        }
+    }
+}
+
+/// The outcome of a single attempt at resolving the package graph.
+enum Attempt {
+    Resolved(Resolve),
+
+    /// A conflict was found that can be settled by using this version for
+    /// every request of the package, so resolution should start over.
+    Upgrade(PackageId),
+}
+
 pub fn resolve(
     root_manifest: &Manifest,
     try_to_use: &BTreeSet<PackageId>,
     package_sources: &PackageSourceMap,
 ) -> anyhow::Result<Resolve> {
+    resolve_traced(
+        root_manifest,
+        try_to_use,
+        package_sources,
+        ErrorMode::FailFast,
+        false,
+        &mut ResolutionTrace::disabled(),
+    )
+}
+
+/// Like `resolve`, but handles problems according to `error_mode` and records
+/// every decision the resolver makes into `trace`. With `prefer_recommended`,
+/// versions their registry recommends are picked ahead of newer ones.
+pub fn resolve_traced(
+    root_manifest: &Manifest,
+    try_to_use: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+    error_mode: ErrorMode,
+    prefer_recommended: bool,
+    trace: &mut ResolutionTrace,
+) -> anyhow::Result<Resolve> {
+    let mut preferred = BTreeSet::new();
+
+    loop {
+        match resolve_attempt(
+            root_manifest,
+            try_to_use,
+            package_sources,
+            &preferred,
+            error_mode,
+            prefer_recommended,
+            trace,
+        )? {
+            Attempt::Resolved(resolve) => return Ok(resolve),
+            Attempt::Upgrade(package_id) => {
+                log::debug!(
+                    "Starting over, preferring {} to settle a conflict",
+                    package_id
+                );
+                trace.record(|| ResolutionEvent::Restarted {
+                    preferring: package_id.clone(),
+                });
+
+                // Every attempt prefers at least one more version than the
+                // last, so we're guaranteed to stop eventually.
+                if !preferred.insert(package_id.clone()) {
+                    bail!("Failed to settle a conflict by upgrading to {}", package_id);
+                }
+            }
+        }
+    }
+}
+
+/// The version `package_req` resolves to right now for a project with
+/// `root_manifest`, picked exactly the way `resolve` would pick it for one of
+/// the project's dependencies, but ignoring the project's own dependencies.
+/// It's requested as a dev dependency, since those can come from any realm.
+pub fn resolve_version(
+    root_manifest: &Manifest,
+    package_req: &PackageReq,
+    package_sources: &PackageSourceMap,
+) -> anyhow::Result<PackageId> {
+    const ALIAS: &str = "Package";
+
+    let mut root_manifest = root_manifest.clone();
+    root_manifest.dependencies.clear();
+    root_manifest.server_dependencies.clear();
+    root_manifest.build_dependencies.clear();
+    root_manifest.dev_dependencies.clear();
+    root_manifest
+        .dev_dependencies
+        .insert(ALIAS.to_owned(), package_req.clone());
+
+    let resolved = resolve(&root_manifest, &BTreeSet::new(), package_sources)?;
+
+    resolved
+        .dev_dependencies
+        .get(&root_manifest.package_id())
+        .and_then(|dependencies| dependencies.get(ALIAS))
+        .cloned()
+        .ok_or_else(|| format_err!("{} didn't resolve to any version", package_req))
+}
+
+/// Resolves the package graph, picking versions in `preferred` ahead of any
+/// other candidates.
+fn resolve_attempt(
+    root_manifest: &Manifest,
+    try_to_use: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+    preferred: &BTreeSet<PackageId>,
+    error_mode: ErrorMode,
+    prefer_recommended: bool,
+    trace: &mut ResolutionTrace,
+) -> anyhow::Result<Attempt> {
     let mut resolve = Resolve::default();
 
+    // Problems found so far when they're being collected instead of failing
+    // on the first one.
+    let mut problems = Vec::new();
+
+    // Every request that led to each activated package, so that conflicts can
+    // be settled by the `newest` conflict policy.
+    let mut requests: BTreeMap<PackageId, Vec<PackageReq>> = BTreeMap::new();
+
+    // Which package made each request that led to each activated package,
+    // with what it asked for, so that conflicts can be reported.
+    let mut requesters: BTreeMap<PackageId, Vec<(PackageId, PackageReq)>> = BTreeMap::new();
+
     // Insert root project into graph and activated dependencies, as it'll
     // always be present.
     resolve.activated.insert(root_manifest.package_id());
@@ -111,8 +568,88 @@ pub fn resolve(
         });
     }
 
+    // Build dependencies are only ever followed for the root package, so they
+    // never propagate to the consumers of a package.
+    for (alias, req) in &root_manifest.build_dependencies {
+        packages_to_visit.push_back(DependencyRequest {
+            request_source: root_manifest.package_id(),
+            request_realm: Realm::Build,
+            origin_realm: Realm::Build,
+            package_alias: alias.clone(),
+            package_req: req.clone(),
+        });
+    }
+
+    // The root's own dependencies are all known up front, so they're fetched
+    // together, which some sources can do with a single request.
+    let mut prefetched =
+        prefetch_root_dependencies(root_manifest, &packages_to_visit, package_sources);
+
     // Workhorse loop: resolve all dependencies, depth-first.
-    'outer: while let Some(dependency_request) = packages_to_visit.pop_front() {
+    'outer: while let Some(mut dependency_request) = packages_to_visit.pop_front() {
+        // The root manifest decides how every request treats 0.x versions,
+        // including the requests its dependencies make.
+        dependency_request.package_req = dependency_request
+            .package_req
+            .with_zero_version_compat(root_manifest.package.zero_version_compat);
+
+        // Overrides from the root manifest narrow the requirement of every
+        // request for a package to the versions both of them allow. We hold
+        // onto the original requirement so that we only ever pick versions
+        // that satisfy the requester as well.
+        let requested_req = dependency_request.package_req.clone();
+
+        trace.record(|| ResolutionEvent::Requested {
+            req: requested_req.clone(),
+            requested_by: dependency_request.request_source.clone(),
+            realm: dependency_request.request_realm,
+        });
+
+        if let Some(override_req) = root_manifest.overrides.get(requested_req.name()) {
+            if override_req.name() != requested_req.name() {
+                bail!(
+                    "The override for {} must refer to the same package, but it refers to {}",
+                    requested_req.name(),
+                    override_req
+                );
+            }
+
+            let narrowed = match override_req.intersect(&requested_req) {
+                Some(narrowed) => narrowed,
+                None => {
+                    let problem = format_err!(
+                        "The override {override_req} does not satisfy {req}, which was \
+                         requested by {source}",
+                        override_req = override_req,
+                        req = requested_req,
+                        source = dependency_request.request_source,
+                    );
+
+                    report_problem(error_mode, &mut problems, problem)?;
+                    continue;
+                }
+            };
+
+            log::debug!(
+                "Overriding {} requested by {} with {}",
+                requested_req,
+                dependency_request.request_source,
+                narrowed
+            );
+            trace.record(|| ResolutionEvent::Overridden {
+                requested: requested_req.clone(),
+                replacement: narrowed.clone(),
+            });
+            dependency_request.package_req = narrowed;
+        }
+
+        log::trace!(
+            "Resolving {} ({:?}) requested by {}",
+            dependency_request.package_req,
+            dependency_request.request_realm,
+            dependency_request.request_source
+        );
+
         // Locate all already-activated packages that might match this
         // dependency request.
         let mut matching_activated: Vec<_> = resolve
@@ -129,7 +666,9 @@ pub fn resolve(
         // Check for the highest version already-activated package that matches
         // our constraints.
         for package_id in &matching_activated {
-            if dependency_request.package_req.matches_id(package_id) {
+            if dependency_request.package_req.matches_id(package_id)
+                && requested_req.matches_id(package_id)
+            {
                 let metadata = resolve
                     .metadata
                     .get_mut(package_id)
@@ -142,17 +681,38 @@ pub fn resolve(
                 // which only originate from dev dependencies get put into the dev folder even
                 // if they usually belong to another realm. Likewise we want to keep shared
                 // dependencies in the server realm unless they are explicitly required as a
-                // shared dependency.
+                // shared dependency. Build dependencies are the most restrictive of all, as they
+                // live outside of the runtime package tree.
                 let realm_match = match (metadata.origin_realm, dependency_request.origin_realm) {
                     (_, Realm::Shared) => Realm::Shared,
                     (Realm::Shared, _) => Realm::Shared,
                     (_, Realm::Server) => Realm::Server,
                     (Realm::Server, _) => Realm::Server,
-                    (Realm::Dev, Realm::Dev) => Realm::Dev,
+                    (_, Realm::Dev) => Realm::Dev,
+                    (Realm::Dev, _) => Realm::Dev,
+                    (Realm::Build, Realm::Build) => Realm::Build,
                 };
 
                 metadata.origin_realm = realm_match;
 
+                let package_requests = requests.entry(package_id.clone()).or_default();
+                package_requests.push(requested_req.clone());
+                package_requests.push(dependency_request.package_req.clone());
+                requesters.entry(package_id.clone()).or_default().push((
+                    dependency_request.request_source.clone(),
+                    requested_req.clone(),
+                ));
+
+                log::trace!(
+                    "Reusing already activated {} for {}",
+                    package_id,
+                    dependency_request.package_req
+                );
+                trace.record(|| ResolutionEvent::Reused {
+                    req: dependency_request.package_req.clone(),
+                    chosen: package_id.clone(),
+                });
+
                 resolve.activate(
                     dependency_request.request_source.clone(),
                     dependency_request.package_alias.clone(),
@@ -164,58 +724,134 @@ pub fn resolve(
             }
         }
 
-        // Look through all our packages sources in order of priority
-        let (source_registry, mut candidates) = package_sources
-            .source_order()
-            .iter()
-            .find_map(|source| {
-                let registry = package_sources.get(source).unwrap();
-
-                // Pull all of the possible candidate versions of the package we're
-                // looking for from the highest priority source which has them.
-                match registry.query(&dependency_request.package_req) {
-                    Ok(manifests) => Some((source, manifests)),
-                    Err(_) => None,
+        // Packages that the root manifest assigns to a folder on disk or a
+        // registry are only looked up there. Everything else looks through all
+        // our package sources in order of priority.
+        let name = dependency_request.package_req.name();
+        let assigned_source = match root_manifest.dependency_paths.get(name) {
+            Some(path) => Some([PackageSourceId::Local(path.clone())]),
+            None => root_manifest
+                .dependency_registries
+                .get(name)
+                .map(|registry| [PackageSourceId::for_registry(registry)]),
+        };
+
+        let sources_to_search = match &assigned_source {
+            Some(assigned_source) => &assigned_source[..],
+            None => &package_sources.source_order()[..],
+        };
+
+        let found = sources_to_search.iter().find_map(|source| {
+            let registry = package_sources.get(source)?;
+
+            log::trace!(
+                "Querying source {:?} for {}",
+                source,
+                dependency_request.package_req
+            );
+            trace.record(|| ResolutionEvent::Queried {
+                source: source.clone(),
+                req: dependency_request.package_req.clone(),
+            });
+
+            // Pull all of the possible candidate versions of the package we're
+            // looking for from the highest priority source which has them.
+            let prefetched = match source {
+                PackageSourceId::DefaultRegistry => {
+                    prefetched.remove(&dependency_request.package_req)
                 }
-            })
-            .ok_or_else(|| {
-                format_err!(
+                _ => None,
+            };
+            let result =
+                prefetched.unwrap_or_else(|| registry.query(&dependency_request.package_req));
+
+            match result {
+                Ok(manifests) => Some((source, manifests)),
+                Err(err) => {
+                    log::trace!("Source {:?} could not provide package: {:#}", source, err);
+                    None
+                }
+            }
+        });
+
+        let (source_registry, mut candidates) = match found {
+            Some(found) => found,
+            None => {
+                let problem = format_err!(
                     "Failed to find a source for {}",
                     dependency_request.package_req
-                )
-            })?;
+                );
+                report_problem(error_mode, &mut problems, problem)?;
+                continue 'outer;
+            }
+        };
 
         // Sort our candidate packages by descending version, so that we try the
         // highest versions first.
         //
         // Additionally, if there were any packages that were previously used by
         // our lockfile (in `try_to_use`), prioritize those first. This
-        // technique is the one used by Cargo.
+        // technique is the one used by Cargo. Versions picked to settle a
+        // conflict take priority over both. When asked to, versions the
+        // registry recommends come next, ahead of newer ones.
         candidates.sort_by(|a, b| {
+            let preferred_a = preferred.contains(&a.package_id());
+            let preferred_b = preferred.contains(&b.package_id());
             let contains_a = try_to_use.contains(&a.package_id());
             let contains_b = try_to_use.contains(&b.package_id());
 
-            match (contains_a, contains_b) {
+            match (preferred_a, preferred_b) {
                 (true, false) => Ordering::Less,
                 (false, true) => Ordering::Greater,
-                _ => b.package.version.cmp(&a.package.version),
+                _ => match (contains_a, contains_b) {
+                    (true, false) => Ordering::Less,
+                    (false, true) => Ordering::Greater,
+                    _ if prefer_recommended && a.recommended != b.recommended => {
+                        b.recommended.cmp(&a.recommended)
+                    }
+                    _ => b.package.version.cmp(&a.package.version),
+                },
             }
         });
 
-        let filtered_candidates = candidates.iter().filter(|candidate| {
-            Realm::is_dependency_valid(dependency_request.request_realm, candidate.package.realm)
+        trace.record(|| ResolutionEvent::Candidates {
+            req: dependency_request.package_req.clone(),
+            versions: candidates
+                .iter()
+                .map(|candidate| candidate.package.version.clone())
+                .collect(),
         });
 
+        let filtered_candidates = candidates
+            .iter()
+            .filter(|candidate| candidate.supports_realm(dependency_request.request_realm));
+
         let mut conflicting = Vec::new();
+        let mut violated_override = false;
 
         for candidate in filtered_candidates {
+            // An overridden candidate is only usable if it also satisfies the
+            // requirement that was originally requested.
+            if !requested_req.matches(&candidate.package.name, &candidate.package.version) {
+                trace.record(|| ResolutionEvent::Skipped {
+                    candidate: candidate.package_id(),
+                    reason: format!("does not satisfy {}", requested_req),
+                });
+                violated_override = true;
+                continue;
+            }
+
             // Conflicts occur if two packages are SemVer compatible. We choose
             // to only allow one compatible copy of a given package to prevent
             // common user errors.
 
-            let has_conflicting = matching_activated
-                .iter()
-                .any(|activated| compatible(&candidate.package.version, activated.version()));
+            let has_conflicting = matching_activated.iter().any(|activated| {
+                compatible(
+                    &candidate.package.version,
+                    activated.version(),
+                    root_manifest.package.zero_version_compat,
+                )
+            });
 
             if has_conflicting {
                 // This is a matching candidate, but it conflicts with a
@@ -223,6 +859,15 @@ pub fn resolve(
                 // happened. If there are no other matching versions that don't
                 // conflict, we'll report this in an error.
 
+                log::trace!(
+                    "Skipping {} as it conflicts with an already activated version",
+                    candidate.package_id()
+                );
+                trace.record(|| ResolutionEvent::Skipped {
+                    candidate: candidate.package_id(),
+                    reason: "conflicts with an already activated version".to_owned(),
+                });
+
                 conflicting.push(candidate.package_id());
                 continue;
             }
@@ -232,6 +877,18 @@ pub fn resolve(
                 candidate.package.version.clone(),
             );
 
+            log::debug!(
+                "Activated {} for {} requested by {}",
+                candidate_id,
+                dependency_request.package_req,
+                dependency_request.request_source
+            );
+            trace.record(|| ResolutionEvent::Chosen {
+                req: dependency_request.package_req.clone(),
+                chosen: candidate_id.clone(),
+                requested_by: dependency_request.request_source.clone(),
+            });
+
             resolve.activate(
                 dependency_request.request_source.clone(),
                 dependency_request.package_alias.to_owned(),
@@ -248,7 +905,34 @@ pub fn resolve(
                 },
             );
 
-            for (alias, req) in &candidate.dependencies {
+            if !candidate.peer_dependencies.is_empty() {
+                resolve
+                    .peer_dependencies
+                    .insert(candidate_id.clone(), candidate.peer_dependencies.clone());
+            }
+
+            requests.insert(
+                candidate_id.clone(),
+                vec![
+                    requested_req.clone(),
+                    dependency_request.package_req.clone(),
+                ],
+            );
+            requesters.insert(
+                candidate_id.clone(),
+                vec![(
+                    dependency_request.request_source.clone(),
+                    requested_req.clone(),
+                )],
+            );
+
+            // Only the root's optional dependencies can be turned on or off,
+            // so every other package gets the ones that are on by default.
+            let enabled = |(alias, _): &(&String, &PackageReq)| {
+                candidate.is_enabled_by_default(alias.as_str())
+            };
+
+            for (alias, req) in candidate.dependencies.iter().filter(enabled) {
                 packages_to_visit.push_back(DependencyRequest {
                     request_source: candidate_id.clone(),
                     request_realm: Realm::Shared,
@@ -258,7 +942,7 @@ pub fn resolve(
                 })
             }
 
-            for (alias, req) in &candidate.server_dependencies {
+            for (alias, req) in candidate.server_dependencies.iter().filter(enabled) {
                 packages_to_visit.push_back(DependencyRequest {
                     request_source: candidate_id.clone(),
                     request_realm: Realm::Server,
@@ -271,20 +955,135 @@ pub fn resolve(
             continue 'outer;
         }
 
-        if conflicting.is_empty() {
-            bail!(
-                "No packages were found that matched ({req_realm:?}) {req}.\nAre you sure this is \
-                 a {req_realm:?} dependency?",
-                req_realm = dependency_request.request_realm,
-                req = dependency_request.package_req,
-            );
+        let problem = if violated_override && conflicting.is_empty() {
+            format_err!(
+                "The override {override_req} does not satisfy {req}, which was requested by \
+                 {source}",
+                override_req = dependency_request.package_req,
+                req = requested_req,
+                source = dependency_request.request_source,
+            )
+        } else if conflicting.is_empty() && candidates.is_empty() {
+            // No version satisfied the requirement at all. Look up every
+            // version of the package so we can show what was available.
+            let available = package_sources
+                .get(source_registry)
+                .unwrap()
+                .all_versions(requested_req.name())
+                .unwrap_or_default();
+
+            let request = ConflictingRequirement {
+                requirement: requested_req.clone(),
+                requested_by: dependency_request.request_source.clone(),
+                path: resolve.path_to(
+                    &root_manifest.package_id(),
+                    &dependency_request.request_source,
+                ),
+                chosen: None,
+            };
+
+            ResolutionConflict::new(
+                requested_req.name().clone(),
+                vec![request],
+                &available,
+                explain_unsatisfiable(&dependency_request, &requested_req, &available),
+            )
+            .into()
+        } else if conflicting.is_empty() {
+            // A package that declared its realms gets a clearer error than
+            // one that's only in the wrong realm.
+            match candidates
+                .iter()
+                .find(|candidate| candidate.package.realms.is_some())
+            {
+                Some(candidate) => {
+                    let realms: Vec<String> = candidate
+                        .package
+                        .realms
+                        .iter()
+                        .flatten()
+                        .map(|realm| realm.to_string())
+                        .collect();
+
+                    format_err!(
+                        "{package} only supports being used from the {realms} realm, but \
+                         {source} requested it as a {req_realm} dependency",
+                        package = candidate.package_id(),
+                        realms = realms.join(" and "),
+                        source = dependency_request.request_source,
+                        req_realm = dependency_request.request_realm,
+                    )
+                }
+                None => format_err!(
+                    "No packages were found that matched ({req_realm:?}) {req}.\nAre you sure \
+                     this is a {req_realm:?} dependency?",
+                    req_realm = dependency_request.request_realm,
+                    req = dependency_request.package_req,
+                ),
+            }
         } else {
-            let conflicting_debug: Vec<_> = conflicting
-                .into_iter()
-                .map(|id| format!("{:?}", id))
-                .collect();
+            if root_manifest.package.conflict_policy == ConflictPolicy::Newest {
+                let upgrade = newest_satisfying_all(
+                    root_manifest.package.zero_version_compat,
+                    &dependency_request,
+                    &requested_req,
+                    &candidates,
+                    &matching_activated,
+                    &requests,
+                );
+
+                if let Some(upgrade) = upgrade {
+                    log::debug!(
+                        "{} requested by {} conflicts with an activated version, but {} \
+                         satisfies every request",
+                        dependency_request.package_req,
+                        dependency_request.request_source,
+                        upgrade
+                    );
+
+                    return Ok(Attempt::Upgrade(upgrade));
+                }
+            }
+
+            // The requests that picked the versions in the way come first,
+            // followed by the one that couldn't be satisfied alongside them.
+            let root_id = root_manifest.package_id();
+            let mut requirements = Vec::new();
+
+            for activated in &matching_activated {
+                let in_the_way = conflicting.iter().any(|candidate| {
+                    compatible(
+                        candidate.version(),
+                        activated.version(),
+                        root_manifest.package.zero_version_compat,
+                    )
+                });
+
+                if !in_the_way {
+                    continue;
+                }
+
+                for (requested_by, requirement) in requesters.get(activated).into_iter().flatten() {
+                    requirements.push(ConflictingRequirement {
+                        requirement: requirement.clone(),
+                        requested_by: requested_by.clone(),
+                        path: resolve.path_to(&root_id, requested_by),
+                        chosen: Some(activated.clone()),
+                    });
+                }
+            }
 
-            bail!(
+            requirements.push(ConflictingRequirement {
+                requirement: requested_req.clone(),
+                requested_by: dependency_request.request_source.clone(),
+                path: resolve.path_to(&root_id, &dependency_request.request_source),
+                chosen: None,
+            });
+
+            let conflicting_debug: Vec<_> =
+                conflicting.iter().map(|id| format!("{:?}", id)).collect();
+
+            let mut message = format!(
                 "All possible candidates for package {req} ({req_realm:?}) conflicted with other \
                  packages that were already installed. These packages were previously selected: \
                  {conflicting}",
@@ -292,24 +1091,192 @@ pub fn resolve(
                 req_realm = dependency_request.request_realm,
                 conflicting = conflicting_debug.join(", "),
             );
+
+            // Whether a version exists that every request allows is the
+            // difference between needing an override and needing a different
+            // version of one of the requesters.
+            let combined = requirements
+                .iter()
+                .try_fold(requested_req.clone(), |combined, request| {
+                    combined.intersect(&request.requirement)
+                });
+            match combined {
+                Some(combined) => message.push_str(&format!(
+                    "\nEvery request allows versions matching {}.",
+                    combined
+                )),
+                None => message.push_str("\nNo version satisfies every request."),
+            }
+
+            let available = package_sources
+                .get(source_registry)
+                .unwrap()
+                .all_versions(requested_req.name())
+                .unwrap_or_default();
+
+            ResolutionConflict::new(
+                requested_req.name().clone(),
+                requirements,
+                &available,
+                message,
+            )
+            .into()
+        };
+
+        report_problem(error_mode, &mut problems, problem)?;
+    }
+
+    match problems.len() {
+        0 => Ok(Attempt::Resolved(resolve)),
+        1 => Err(problems.remove(0)),
+        _ => Err(ResolutionErrors::new(problems).into()),
+    }
+}
+
+/// Fails with `problem` when failing fast, and otherwise holds onto it so that
+/// resolution can carry on.
+fn report_problem(
+    error_mode: ErrorMode,
+    problems: &mut Vec<anyhow::Error>,
+    problem: anyhow::Error,
+) -> anyhow::Result<()> {
+    match error_mode {
+        ErrorMode::FailFast => Err(problem),
+        ErrorMode::Collect => {
+            log::debug!(
+                "Skipping a dependency that can't be resolved: {:#}",
+                problem
+            );
+            problems.push(problem);
+            Ok(())
         }
     }
+}
+
+/// Finds the newest candidate that satisfies the given request along with
+/// every request that led to an already activated, conflicting version of the
+/// same package.
+fn newest_satisfying_all(
+    zero_version_compat: ZeroVersionCompat,
+    dependency_request: &DependencyRequest,
+    requested_req: &PackageReq,
+    candidates: &[Manifest],
+    matching_activated: &[PackageId],
+    requests: &BTreeMap<PackageId, Vec<PackageReq>>,
+) -> Option<PackageId> {
+    matching_activated.iter().find_map(|activated| {
+        let combined = requests.get(activated)?.iter().try_fold(
+            dependency_request.package_req.intersect(requested_req)?,
+            |combined, request| combined.intersect(request),
+        )?;
+
+        candidates
+            .iter()
+            .filter(|candidate| candidate.supports_realm(dependency_request.request_realm))
+            .map(|candidate| candidate.package_id())
+            .filter(|candidate_id| {
+                compatible(
+                    candidate_id.version(),
+                    activated.version(),
+                    zero_version_compat,
+                ) && combined.matches_id(candidate_id)
+            })
+            .max_by(|a, b| a.version().cmp(b.version()))
+    })
+}
+
+/// Builds an error message explaining why no version of a package satisfied a
+/// dependency request, listing the versions that were available instead.
+fn explain_unsatisfiable(
+    dependency_request: &DependencyRequest,
+    requested_req: &PackageReq,
+    available: &[Manifest],
+) -> String {
+    let mut lines = vec![format!(
+        "No version of {name} satisfies {req}, which was requested by {source}.",
+        name = requested_req.name(),
+        req = requested_req,
+        source = dependency_request.request_source,
+    )];
+
+    if requested_req != &dependency_request.package_req {
+        lines.push(format!(
+            "The requirement was narrowed to {} by an override in the root manifest.",
+            dependency_request.package_req
+        ));
+    }
+
+    if available.is_empty() {
+        lines.push(format!(
+            "There are no versions of {} available.",
+            requested_req.name()
+        ));
+    } else {
+        let mut versions: Vec<_> = available
+            .iter()
+            .map(|manifest| &manifest.package.version)
+            .collect();
+        versions.sort_by(|a, b| b.cmp(a));
+
+        let versions: Vec<_> = versions.iter().map(|version| version.to_string()).collect();
+        lines.push(format!("Available versions: {}", versions.join(", ")));
+    }
 
-    Ok(resolve)
+    lines.join("\n")
 }
 
-fn compatible(a: &Version, b: &Version) -> bool {
+pub(crate) fn compatible(a: &Version, b: &Version, zero_version_compat: ZeroVersionCompat) -> bool {
     if a == b {
         return true;
     }
 
     if a.major == 0 && b.major == 0 {
-        a.minor == b.minor
+        zero_version_compat == ZeroVersionCompat::Relaxed || a.minor == b.minor
     } else {
         a.major == b.major
     }
 }
 
+/// Queries the default registry for the root's direct dependencies all at
+/// once, keyed by the requirement each of them is resolved with. Dependencies
+/// that the root manifest assigns to another source are left out.
+fn prefetch_root_dependencies(
+    root_manifest: &Manifest,
+    requests: &VecDeque<DependencyRequest>,
+    package_sources: &PackageSourceMap,
+) -> HashMap<PackageReq, anyhow::Result<Vec<Manifest>>> {
+    let default_registry = match package_sources.get(&PackageSourceId::DefaultRegistry) {
+        Some(default_registry) => default_registry,
+        None => return HashMap::new(),
+    };
+
+    // Requirements are rewritten the same way the resolver does before
+    // querying for them.
+    let package_reqs: BTreeSet<PackageReq> = requests
+        .iter()
+        .map(|request| {
+            let package_req = request
+                .package_req
+                .with_zero_version_compat(root_manifest.package.zero_version_compat);
+
+            match root_manifest.overrides.get(package_req.name()) {
+                Some(override_req) => override_req.intersect(&package_req).unwrap_or(package_req),
+                None => package_req,
+            }
+        })
+        .filter(|package_req| {
+            let name = package_req.name();
+            !root_manifest.dependency_paths.contains_key(name)
+                && !root_manifest.dependency_registries.contains_key(name)
+        })
+        .collect();
+
+    let package_reqs: Vec<PackageReq> = package_reqs.into_iter().collect();
+    let results = default_registry.query_many(&package_reqs);
+
+    package_reqs.into_iter().zip(results).collect()
+}
+
 pub struct DependencyRequest {
     request_source: PackageId,
     request_realm: Realm,
@@ -322,9 +1289,41 @@ pub struct DependencyRequest {
 mod tests {
     use super::*;
 
-    use crate::{
-        package_name::PackageName, package_source::InMemoryRegistry, test_package::PackageBuilder,
-    };
+    use std::sync::Mutex;
+
+    use once_cell::sync::Lazy;
+
+    use crate::{package_source::InMemoryRegistry, test_package::PackageBuilder};
+
+    static CAPTURED_LOGS: Lazy<Mutex<Vec<String>>> = Lazy::new(Default::default);
+
+    /// A logger that records every message so that tests can make assertions
+    /// about the decisions made during resolution.
+    struct CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static LOGGER: CapturingLogger = CapturingLogger;
+
+        // Another test may have already installed the logger.
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+    }
 
     fn test_project(registry: InMemoryRegistry, package: PackageBuilder) -> anyhow::Result<()> {
         let package_sources = PackageSourceMap::new(Box::new(registry.source()));
@@ -367,18 +1366,113 @@ mod tests {
         test_project(registry, root)
     }
 
-    /// When there are shared dependencies, Wally should select the same
-    /// dependency. Here, A depends on B and C, which both in turn depend on D.
     #[test]
-    fn unified_dependencies() -> anyhow::Result<()> {
+    fn trace_two_levels() -> anyhow::Result<()> {
         let registry = InMemoryRegistry::new();
-        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
-        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@1.0.0"));
-        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
-
-        let root = PackageBuilder::new("biff/a@1.0.0")
-            .with_dep("B", "biff/b@1.0.0")
-            .with_dep("C", "biff/c@1.0.0");
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_dep("B", "biff/b@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/a@1.1.0").with_dep("B", "biff/b@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .into_manifest();
+
+        let mut trace = ResolutionTrace::new();
+        resolve_traced(
+            &manifest,
+            &Default::default(),
+            &package_sources,
+            ErrorMode::FailFast,
+            false,
+            &mut trace,
+        )?;
+
+        let req_a: PackageReq = "biff/a@1.0.0".parse()?;
+        let req_b: PackageReq = "biff/b@1.0.0".parse()?;
+        let a: PackageId = "biff/a@1.1.0".parse()?;
+        let b: PackageId = "biff/b@1.0.0".parse()?;
+
+        assert_eq!(
+            trace.events(),
+            [
+                ResolutionEvent::Requested {
+                    req: req_a.clone(),
+                    requested_by: manifest.package_id(),
+                    realm: Realm::Shared,
+                },
+                ResolutionEvent::Queried {
+                    source: PackageSourceId::DefaultRegistry,
+                    req: req_a.clone(),
+                },
+                ResolutionEvent::Candidates {
+                    req: req_a.clone(),
+                    versions: vec![Version::new(1, 1, 0), Version::new(1, 0, 0)],
+                },
+                ResolutionEvent::Chosen {
+                    req: req_a,
+                    chosen: a.clone(),
+                    requested_by: manifest.package_id(),
+                },
+                ResolutionEvent::Requested {
+                    req: req_b.clone(),
+                    requested_by: a.clone(),
+                    realm: Realm::Shared,
+                },
+                ResolutionEvent::Queried {
+                    source: PackageSourceId::DefaultRegistry,
+                    req: req_b.clone(),
+                },
+                ResolutionEvent::Candidates {
+                    req: req_b.clone(),
+                    versions: vec![Version::new(1, 0, 0)],
+                },
+                ResolutionEvent::Chosen {
+                    req: req_b,
+                    chosen: b,
+                    requested_by: a,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// A dependency without a version resolves to the newest version of the
+    /// package that isn't a pre-release.
+    #[test]
+    fn latest_skips_pre_release() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.2.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.3.0-beta.1"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/one-dependency@0.1.0")
+            .with_dep("Minimal", "biff/minimal")
+            .into_manifest();
+        let resolve = resolve(&manifest, &Default::default(), &package_sources)?;
+
+        let root: PackageId = "biff/one-dependency@0.1.0".parse()?;
+        assert_eq!(
+            resolve.shared_dependencies[&root]["Minimal"],
+            "biff/minimal@0.2.0".parse()?
+        );
+        Ok(())
+    }
+
+    /// When there are shared dependencies, Wally should select the same
+    /// dependency. Here, A depends on B and C, which both in turn depend on D.
+    #[test]
+    fn unified_dependencies() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0");
 
         test_project(registry, root)
     }
@@ -446,6 +1540,772 @@ mod tests {
         insta::assert_display_snapshot!(err);
     }
 
+    /// Overrides in the root manifest apply to every request for a package.
+    /// Here, B and C both depend on D, but the root forces D to exactly 1.1.0
+    /// even though a newer compatible version is available.
+    #[test]
+    fn override_satisfies_all() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.2.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0")
+            .with_override("biff/d@=1.1.0");
+
+        test_project(registry, root)
+    }
+
+    /// An override must still satisfy every package that requests it. C wants
+    /// D 2.x, so forcing D to 1.x should fail.
+    #[test]
+    fn fail_override_violates_requirement() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_dep("D", "biff/d@2.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/d@2.0.0"));
+
+        let root = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_dep("C", "biff/c@1.0.0")
+            .with_override("biff/d@1.1.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        insta::assert_display_snapshot!(err);
+    }
+
+    /// An override only narrows what each package requested, so B's request
+    /// for D 1.x still rules out D 2.0.0 when the override allows it.
+    #[test]
+    fn override_narrows_requirement() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("D", "biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/d@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/d@2.0.0"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_override("biff/d@>=1.0.0")
+            .into_manifest();
+
+        let mut trace = ResolutionTrace::new();
+        let resolve = resolve_traced(
+            &manifest,
+            &Default::default(),
+            &package_sources,
+            ErrorMode::FailFast,
+            false,
+            &mut trace,
+        )?;
+        assert!(resolve.activated.contains(&"biff/d@1.2.0".parse()?));
+
+        let replacement = trace.events().iter().find_map(|event| match event {
+            ResolutionEvent::Overridden { replacement, .. } => Some(replacement.to_string()),
+            _ => None,
+        });
+        assert_eq!(replacement.as_deref(), Some("biff/d@>=1.0.0, <2.0.0"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn logs_resolution_decisions() -> anyhow::Result<()> {
+        capture_logs();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+
+        let root =
+            PackageBuilder::new("biff/logged@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        resolve(root.manifest(), &Default::default(), &package_sources)?;
+
+        let logs = CAPTURED_LOGS.lock().unwrap();
+        assert!(
+            logs.iter().any(|line| line
+                == "Activated biff/minimal@0.1.0 for biff/minimal@>=0.1.0, <0.2.0 requested by \
+                    biff/logged@0.1.0"),
+            "Expected the activation of biff/minimal to be logged. Instead we got: {:#?}",
+            logs
+        );
+
+        Ok(())
+    }
+
+    /// When no version satisfies a requirement, the error should list the
+    /// versions that do exist.
+    #[test]
+    fn fail_unsatisfiable_lists_versions() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Minimal", "biff/minimal@2.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        insta::assert_display_snapshot!(err);
+    }
+
+    #[test]
+    fn collect_errors() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/server@1.0.0").with_realm(Realm::Server));
+        registry.publish(PackageBuilder::new("biff/shared@1.0.0"));
+
+        // Neither problem has anything to do with the other, or with Shared.
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Minimal", "biff/minimal@2.0.0")
+            .with_dep("Server", "biff/server@1.0.0")
+            .with_dep("Shared", "biff/shared@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolve_with = |error_mode| {
+            resolve_traced(
+                root.manifest(),
+                &Default::default(),
+                &package_sources,
+                error_mode,
+                false,
+                &mut ResolutionTrace::disabled(),
+            )
+            .unwrap_err()
+        };
+
+        let first = resolve_with(ErrorMode::FailFast);
+        assert!(first.downcast_ref::<ResolutionErrors>().is_none());
+
+        let err = resolve_with(ErrorMode::Collect);
+        let collected = err.downcast_ref::<ResolutionErrors>().unwrap();
+        assert_eq!(collected.problems().len(), 2);
+        assert_eq!(collected.conflicts().count(), 1);
+
+        let message = err.to_string();
+        assert!(
+            message.starts_with("Found 2 problems while resolving the package graph:"),
+            "Unexpected error: {}",
+            message
+        );
+        assert!(
+            message.contains(&first.to_string()),
+            "Unexpected error: {}",
+            message
+        );
+        assert!(
+            message.contains("Are you sure this is a Shared dependency?"),
+            "Unexpected error: {}",
+            message
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_single_version() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@2.0.0"));
+        registry.publish(PackageBuilder::new("biff/server@1.0.0").with_realm(Realm::Server));
+
+        // The project's own dependencies have nothing to do with it.
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Missing", "biff/missing@1.0.0");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let resolved = resolve_version(
+            root.manifest(),
+            &"biff/minimal@^1".parse()?,
+            &package_sources,
+        )?;
+        assert_eq!(resolved, "biff/minimal@1.2.0".parse()?);
+
+        // Packages from any realm can be looked up.
+        let resolved = resolve_version(
+            root.manifest(),
+            &"biff/server@1.0.0".parse()?,
+            &package_sources,
+        )?;
+        assert_eq!(resolved, "biff/server@1.0.0".parse()?);
+
+        let err = resolve_version(
+            root.manifest(),
+            &"biff/minimal@^3".parse()?,
+            &package_sources,
+        )
+        .unwrap_err();
+        assert!(
+            err.downcast_ref::<ResolutionConflict>().is_some(),
+            "{}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn prefer_recommended() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/slow@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/slow@1.1.0").with_recommended(true));
+        registry.publish(PackageBuilder::new("biff/slow@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/plain@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/plain@1.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Slow", "biff/slow@^1")
+            .with_dep("Plain", "biff/plain@^1");
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let resolve_with = |prefer_recommended| {
+            resolve_traced(
+                root.manifest(),
+                &Default::default(),
+                &package_sources,
+                ErrorMode::FailFast,
+                prefer_recommended,
+                &mut ResolutionTrace::disabled(),
+            )
+            .unwrap()
+            .activated
+        };
+
+        let newest = resolve_with(false);
+        assert!(newest.contains(&"biff/slow@1.2.0".parse()?));
+
+        // Packages without a recommended version still get their newest.
+        let recommended = resolve_with(true);
+        assert!(recommended.contains(&"biff/slow@1.1.0".parse()?));
+        assert!(!recommended.contains(&"biff/slow@1.2.0".parse()?));
+        assert!(recommended.contains(&"biff/plain@1.1.0".parse()?));
+
+        Ok(())
+    }
+
+    /// A and B both depend on C. The lockfile pins C to 1.0.0, but B needs at
+    /// least 1.1.0, which would also satisfy A.
+    fn near_conflict(conflict_policy: ConflictPolicy) -> anyhow::Result<Resolve> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_dep("C", "biff/c@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("C", "biff/c@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_conflict_policy(conflict_policy);
+
+        let try_to_use = vec!["biff/c@1.0.0".parse().unwrap()].into_iter().collect();
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        resolve(root.manifest(), &try_to_use, &package_sources)
+    }
+
+    #[test]
+    fn fail_conflict_policy_error() {
+        let err = near_conflict(ConflictPolicy::Error).unwrap_err();
+
+        assert!(
+            err.to_string().contains("conflicted with other packages"),
+            "Expected a conflict error, but got: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn conflict_policy_newest() -> anyhow::Result<()> {
+        let resolved = near_conflict(ConflictPolicy::Newest)?;
+
+        let c_versions: Vec<_> = resolved
+            .activated
+            .iter()
+            .filter(|id| id.name().name() == "c")
+            .map(|id| id.version().to_string())
+            .collect();
+
+        assert_eq!(c_versions, vec!["1.1.0"]);
+
+        Ok(())
+    }
+
+    /// The `newest` policy can't help when no version satisfies every request.
+    #[test]
+    fn fail_conflict_policy_newest_without_common_version() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/a@1.0.0").with_dep("C", "biff/c@=1.0.0"));
+        registry.publish(PackageBuilder::new("biff/b@1.0.0").with_dep("C", "biff/c@1.1.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/c@1.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0")
+            .with_conflict_policy(ConflictPolicy::Newest);
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+
+        assert!(
+            err.to_string().contains("conflicted with other packages"),
+            "Expected a conflict error, but got: {}",
+            err
+        );
+    }
+
+    /// The same manifest should resolve different dependencies depending on
+    /// which target is being installed.
+    fn resolve_zero_versions(zero_version_compat: ZeroVersionCompat) -> anyhow::Result<Resolve> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.2.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.3.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Minimal", "biff/minimal@0.2.0")
+            .with_zero_version_compat(zero_version_compat);
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        resolve(root.manifest(), &BTreeSet::new(), &package_sources)
+    }
+
+    #[test]
+    fn zero_version_compat() -> anyhow::Result<()> {
+        let strict = resolve_zero_versions(ZeroVersionCompat::Strict)?;
+        assert!(strict
+            .activated
+            .contains(&"biff/minimal@0.2.0".parse().unwrap()));
+
+        let relaxed = resolve_zero_versions(ZeroVersionCompat::Relaxed)?;
+        assert!(relaxed
+            .activated
+            .contains(&"biff/minimal@0.3.0".parse().unwrap()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_patches() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/signal@1.2.0"));
+        registry.publish(PackageBuilder::new("biff/signal@1.2.5"));
+        registry.publish(PackageBuilder::new("biff/signal@1.3.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Signal", "biff/signal@1.2")
+            .with_dep("Minimal", "biff/minimal@0.1.0");
+
+        // The lockfile pins the old patch, so it's kept.
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let mut locked = BTreeSet::new();
+        locked.insert("biff/signal@1.2.0".parse().unwrap());
+        locked.insert("biff/minimal@0.1.0".parse().unwrap());
+
+        let resolve = resolve(root.manifest(), &locked, &package_sources)?;
+        assert!(resolve
+            .activated
+            .contains(&"biff/signal@1.2.0".parse().unwrap()));
+
+        // 1.3.0 is allowed too, but only patches are suggested.
+        assert_eq!(
+            resolve.stale_patches(root.manifest(), &package_sources)?,
+            vec![
+                "biff/signal is locked to 1.2.0, but 1.2.5 is available and still satisfies \
+                 its requirements"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn target_dependencies() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/common@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/plugin-only@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/game-only@1.0.0"));
+
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/root"
+            version = "1.0.0"
+            registry = "test"
+            realm = "shared"
+            targets = ["plugin", "game"]
+
+            [dependencies]
+            Common = "biff/common@1.0.0"
+
+            [target.plugin.dependencies]
+            PluginOnly = "biff/plugin-only@1.0.0"
+
+            [target.game.dependencies]
+            GameOnly = "biff/game-only@1.0.0"
+            "#,
+        )?;
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolve_target = |target| -> anyhow::Result<Vec<String>> {
+            let manifest = manifest.for_target(Some(target))?;
+            let resolved = resolve(&manifest, &Default::default(), &package_sources)?;
+            Ok(resolved.activated.iter().map(|id| id.to_string()).collect())
+        };
+
+        assert_eq!(
+            resolve_target("plugin")?,
+            vec![
+                "biff/common@1.0.0",
+                "biff/plugin-only@1.0.0",
+                "biff/root@1.0.0"
+            ]
+        );
+        assert_eq!(
+            resolve_target("game")?,
+            vec![
+                "biff/common@1.0.0",
+                "biff/game-only@1.0.0",
+                "biff/root@1.0.0"
+            ]
+        );
+
+        Ok(())
+    }
+
+    /// Optional dependencies that are on by default are installed unless the
+    /// root turns them off, and ones that aren't are only installed when it
+    /// names them. Packages below the root always get their defaults.
+    #[test]
+    fn optional_dependencies() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/common@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/telemetry@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/debugger@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/colors@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/profiler@1.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/logger@1.0.0")
+                .with_optional_dep("Colors", "biff/colors@1.0.0", true)
+                .with_optional_dep("Profiler", "biff/profiler@1.0.0", false),
+        );
+
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/root"
+            version = "1.0.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Common = "biff/common@1.0.0"
+            Logger = "biff/logger@1.0.0"
+            Telemetry = { name = "biff/telemetry", version = "1.0.0", optional = true, default = true }
+            Debugger = { name = "biff/debugger", version = "1.0.0", optional = true }
+            "#,
+        )?;
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolve_features =
+            |no_default_features, features: &[&str]| -> anyhow::Result<Vec<String>> {
+                let features: Vec<String> = features.iter().map(|f| f.to_string()).collect();
+                let manifest = manifest.with_features(no_default_features, &features)?;
+                let resolved = resolve(&manifest, &Default::default(), &package_sources)?;
+                Ok(resolved.activated.iter().map(|id| id.to_string()).collect())
+            };
+
+        assert_eq!(
+            resolve_features(false, &[])?,
+            vec![
+                "biff/colors@1.0.0",
+                "biff/common@1.0.0",
+                "biff/logger@1.0.0",
+                "biff/root@1.0.0",
+                "biff/telemetry@1.0.0"
+            ]
+        );
+        assert_eq!(
+            resolve_features(true, &[])?,
+            vec![
+                "biff/colors@1.0.0",
+                "biff/common@1.0.0",
+                "biff/logger@1.0.0",
+                "biff/root@1.0.0"
+            ]
+        );
+        assert_eq!(
+            resolve_features(true, &["Telemetry"])?,
+            vec![
+                "biff/colors@1.0.0",
+                "biff/common@1.0.0",
+                "biff/logger@1.0.0",
+                "biff/root@1.0.0",
+                "biff/telemetry@1.0.0"
+            ]
+        );
+        assert_eq!(
+            resolve_features(false, &["Debugger"])?,
+            vec![
+                "biff/colors@1.0.0",
+                "biff/common@1.0.0",
+                "biff/debugger@1.0.0",
+                "biff/logger@1.0.0",
+                "biff/root@1.0.0",
+                "biff/telemetry@1.0.0"
+            ]
+        );
+
+        let err = resolve_features(false, &["Logger"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "biff/root does not have an optional dependency named `Logger`"
+        );
+
+        Ok(())
+    }
+
+    /// A server package that declared it's also safe for the shared realm
+    /// can be a shared dependency.
+    #[test]
+    fn declared_realm_supported() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(
+            PackageBuilder::new("biff/both@1.0.0")
+                .with_realm(Realm::Server)
+                .with_realms(&[Realm::Shared, Realm::Server]),
+        );
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Both", "biff/both@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+
+        let activated: Vec<String> = resolved.activated.iter().map(|id| id.to_string()).collect();
+        assert_eq!(activated, vec!["biff/both@1.0.0", "biff/root@1.0.0"]);
+        assert_eq!(
+            resolved.metadata[&"biff/both@1.0.0".parse()?].origin_realm,
+            Realm::Shared
+        );
+
+        Ok(())
+    }
+
+    /// A shared package that declared it's only for the shared realm can't be
+    /// a server dependency, even though shared packages usually can be.
+    #[test]
+    fn declared_realm_unsupported() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/client@1.0.0").with_realms(&[Realm::Shared]));
+
+        let root =
+            PackageBuilder::new("biff/root@1.0.0").with_server_dep("Client", "biff/client@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "biff/client@1.0.0 only supports being used from the shared realm, but \
+             biff/root@1.0.0 requested it as a server dependency"
+        );
+    }
+
+    /// Versions a requirement excludes are skipped, while the rest of its
+    /// range is still used.
+    #[test]
+    fn excluded_versions() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/flaky@1.3.1"));
+        registry.publish(PackageBuilder::new("biff/flaky@1.3.2"));
+
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/root"
+            version = "1.0.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Flaky = { name = "biff/flaky", version = "^1", exclude = ["1.3.2"] }
+            "#,
+        )?;
+
+        let resolve_flaky = || -> anyhow::Result<Vec<String>> {
+            let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+            let resolved = resolve(&manifest, &Default::default(), &package_sources)?;
+            Ok(resolved.activated.iter().map(|id| id.to_string()).collect())
+        };
+
+        assert_eq!(
+            resolve_flaky()?,
+            vec!["biff/flaky@1.3.1", "biff/root@1.0.0"]
+        );
+
+        registry.publish(PackageBuilder::new("biff/flaky@1.3.3"));
+        assert_eq!(
+            resolve_flaky()?,
+            vec!["biff/flaky@1.3.3", "biff/root@1.0.0"]
+        );
+
+        Ok(())
+    }
+
+    /// A graph that can't be satisfied fails with a conflict that lists every
+    /// request for the package, who made it and how they're reached, and
+    /// which versions there were to pick from.
+    #[test]
+    fn conflict_report() -> anyhow::Result<()> {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/shared@1.0.0"));
+        registry.publish(PackageBuilder::new("biff/shared@1.1.0"));
+        registry
+            .publish(PackageBuilder::new("biff/a@1.0.0").with_dep("Shared", "biff/shared@=1.0.0"));
+        registry
+            .publish(PackageBuilder::new("biff/b@1.0.0").with_dep("Shared", "biff/shared@=1.1.0"));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0");
+
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+        let err = resolve(root.manifest(), &Default::default(), &package_sources).unwrap_err();
+        let conflict = err.downcast_ref::<ResolutionConflict>().unwrap();
+
+        assert_eq!(
+            serde_json::to_value(conflict)?,
+            serde_json::json!({
+                "package": "biff/shared",
+                "requirements": [
+                    {
+                        "requirement": "biff/shared@=1.0.0",
+                        "requested_by": "biff/a@1.0.0",
+                        "path": ["biff/root@1.0.0", "biff/a@1.0.0"],
+                        "chosen": "biff/shared@1.0.0",
+                    },
+                    {
+                        "requirement": "biff/shared@=1.1.0",
+                        "requested_by": "biff/b@1.0.0",
+                        "path": ["biff/root@1.0.0", "biff/b@1.0.0"],
+                    },
+                ],
+                "available": ["1.1.0", "1.0.0"],
+            })
+        );
+
+        // The conflict still reads as the usual error.
+        assert!(err
+            .to_string()
+            .starts_with("All possible candidates for package biff/shared@=1.1.0"));
+        assert!(err
+            .to_string()
+            .ends_with("No version satisfies every request."));
+
+        Ok(())
+    }
+
+    /// A dependency assigned to a registry should only be looked up there,
+    /// while everything else still comes from the default registry.
+    #[test]
+    fn dependency_registry() -> anyhow::Result<()> {
+        let default_registry = InMemoryRegistry::new();
+        default_registry.publish(PackageBuilder::new("biff/minimal@1.0.0"));
+        default_registry.publish(PackageBuilder::new("biff/internal@1.0.0"));
+
+        let internal_registry = InMemoryRegistry::new();
+        internal_registry.publish(PackageBuilder::new("biff/internal@1.1.0"));
+
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/root"
+            version = "1.0.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Minimal = "biff/minimal@1.0.0"
+            Internal = { name = "biff/internal", version = "1.0.0", registry = "internal" }
+            "#,
+        )?;
+
+        let internal_source = PackageSourceId::Git("internal".to_owned());
+        let mut package_sources = PackageSourceMap::new(Box::new(default_registry.source()));
+        package_sources.add_source(
+            internal_source.clone(),
+            Box::new(internal_registry.source()),
+        );
+
+        let resolved = resolve(&manifest, &Default::default(), &package_sources)?;
+
+        let internal: PackageId = "biff/internal@1.1.0".parse()?;
+        let minimal: PackageId = "biff/minimal@1.0.0".parse()?;
+        assert_eq!(
+            resolved.metadata[&internal].source_registry,
+            internal_source
+        );
+        assert_eq!(
+            resolved.metadata[&minimal].source_registry,
+            PackageSourceId::DefaultRegistry
+        );
+
+        Ok(())
+    }
+
+    /// A dependency assigned to a sparse registry is looked up under the ID of
+    /// an HTTP registry, the same one its source is added to the map with.
+    #[test]
+    fn sparse_dependency_registry() -> anyhow::Result<()> {
+        let default_registry = InMemoryRegistry::new();
+        let internal_registry = InMemoryRegistry::new();
+        internal_registry.publish(PackageBuilder::new("biff/internal@1.0.0"));
+
+        let manifest = Manifest::from_slice(
+            br#"
+            [package]
+            name = "biff/root"
+            version = "1.0.0"
+            registry = "test"
+            realm = "shared"
+
+            [dependencies]
+            Internal = { name = "biff/internal", version = "1.0.0", registry = "sparse+https://internal.example.com" }
+            "#,
+        )?;
+
+        let internal_source = PackageSourceId::for_registry("sparse+https://internal.example.com");
+        assert_eq!(
+            internal_source,
+            PackageSourceId::Http("sparse+https://internal.example.com".to_owned())
+        );
+
+        let mut package_sources = PackageSourceMap::new(Box::new(default_registry.source()));
+        package_sources.add_source(
+            internal_source.clone(),
+            Box::new(internal_registry.source()),
+        );
+
+        let resolved = resolve(&manifest, &Default::default(), &package_sources)?;
+
+        let internal: PackageId = "biff/internal@1.0.0".parse()?;
+        assert_eq!(
+            resolved.metadata[&internal].source_registry,
+            internal_source
+        );
+
+        Ok(())
+    }
+
     /// Tests the simple one dependency case, except that a new version of the
     /// dependency will be published after the initial resolve. By persisting
     /// the set of activated packages from the initial install, we signal that
@@ -498,4 +2358,70 @@ mod tests {
 
         Ok(())
     }
+
+    fn plugin_registry() -> InMemoryRegistry {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/host@1.0.0"));
+        registry.publish(
+            PackageBuilder::new("biff/plugin@1.0.0").with_peer_dep("Host", "biff/host@^1.0.0"),
+        );
+        registry
+    }
+
+    #[test]
+    fn peer_dependency_satisfied() -> anyhow::Result<()> {
+        let package_sources = PackageSourceMap::new(Box::new(plugin_registry().source()));
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("Host", "biff/host@1.0.0")
+            .with_dep("Plugin", "biff/plugin@1.0.0");
+
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+
+        assert!(resolved.check_peer_dependencies(true)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn peer_dependency_unmet() -> anyhow::Result<()> {
+        let package_sources = PackageSourceMap::new(Box::new(plugin_registry().source()));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Plugin", "biff/plugin@1.0.0");
+
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+
+        // Peer dependencies are never installed for the package that has them.
+        let host: PackageId = "biff/host@1.0.0".parse().unwrap();
+        assert!(!resolved.activated.contains(&host));
+
+        assert_eq!(
+            resolved.check_peer_dependencies(false)?,
+            vec![
+                "biff/plugin@1.0.0 has a peer dependency on biff/host@^1.0.0, but no package \
+                 that depends on it provides a satisfying version"
+                    .to_owned()
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn peer_dependency_unmet_strict() -> anyhow::Result<()> {
+        let package_sources = PackageSourceMap::new(Box::new(plugin_registry().source()));
+
+        let root = PackageBuilder::new("biff/root@1.0.0").with_dep("Plugin", "biff/plugin@1.0.0");
+
+        let resolved = resolve(root.manifest(), &Default::default(), &package_sources)?;
+        let err = resolved.check_peer_dependencies(true).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "biff/plugin@1.0.0 has a peer dependency on biff/host@^1.0.0, but no package that \
+             depends on it provides a satisfying version"
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,327 @@
+//! Remembers the last resolution of a project so that installing again with
+//! nothing changed doesn't need to solve the package graph again.
+//!
+//! Resolutions are keyed by a hash of the project's manifest and configured
+//! package sources, so editing either one invalidates the cache.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_source::{PackageSourceId, PackageSourceMap};
+use crate::resolution::{resolve_traced, ErrorMode, Resolve};
+use crate::resolution_trace::ResolutionTrace;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Serialize)]
+struct CacheKey<'a> {
+    wally_version: &'static str,
+    project_path: &'a Path,
+    manifest: &'a Manifest,
+    dependency_registries: &'a BTreeMap<PackageName, String>,
+    sources: Vec<String>,
+    index_rev: Option<String>,
+    prefer_recommended: bool,
+}
+
+/// The package graph returned by `resolve_cached`.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub resolve: Resolve,
+
+    /// Whether the graph came from the cache instead of being solved.
+    pub from_cache: bool,
+}
+
+impl Resolution {
+    fn solved(resolve: Resolve) -> Self {
+        Self {
+            resolve,
+            from_cache: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedResolve {
+    resolve: Resolve,
+}
+
+/// Works out where the cached resolution for this project lives, or returns
+/// `None` if its resolution can't be cached.
+fn cache_path(
+    cache_dir: &Path,
+    project_path: &Path,
+    manifest: &Manifest,
+    package_sources: &PackageSourceMap,
+    prefer_recommended: bool,
+) -> anyhow::Result<Option<PathBuf>> {
+    // Path dependencies can change without the root manifest changing, so
+    // there's nothing we could key their resolution on.
+    if !manifest.dependency_paths.is_empty() {
+        return Ok(None);
+    }
+
+    let project_path = fs_err::canonicalize(project_path)?;
+    let key = CacheKey {
+        wally_version: VERSION,
+        project_path: &project_path,
+        manifest,
+        dependency_registries: &manifest.dependency_registries,
+        sources: package_sources
+            .source_order()
+            .iter()
+            .map(|source| source_key(source, manifest))
+            .collect(),
+        index_rev: package_sources.index_rev()?,
+        prefer_recommended,
+    };
+
+    let hash = blake3::hash(&serde_json::to_vec(&key)?);
+    let file_name = format!("{}.json", hex::encode(&hash.as_bytes()[..16]));
+
+    Ok(Some(cache_dir.join(file_name)))
+}
+
+/// Where a package source's packages come from, which is what a cached
+/// resolution depends on.
+fn source_key(source: &PackageSourceId, manifest: &Manifest) -> String {
+    match source {
+        PackageSourceId::DefaultRegistry => format!("registry:{}", manifest.package.registry),
+        PackageSourceId::Git(url) => format!("git:{}", url),
+        PackageSourceId::Http(url) => format!("http:{}", url),
+        PackageSourceId::Path(path) => format!("path:{}", path.display()),
+        PackageSourceId::Local(path) => format!("local:{}", path.display()),
+    }
+}
+
+/// Resolves the package graph of `manifest` like `resolve`, but reuses the
+/// resolution cached in `cache_dir` by an earlier call when the manifest and
+/// sources haven't changed since, and the packages in `try_to_use` are still
+/// exactly the ones it picked. `prefer_recommended` is passed on to
+/// `resolve_traced`.
+///
+/// Without a `cache_dir` the graph is always solved. Problems reading or
+/// writing the cache are never fatal either; the graph is solved instead.
+pub fn resolve_cached(
+    cache_dir: Option<&Path>,
+    project_path: &Path,
+    manifest: &Manifest,
+    try_to_use: &BTreeSet<PackageId>,
+    package_sources: &PackageSourceMap,
+    prefer_recommended: bool,
+) -> anyhow::Result<Resolution> {
+    let resolve = || {
+        resolve_traced(
+            manifest,
+            try_to_use,
+            package_sources,
+            ErrorMode::FailFast,
+            prefer_recommended,
+            &mut ResolutionTrace::disabled(),
+        )
+    };
+
+    let cache_dir = match cache_dir {
+        Some(cache_dir) => cache_dir,
+        None => return resolve().map(Resolution::solved),
+    };
+
+    let path = match cache_path(
+        cache_dir,
+        project_path,
+        manifest,
+        package_sources,
+        prefer_recommended,
+    ) {
+        Ok(Some(path)) => path,
+        Ok(None) => return resolve().map(Resolution::solved),
+        Err(err) => {
+            log::debug!("Not caching resolution: {:#}", err);
+            return resolve().map(Resolution::solved);
+        }
+    };
+
+    if let Some(cached) = read_cached(&path) {
+        // The lockfile may have been changed by hand, or by Wally since this
+        // resolution was cached.
+        if &cached.activated == try_to_use {
+            log::debug!("Using cached resolution from {}", path.display());
+            return Ok(Resolution {
+                resolve: cached,
+                from_cache: true,
+            });
+        }
+    }
+
+    let resolved = resolve()?;
+
+    if let Err(err) = write_cached(&path, &resolved) {
+        log::debug!("Could not cache resolution: {:#}", err);
+    }
+
+    Ok(Resolution::solved(resolved))
+}
+
+fn read_cached(path: &Path) -> Option<Resolve> {
+    let contents = fs_err::read(path).ok()?;
+
+    match serde_json::from_slice::<CachedResolve>(&contents) {
+        Ok(cached) => Some(cached.resolve),
+        Err(err) => {
+            log::debug!("Ignoring unreadable cached resolution: {}", err);
+            None
+        }
+    }
+}
+
+fn write_cached(path: &Path, resolve: &Resolve) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs_err::create_dir_all(parent)?;
+    }
+
+    let cached = CachedResolve {
+        resolve: resolve.clone(),
+    };
+    fs_err::write(path, serde_json::to_vec(&cached)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::package_source::InMemoryRegistry;
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn unchanged_project_skips_solve() {
+        let cache_dir = tempdir().unwrap();
+        let project = tempdir().unwrap();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/one-dependency@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+
+        let first = resolve_cached(
+            Some(cache_dir.path()),
+            project.path(),
+            &manifest,
+            &BTreeSet::new(),
+            &package_sources,
+            false,
+        )
+        .unwrap();
+        let queries = registry.query_count();
+        assert!(queries > 0);
+        assert!(!first.from_cache);
+
+        // The second install tries to use what the first one locked.
+        let second = resolve_cached(
+            Some(cache_dir.path()),
+            project.path(),
+            &manifest,
+            &first.resolve.activated,
+            &package_sources,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(registry.query_count(), queries);
+        assert!(second.from_cache);
+        assert_eq!(second.resolve.activated, first.resolve.activated);
+    }
+
+    #[test]
+    fn no_cache_dir_always_solves() {
+        let project = tempdir().unwrap();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/one-dependency@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+
+        let first = resolve_cached(
+            None,
+            project.path(),
+            &manifest,
+            &BTreeSet::new(),
+            &package_sources,
+            false,
+        )
+        .unwrap();
+        let second = resolve_cached(
+            None,
+            project.path(),
+            &manifest,
+            &first.resolve.activated,
+            &package_sources,
+            false,
+        )
+        .unwrap();
+
+        assert!(!second.from_cache);
+        assert_eq!(second.resolve.activated, first.resolve.activated);
+    }
+
+    #[test]
+    fn manifest_edit_invalidates_cache() {
+        let cache_dir = tempdir().unwrap();
+        let project = tempdir().unwrap();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+        registry.publish(PackageBuilder::new("biff/other@0.1.0"));
+        let package_sources = PackageSourceMap::new(Box::new(registry.source()));
+
+        let manifest = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+        let first = resolve_cached(
+            Some(cache_dir.path()),
+            project.path(),
+            &manifest,
+            &BTreeSet::new(),
+            &package_sources,
+            false,
+        )
+        .unwrap();
+        let queries = registry.query_count();
+
+        let edited = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("Other", "biff/other@0.1.0")
+            .into_manifest();
+        let second = resolve_cached(
+            Some(cache_dir.path()),
+            project.path(),
+            &edited,
+            &first.resolve.activated,
+            &package_sources,
+            false,
+        )
+        .unwrap();
+
+        assert!(registry.query_count() > queries);
+        assert!(!second.from_cache);
+        assert!(second
+            .resolve
+            .activated
+            .contains(&"biff/other@0.1.0".parse().unwrap()));
+    }
+}
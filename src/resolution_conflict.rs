@@ -0,0 +1,122 @@
+//! Why resolution failed, in a form that tools can act on, printed by
+//! `wally install --format json` instead of leaving them to read the error
+//! message, along with the report of every problem resolution ran into when
+//! it's collecting them.
+
+use std::fmt;
+
+use semver::Version;
+use serde::Serialize;
+
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+
+/// A package that no version could be picked for. This is the error
+/// resolution fails with when the graph can't be satisfied, and it displays
+/// as the usual error message.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolutionConflict {
+    pub package: PackageName,
+
+    /// Every request for the package that couldn't be satisfied together,
+    /// ending with the request that failed.
+    pub requirements: Vec<ConflictingRequirement>,
+
+    /// Every version of the package that its source has, newest first.
+    pub available: Vec<Version>,
+
+    #[serde(skip)]
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConflictingRequirement {
+    pub requirement: PackageReq,
+    pub requested_by: PackageId,
+
+    /// How the root depends on the requester, starting with the root and
+    /// ending with the requester.
+    pub path: Vec<PackageId>,
+
+    /// The version that was already picked for this request, if there was
+    /// one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chosen: Option<PackageId>,
+}
+
+impl ResolutionConflict {
+    pub(crate) fn new(
+        package: PackageName,
+        requirements: Vec<ConflictingRequirement>,
+        available: &[Manifest],
+        message: String,
+    ) -> Self {
+        let mut available: Vec<Version> = available
+            .iter()
+            .map(|manifest| manifest.package.version.clone())
+            .collect();
+        available.sort_by(|a, b| b.cmp(a));
+        available.dedup();
+
+        Self {
+            package,
+            requirements,
+            available,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ResolutionConflict {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ResolutionConflict {}
+
+/// Every problem resolution ran into when it was asked to collect them
+/// instead of stopping at the first one. It displays as one numbered problem
+/// after another.
+#[derive(Debug)]
+pub struct ResolutionErrors {
+    problems: Vec<anyhow::Error>,
+}
+
+impl ResolutionErrors {
+    pub(crate) fn new(problems: Vec<anyhow::Error>) -> Self {
+        Self { problems }
+    }
+
+    pub fn problems(&self) -> &[anyhow::Error] {
+        &self.problems
+    }
+
+    /// The problems that were version conflicts, which have details that
+    /// tools can act on.
+    pub fn conflicts(&self) -> impl Iterator<Item = &ResolutionConflict> {
+        self.problems
+            .iter()
+            .filter_map(|problem| problem.downcast_ref::<ResolutionConflict>())
+    }
+}
+
+impl fmt::Display for ResolutionErrors {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "Found {} problems while resolving the package graph:",
+            self.problems.len()
+        )?;
+
+        for (number, problem) in self.problems.iter().enumerate() {
+            write!(formatter, "\n\n{}. {:#}", number + 1, problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ResolutionErrors {}
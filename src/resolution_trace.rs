@@ -0,0 +1,109 @@
+//! An ordered record of every decision the resolver makes, written out by
+//! `wally install --trace-resolution` to debug resolutions that turn out
+//! differently than expected.
+
+use std::path::Path;
+
+use anyhow::Context;
+use semver::Version;
+use serde::Serialize;
+
+use crate::manifest::Realm;
+use crate::package_id::PackageId;
+use crate::package_req::PackageReq;
+use crate::package_source::PackageSourceId;
+
+/// One decision made by the resolver.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum ResolutionEvent {
+    /// A dependency was taken off the queue to be resolved.
+    Requested {
+        req: PackageReq,
+        requested_by: PackageId,
+        realm: Realm,
+    },
+
+    /// An override from the root manifest replaced a requested requirement.
+    Overridden {
+        requested: PackageReq,
+        replacement: PackageReq,
+    },
+
+    /// A version that was already activated satisfied the request.
+    Reused { req: PackageReq, chosen: PackageId },
+
+    /// A package source was asked for versions of a package.
+    Queried {
+        source: PackageSourceId,
+        req: PackageReq,
+    },
+
+    /// The versions found for a request, in the order they'll be tried.
+    Candidates {
+        req: PackageReq,
+        versions: Vec<Version>,
+    },
+
+    /// A candidate was passed over.
+    Skipped {
+        candidate: PackageId,
+        reason: String,
+    },
+
+    /// A new version was activated for the request.
+    Chosen {
+        req: PackageReq,
+        chosen: PackageId,
+        requested_by: PackageId,
+    },
+
+    /// Resolution started over, preferring a version to settle a conflict.
+    Restarted { preferring: PackageId },
+}
+
+/// Collects resolver events when enabled. A disabled trace ignores everything
+/// recorded into it, so the resolver can record unconditionally.
+#[derive(Debug, Default)]
+pub struct ResolutionTrace {
+    enabled: bool,
+    events: Vec<ResolutionEvent>,
+}
+
+impl ResolutionTrace {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Records an event. The event is only built if the trace is enabled.
+    pub fn record(&mut self, event: impl FnOnce() -> ResolutionEvent) {
+        if self.enabled {
+            self.events.push(event());
+        }
+    }
+
+    pub fn events(&self) -> &[ResolutionEvent] {
+        &self.events
+    }
+
+    /// Writes the trace to `path` with one JSON object per line, in the order
+    /// the decisions were made.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let mut contents = String::new();
+
+        for event in &self.events {
+            contents.push_str(&serde_json::to_string(event)?);
+            contents.push('\n');
+        }
+
+        fs_err::write(path, contents)
+            .with_context(|| format!("could not write resolution trace to {}", path.display()))
+    }
+}
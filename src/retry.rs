@@ -0,0 +1,211 @@
+//! Retrying network operations that fail for reasons that might not last,
+//! like a registry that's briefly overloaded or a dropped connection.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How many times to try an operation and how long to wait between tries.
+/// Delays double after every attempt, starting at `base_delay` and never
+/// going past `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to try the operation in total, including the first try.
+    pub max_attempts: u32,
+
+    /// How long to wait before the first retry.
+    pub base_delay: Duration,
+
+    /// The longest to wait between two attempts.
+    pub max_delay: Duration,
+
+    /// Wait a random amount between half of each delay and all of it, so that
+    /// clients which failed at the same time don't all retry at the same time.
+    pub jitter: bool,
+}
+
+pub const DEFAULT_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(10),
+    jitter: true,
+};
+
+/// Why a single attempt at an operation failed.
+#[derive(Debug)]
+pub enum Failure {
+    /// Trying again might work, like after a timeout or a 503.
+    Transient(anyhow::Error),
+
+    /// Trying again would fail the same way, like after a 404.
+    Permanent(anyhow::Error),
+}
+
+impl RetryPolicy {
+    /// Tries operations once, for ones that aren't safe to repeat.
+    pub const NEVER: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        base_delay: Duration::from_secs(0),
+        max_delay: Duration::from_secs(0),
+        jitter: false,
+    };
+
+    /// How long to wait before the given retry, counting from 1. `random` is
+    /// between 0 and 1, and is only used with jitter.
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if self.jitter {
+            delay / 2 + delay.mul_f64(random.max(0.0).min(1.0) / 2.0)
+        } else {
+            delay
+        }
+    }
+
+    /// Runs `operation` until it succeeds, fails permanently, or runs out of
+    /// attempts, returning the last error. `sleep` is called with each delay
+    /// between attempts.
+    pub fn run<T>(
+        &self,
+        mut sleep: impl FnMut(Duration),
+        mut operation: impl FnMut() -> Result<T, Failure>,
+    ) -> anyhow::Result<T> {
+        let mut attempt = 1;
+
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(Failure::Permanent(err)) => return Err(err),
+                Err(Failure::Transient(err)) if attempt >= self.max_attempts => return Err(err),
+                Err(Failure::Transient(err)) => {
+                    let delay = self.delay(attempt, random());
+                    log::warn!(
+                        "Attempt {} of {} failed, retrying in {:?}: {:#}",
+                        attempt,
+                        self.max_attempts,
+                        delay,
+                        err
+                    );
+
+                    sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// A number between 0 and 1 that's different every time, which is plenty
+/// for spreading out retries.
+fn random() -> f64 {
+    let value = RandomState::new().build_hasher().finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::format_err;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: false,
+        }
+    }
+
+    #[test]
+    fn delays_double_up_to_the_limit() {
+        let policy = policy();
+        let delays: Vec<_> = (1..=4).map(|retry| policy.delay(retry, 0.5)).collect();
+
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(100),
+                Duration::from_millis(200),
+                Duration::from_millis(300),
+                Duration::from_millis(300),
+            ]
+        );
+
+        let jittered = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        assert_eq!(jittered.delay(2, 0.0), Duration::from_millis(100));
+        assert_eq!(jittered.delay(2, 1.0), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        let mut attempts = 0;
+        let mut slept = Vec::new();
+
+        let value = policy()
+            .run(
+                |delay| slept.push(delay),
+                || {
+                    attempts += 1;
+
+                    if attempts < 3 {
+                        Err(Failure::Transient(format_err!("503")))
+                    } else {
+                        Ok(attempts)
+                    }
+                },
+            )
+            .unwrap();
+
+        assert_eq!(value, 3);
+        assert_eq!(
+            slept,
+            vec![Duration::from_millis(100), Duration::from_millis(200)]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut attempts = 0;
+        let mut slept = Vec::new();
+
+        let err = policy()
+            .run(
+                |delay| slept.push(delay),
+                || -> Result<(), _> {
+                    attempts += 1;
+                    Err(Failure::Transient(format_err!("attempt {}", attempts)))
+                },
+            )
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "attempt 4");
+        assert_eq!(slept.len(), 3);
+    }
+
+    #[test]
+    fn permanent_failures_are_not_retried() {
+        let mut attempts = 0;
+
+        let err = policy()
+            .run(
+                |_| panic!("should not sleep"),
+                || -> Result<(), _> {
+                    attempts += 1;
+                    Err(Failure::Permanent(format_err!("404")))
+                },
+            )
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "404");
+        assert_eq!(attempts, 1);
+    }
+}
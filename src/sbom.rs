@@ -0,0 +1,384 @@
+//! Software bills of materials for a project's locked package graph, in the
+//! CycloneDX and SPDX JSON formats.
+
+use std::str::FromStr;
+
+use anyhow::{bail, format_err};
+use serde_json::{json, Value};
+
+use crate::checksum::{Checksum, ChecksumAlgorithm};
+use crate::lockfile::{LockPackage, Lockfile};
+use crate::manifest::Manifest;
+use crate::package_id::PackageId;
+use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProvider};
+use crate::warnings;
+
+/// What SPDX, and our CycloneDX output, say about information that's missing.
+const NO_ASSERTION: &str = "NOASSERTION";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbomFormat {
+    CycloneDx,
+    Spdx,
+}
+
+impl FromStr for SbomFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "cyclonedx" => Ok(SbomFormat::CycloneDx),
+            "spdx" => Ok(SbomFormat::Spdx),
+            _ => bail!(
+                "Unknown SBOM format `{}`. Expected cyclonedx or spdx",
+                value
+            ),
+        }
+    }
+}
+
+/// A package in the bill of materials.
+#[derive(Debug, Clone)]
+pub struct SbomPackage {
+    pub id: PackageId,
+    pub license: Option<String>,
+    pub checksum: Option<Checksum>,
+
+    /// Where the package came from, like the URL of its registry.
+    pub source: Option<String>,
+
+    pub dependencies: Vec<PackageId>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sbom {
+    pub root: SbomPackage,
+    pub packages: Vec<SbomPackage>,
+}
+
+impl Sbom {
+    /// Builds a bill of materials from the packages pinned in `lockfile`,
+    /// looking up each package's license from the source it comes from.
+    pub fn from_lockfile(
+        manifest: &Manifest,
+        lockfile: &Lockfile,
+        package_sources: &PackageSourceMap,
+    ) -> anyhow::Result<Self> {
+        let root_id = manifest.package_id();
+        let mut root = None;
+        let mut packages = Vec::new();
+
+        for lock_package in &lockfile.packages {
+            let lock_package = match lock_package {
+                LockPackage::Registry(lock_package) => lock_package,
+                LockPackage::Git(lock_package) => {
+                    warnings::warn(format!(
+                        "Leaving git package {} out of the SBOM, since git packages aren't \
+                         supported yet",
+                        lock_package.name
+                    ));
+                    continue;
+                }
+            };
+
+            let id = PackageId::new(lock_package.name.clone(), lock_package.version.clone());
+
+            let mut dependencies: Vec<PackageId> = lock_package
+                .dependencies
+                .iter()
+                .map(|(_, dependency)| dependency.clone())
+                .collect();
+            dependencies.sort();
+            dependencies.dedup();
+
+            if id == root_id {
+                root = Some(SbomPackage {
+                    id,
+                    license: manifest.package.license.clone(),
+                    checksum: None,
+                    source: None,
+                    dependencies,
+                });
+                continue;
+            }
+
+            let (source_id, found) = find_manifest(manifest, package_sources, &id)?;
+            let checksum = match &lock_package.checksum {
+                Some(checksum) => Some(checksum.parse()?),
+                None => None,
+            };
+
+            packages.push(SbomPackage {
+                id,
+                license: found.package.license,
+                checksum,
+                source: Some(source_url(manifest, &source_id)),
+                dependencies,
+            });
+        }
+
+        let root = root.ok_or_else(|| {
+            format_err!(
+                "The lockfile does not include {}. Try running wally install first.",
+                root_id
+            )
+        })?;
+
+        Ok(Self { root, packages })
+    }
+
+    pub fn to_format(&self, format: SbomFormat, created: &str) -> Value {
+        match format {
+            SbomFormat::CycloneDx => self.to_cyclonedx(),
+            SbomFormat::Spdx => self.to_spdx(created),
+        }
+    }
+
+    /// The bill of materials as a CycloneDX 1.4 document.
+    pub fn to_cyclonedx(&self) -> Value {
+        let component = |package: &SbomPackage, kind: &str| {
+            let mut component = json!({
+                "type": kind,
+                "bom-ref": package.id.to_string(),
+                "group": package.id.name().scope(),
+                "name": package.id.name().name(),
+                "version": package.id.version().to_string(),
+                "licenses": [match &package.license {
+                    Some(license) => json!({ "expression": license }),
+                    None => json!({ "license": { "name": NO_ASSERTION } }),
+                }],
+            });
+
+            if let Some(checksum) = &package.checksum {
+                component["hashes"] = json!([{
+                    "alg": cyclonedx_algorithm(checksum.algorithm()),
+                    "content": checksum.digest(),
+                }]);
+            }
+
+            if let Some(source) = &package.source {
+                component["externalReferences"] = json!([{
+                    "type": "distribution",
+                    "url": source,
+                }]);
+            }
+
+            component
+        };
+
+        let dependencies: Vec<Value> = self
+            .all_packages()
+            .map(|package| {
+                json!({
+                    "ref": package.id.to_string(),
+                    "dependsOn": package
+                        .dependencies
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "version": 1,
+            "metadata": {
+                "tools": [{ "name": "wally" }],
+                "component": component(&self.root, "application"),
+            },
+            "components": self
+                .packages
+                .iter()
+                .map(|package| component(package, "library"))
+                .collect::<Vec<_>>(),
+            "dependencies": dependencies,
+        })
+    }
+
+    /// The bill of materials as an SPDX 2.3 document, created at `created`,
+    /// which must be a timestamp like `2024-01-02T03:04:05Z`.
+    pub fn to_spdx(&self, created: &str) -> Value {
+        let packages: Vec<Value> = self
+            .all_packages()
+            .map(|package| {
+                let mut spdx_package = json!({
+                    "SPDXID": spdx_id(&package.id),
+                    "name": package.id.name().to_string(),
+                    "versionInfo": package.id.version().to_string(),
+                    "downloadLocation": package.source.as_deref().unwrap_or(NO_ASSERTION),
+                    "filesAnalyzed": false,
+                    "licenseConcluded": NO_ASSERTION,
+                    "licenseDeclared": package.license.as_deref().unwrap_or(NO_ASSERTION),
+                    "copyrightText": NO_ASSERTION,
+                });
+
+                if let Some(checksum) = &package.checksum {
+                    spdx_package["checksums"] = json!([{
+                        "algorithm": spdx_algorithm(checksum.algorithm()),
+                        "checksumValue": checksum.digest(),
+                    }]);
+                }
+
+                spdx_package
+            })
+            .collect();
+
+        let mut relationships = vec![json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": spdx_id(&self.root.id),
+        })];
+
+        for package in self.all_packages() {
+            for dependency in &package.dependencies {
+                relationships.push(json!({
+                    "spdxElementId": spdx_id(&package.id),
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": spdx_id(dependency),
+                }));
+            }
+        }
+
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.root.id.to_string(),
+            "documentNamespace": format!(
+                "https://spdx.org/spdxdocs/{}-{}",
+                spdx_id(&self.root.id).trim_start_matches("SPDXRef-"),
+                created
+            ),
+            "creationInfo": {
+                "created": created,
+                "creators": ["Tool: wally"],
+            },
+            "packages": packages,
+            "relationships": relationships,
+        })
+    }
+
+    fn all_packages(&self) -> impl Iterator<Item = &SbomPackage> {
+        Some(&self.root).into_iter().chain(&self.packages)
+    }
+}
+
+/// Finds the manifest of a locked package from the source it would be
+/// resolved from, following the sources the root manifest assigns to specific
+/// packages the same way resolution does.
+fn find_manifest(
+    manifest: &Manifest,
+    package_sources: &PackageSourceMap,
+    package_id: &PackageId,
+) -> anyhow::Result<(PackageSourceId, Manifest)> {
+    let name = package_id.name();
+    let assigned_source = match manifest.dependency_paths.get(name) {
+        Some(path) => Some(PackageSourceId::Local(path.clone())),
+        None => manifest
+            .dependency_registries
+            .get(name)
+            .map(|registry| PackageSourceId::for_registry(registry)),
+    };
+
+    let sources_to_search = match &assigned_source {
+        Some(assigned_source) => std::slice::from_ref(assigned_source),
+        None => &package_sources.source_order()[..],
+    };
+
+    for source_id in sources_to_search {
+        let source = match package_sources.get(source_id) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        if let Some(found) = source.get(package_id)? {
+            return Ok((source_id.clone(), found));
+        }
+    }
+
+    bail!("Could not find {} in any package source", package_id)
+}
+
+fn source_url(manifest: &Manifest, source_id: &PackageSourceId) -> String {
+    match source_id {
+        PackageSourceId::DefaultRegistry => manifest.package.registry.clone(),
+        PackageSourceId::Git(url) | PackageSourceId::Http(url) => url.clone(),
+        PackageSourceId::Path(path) | PackageSourceId::Local(path) => path.display().to_string(),
+    }
+}
+
+/// An SPDX identifier for a package, which may only contain letters, numbers,
+/// `.`, and `-`.
+fn spdx_id(package_id: &PackageId) -> String {
+    let id: String = package_id
+        .to_string()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    format!("SPDXRef-Package-{}", id)
+}
+
+fn cyclonedx_algorithm(algorithm: ChecksumAlgorithm) -> &'static str {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => "SHA-256",
+    }
+}
+
+fn spdx_algorithm(algorithm: ChecksumAlgorithm) -> &'static str {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => "SHA256",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn small_graph() -> Sbom {
+        let root: PackageId = "biff/root@1.0.0".parse().unwrap();
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        Sbom {
+            root: SbomPackage {
+                id: root,
+                license: Some("MIT".to_owned()),
+                checksum: None,
+                source: None,
+                dependencies: vec![minimal.clone()],
+            },
+            packages: vec![SbomPackage {
+                id: minimal,
+                license: None,
+                checksum: Some(Checksum::compute(b"hello")),
+                source: Some("https://github.com/UpliftGames/wally-test-index".to_owned()),
+                dependencies: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn cyclonedx() {
+        let document = small_graph().to_format(SbomFormat::CycloneDx, "2024-01-02T03:04:05Z");
+
+        insta::assert_snapshot!(
+            "cyclonedx",
+            serde_json::to_string_pretty(&document).unwrap()
+        );
+    }
+
+    #[test]
+    fn spdx() {
+        let document = small_graph().to_format(SbomFormat::Spdx, "2024-01-02T03:04:05Z");
+
+        insta::assert_snapshot!("spdx", serde_json::to_string_pretty(&document).unwrap());
+    }
+}
@@ -8,7 +8,7 @@ use std::{
 use zip::write::{FileOptions, ZipWriter};
 
 use crate::{
-    manifest::{Manifest, Package, Realm},
+    manifest::{ConflictPolicy, Manifest, Package, Realm, ZeroVersionCompat},
     package_contents::PackageContents,
     package_id::PackageId,
     package_req::PackageReq,
@@ -33,17 +33,40 @@ impl PackageBuilder {
                 version,
                 registry: String::new(),
                 realm: Realm::Shared,
+                realms: None,
+                default_realm: None,
+                default_scope: None,
                 description: None,
                 license: None,
                 authors: Vec::new(),
+                repository: None,
                 include: Vec::new(),
                 exclude: Vec::new(),
                 private: false,
+                conflict_policy: Default::default(),
+                zero_version_compat: Default::default(),
+                targets: Vec::new(),
+                lockfile_max_age: None,
+                temp_dir: None,
             },
             place: Default::default(),
             dependencies: Default::default(),
             server_dependencies: Default::default(),
             dev_dependencies: Default::default(),
+            build_dependencies: Default::default(),
+            peer_dependencies: Default::default(),
+            overrides: Default::default(),
+            target: Default::default(),
+            license_policy: Default::default(),
+            scripts: Default::default(),
+            optional_dependencies: Default::default(),
+            dependency_registries: Default::default(),
+            dependency_paths: Default::default(),
+            dependency_destinations: Default::default(),
+            dependency_checksums: Default::default(),
+            extracted_size: None,
+            checksum: None,
+            recommended: false,
         };
 
         Self {
@@ -57,6 +80,26 @@ impl PackageBuilder {
         self
     }
 
+    pub fn with_realms(mut self, realms: &[Realm]) -> Self {
+        self.manifest.package.realms = Some(realms.to_vec());
+        self
+    }
+
+    pub fn with_license<S: Into<String>>(mut self, license: S) -> Self {
+        self.manifest.package.license = Some(license.into());
+        self
+    }
+
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.manifest.package.conflict_policy = conflict_policy;
+        self
+    }
+
+    pub fn with_zero_version_compat(mut self, zero_version_compat: ZeroVersionCompat) -> Self {
+        self.manifest.package.zero_version_compat = zero_version_compat;
+        self
+    }
+
     pub fn with_dep<A, R>(mut self, alias: A, package_req: R) -> Self
     where
         A: Into<String>,
@@ -79,6 +122,40 @@ impl PackageBuilder {
         self
     }
 
+    pub fn with_optional_dep<A, R>(mut self, alias: A, package_req: R, default: bool) -> Self
+    where
+        A: Into<String>,
+        R: AsRef<str>,
+    {
+        let alias = alias.into();
+        let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
+
+        self.manifest.dependencies.insert(alias.clone(), req);
+        self.manifest.optional_dependencies.insert(alias, default);
+        self
+    }
+
+    pub fn with_peer_dep<A, R>(mut self, alias: A, package_req: R) -> Self
+    where
+        A: Into<String>,
+        R: AsRef<str>,
+    {
+        let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
+
+        self.manifest.peer_dependencies.insert(alias.into(), req);
+        self
+    }
+
+    pub fn with_override<R>(mut self, package_req: R) -> Self
+    where
+        R: AsRef<str>,
+    {
+        let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
+
+        self.manifest.overrides.insert(req.name().clone(), req);
+        self
+    }
+
     pub fn with_file<P, C>(mut self, path: P, contents: C) -> Self
     where
         P: Into<String>,
@@ -88,6 +165,16 @@ impl PackageBuilder {
         self
     }
 
+    pub fn with_recommended(mut self, recommended: bool) -> Self {
+        self.manifest.recommended = recommended;
+        self
+    }
+
+    pub fn with_extracted_size(mut self, extracted_size: u64) -> Self {
+        self.manifest.extracted_size = Some(extracted_size);
+        self
+    }
+
     pub fn into_manifest(self) -> Manifest {
         self.manifest
     }
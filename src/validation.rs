@@ -0,0 +1,354 @@
+//! Checks that a package's manifest is fit to be published. These are shared
+//! by `wally publish` and `wally validate-manifest`, so that registry CI sees
+//! exactly the problems a publish would run into.
+
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Context;
+use fs_err::File;
+use semver::Version;
+use serde::Serialize;
+
+use crate::license_policy::{LicensePolicy, UnlicensedPolicy};
+use crate::manifest::{load_flattened, Manifest, Realm, DEPENDENCY_TABLES, MANIFEST_FILE_NAME};
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+use crate::warnings;
+
+/// Something about a manifest that would stop it from being published.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    /// The key in the manifest that the problem is with, like `package.name`.
+    pub field: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub(crate) fn new<F: Into<String>, M: Into<String>>(field: F, message: M) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Checks the manifest of the project in `project_path`, returning every
+/// problem found rather than stopping at the first one.
+///
+/// If `index_path` points at a checkout of a package index, the package's
+/// version must not already be in it.
+pub fn validate_manifest(
+    project_path: &Path,
+    index_path: Option<&Path>,
+) -> anyhow::Result<Vec<Problem>> {
+    let file_path = project_path.join(MANIFEST_FILE_NAME);
+
+    let value = match load_flattened(&file_path, &mut Vec::new()) {
+        Ok(value) => value,
+        Err(err) => return Ok(vec![Problem::new("", format!("{:#}", err))]),
+    };
+
+    let mut problems = Vec::new();
+
+    let package = match value.get("package").and_then(toml::Value::as_table) {
+        Some(package) => package,
+        None => {
+            problems.push(Problem::new("package", "the [package] table is missing"));
+            return Ok(problems);
+        }
+    };
+
+    let string_field =
+        |problems: &mut Vec<Problem>, key: &str| match package.get(key).map(toml::Value::as_str) {
+            Some(Some(value)) if !value.trim().is_empty() => Some(value.to_owned()),
+            Some(Some(_)) => {
+                problems.push(Problem::new(
+                    format!("package.{}", key),
+                    "must not be empty",
+                ));
+                None
+            }
+            Some(None) => {
+                problems.push(Problem::new(format!("package.{}", key), "must be a string"));
+                None
+            }
+            None => {
+                problems.push(Problem::new(format!("package.{}", key), "is required"));
+                None
+            }
+        };
+
+    let name = string_field(&mut problems, "name").and_then(|name| {
+        name.parse::<PackageName>()
+            .map_err(|err| problems.push(Problem::new("package.name", err.to_string())))
+            .ok()
+    });
+
+    let version = string_field(&mut problems, "version").and_then(|version| {
+        Version::parse(&version)
+            .map_err(|err| {
+                problems.push(Problem::new(
+                    "package.version",
+                    format!("'{}' is not a valid version: {}", version, err),
+                ))
+            })
+            .ok()
+    });
+
+    string_field(&mut problems, "registry");
+
+    // A missing license is up to the package's own license policy, just like
+    // it is for the packages it depends on.
+    if package.contains_key("license") {
+        string_field(&mut problems, "license");
+    } else {
+        let unlicensed = value
+            .get("license-policy")
+            .and_then(|policy| policy.clone().try_into::<LicensePolicy>().ok())
+            .unwrap_or_default()
+            .unlicensed;
+
+        match unlicensed {
+            UnlicensedPolicy::Allow => {}
+            UnlicensedPolicy::Warn => warnings::warn(
+                "package.license is missing, so users of this package can't tell its terms",
+            ),
+            UnlicensedPolicy::Deny => problems.push(Problem::new(
+                "package.license",
+                "is required by the license policy",
+            )),
+        }
+    }
+
+    if let Some(realm) = package.get("realm") {
+        if realm.clone().try_into::<Realm>().is_err() {
+            problems.push(Problem::new(
+                "package.realm",
+                format!("{} is not a realm", realm),
+            ));
+        }
+    } else {
+        problems.push(Problem::new("package.realm", "is required"));
+    }
+
+    if package.get("private").and_then(toml::Value::as_bool) == Some(true) {
+        problems.push(Problem::new(
+            "package.private",
+            "private packages cannot be published",
+        ));
+    }
+
+    check_dependencies(&value, &mut problems);
+
+    if let (Some(index_path), Some(name), Some(version)) = (index_path, name, version) {
+        if is_in_index(index_path, &name, &version)? {
+            problems.push(Problem::new(
+                "package.version",
+                format!("{}@{} is already in the index", name, version),
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Checks every dependency table, including the ones within targets.
+fn check_dependencies(value: &toml::Value, problems: &mut Vec<Problem>) {
+    let mut tables = Vec::new();
+
+    for key in DEPENDENCY_TABLES {
+        if let Some(table) = value.get(key) {
+            tables.push((key.to_string(), table));
+        }
+    }
+
+    if let Some(targets) = value.get("target").and_then(toml::Value::as_table) {
+        for (target, target_table) in targets {
+            for key in DEPENDENCY_TABLES {
+                if let Some(table) = target_table.get(key) {
+                    tables.push((format!("target.{}.{}", target, key), table));
+                }
+            }
+        }
+    }
+
+    for (table_name, table) in tables {
+        let table = match table.as_table() {
+            Some(table) => table,
+            None => {
+                problems.push(Problem::new(table_name, "must be a table"));
+                continue;
+            }
+        };
+
+        for (alias, dependency) in table {
+            let field = format!("{}.{}", table_name, alias);
+
+            match dependency {
+                toml::Value::String(package_req) => {
+                    if let Err(err) = package_req.parse::<PackageReq>() {
+                        problems.push(Problem::new(field, err.to_string()));
+                    }
+                }
+                toml::Value::Table(spec) if spec.contains_key("path") => {
+                    problems.push(Problem::new(field, "path dependencies cannot be published"));
+                }
+                toml::Value::Table(spec) if spec.contains_key("git") => {
+                    problems.push(Problem::new(field, "git dependencies cannot be published"));
+                }
+                toml::Value::Table(_) => {}
+                _ => problems.push(Problem::new(
+                    field,
+                    "must be a package requirement or a table",
+                )),
+            }
+        }
+    }
+}
+
+/// Tells whether the given version of a package is already in the package
+/// index checked out at `index_path`.
+fn is_in_index(index_path: &Path, name: &PackageName, version: &Version) -> anyhow::Result<bool> {
+    let package_path = index_path.join(name.scope()).join(name.name());
+
+    if !package_path.exists() {
+        return Ok(false);
+    }
+
+    let file = BufReader::new(File::open(&package_path)?);
+
+    for manifest in serde_json::Deserializer::from_reader(file).into_iter::<Manifest>() {
+        let manifest = manifest
+            .with_context(|| format!("could not parse package index entry for {}", name))?;
+
+        if &manifest.package.version == version {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn clean_manifest() {
+        let project = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/minimal"
+        ));
+
+        assert_eq!(validate_manifest(project, None).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let dir = tempdir().unwrap();
+
+        fs_err::write(
+            dir.path().join(MANIFEST_FILE_NAME),
+            r#"
+            [package]
+            name = "Biff/Bad Name"
+            version = "one"
+            registry = "https://github.com/UpliftGames/wally-index"
+            realm = "galaxy"
+            private = true
+
+            [dependencies]
+            Roact = "roblox/roact@not-a-version"
+            Local = { name = "biff/local", version = "0.1.0", path = "../local" }
+            "#,
+        )
+        .unwrap();
+
+        let problems = validate_manifest(dir.path(), None).unwrap();
+        let fields: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.field.as_str())
+            .collect();
+
+        assert_eq!(
+            fields,
+            [
+                "package.name",
+                "package.version",
+                "package.realm",
+                "package.private",
+                "dependencies.Local",
+                "dependencies.Roact",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_license_follows_the_license_policy() {
+        let validate = |policy: &str| {
+            let dir = tempdir().unwrap();
+
+            fs_err::write(
+                dir.path().join(MANIFEST_FILE_NAME),
+                format!(
+                    r#"
+                    [package]
+                    name = "biff/unlicensed"
+                    version = "0.1.0"
+                    registry = "https://github.com/UpliftGames/wally-index"
+                    realm = "shared"
+
+                    {}
+                    "#,
+                    policy
+                ),
+            )
+            .unwrap();
+
+            let collector = warnings::Collector::default();
+            let problems = collector.scope(|| validate_manifest(dir.path(), None).unwrap());
+            (problems, collector.take().len())
+        };
+
+        assert_eq!(validate(""), (Vec::new(), 1));
+        assert_eq!(
+            validate("[license-policy]\nunlicensed = \"allow\""),
+            (Vec::new(), 0)
+        );
+        assert_eq!(
+            validate("[license-policy]\nunlicensed = \"deny\""),
+            (
+                vec![Problem::new(
+                    "package.license",
+                    "is required by the license policy"
+                )],
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn version_already_in_index() {
+        let project = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-projects/minimal"
+        ));
+        let index = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test-registries/primary-registry/index"
+        ));
+
+        let problems = validate_manifest(project, Some(index)).unwrap();
+
+        assert_eq!(
+            problems,
+            [Problem::new(
+                "package.version",
+                "biff/minimal@0.1.0 is already in the index"
+            )]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Where warnings end up. Everything a command warns the user about is
+//! reported here, so that `--deny-warnings` can fail the command if there
+//! were any, no matter where they came from.
+//!
+//! Each command collects its own warnings: `Args::run` sets up a [`Collector`]
+//! for the thread running the command, and work handed to other threads runs
+//! in the same collector's scope. Commands running at the same time, like
+//! tests do, never see each other's warnings.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static CURRENT: RefCell<Option<Collector>> = RefCell::new(None);
+}
+
+/// The warnings of one command.
+#[derive(Clone, Debug, Default)]
+pub struct Collector {
+    warnings: Arc<Mutex<Vec<String>>>,
+}
+
+impl Collector {
+    /// The collector warnings on this thread go to, if there is one.
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Runs `work`, recording warnings on this thread with this collector.
+    /// Whichever collector was there before is put back afterwards.
+    pub fn scope<T>(&self, work: impl FnOnce() -> T) -> T {
+        struct Restore(Option<Collector>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                CURRENT.with(|current| *current.borrow_mut() = previous);
+            }
+        }
+
+        let _restore = Restore(CURRENT.with(|current| current.replace(Some(self.clone()))));
+        work()
+    }
+
+    /// Every warning recorded since the last call, oldest first.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+}
+
+/// Logs a warning and records it.
+pub fn warn(message: impl Into<String>) {
+    let message = message.into();
+    log::warn!("{}", message);
+    record(message);
+}
+
+/// Records a warning that the caller shows the user itself, like the ones
+/// printed above a progress bar. Outside of a collector's scope, the warning
+/// has already been shown and there's nothing to fail, so it's dropped.
+pub fn record(message: impl Into<String>) {
+    if let Some(collector) = Collector::current() {
+        collector.warnings.lock().unwrap().push(message.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn concurrent_collectors_keep_their_own_warnings() {
+        let barrier = Arc::new(Barrier::new(2));
+
+        let handles: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    let collector = Collector::default();
+                    collector.scope(|| {
+                        record(format!("{} before", name));
+                        barrier.wait();
+                        record(format!("{} after", name));
+                        barrier.wait();
+                    });
+                    collector.take()
+                })
+            })
+            .collect();
+
+        let warnings: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+
+        assert_eq!(warnings[0], vec!["first before", "first after"]);
+        assert_eq!(warnings[1], vec!["second before", "second after"]);
+    }
+
+    #[test]
+    fn scopes_are_restored() {
+        let outer = Collector::default();
+        let inner = Collector::default();
+
+        outer.scope(|| {
+            record("outer");
+            inner.scope(|| record("inner"));
+            record("outer again");
+        });
+        record("nowhere");
+
+        assert!(Collector::current().is_none());
+        assert_eq!(outer.take(), vec!["outer", "outer again"]);
+        assert_eq!(inner.take(), vec!["inner"]);
+    }
+}
@@ -0,0 +1,5 @@
+local Shared = require(script.Parent.Shared)
+
+return function()
+	print(Shared)
+end
@@ -0,0 +1 @@
+return "who knows"
\ No newline at end of file
@@ -0,0 +1 @@
+return nil
\ No newline at end of file
@@ -0,0 +1,5 @@
+local OneDependency = require(script.Parent.OneDependency)
+
+return function()
+	OneDependency()
+end
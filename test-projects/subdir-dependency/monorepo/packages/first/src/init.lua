@@ -0,0 +1 @@
+return "first"
\ No newline at end of file
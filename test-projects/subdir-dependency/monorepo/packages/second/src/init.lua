@@ -0,0 +1 @@
+return "second"
\ No newline at end of file
@@ -0,0 +1,5 @@
+local Second = require(script.Parent.Second)
+
+return function()
+	print(Second)
+end
\ No newline at end of file
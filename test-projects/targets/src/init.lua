@@ -0,0 +1,5 @@
+local Minimal = require(script.Parent.Minimal)
+
+return function()
+	print(Minimal)
+end
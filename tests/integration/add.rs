@@ -0,0 +1,80 @@
+use crate::temp_project::TempProject;
+use fs_err as fs;
+use insta::assert_snapshot;
+use libwally::{AddSubcommand, Args, GlobalOptions, Subcommand};
+use std::path::Path;
+
+#[test]
+fn add_dependency() {
+    let source_project = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects/add"));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_add(&project, "biff/minimal@0.1").unwrap();
+
+    let manifest = fs::read_to_string(project.path().join("wally.toml")).unwrap();
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert_snapshot!("add_dependency_manifest", manifest);
+    assert_snapshot!("add_dependency_lockfile", lockfile);
+
+    assert!(project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua")
+        .is_file());
+
+    // Adding a package that's already a dependency updates it in place.
+    run_add(&project, "biff/minimal").unwrap();
+
+    assert_eq!(
+        fs::read_to_string(project.path().join("wally.toml")).unwrap(),
+        manifest
+    );
+}
+
+#[test]
+fn add_keeps_comments() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/add-comments"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_add(&project, "biff/one-dependency").unwrap();
+
+    let manifest = fs::read_to_string(project.path().join("wally.toml")).unwrap();
+    assert!(manifest.contains(concat!(
+        "[server-dependencies]\n",
+        "# Held back until the new API lands.\n",
+        "Minimal = \"biff/minimal@0.1\" # Don't bump without checking Minimal's changelog.\n",
+    )));
+    assert!(manifest.contains("OneDependency = \"biff/one-dependency@0.1.0\""));
+    assert!(manifest.contains("# Nothing below here should move."));
+
+    // Updating a requirement keeps the comment after it.
+    run_add(&project, "biff/minimal").unwrap();
+
+    let manifest = fs::read_to_string(project.path().join("wally.toml")).unwrap();
+    assert!(manifest.contains(concat!(
+        "# Held back until the new API lands.\n",
+        "Minimal = \"biff/minimal@0.1.0\" # Don't bump without checking Minimal's changelog.\n",
+    )));
+    assert!(manifest.contains("# Nothing below here should move."));
+}
+
+fn run_add(project: &TempProject, package: &str) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Add(AddSubcommand {
+            project_path: project.path().to_owned(),
+            package: package.to_owned(),
+            alias: None,
+            server: true,
+            dev: false,
+        }),
+    }
+    .run()
+}
@@ -0,0 +1,88 @@
+use super::temp_project::TempProject;
+use fs_err as fs;
+use libwally::{Args, CleanSubcommand, GlobalOptions, InstallSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn clean_only_managed_folders() {
+    let project = install_project("one-dependency");
+
+    // This folder shares a name with a Wally package folder but wasn't made by
+    // Wally, so it should survive a clean.
+    fs::create_dir(project.path().join("DevPackages")).unwrap();
+    fs::write(project.path().join("DevPackages/Keep.lua"), "return nil").unwrap();
+
+    run_clean(&project, false).unwrap();
+
+    assert!(!project.path().join("ServerPackages").exists());
+    assert!(project.path().join("DevPackages/Keep.lua").is_file());
+    assert!(project.path().join("src").is_dir());
+    assert!(project.path().join("wally.toml").is_file());
+    assert!(project.path().join("wally.lock").is_file());
+}
+
+#[test]
+fn clean_lockfile() {
+    let project = install_project("one-dependency");
+
+    run_clean(&project, true).unwrap();
+
+    assert!(!project.path().join("ServerPackages").exists());
+    assert!(!project.path().join("wally.lock").exists());
+    assert!(project.path().join("wally.toml").is_file());
+}
+
+fn install_project(name: &str) -> TempProject {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    project
+}
+
+fn run_clean(project: &TempProject, lockfile: bool) -> anyhow::Result<()> {
+    Args {
+        global: Default::default(),
+        subcommand: Subcommand::Clean(CleanSubcommand {
+            project_path: project.path().to_owned(),
+            lockfile,
+        }),
+    }
+    .run()
+}
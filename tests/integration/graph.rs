@@ -0,0 +1,65 @@
+use crate::temp_project::TempProject;
+use libwally::{Args, GlobalOptions, GraphSubcommand, InstallSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn graph_leaves_out_optional_dependencies_that_are_off() {
+    let source_project = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"))
+        .join("optional-dependency");
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let graph = GraphSubcommand {
+        project_path: project.path().to_owned(),
+        json: false,
+    }
+    .graph(&GlobalOptions {
+        test_registry: true,
+        ..Default::default()
+    })
+    .unwrap();
+
+    let nodes: Vec<String> = graph.nodes.iter().map(|node| node.id.to_string()).collect();
+    assert_eq!(
+        nodes,
+        vec![
+            "biff/minimal@0.1.0",
+            "biff/one-dependency@0.1.0",
+            "biff/optional-dependency@0.1.0",
+        ]
+    );
+}
@@ -0,0 +1,28 @@
+use crate::temp_project::TempProject;
+use fs_err as fs;
+use insta::assert_snapshot;
+use libwally::{Args, GlobalOptions, ImportSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn import_vendored_packages() {
+    let source_project = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects/import"));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Import(ImportSubcommand {
+            project_path: project.path().to_owned(),
+            source: project.path().join("VendoredPackages"),
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let manifest = fs::read_to_string(project.path().join("wally.toml")).unwrap();
+    assert_snapshot!(manifest);
+}
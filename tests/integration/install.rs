@@ -1,6 +1,13 @@
 use super::temp_project::TempProject;
+use super::util::read_bytes;
+use fs_err as fs;
+use libwally::checksum::Checksum;
+use libwally::lockfile::Lockfile;
+use libwally::package_id::PackageId;
 use libwally::{Args, GlobalOptions, InstallSubcommand, Subcommand};
+use std::ffi::OsStr;
 use std::path::Path;
+use structopt::StructOpt;
 
 #[test]
 fn minimal() {
@@ -42,6 +49,11 @@ fn cross_realm_explicit_dependency() {
     run_install_test("cross-realm-explicit-dependency");
 }
 
+#[test]
+fn build_dependency() {
+    run_install_test("build-dependency");
+}
+
 #[test]
 fn locked_pass() {
     let result = run_locked_install("diamond-graph/root/latest");
@@ -55,6 +67,1122 @@ fn locked_catches_dated_packages() {
     assert!(result.is_err(), "Should fail!");
 }
 
+#[test]
+fn reinstall_restores_corrupted_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_install(&project, false).unwrap();
+
+    let lockfile_path = project.path().join("wally.lock");
+    let lockfile = fs::read_to_string(&lockfile_path).unwrap();
+
+    let installed_file = project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua");
+    fs::write(&installed_file, "corrupted").unwrap();
+
+    run_install(&project, true).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&installed_file).unwrap(),
+        "return \"hey\"",
+        "The corrupted file should've been extracted again."
+    );
+    assert_eq!(
+        fs::read_to_string(&lockfile_path).unwrap(),
+        lockfile,
+        "Reinstalling shouldn't touch the lockfile."
+    );
+}
+
+#[test]
+fn deny_warnings_fails_on_deprecated_lockfile() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_install(&project, false).unwrap();
+
+    // Checksums without an algorithm prefix are the deprecated format.
+    let lockfile_path = project.path().join("wally.lock");
+    let lockfile = fs::read_to_string(&lockfile_path).unwrap();
+    fs::write(&lockfile_path, lockfile.replace("sha256:", "")).unwrap();
+
+    let err = Args {
+        global: GlobalOptions {
+            test_registry: true,
+            deny_warnings: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap_err()
+    .to_string();
+
+    assert!(err.contains("--deny-warnings"), "{}", err);
+    assert!(err.contains("deprecated"), "{}", err);
+}
+
+#[test]
+fn no_verify_installs_mismatched_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_install(&project, false).unwrap();
+
+    // Pretend the registry sent different contents than it did the first
+    // time.
+    let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+    let mut lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    let mut checksums = lockfile.checksums();
+    checksums.insert(
+        package_id.clone(),
+        Checksum::compute(b"tampered").to_string(),
+    );
+    lockfile.set_checksums(&checksums);
+    lockfile.save(project.path()).unwrap();
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+
+    let installed_file = project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua");
+    fs::remove_dir_all(project.path().join("ServerPackages")).unwrap();
+
+    let err = run_no_verify_install(&project, false, false)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.contains("do not match the recorded checksum"),
+        "{}",
+        err
+    );
+    assert!(!installed_file.exists());
+
+    // Warnings are denied so that they show up in the error, but the
+    // install itself goes through.
+    let err = run_no_verify_install(&project, true, true)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("--no-verify is set"), "{}", err);
+    assert!(
+        err.contains("It was installed anyway because of --no-verify"),
+        "{}",
+        err
+    );
+    assert_eq!(
+        fs::read_to_string(&installed_file).unwrap(),
+        "return \"hey\""
+    );
+    assert_eq!(
+        fs::read_to_string(project.path().join("wally.lock")).unwrap(),
+        lockfile,
+        "Installing with --no-verify shouldn't record anything in the lockfile."
+    );
+
+    // Without --deny-warnings, the same install succeeds.
+    fs::remove_dir_all(project.path().join("ServerPackages")).unwrap();
+    run_no_verify_install(&project, true, false).unwrap();
+    assert_eq!(
+        fs::read_to_string(&installed_file).unwrap(),
+        "return \"hey\""
+    );
+}
+
+#[test]
+fn pinned_checksums_only_apply_to_their_version() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/minimal"
+    ));
+    let project = TempProject::new(&source_project).unwrap();
+
+    // Direct dependency A pulls in indirect-dependency-a 0.1.x, while the
+    // project pins 0.2.0 of the same package.
+    let write_manifest = |checksum: &Checksum| {
+        let manifest = format!(
+            "[package]\n\
+             name = \"biff/minimal\"\n\
+             version = \"0.1.0\"\n\
+             license = \"MIT\"\n\
+             realm = \"server\"\n\
+             registry = \"test-registries/primary-registry\"\n\
+             \n\
+             [server-dependencies]\n\
+             A = \"diamond-graph/direct-dependency-a@0.1.0\"\n\
+             Indirect = {{ name = \"diamond-graph/indirect-dependency-a\", version = \"=0.2.0\", checksum = \"{}\" }}\n",
+            checksum
+        );
+        fs::write(project.path().join("wally.toml"), manifest).unwrap();
+    };
+
+    write_manifest(&Checksum::compute(b"tampered"));
+    let err = run_install(&project, false).unwrap_err();
+    let err = format!("{:#}", err);
+    assert!(
+        err.contains(
+            "The contents of diamond-graph/indirect-dependency-a@0.2.0 do not match the checksum \
+             pinned in the manifest"
+        ),
+        "{}",
+        err
+    );
+
+    let contents = fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-registries/primary-registry/contents/diamond-graph/indirect-dependency-a/0.2.0.zip"
+    ))
+    .unwrap();
+    write_manifest(&Checksum::compute(&contents));
+    run_install(&project, false).unwrap();
+
+    let index = project.path().join("ServerPackages/_Index");
+    assert!(index
+        .join("diamond-graph_indirect-dependency-a@0.2.0")
+        .is_dir());
+    assert!(index
+        .join("diamond-graph_indirect-dependency-a@0.1.1")
+        .is_dir());
+}
+
+/// Installs with `--no-verify` and `--deny-warnings` if they're set, and
+/// without them otherwise.
+fn run_no_verify_install(
+    project: &TempProject,
+    no_verify: bool,
+    deny_warnings: bool,
+) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            deny_warnings,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+}
+
+#[test]
+fn manifest_path() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    let manifest_path = project.path().join("generated.toml");
+    fs::rename(project.path().join("wally.toml"), &manifest_path).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            manifest_path: Some(manifest_path),
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            // The project is wherever the manifest is, not here.
+            project_path: project.path().join("elsewhere"),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    assert!(project.path().join("wally.lock").is_file());
+    assert!(project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal/init.lua")
+        .is_file());
+    assert!(!project.path().join("elsewhere").exists());
+}
+
+#[test]
+fn trace_resolution_flag() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    let trace_path = project.path().join("trace.jsonl");
+
+    let mut args = Args::from_iter(&[
+        OsStr::new("wally"),
+        OsStr::new("install"),
+        OsStr::new("--project-path"),
+        project.path().as_os_str(),
+        OsStr::new("--trace-resolution"),
+        trace_path.as_os_str(),
+    ]);
+    args.global.test_registry = true;
+
+    match &args.subcommand {
+        Subcommand::Install(install) => {
+            assert_eq!(
+                install.trace_resolution.as_deref(),
+                Some(trace_path.as_path())
+            )
+        }
+        subcommand => panic!("Expected an install, got {:?}", subcommand),
+    }
+
+    args.run().unwrap();
+    assert!(!fs::read_to_string(&trace_path).unwrap().is_empty());
+}
+
+/// The concurrency the command line defaults to is the same used everywhere
+/// else.
+#[test]
+fn concurrency_defaults() {
+    let args = Args::from_iter(&["wally", "install"]);
+    assert_eq!(
+        args.global.download_concurrency,
+        GlobalOptions::default().download_concurrency
+    );
+    assert_eq!(
+        args.global.extract_concurrency,
+        GlobalOptions::default().extract_concurrency
+    );
+}
+
+/// Installs built from `InstallSubcommand::default()` run the same install as
+/// `wally install` with no flags.
+#[test]
+fn install_defaults() {
+    match Args::from_iter(&["wally", "install"]).subcommand {
+        Subcommand::Install(install) => assert_eq!(
+            format!("{:?}", install),
+            format!("{:?}", InstallSubcommand::default())
+        ),
+        subcommand => panic!("Expected an install, got {:?}", subcommand),
+    }
+}
+
+#[test]
+fn path_dependency_subdir() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/subdir-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_install(&project, false).unwrap();
+
+    let index = project.path().join("Packages/_Index");
+    assert_eq!(
+        fs::read_to_string(index.join("biff_second@0.1.0/second/src/init.lua")).unwrap(),
+        "return \"second\"",
+        "The package in the subdir should've been installed."
+    );
+    assert!(
+        !index.join("biff_first@0.1.0").exists(),
+        "Other packages in the same folder shouldn't be installed."
+    );
+    assert!(project.path().join("Packages/Second.lua").is_file());
+}
+
+#[test]
+fn link_path_dependency() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/subdir-dependency"
+    ));
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: true,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let installed = project
+        .path()
+        .join("Packages/_Index/biff_second@0.1.0/second");
+    assert!(fs::symlink_metadata(&installed)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+
+    // Edits to the package show up without installing again.
+    fs::write(
+        project.path().join("monorepo/packages/second/src/init.lua"),
+        "return \"edited\"",
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read_to_string(installed.join("src/init.lua")).unwrap(),
+        "return \"edited\""
+    );
+
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert!(
+        lockfile.contains("link = true"),
+        "Expected the lockfile to record the link, but it is:\n{}",
+        lockfile
+    );
+}
+
+#[test]
+fn custom_destination() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/custom-destination"
+    ));
+    let project = TempProject::new(&source_project).unwrap();
+
+    run_install(&project, false).unwrap();
+
+    let dest = project.path().join("Plugins/Shared");
+    assert!(dest.join("src/init.lua").is_file());
+    assert!(
+        !project.path().join("Packages").exists(),
+        "A package with a destination shouldn't be linked from the package folders."
+    );
+
+    // Everything else is installed the usual way.
+    assert!(project.path().join("ServerPackages/Minimal.lua").is_file());
+    assert!(project
+        .path()
+        .join("ServerPackages/_Index/biff_minimal@0.1.0/minimal")
+        .is_dir());
+
+    // Installing again replaces the destination instead of adding to it.
+    fs::write(dest.join("stale.lua"), "return nil").unwrap();
+    run_install(&project, false).unwrap();
+    assert!(dest.join("src/init.lua").is_file());
+    assert!(!dest.join("stale.lua").exists());
+}
+
+#[test]
+fn pin_latest() {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects")).join("pin-latest");
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: true,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let manifest = fs::read_to_string(project.path().join("wally.toml")).unwrap();
+    assert!(
+        manifest.contains(r#"Minimal = "biff/minimal@0.1.0""#),
+        "Expected the dependency to be pinned, but the manifest is:\n{}",
+        manifest
+    );
+}
+
+#[test]
+fn install_report() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    let report_path = project.path().join("report.json");
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: Some(report_path.clone()),
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_path).unwrap()).unwrap();
+
+    assert_eq!(report["root"], "biff/one-dependency@0.1.0");
+    assert_eq!(
+        report["resolve"]["activated"],
+        serde_json::json!(["biff/minimal@0.1.0", "biff/one-dependency@0.1.0"])
+    );
+    assert_eq!(
+        report["resolve"]["server_dependencies"]["biff/one-dependency@0.1.0"],
+        serde_json::json!({ "Minimal": "biff/minimal@0.1.0" })
+    );
+    assert_eq!(
+        report["checksums"],
+        serde_json::json!({
+            "biff/minimal@0.1.0":
+                "sha256:3fbdca6265dee72fbf1601e4121167efd74c724791d515b1f0e0d32e44b86c04"
+        })
+    );
+    assert_eq!(
+        report["cache_hits"],
+        serde_json::json!({ "resolution": false, "packages": [] })
+    );
+    assert!(report["timings"]["resolve_ms"].is_u64());
+    assert!(report["timings"]["install_ms"].is_u64());
+}
+
+#[test]
+fn install_timings() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    let timings_path = project.path().join("timings.json");
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: Some(Some(timings_path.clone())),
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let timings: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&timings_path).unwrap()).unwrap();
+
+    for phase in &[
+        "index_update_ms",
+        "resolution_ms",
+        "download_ms",
+        "verification_ms",
+        "extraction_ms",
+        "link_generation_ms",
+    ] {
+        assert!(
+            timings["phases"][phase].is_u64(),
+            "{} is missing from {}",
+            phase,
+            timings
+        );
+    }
+
+    let mut slowest: Vec<&str> = timings["slowest"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|package| package["package"].as_str().unwrap())
+        .collect();
+    slowest.sort_unstable();
+    assert_eq!(
+        slowest,
+        vec!["biff/minimal@0.1.0", "biff/one-dependency@0.1.0"]
+    );
+}
+
+#[test]
+fn locked_transitive() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    run_locked_transitive_install(&project, None).unwrap();
+
+    let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    let graph_hash = lockfile.graph_hash();
+    assert!(graph_hash.starts_with("sha256:"), "{}", graph_hash);
+
+    // Resolving and installing again ends up with exactly the same graph.
+    run_locked_transitive_install(&project, Some(graph_hash.clone())).unwrap();
+
+    let stale = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+    let err = run_locked_transitive_install(&project, Some(stale.to_owned())).unwrap_err();
+    assert!(
+        err.to_string().starts_with(&format!(
+            "The installed graph hash is {}, but --locked-transitive expected {}.",
+            graph_hash, stale
+        )),
+        "Unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn locked_transitive_mismatch_writes_nothing() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/one-dependency"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    run_locked_transitive_install(&project, None).unwrap();
+
+    // An install would otherwise rewrite the lockfile in its canonical form
+    // and extract the package again.
+    let lockfile_path = project.path().join("wally.lock");
+    let lockfile = format!(
+        "{}\n# Edited by hand\n",
+        fs::read_to_string(&lockfile_path).unwrap()
+    );
+    fs::write(&lockfile_path, &lockfile).unwrap();
+    fs::remove_dir_all(project.path().join("ServerPackages")).unwrap();
+
+    let stale = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+    run_locked_transitive_install(&project, Some(stale.to_owned())).unwrap_err();
+
+    assert_eq!(fs::read_to_string(&lockfile_path).unwrap(), lockfile);
+    assert!(!project.path().join("ServerPackages").exists());
+}
+
+fn run_locked_transitive_install(
+    project: &TempProject,
+    locked_transitive: Option<String>,
+) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive,
+        }),
+    }
+    .run()
+}
+
+#[test]
+fn only_subtree() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/latest"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: Some("diamond-graph/direct-dependency-a".parse().unwrap()),
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let packages = project.path().join("ServerPackages");
+    let mut installed: Vec<String> = fs::read_dir(packages.join("_Index"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    installed.sort();
+
+    assert_eq!(
+        installed,
+        vec![
+            "diamond-graph_direct-dependency-a@0.1.1",
+            "diamond-graph_indirect-dependency-a@0.1.1",
+        ]
+    );
+    assert!(packages.join("A.lua").is_file());
+    assert!(!packages.join("B.lua").exists());
+
+    // The lockfile still pins the whole graph.
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert!(lockfile.contains("diamond-graph/direct-dependency-b"));
+    assert!(lockfile.contains("0.2.1"));
+}
+
+#[test]
+fn lockfile_only() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    ));
+
+    let installed = TempProject::new(&source_project).unwrap();
+    run_install(&installed, false).unwrap();
+    let lockfile = fs::read_to_string(installed.path().join("wally.lock")).unwrap();
+
+    // A deploy with nothing but the lockfile.
+    let deployed = tempfile::tempdir().unwrap();
+    fs::write(deployed.path().join("wally.lock"), &lockfile).unwrap();
+
+    run_lockfile_install(deployed.path(), None).unwrap();
+
+    assert!(!deployed.path().join("wally.toml").exists());
+    assert_eq!(
+        fs::read_to_string(deployed.path().join("wally.lock")).unwrap(),
+        lockfile,
+        "Installing from the lockfile shouldn't touch it."
+    );
+    assert_eq!(
+        read_bytes(&deployed.path().join("ServerPackages")).unwrap(),
+        read_bytes(&installed.path().join("ServerPackages")).unwrap(),
+        "The packages should be installed exactly like a normal install would."
+    );
+}
+
+#[test]
+fn lockfile_only_without_lockfile() {
+    let deployed = tempfile::tempdir().unwrap();
+
+    let err = run_lockfile_install(deployed.path(), None).unwrap_err();
+    assert!(
+        err.to_string().contains("there is no lockfile"),
+        "Unexpected error: {}",
+        err
+    );
+}
+
+#[test]
+fn from_lockfile() {
+    let teammate = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/transitive-dependency"
+    )))
+    .unwrap();
+    run_install(&teammate, false).unwrap();
+
+    let project = TempProject::new(Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/minimal"
+    )))
+    .unwrap();
+    let own_lockfile = project.path().join("wally.lock");
+    let had_lockfile = own_lockfile.exists();
+
+    run_lockfile_install(project.path(), Some(&teammate.path().join("wally.lock"))).unwrap();
+
+    assert_eq!(
+        read_bytes(&project.path().join("ServerPackages")).unwrap(),
+        read_bytes(&teammate.path().join("ServerPackages")).unwrap(),
+        "The packages should match the project the lockfile came from."
+    );
+    assert_eq!(
+        own_lockfile.exists(),
+        had_lockfile,
+        "The project's own lockfile shouldn't be touched."
+    );
+}
+
+#[test]
+fn target_lockfiles() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/targets"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+    let index = project.path().join("ServerPackages/_Index");
+
+    run_target_install(&project, None).unwrap();
+    run_target_install(&project, Some("plugin")).unwrap();
+    assert!(index.join("biff_one-dependency@0.1.0").exists());
+    assert!(!index.join("biff_transitive-dependency@0.1.0").exists());
+
+    run_target_install(&project, Some("game")).unwrap();
+    assert!(index.join("biff_one-dependency@0.1.0").exists());
+    assert!(index.join("biff_transitive-dependency@0.1.0").exists());
+
+    let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    let pins = |target| {
+        let mut ids: Vec<_> = lockfile
+            .for_target(target)
+            .as_ids()
+            .map(|id| id.to_string())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(pins(None), vec!["biff/minimal@0.1.0", "biff/targets@0.1.0"]);
+    assert_eq!(
+        pins(Some("plugin")),
+        vec![
+            "biff/minimal@0.1.0",
+            "biff/one-dependency@0.1.0",
+            "biff/targets@0.1.0"
+        ]
+    );
+    assert_eq!(
+        pins(Some("game")),
+        vec![
+            "biff/minimal@0.1.0",
+            "biff/one-dependency@0.1.0",
+            "biff/targets@0.1.0",
+            "biff/transitive-dependency@0.1.0"
+        ]
+    );
+
+    // Pins every target shares with the default graph are only stored once.
+    for target_lock in lockfile.targets.values() {
+        assert_eq!(
+            target_lock.shared,
+            vec!["biff/minimal@0.1.0".parse().unwrap()]
+        );
+    }
+
+    // Installing for a target again sticks to that target's pins.
+    run_target_install(&project, Some("plugin")).unwrap();
+    assert!(!index.join("biff_transitive-dependency@0.1.0").exists());
+    assert_eq!(
+        Lockfile::load(project.path())
+            .unwrap()
+            .unwrap()
+            .to_canonical_string()
+            .unwrap(),
+        lockfile.to_canonical_string().unwrap(),
+        "Installing for a target that's already locked shouldn't change the lockfile."
+    );
+}
+
+fn run_target_install(project: &TempProject, target: Option<&str>) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: target.map(str::to_owned),
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+}
+
+/// Installs from the project's own lockfile with `--lockfile-only`, or from
+/// `from_lockfile` if it's given.
+fn run_lockfile_install(project_path: &Path, from_lockfile: Option<&Path>) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project_path.to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: from_lockfile.is_none(),
+            from_lockfile: from_lockfile.map(Path::to_owned),
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+}
+
+fn run_install(project: &TempProject, reinstall: bool) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+}
+
 fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
     let source_project =
         Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
@@ -69,6 +1197,28 @@ fn run_locked_install(name: &str) -> Result<(), anyhow::Error> {
         subcommand: Subcommand::Install(InstallSubcommand {
             project_path: project.path().to_owned(),
             locked: true,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
         }),
     }
     .run()
@@ -88,6 +1238,28 @@ fn run_install_test(name: &str) -> TempProject {
         subcommand: Subcommand::Install(InstallSubcommand {
             project_path: project.path().to_owned(),
             locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
         }),
     };
 
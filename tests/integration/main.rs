@@ -2,7 +2,18 @@
 mod util;
 mod temp_project;
 
+mod add;
+mod clean;
+mod graph;
+mod import;
 mod install;
+mod migrate_lockfile;
+mod prune_cache;
 mod publish;
 mod read_projects;
+mod relock;
+mod remove;
+mod reproducible;
+mod resolve;
+mod run;
 mod update;
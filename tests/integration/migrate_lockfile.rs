@@ -0,0 +1,94 @@
+use super::temp_project::TempProject;
+use fs_err as fs;
+use libwally::lockfile::Lockfile;
+use libwally::{Args, GlobalOptions, MigrateLockfileSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn migrate_unversioned_lockfile() {
+    let project = legacy_project();
+    let lockfile_path = project.path().join("wally.lock");
+    // Parsed directly, since loading it would warn about its checksums.
+    let original: Lockfile = fs::read_to_string(&lockfile_path).unwrap().parse().unwrap();
+    assert_eq!(original.version, 1);
+
+    run_migrate(&project).unwrap();
+
+    let migrated = fs::read_to_string(&lockfile_path).unwrap();
+    assert_eq!(
+        migrated,
+        r#"# This file is automatically @generated by Wally.
+# It is not intended for manual editing.
+version = 2
+registry = "test-registries/primary-registry"
+
+[[package]]
+name = "biff/legacy-lockfile"
+version = "0.1.0"
+dependencies = [["OneDependency", "biff/one-dependency@0.1.0"]]
+
+[[package]]
+name = "biff/minimal"
+version = "0.1.0"
+checksum = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+dependencies = []
+
+[[package]]
+name = "biff/one-dependency"
+version = "0.1.0"
+checksum = "sha256:486ea46224d1bb4fb680f34f7c9ad96a8f24ec88be73ea8e5a6c65260e9cb8a7"
+dependencies = [["Minimal", "biff/minimal@0.1.0"]]
+"#
+    );
+
+    // Only how the lockfile is written changed, not what it pins.
+    let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+    assert_eq!(
+        lockfile.as_ids().collect::<Vec<_>>(),
+        original.as_ids().collect::<Vec<_>>()
+    );
+    assert_eq!(lockfile.graph_hash(), original.graph_hash());
+
+    // Migrating again leaves the lockfile alone.
+    run_migrate(&project).unwrap();
+    assert_eq!(fs::read_to_string(&lockfile_path).unwrap(), migrated);
+}
+
+#[test]
+fn migrate_newer_lockfile_fails() {
+    let project = legacy_project();
+    let lockfile_path = project.path().join("wally.lock");
+    let contents = fs::read_to_string(&lockfile_path)
+        .unwrap()
+        .replace("registry = ", "version = 3\nregistry = ");
+    fs::write(&lockfile_path, &contents).unwrap();
+
+    let err = run_migrate(&project).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Lockfile version 3 isn't supported"),
+        "Unexpected error: {}",
+        err
+    );
+    assert_eq!(fs::read_to_string(&lockfile_path).unwrap(), contents);
+}
+
+fn legacy_project() -> TempProject {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects")).join("legacy-lockfile");
+
+    TempProject::new(&source_project).unwrap()
+}
+
+fn run_migrate(project: &TempProject) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::MigrateLockfile(MigrateLockfileSubcommand {
+            project_path: project.path().to_owned(),
+        }),
+    }
+    .run()
+}
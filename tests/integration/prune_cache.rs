@@ -0,0 +1,117 @@
+use super::temp_project::TempProject;
+use fs_err as fs;
+use libwally::lockfile::Lockfile;
+use libwally::Args;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[test]
+fn prune_keeps_packages_used_by_projects() {
+    let cache_dir = tempfile::tempdir().unwrap();
+
+    // Both of these use biff/minimal, but only the second uses
+    // biff/one-dependency.
+    let one_dependency = install_project("one-dependency", cache_dir.path());
+    let transitive = install_project("transitive-dependency", cache_dir.path());
+
+    // The only project using biff/minimal-shared isn't kept.
+    let build_dependency = install_project("build-dependency", cache_dir.path());
+
+    let checksum_of = |project: &TempProject, package_id: &str| -> String {
+        let lockfile = Lockfile::load(project.path()).unwrap().unwrap();
+        lockfile.checksums()[&package_id.parse().unwrap()].clone()
+    };
+    let minimal = checksum_of(&one_dependency, "biff/minimal@0.1.0");
+    let one_dependency_package = checksum_of(&transitive, "biff/one-dependency@0.1.0");
+    let minimal_shared = checksum_of(&build_dependency, "biff/minimal-shared@0.1.0");
+    assert_eq!(
+        cached_packages(cache_dir.path()),
+        sorted(vec![&minimal, &one_dependency_package, &minimal_shared])
+    );
+
+    let keep_used = vec![
+        one_dependency.path().to_owned(),
+        transitive.path().to_owned(),
+    ];
+
+    run_prune_cache(cache_dir.path(), keep_used.clone(), true).unwrap();
+    assert_eq!(cached_packages(cache_dir.path()).len(), 3);
+
+    run_prune_cache(cache_dir.path(), keep_used, false).unwrap();
+    assert_eq!(
+        cached_packages(cache_dir.path()),
+        sorted(vec![&minimal, &one_dependency_package])
+    );
+}
+
+#[test]
+fn prune_needs_a_lockfile_for_every_project() {
+    let cache_dir = tempfile::tempdir().unwrap();
+    let project = install_project("one-dependency", cache_dir.path());
+    fs::remove_file(project.path().join("wally.lock")).unwrap();
+
+    let err =
+        run_prune_cache(cache_dir.path(), vec![project.path().to_owned()], false).unwrap_err();
+    assert!(
+        err.to_string().starts_with("There is no lockfile in"),
+        "Unexpected error: {}",
+        err
+    );
+    assert_eq!(cached_packages(cache_dir.path()).len(), 1);
+}
+
+/// The checksums of every package in the cache, going by the names of their
+/// entries.
+fn cached_packages(cache_dir: &Path) -> Vec<String> {
+    let mut checksums: Vec<String> = fs::read_dir(cache_dir.join("packages/entries"))
+        .unwrap()
+        .map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            name.trim_end_matches(".json").replacen('-', ":", 1)
+        })
+        .collect();
+    checksums.sort();
+    checksums
+}
+
+fn sorted(checksums: Vec<&String>) -> Vec<String> {
+    let mut checksums: Vec<String> = checksums.into_iter().cloned().collect();
+    checksums.sort();
+    checksums
+}
+
+fn install_project(name: &str, cache_dir: &Path) -> TempProject {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects")).join(name);
+    let project = TempProject::new(&source_project).unwrap();
+
+    let mut args = Args::from_iter(&[
+        OsStr::new("wally"),
+        OsStr::new("install"),
+        OsStr::new("--project-path"),
+        project.path().as_os_str(),
+        OsStr::new("--cache-dir"),
+        cache_dir.as_os_str(),
+    ]);
+    args.global.test_registry = true;
+    args.run().unwrap();
+
+    project
+}
+
+fn run_prune_cache(cache_dir: &Path, keep_used: Vec<PathBuf>, dry_run: bool) -> anyhow::Result<()> {
+    let mut args = vec![
+        OsStr::new("wally"),
+        OsStr::new("prune-cache"),
+        OsStr::new("--cache-dir"),
+        cache_dir.as_os_str(),
+        OsStr::new("--keep-used"),
+    ];
+    args.extend(keep_used.iter().map(|path| path.as_os_str()));
+    if dry_run {
+        args.push(OsStr::new("--dry-run"));
+    }
+
+    Args::from_iter(args).run()
+}
@@ -2,11 +2,15 @@ use std::path::Path;
 
 use fs_err::File;
 use libwally::{
-    git_util, package_contents::PackageContents, Args, GlobalOptions, PublishSubcommand, Subcommand,
+    git_util, package_contents::PackageContents, Args, GlobalOptions, PublishSubcommand,
+    Subcommand, DEFAULT_MAX_PUBLISH_SIZE,
 };
 use serial_test::serial;
+use structopt::StructOpt;
 use tempfile::tempdir;
 
+use crate::temp_project::TempProject;
+
 /// If the user tries to publish without providing any auth tokens
 /// then we should prompt them to provide a token via 'wally login'
 #[test]
@@ -29,6 +33,9 @@ fn check_prompts_auth() {
         subcommand: Subcommand::Publish(PublishSubcommand {
             project_path: test_projects.join("minimal"),
             token: None,
+            allow_dirty: true,
+            max_size: DEFAULT_MAX_PUBLISH_SIZE,
+            simulate_ingest: false,
         }),
     };
 
@@ -49,7 +56,7 @@ fn check_mismatched_names() {
     let contents = PackageContents::pack_from_path(&test_projects.join("mismatched-name")).unwrap();
 
     let unpacked_contents = tempdir().unwrap();
-    contents.unpack_into_path(unpacked_contents.path()).unwrap();
+    contents.extract_to(unpacked_contents.path()).unwrap();
 
     let project_json_path = unpacked_contents.path().join("default.project.json");
 
@@ -79,6 +86,9 @@ fn check_private_field() {
         subcommand: Subcommand::Publish(PublishSubcommand {
             project_path: test_projects.join("private-package"),
             token: None,
+            allow_dirty: true,
+            max_size: DEFAULT_MAX_PUBLISH_SIZE,
+            simulate_ingest: false,
         }),
     };
 
@@ -113,9 +123,159 @@ fn check_token_arg() {
         subcommand: Subcommand::Publish(PublishSubcommand {
             project_path: test_projects.join("minimal"),
             token: Some("token".to_owned()),
+            allow_dirty: true,
+            max_size: DEFAULT_MAX_PUBLISH_SIZE,
+            simulate_ingest: false,
         }),
     };
 
     args.run()
         .expect("Publish did not use the provided token in the publish request");
 }
+
+/// Packages larger than the size limit should be refused before anything is
+/// sent to the registry.
+#[test]
+#[serial]
+fn check_size_limit() {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let test_registry = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-registries/primary-registry"
+    ));
+
+    git_util::init_test_repo(&test_registry.join("index")).unwrap();
+
+    let args = Args {
+        global: GlobalOptions {
+            test_registry: true,
+            use_temp_index: true,
+            check_token: Some("token".to_owned()),
+            ..Default::default()
+        },
+        subcommand: Subcommand::Publish(PublishSubcommand {
+            project_path: test_projects.join("minimal"),
+            token: Some("token".to_owned()),
+            allow_dirty: true,
+            max_size: 16,
+            simulate_ingest: false,
+        }),
+    };
+
+    let error = args
+        .run()
+        .expect_err("Expected publish to refuse a package over the size limit");
+
+    assert!(
+        error.to_string().contains("exceeds the limit"),
+        "Expected error message about the package size. Instead we got: {:#}",
+        error
+    );
+}
+
+/// Publishing a project with uncommitted changes to its package files should
+/// be refused unless --allow-dirty is given.
+#[test]
+#[serial]
+fn check_dirty_working_tree() {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let test_registry = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-registries/primary-registry"
+    ));
+
+    git_util::init_test_repo(&test_registry.join("index")).unwrap();
+
+    let project = TempProject::new(&test_projects.join("minimal")).unwrap();
+    git_util::init_test_repo(project.path()).unwrap();
+    fs_err::write(project.path().join("src/init.lua"), "return \"changed\"").unwrap();
+
+    let publish = |allow_dirty| Args {
+        global: GlobalOptions {
+            test_registry: true,
+            use_temp_index: true,
+            check_token: Some("token".to_owned()),
+            ..Default::default()
+        },
+        subcommand: Subcommand::Publish(PublishSubcommand {
+            project_path: project.path().to_owned(),
+            token: Some("token".to_owned()),
+            allow_dirty,
+            max_size: DEFAULT_MAX_PUBLISH_SIZE,
+            simulate_ingest: false,
+        }),
+    };
+
+    let error = publish(false)
+        .run()
+        .expect_err("Expected publish to refuse a dirty working tree");
+
+    assert!(
+        error.to_string().contains("--allow-dirty"),
+        "Expected error message about uncommitted changes. Instead we got: {:#}",
+        error
+    );
+
+    publish(true)
+        .run()
+        .expect("Publish should be allowed with --allow-dirty");
+}
+
+/// Simulating the registry ingesting a version that's already in the index
+/// should report the conflict without publishing anything.
+#[test]
+#[serial]
+fn check_simulate_ingest_conflicting_version() {
+    let test_projects = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects"));
+    let test_registry = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-registries/primary-registry"
+    ));
+
+    git_util::init_test_repo(&test_registry.join("index")).unwrap();
+
+    let args = Args {
+        global: GlobalOptions {
+            test_registry: true,
+            use_temp_index: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Publish(PublishSubcommand {
+            project_path: test_projects.join("minimal"),
+            token: None,
+            allow_dirty: true,
+            max_size: DEFAULT_MAX_PUBLISH_SIZE,
+            simulate_ingest: true,
+        }),
+    };
+
+    let error = args
+        .run()
+        .expect_err("Expected ingesting an existing version to be rejected");
+
+    assert!(
+        error.to_string().contains("listed more than once"),
+        "Expected error message about the duplicate version. Instead we got: {:#}",
+        error
+    );
+}
+
+/// The size limits the command line defaults to are the same ones used
+/// everywhere else.
+#[test]
+fn size_limit_defaults() {
+    let args = Args::from_iter(&["wally", "publish"]);
+    assert_eq!(
+        args.global.max_download_size,
+        GlobalOptions::default().max_download_size
+    );
+    assert_eq!(
+        args.global.max_index_entries,
+        GlobalOptions::default().max_index_entries
+    );
+
+    match args.subcommand {
+        Subcommand::Publish(publish) => assert_eq!(publish.max_size, DEFAULT_MAX_PUBLISH_SIZE),
+        subcommand => panic!("Expected a publish, got {:?}", subcommand),
+    }
+}
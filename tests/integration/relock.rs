@@ -0,0 +1,102 @@
+use super::temp_project::TempProject;
+use fs_err as fs;
+use libwally::{Args, GlobalOptions, InstallSubcommand, RelockSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn relock_transitive_dependency() {
+    assert_relock_restores_lockfile("transitive-dependency");
+}
+
+#[test]
+fn relock_cross_realm_dependency() {
+    assert_relock_restores_lockfile("cross-realm-dependency");
+}
+
+#[test]
+fn relock_dev_dependency() {
+    assert_relock_restores_lockfile("dev-dependency-also-required-as-non-dev");
+}
+
+#[test]
+fn relock_refuses_to_overwrite_lockfile() {
+    let project = install_project("one-dependency");
+
+    let err = run_relock(&project).unwrap_err();
+    assert!(
+        err.to_string().contains("already exists"),
+        "Unexpected error: {}",
+        err
+    );
+}
+
+fn assert_relock_restores_lockfile(name: &str) {
+    let project = install_project(name);
+
+    let lockfile_path = project.path().join("wally.lock");
+    let original = fs::read_to_string(&lockfile_path).unwrap();
+    fs::remove_file(&lockfile_path).unwrap();
+
+    run_relock(&project).unwrap();
+
+    let rebuilt = fs::read_to_string(&lockfile_path).unwrap();
+    assert_eq!(rebuilt, original);
+}
+
+fn install_project(name: &str) -> TempProject {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    project
+}
+
+fn run_relock(project: &TempProject) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            deny_warnings: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Relock(RelockSubcommand {
+            project_path: project.path().to_owned(),
+            from_installed: true,
+        }),
+    }
+    .run()
+}
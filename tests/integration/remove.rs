@@ -0,0 +1,122 @@
+use crate::temp_project::TempProject;
+use fs_err as fs;
+use libwally::{Args, GlobalOptions, InstallSubcommand, RemoveSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn remove_prunes_orphaned_packages() {
+    let project = install_project("transitive-dependency");
+    let index = project.path().join("ServerPackages/_Index");
+    assert!(index.join("biff_minimal@0.1.0").is_dir());
+
+    run_remove(&project, "biff/one-dependency").unwrap();
+
+    let manifest = fs::read_to_string(project.path().join("wally.toml")).unwrap();
+    assert_eq!(
+        manifest,
+        "[package]\n\
+         name = \"biff/transitive-dependency\"\n\
+         version = \"0.1.0\"\n\
+         license = \"MIT\"\n\
+         realm = \"server\"\n\
+         registry = \"test-registries/primary-registry\"\n\
+         \n\
+         [server-dependencies]\n"
+    );
+
+    // Minimal was only needed by the package that was removed.
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert!(!lockfile.contains("biff/one-dependency"), "{}", lockfile);
+    assert!(!lockfile.contains("biff/minimal"), "{}", lockfile);
+
+    run_install(&project);
+    assert!(!project.path().join("ServerPackages").exists());
+}
+
+#[test]
+fn remove_transitive_dependency_fails() {
+    let project = install_project("transitive-dependency");
+
+    let error = run_remove(&project, "biff/minimal").unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("required by biff/one-dependency"),
+        "Unexpected error: {}",
+        error
+    );
+}
+
+#[test]
+fn remove_leaves_out_optional_dependencies_that_are_off() {
+    let project = install_project("optional-dependency");
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert!(!lockfile.contains("biff/minimal-shared"), "{}", lockfile);
+
+    run_remove(&project, "biff/one-dependency").unwrap();
+
+    let lockfile = fs::read_to_string(project.path().join("wally.lock")).unwrap();
+    assert!(!lockfile.contains("biff/minimal-shared"), "{}", lockfile);
+    assert!(!lockfile.contains("biff/one-dependency"), "{}", lockfile);
+}
+
+fn install_project(name: &str) -> TempProject {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
+
+    let project = TempProject::new(&source_project).unwrap();
+    run_install(&project);
+
+    project
+}
+
+fn run_install(project: &TempProject) {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+}
+
+fn run_remove(project: &TempProject, package: &str) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Remove(RemoveSubcommand {
+            project_path: project.path().to_owned(),
+            package: package.parse().unwrap(),
+        }),
+    }
+    .run()
+}
@@ -0,0 +1,89 @@
+use libwally::{Args, GlobalOptions, InstallSubcommand, Subcommand};
+use std::path::Path;
+
+use crate::temp_project::TempProject;
+use crate::util::read_bytes;
+
+#[test]
+fn transitive_dependency() {
+    assert_reproducible_install("transitive-dependency");
+}
+
+#[test]
+fn cross_realm_dependency() {
+    assert_reproducible_install("cross-realm-dependency");
+}
+
+#[test]
+fn diamond_graph() {
+    assert_reproducible_install("diamond-graph/root/latest");
+}
+
+/// Installs the same project into two separate temporary directories and
+/// asserts that every file they end up with, including the lockfile, package
+/// links, and install records, is byte-for-byte the same.
+fn assert_reproducible_install(name: &str) {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects")).join(name);
+
+    let first = TempProject::new(&source_project).unwrap();
+    let second = TempProject::new(&source_project).unwrap();
+
+    for project in [&first, &second] {
+        Args {
+            global: GlobalOptions {
+                test_registry: true,
+                ..Default::default()
+            },
+            subcommand: Subcommand::Install(InstallSubcommand {
+                project_path: project.path().to_owned(),
+                locked: false,
+                reinstall: false,
+                target: None,
+                report: None,
+                pin: false,
+                link: false,
+                trace_resolution: None,
+                strict_peers: false,
+                error_mode: Default::default(),
+                prefer_recommended: false,
+                only: None,
+                warn_stale_patches: false,
+                lockfile_only: false,
+                from_lockfile: None,
+                index_rev: None,
+                require_space: false,
+                no_verify: false,
+                no_default_features: false,
+                features: Vec::new(),
+                verify_manifests: false,
+                format: "text".to_owned(),
+                timings: None,
+                locked_transitive: None,
+            }),
+        }
+        .run()
+        .unwrap();
+    }
+
+    let first_files = read_bytes(first.path()).unwrap();
+    let second_files = read_bytes(second.path()).unwrap();
+
+    let first_paths: Vec<&String> = first_files.keys().collect();
+    let second_paths: Vec<&String> = second_files.keys().collect();
+    assert_eq!(
+        first_paths, second_paths,
+        "The installs produced different files"
+    );
+
+    let differing: Vec<&String> = first_files
+        .iter()
+        .filter(|(path, contents)| second_files[*path] != **contents)
+        .map(|(path, _)| path)
+        .collect();
+    assert!(
+        differing.is_empty(),
+        "These files differ between the installs: {:?}",
+        differing
+    );
+}
@@ -0,0 +1,108 @@
+use super::temp_project::TempProject;
+use fs_err as fs;
+use libwally::lockfile::{LockPackage, Lockfile};
+use libwally::{Args, GlobalOptions, InstallSubcommand, ResolveSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn resolve_without_contents() {
+    // A copy of the registry with none of its packages' contents, so that
+    // anything trying to download a package fails.
+    let registry = TempProject::new(&test_path("test-registries/primary-registry")).unwrap();
+    fs::remove_dir_all(registry.path().join("contents")).unwrap();
+
+    let project = TempProject::new(&test_path("test-projects/transitive-dependency")).unwrap();
+    let manifest_path = project.path().join("wally.toml");
+    let manifest = fs::read_to_string(&manifest_path).unwrap().replace(
+        "registry = \"test-registries/primary-registry\"",
+        &format!("registry = {:?}", registry.path().display().to_string()),
+    );
+    fs::write(&manifest_path, manifest).unwrap();
+
+    let output_path = project.path().join("resolved.lock");
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Resolve(ResolveSubcommand {
+            project_path: project.path().to_owned(),
+            target: None,
+            output: Some(output_path.clone()),
+        }),
+    }
+    .run()
+    .unwrap();
+
+    assert!(!project.path().join("wally.lock").exists());
+    assert!(!project.path().join("ServerPackages").exists());
+
+    let resolved = Lockfile::load_file(&output_path).unwrap().unwrap();
+    let installed = install_project("transitive-dependency");
+
+    assert_eq!(pins(&resolved), pins(&installed));
+}
+
+fn test_path(path: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+/// Installs a copy of the project and returns the lockfile it wrote.
+fn install_project(name: &str) -> Lockfile {
+    let project = TempProject::new(&test_path("test-projects").join(name)).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    Lockfile::load(project.path()).unwrap().unwrap()
+}
+
+/// The lockfile's pins, without the checksums that only a download can fill
+/// in for packages published before checksums were recorded.
+fn pins(lockfile: &Lockfile) -> Vec<LockPackage> {
+    lockfile
+        .packages
+        .iter()
+        .cloned()
+        .map(|lock_package| match lock_package {
+            LockPackage::Registry(mut lock_package) => {
+                lock_package.checksum = None;
+                LockPackage::Registry(lock_package)
+            }
+            other => other,
+        })
+        .collect()
+}
@@ -0,0 +1,57 @@
+use crate::temp_project::TempProject;
+use libwally::{Args, GlobalOptions, RunSubcommand, Subcommand};
+use std::path::Path;
+
+#[test]
+fn run_defined_script() {
+    let project = scripts_project();
+
+    run_script(&project, "touch").unwrap();
+
+    // Scripts run in the project's folder, not wherever Wally was run from.
+    assert!(project.path().join("ran.txt").is_file());
+}
+
+#[test]
+fn run_undefined_script() {
+    let project = scripts_project();
+
+    let error = run_script(&project, "build").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "There is no script named `build`. Available scripts: fail, touch"
+    );
+}
+
+#[test]
+fn run_failing_script() {
+    let project = scripts_project();
+
+    let error = run_script(&project, "fail").unwrap_err();
+    assert!(
+        error.to_string().starts_with("Script `fail` failed"),
+        "{}",
+        error
+    );
+}
+
+fn scripts_project() -> TempProject {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/scripts"
+    ));
+
+    TempProject::new(source_project).unwrap()
+}
+
+fn run_script(project: &TempProject, script: &str) -> anyhow::Result<()> {
+    Args {
+        global: GlobalOptions::default(),
+        subcommand: Subcommand::Run(RunSubcommand {
+            project_path: project.path().to_owned(),
+            script: script.to_owned(),
+            args: Vec::new(),
+        }),
+    }
+    .run()
+}
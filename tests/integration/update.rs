@@ -2,10 +2,24 @@ use crate::temp_project::TempProject;
 use fs_err as fs;
 use insta::assert_snapshot;
 use libwally::{
-    package_name::PackageName, package_req::PackageReq, Args, GlobalOptions, PackageSpec,
-    Subcommand, UpdateSubcommand,
+    package_name::PackageName, package_req::PackageReq, Args, GlobalOptions, InstallSubcommand,
+    PackageSpec, Subcommand, UpdateSubcommand,
 };
 use std::{path::Path, str::FromStr};
+use structopt::StructOpt;
+
+/// Updates built from `UpdateSubcommand::default()` run the same update as
+/// `wally update` with no arguments.
+#[test]
+fn update_defaults() {
+    match Args::from_iter(&["wally", "update"]).subcommand {
+        Subcommand::Update(update) => assert_eq!(
+            format!("{:?}", update),
+            format!("{:?}", UpdateSubcommand::default())
+        ),
+        subcommand => panic!("Expected an update, got {:?}", subcommand),
+    }
+}
 
 #[test]
 fn generate_new_lockfile_if_missing() {
@@ -133,6 +147,87 @@ fn update_list_of_specs() {
     assert_snapshot!(lockfile_content);
 }
 
+#[test]
+/// Packages that didn't change between installs should be left alone, while
+/// the updated package is extracted again.
+fn only_reextract_changed_packages() {
+    let source_project = Path::new(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/test-projects/diamond-graph/root/dated"
+    ));
+
+    let project = TempProject::new(&source_project).unwrap();
+
+    Args {
+        global: GlobalOptions {
+            test_registry: true,
+            ..Default::default()
+        },
+        subcommand: Subcommand::Install(InstallSubcommand {
+            project_path: project.path().to_owned(),
+            locked: false,
+            reinstall: false,
+            target: None,
+            report: None,
+            pin: false,
+            link: false,
+            trace_resolution: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
+            only: None,
+            warn_stale_patches: false,
+            lockfile_only: false,
+            from_lockfile: None,
+            index_rev: None,
+            require_space: false,
+            no_verify: false,
+            no_default_features: false,
+            features: Vec::new(),
+            verify_manifests: false,
+            format: "text".to_owned(),
+            timings: None,
+            locked_transitive: None,
+        }),
+    }
+    .run()
+    .unwrap();
+
+    let index = project.path().join("ServerPackages").join("_Index");
+    let unchanged_sentinel = index
+        .join("diamond-graph_direct-dependency-b@0.1.0")
+        .join("direct-dependency-b")
+        .join("sentinel.txt");
+
+    fs::write(&unchanged_sentinel, "untouched").unwrap();
+
+    run_update_with_specs(
+        &project,
+        vec![PackageSpec::Named(
+            PackageName::new("diamond-graph", "direct-dependency-a").unwrap(),
+        )],
+    )
+    .unwrap();
+
+    assert!(
+        unchanged_sentinel.exists(),
+        "direct-dependency-b didn't change, so it shouldn't have been extracted again."
+    );
+    assert!(
+        !index
+            .join("diamond-graph_direct-dependency-a@0.1.0")
+            .exists(),
+        "The old version of direct-dependency-a should've been removed."
+    );
+    assert!(
+        index
+            .join("diamond-graph_direct-dependency-a@0.1.1")
+            .join("direct-dependency-a")
+            .is_dir(),
+        "The new version of direct-dependency-a should've been extracted."
+    );
+}
+
 fn run_update(project: &TempProject) -> anyhow::Result<()> {
     run_update_with_specs(project, Vec::new())
 }
@@ -146,6 +241,10 @@ fn run_update_with_specs(project: &TempProject, specs: Vec<PackageSpec>) -> anyh
         subcommand: Subcommand::Update(UpdateSubcommand {
             project_path: project.path().to_owned(),
             package_specs: specs,
+            target: None,
+            strict_peers: false,
+            error_mode: Default::default(),
+            prefer_recommended: false,
         }),
     }
     .run()
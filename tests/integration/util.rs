@@ -38,3 +38,28 @@ pub fn read_path(path: &Path) -> anyhow::Result<Entry> {
         Ok(Entry::File(contents))
     }
 }
+
+/// Reads every file under `path` as raw bytes, keyed by its path relative to
+/// `path` with `/` separators. Unlike `read_path`, this also works for files
+/// that aren't UTF-8 and can tell apart differences in line endings.
+pub fn read_bytes(path: &Path) -> anyhow::Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+
+    for entry in walkdir::WalkDir::new(path).min_depth(1) {
+        let entry = entry?;
+
+        if entry.file_type().is_file() {
+            let relative = entry
+                .path()
+                .strip_prefix(path)?
+                .components()
+                .map(|component| component.as_os_str().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            files.insert(relative, fs_err::read(entry.path())?);
+        }
+    }
+
+    Ok(files)
+}
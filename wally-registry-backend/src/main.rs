@@ -20,7 +20,9 @@ use figment::{
     Figment,
 };
 use libwally::{
+    checksum::Checksum,
     manifest::{Manifest, MANIFEST_FILE_NAME},
+    package_contents::PackageContents,
     package_id::PackageId,
     package_index::PackageIndex,
     package_name::PackageName,
@@ -145,16 +147,32 @@ async fn publish(
         return Err(format_err!("request body too large").status(Status::BadRequest));
     }
 
-    let contents = Cursor::new(contents.value);
-    let mut archive = ZipArchive::new(contents)
+    // Archives with more than one entry for a path extract differently
+    // depending on which one is written last.
+    let contents = PackageContents::from_buffer(contents.value);
+    contents.check_entries().status(Status::BadRequest)?;
+
+    let checksum = Checksum::compute(contents.data());
+    let mut archive = ZipArchive::new(Cursor::new(contents.data()))
         .context("could not read ZIP archive")
         .status(Status::BadRequest)?;
 
     index.update()?;
 
-    let manifest = get_manifest(&mut archive).status(Status::BadRequest)?;
+    let mut manifest = get_manifest(&mut archive).status(Status::BadRequest)?;
     let package_id = manifest.package_id();
 
+    // Recorded in the index so that installs can check for disk space before
+    // downloading anything.
+    manifest.extracted_size = Some(extracted_size(&mut archive).status(Status::BadRequest)?);
+
+    // Recorded so that the stored contents can be audited against what was
+    // published.
+    manifest.checksum = Some(checksum.to_string());
+
+    // Only the registry's operators decide which versions are recommended.
+    manifest.recommended = false;
+
     if !authorization.can_write_package(&package_id, &index)? {
         return Err(format_err!(
             "you do not have permission to write in scope {}",
@@ -184,7 +202,7 @@ async fn publish(
     }
 
     storage
-        .write(&manifest.package_id(), &archive.into_inner().into_inner())
+        .write(&manifest.package_id(), contents.data())
         .await
         .context("could not write package to storage backend")?;
 
@@ -223,6 +241,20 @@ fn get_manifest<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<M
     Ok(manifest)
 }
 
+/// How many bytes the files in the archive take up once extracted.
+fn extracted_size<R: Read + Seek>(archive: &mut ZipArchive<R>) -> anyhow::Result<u64> {
+    let mut size = 0;
+
+    for index in 0..archive.len() {
+        size += archive
+            .by_index(index)
+            .context("could not read ZIP archive")?
+            .size();
+    }
+
+    Ok(size)
+}
+
 pub fn server(figment: Figment) -> rocket::Rocket<Build> {
     let config: Config = figment.extract().expect("could not read configuration");
 
@@ -71,7 +71,8 @@ struct Expectation {
 }
 
 impl Expectation {
-    fn assert(self, response: LocalResponse<'_>) {
+    /// Checks the response, returning its body for any further checks.
+    fn assert(self, response: LocalResponse<'_>) -> String {
         fn get_body(response: LocalResponse<'_>) -> String {
             let body = response
                 .into_string()
@@ -103,6 +104,8 @@ impl Expectation {
                 get_body(response),
             );
         }
+
+        get_body(response)
     }
 }
 
@@ -250,6 +253,34 @@ fn publish_duplicate() {
     .assert(send_request());
 }
 
+#[test]
+fn publish_duplicate_paths() {
+    let contents = PackageBuilder::new("biff/hello@1.0.0")
+        .with_file("src/init.lua", "return 1")
+        .with_file("src/./init.lua", "return 2")
+        .contents();
+
+    let client = new_client(AuthMode::ApiKey(String::from("hello")));
+    let response = client
+        .post("/v1/publish")
+        .header(Accept::JSON)
+        .body(contents.data())
+        .header(Header::new("Authorization", "Bearer hello"))
+        .dispatch();
+
+    let body = Expectation {
+        status: Status::BadRequest,
+        content_type: ContentType::JSON,
+    }
+    .assert(response);
+
+    assert!(
+        body.contains("more than one entry for the path src/init.lua"),
+        "Expected the error to name src/init.lua. Instead we got: {}",
+        body
+    );
+}
+
 #[test]
 fn publish_updates_git_remote() {
     let remote = init_test_index_remote().unwrap();